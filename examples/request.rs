@@ -1,9 +1,12 @@
-use std::net::SocketAddr;
+use std::{error::Error, net::SocketAddr};
 
 use hickory_resolver::config::NameServerConfig;
-use httptrace::{client::ClientBuilder, request::Request, stats::Recorder};
-use tokio::net::TcpStream;
-use tokio_rustls::client::TlsStream;
+use httptrace::{
+    client::ClientBuilder,
+    dns::DnsInfo,
+    request::Request,
+    stats::{Recorder, TcpInfo, TlsInfo},
+};
 
 #[tokio::main]
 pub async fn main() {
@@ -49,7 +52,7 @@ impl Recorder for LogRecorder {
         _request: &Request,
         _name_servers: &[NameServerConfig],
         _host: &str,
-        _result: Result<(&[SocketAddr], bool), String>,
+        _result: Result<&DnsInfo, &dyn Error>,
     ) {
         println!(
             "{} [dns-done]   {} - {:?} --> {:?}",
@@ -71,28 +74,20 @@ impl Recorder for LogRecorder {
         &self,
         _request: &Request,
         _dest: &SocketAddr,
-        _stream: Result<&TcpStream, String>,
+        _tcp: Result<&TcpInfo, &dyn Error>,
     ) {
-        println!(
-            "{} [tcp-done]   {:?} --> {:?}",
-            _request.uri(),
-            _dest,
-            _stream
-        );
+        println!("{} [tcp-done]   {:?} --> {:?}", _request.uri(), _dest, _tcp);
     }
 
-    fn on_tls_start(&self, _request: &Request, _stream: &TcpStream) {
-        println!("{} [tls-start]  {:?}", _request.uri(), _stream.peer_addr());
+    fn on_tls_start(&self, _request: &Request, _tcp: &TcpInfo) {
+        println!("{} [tls-start]  {:?}", _request.uri(), _tcp.peer_addr);
     }
 
-    fn on_tls_done(&self, _request: &Request, _stream: Result<&TlsStream<TcpStream>, String>) {
+    fn on_tls_done(&self, _request: &Request, _tls: Result<&TlsInfo, &dyn Error>) {
         println!(
             "{} [tls-done]   {:?}",
             _request.uri(),
-            _stream.map(|stream| {
-                let session = stream.get_ref().1;
-                format!("protocol: {:?}", session.protocol_version(),)
-            })
+            _tls.map(|tls| format!("protocol: {:?}", tls.protocol_version))
         );
     }
 
@@ -0,0 +1,14 @@
+use httptrace::{client::ClientBuilder, verbose::VerboseRecorder};
+
+#[tokio::main]
+pub async fn main() {
+    let client = ClientBuilder::new().build().unwrap();
+    let result = client
+        .get("https://www.example.com")
+        .recorder(Box::new(VerboseRecorder::new()))
+        .send()
+        .await
+        .unwrap();
+
+    println!("{:?}", result.text().await);
+}
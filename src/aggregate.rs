@@ -0,0 +1,322 @@
+//! Aggregate the [`Stats`] of repeated requests.
+
+use std::time::Duration;
+
+use crate::stats::Stats;
+
+// 2^SUB_BUCKET_BITS values per power of two, a relative error below 0.1%.
+const SUB_BUCKET_BITS: u32 = 11;
+const SUB_BUCKETS: u64 = 1 << SUB_BUCKET_BITS;
+const HALF_SUB_BUCKETS: u64 = SUB_BUCKETS / 2;
+
+/// A latency histogram with logarithmic buckets, in the spirit of HdrHistogram.
+///
+/// Durations are recorded in microseconds, values are kept to three
+/// significant digits whatever their magnitude, so high percentiles of long
+/// runs stay accurate with a bounded memory.
+#[derive(Debug, Clone, Default)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    count: u64,
+    sum: u128,
+    min: u64,
+    max: u64,
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, duration: Duration) {
+        let value = u64::try_from(duration.as_micros()).unwrap_or(u64::MAX);
+        let index = bucket_index(value);
+        if index >= self.counts.len() {
+            self.counts.resize(index + 1, 0);
+        }
+        self.counts[index] += 1;
+        self.min = if self.count == 0 {
+            value
+        } else {
+            self.min.min(value)
+        };
+        self.max = self.max.max(value);
+        self.count += 1;
+        self.sum += u128::from(value);
+    }
+
+    /// Add the samples of another histogram.
+    pub fn merge(&mut self, other: &Histogram) {
+        if other.count == 0 {
+            return;
+        }
+        if other.counts.len() > self.counts.len() {
+            self.counts.resize(other.counts.len(), 0);
+        }
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+        self.min = if self.count == 0 {
+            other.min
+        } else {
+            self.min.min(other.min)
+        };
+        self.max = self.max.max(other.max);
+        self.count += other.count;
+        self.sum += other.sum;
+    }
+
+    /// The number of samples.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn min(&self) -> Duration {
+        Duration::from_micros(self.min)
+    }
+
+    pub fn max(&self) -> Duration {
+        Duration::from_micros(self.max)
+    }
+
+    pub fn mean(&self) -> Duration {
+        match self.count {
+            0 => Duration::ZERO,
+            count => Duration::from_micros((self.sum / u128::from(count)) as u64),
+        }
+    }
+
+    /// The duration below which `percentile` percent of the samples fall, e.g.
+    /// `99.9`. Zero if the histogram is empty.
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+        // Rounded rather than ceiled, `99.9 / 100.0 * 1000.0` is above 999.
+        let rank = (percentile.clamp(0.0, 100.0) / 100.0 * self.count as f64).round() as u64;
+        let rank = rank.max(1);
+        let mut seen = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let value = bucket_highest(index).clamp(self.min, self.max);
+                return Duration::from_micros(value);
+            }
+        }
+        self.max()
+    }
+
+    /// The number of samples at most `limit`, to within the bucket precision.
+    pub fn count_within(&self, limit: Duration) -> u64 {
+        let value = u64::try_from(limit.as_micros()).unwrap_or(u64::MAX);
+        let end = (bucket_index(value) + 1).min(self.counts.len());
+        self.counts[..end].iter().sum()
+    }
+}
+
+fn bucket_index(value: u64) -> usize {
+    if value < SUB_BUCKETS {
+        return value as usize;
+    }
+    // Keep the SUB_BUCKET_BITS most significant bits.
+    let shift = 64 - value.leading_zeros() - SUB_BUCKET_BITS;
+    let mantissa = value >> shift;
+    (SUB_BUCKETS + u64::from(shift - 1) * HALF_SUB_BUCKETS + mantissa - HALF_SUB_BUCKETS) as usize
+}
+
+/// The highest value falling into the bucket.
+fn bucket_highest(index: usize) -> u64 {
+    let index = index as u64;
+    if index < SUB_BUCKETS {
+        return index;
+    }
+    let offset = index - SUB_BUCKETS;
+    let shift = offset / HALF_SUB_BUCKETS + 1;
+    let mantissa = offset % HALF_SUB_BUCKETS + HALF_SUB_BUCKETS;
+    (mantissa << shift) | ((1 << shift) - 1)
+}
+
+/// A phase of a request, see [`StatsAggregate::histogram`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Phase {
+    Blocked,
+    Dns,
+    Tcp,
+    Tls,
+    Request,
+    Total,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 6] = [
+        Phase::Blocked,
+        Phase::Dns,
+        Phase::Tcp,
+        Phase::Tls,
+        Phase::Request,
+        Phase::Total,
+    ];
+
+    /// The time the request spent in the phase, `None` if it didn't go
+    /// through it or failed there.
+    pub fn duration(self, stats: &Stats) -> Option<Duration> {
+        let stat = match self {
+            Phase::Blocked => stats.blocked_stats.as_ref(),
+            Phase::Dns => Some(&stats.dns_stats).filter(|v| v.started_at.is_some()),
+            Phase::Tcp => {
+                return stats
+                    .tcp_stats
+                    .iter()
+                    .flatten()
+                    .find(|stat| stat.error.is_none())
+                    .map(|stat| stat.duration);
+            }
+            Phase::Tls => stats.tls_stats.as_ref(),
+            Phase::Request => stats.request_stats.as_ref(),
+            Phase::Total => return Some(stats.total_duration),
+        };
+        stat.filter(|stat| stat.error.is_none())
+            .map(|stat| stat.duration)
+    }
+}
+
+impl std::fmt::Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Phase::Blocked => "blocked",
+            Phase::Dns => "dns",
+            Phase::Tcp => "tcp",
+            Phase::Tls => "tls",
+            Phase::Request => "request",
+            Phase::Total => "total",
+        })
+    }
+}
+
+/// One [`Histogram`] per phase, fed with the [`Stats`] of each request.
+///
+/// Phases a request didn't go through are not recorded, e.g. `tls` only counts
+/// `https` requests.
+#[derive(Debug, Clone, Default)]
+pub struct StatsAggregate {
+    pub blocked: Histogram,
+    pub dns: Histogram,
+    /// The successful connect attempt.
+    pub tcp: Histogram,
+    pub tls: Histogram,
+    /// The resumed handshakes among [`tls`](Self::tls), to tell what the
+    /// session cache saves.
+    pub tls_resumed: Histogram,
+    pub request: Histogram,
+    pub total: Histogram,
+    /// The number of requests that failed at some phase.
+    pub errors: u64,
+}
+
+impl StatsAggregate {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, stats: &Stats) {
+        let mut failed = false;
+
+        if let Some(stat) = stats.blocked_stats.as_ref() {
+            self.blocked.record(stat.duration);
+        }
+        // Cached responses don't resolve anything.
+        if stats.dns_stats.error.is_some() {
+            failed = true;
+        } else if stats.dns_stats.started_at.is_some() {
+            self.dns.record(stats.dns_stats.duration);
+        }
+        if let Some(tcp_stats) = stats.tcp_stats.as_ref() {
+            match tcp_stats.iter().find(|stat| stat.error.is_none()) {
+                Some(stat) => self.tcp.record(stat.duration),
+                None => failed = true,
+            }
+        }
+        if let Some(stat) = stats.tls_stats.as_ref()
+            && stat.error.is_none()
+            && stats.tls_info.as_ref().is_some_and(|v| v.resumed)
+        {
+            self.tls_resumed.record(stat.duration);
+        }
+        for (histogram, stat) in [
+            (&mut self.tls, stats.tls_stats.as_ref()),
+            (&mut self.request, stats.request_stats.as_ref()),
+        ] {
+            match stat {
+                Some(stat) if stat.error.is_some() => failed = true,
+                Some(stat) => histogram.record(stat.duration),
+                None => {}
+            }
+        }
+        self.total.record(stats.total_duration);
+        if failed {
+            self.errors += 1;
+        }
+    }
+
+    pub fn histogram(&self, phase: Phase) -> &Histogram {
+        match phase {
+            Phase::Blocked => &self.blocked,
+            Phase::Dns => &self.dns,
+            Phase::Tcp => &self.tcp,
+            Phase::Tls => &self.tls,
+            Phase::Request => &self.request,
+            Phase::Total => &self.total,
+        }
+    }
+
+    /// Add the samples of another aggregate, e.g. one of another worker.
+    pub fn merge(&mut self, other: &StatsAggregate) {
+        self.blocked.merge(&other.blocked);
+        self.dns.merge(&other.dns);
+        self.tcp.merge(&other.tcp);
+        self.tls.merge(&other.tls);
+        self.tls_resumed.merge(&other.tls_resumed);
+        self.request.merge(&other.request);
+        self.total.merge(&other.total);
+        self.errors += other.errors;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Histogram, bucket_highest, bucket_index};
+
+    #[test]
+    fn test_histogram_percentiles() {
+        for value in [0, 1, 2047, 2048, 2049, 4095, 4096, 123_456_789, u64::MAX] {
+            let index = bucket_index(value);
+            assert!(bucket_highest(index) >= value);
+            assert!(index == 0 || bucket_highest(index - 1) < value);
+        }
+
+        let mut histogram = Histogram::new();
+        for ms in 1..=1000 {
+            histogram.record(Duration::from_millis(ms));
+        }
+        assert_eq!(histogram.count(), 1000);
+        assert_eq!(histogram.min(), Duration::from_millis(1));
+        assert_eq!(histogram.max(), Duration::from_millis(1000));
+        assert_eq!(histogram.mean(), Duration::from_micros(500_500));
+        for (percentile, ms) in [(50.0, 500), (99.0, 990), (99.9, 999), (100.0, 1000)] {
+            let value = histogram.percentile(percentile).as_micros() as f64;
+            let expected = (ms * 1000) as f64;
+            assert!((value - expected).abs() / expected < 0.001, "p{percentile}");
+        }
+
+        let mut merged = Histogram::new();
+        merged.merge(&histogram);
+        merged.merge(&histogram);
+        assert_eq!(merged.count(), 2000);
+        assert_eq!(merged.percentile(50.0), histogram.percentile(50.0));
+        assert_eq!(histogram.count_within(Duration::from_millis(250)), 250);
+        assert_eq!(histogram.count_within(Duration::ZERO), 0);
+        assert_eq!(histogram.count_within(Duration::MAX), 1000);
+    }
+}
@@ -0,0 +1,321 @@
+//! Alternative services advertised with `Alt-Svc`, see
+//! [`ClientBuilder::alt_svc`](crate::client::ClientBuilder::alt_svc).
+//!
+//! Only `https` origins are cached: their alternatives are reached with the
+//! origin's TLS server name, so the certificate still vouches for the origin.
+
+use std::{collections::HashMap, fmt, sync::Mutex, time::Duration};
+
+use http::{HeaderMap, Uri, header};
+use tokio::time::Instant;
+
+/// The protocols the client can speak to an alternative.
+const SUPPORTED: &[&str] = &["h2", "http/1.1"];
+
+const DEFAULT_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The longest an alternative is kept, whatever its `ma`.
+const MAX_MAX_AGE: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// An alternative service of an origin.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AltSvc {
+    /// The ALPN protocol of the alternative, e.g. `h2` or `h3`.
+    pub protocol: String,
+    /// The host of the alternative, `None` for the host of the origin.
+    pub host: Option<String>,
+    pub port: u16,
+    /// How long the alternative stays valid, from `ma`, at most a year.
+    pub max_age: Duration,
+}
+
+impl AltSvc {
+    /// Whether the client can connect to this alternative.
+    pub fn is_supported(&self) -> bool {
+        SUPPORTED.contains(&self.protocol.as_str())
+    }
+}
+
+impl fmt::Display for AltSvc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let host = self.host.as_deref().unwrap_or_default();
+        write!(f, "{}=\"{host}:{}\"", self.protocol, self.port)
+    }
+}
+
+/// What the client made of the cached alternatives of an origin.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AltSvcDecision {
+    /// The alternative connected to, `None` to connect to the origin.
+    pub used: Option<AltSvc>,
+    /// The alternatives passed over for a protocol the client doesn't speak,
+    /// such as `h3`.
+    pub skipped: Vec<AltSvc>,
+}
+
+/// Parse the value of an `Alt-Svc` header, an empty list for `clear`. `None`
+/// if it's malformed.
+pub fn parse(value: &str) -> Option<Vec<AltSvc>> {
+    if value.trim() == "clear" {
+        return Some(Vec::new());
+    }
+    split_quoted(value, ',')
+        .into_iter()
+        .filter(|v| !v.trim().is_empty())
+        .map(parse_alternative)
+        .collect()
+}
+
+fn parse_alternative(value: &str) -> Option<AltSvc> {
+    let mut params = split_quoted(value, ';').into_iter();
+    let (protocol, authority) = params.next()?.split_once('=')?;
+    // The protocol id is percent-encoded, e.g. `http%2F1.1`.
+    let protocol = crate::util::percent_decode(protocol.trim())?;
+    let authority = authority.trim().strip_prefix('"')?.strip_suffix('"')?;
+    let (host, port) = authority.rsplit_once(':')?;
+    let port = port.parse().ok()?;
+    let host = (!host.is_empty()).then(|| host.trim_matches(['[', ']']).to_string());
+
+    let mut max_age = DEFAULT_MAX_AGE;
+    for param in params {
+        if let Some((name, value)) = param.split_once('=')
+            && name.trim().eq_ignore_ascii_case("ma")
+        {
+            let secs = value.trim().trim_matches('"').parse().ok()?;
+            max_age = Duration::from_secs(secs).min(MAX_MAX_AGE);
+        }
+    }
+    Some(AltSvc {
+        protocol,
+        host,
+        port,
+        max_age,
+    })
+}
+
+/// Split `value` on `sep`, except within quotes.
+fn split_quoted(value: &str, sep: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut start, mut quoted) = (0, false);
+    for (index, c) in value.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c == sep && !quoted => {
+                parts.push(&value[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&value[start..]);
+    parts
+}
+
+/// The alternatives of the origins the client talked to, shared by its
+/// requests.
+#[derive(Debug, Default)]
+pub(crate) struct AltSvcCache {
+    origins: Mutex<HashMap<String, Vec<(AltSvc, Instant)>>>,
+}
+
+impl AltSvcCache {
+    /// Replace the alternatives of the origin of `uri` with those of the
+    /// response headers, if it has any.
+    pub(crate) fn update(&self, uri: &Uri, headers: &HeaderMap) {
+        let Some(origin) = origin(uri) else {
+            return;
+        };
+        let mut values = headers.get_all(header::ALT_SVC).iter().peekable();
+        if values.peek().is_none() {
+            return;
+        }
+        let mut alternatives = Vec::new();
+        for value in values {
+            let Some(parsed) = value.to_str().ok().and_then(parse) else {
+                return;
+            };
+            alternatives.extend(parsed);
+        }
+
+        let now = Instant::now();
+        let mut origins = self.origins.lock().unwrap();
+        if alternatives.is_empty() {
+            origins.remove(&origin);
+            return;
+        }
+        let entries = alternatives
+            .into_iter()
+            .filter_map(|alt| {
+                let expires = now.checked_add(alt.max_age)?;
+                Some((alt, expires))
+            })
+            .collect();
+        origins.insert(origin, entries);
+    }
+
+    /// The alternative to connect to for `uri`, the first supported one of
+    /// those not expired. `None` if the origin has none.
+    pub(crate) fn decide(&self, uri: &Uri) -> Option<AltSvcDecision> {
+        let origin = origin(uri)?;
+        let now = Instant::now();
+        let mut origins = self.origins.lock().unwrap();
+        let entries = origins.get_mut(&origin)?;
+        entries.retain(|(_, expires)| *expires > now);
+        if entries.is_empty() {
+            origins.remove(&origin);
+            return None;
+        }
+
+        let mut decision = AltSvcDecision::default();
+        for (alt, _) in entries.iter() {
+            if !alt.is_supported() {
+                decision.skipped.push(alt.clone());
+            } else if decision.used.is_none() {
+                decision.used = Some(alt.clone());
+            }
+        }
+        Some(decision)
+    }
+
+    /// Forget `alt` for the origin of `uri`, after failing to connect to it.
+    pub(crate) fn remove(&self, uri: &Uri, alt: &AltSvc) {
+        let Some(origin) = origin(uri) else {
+            return;
+        };
+        let mut origins = self.origins.lock().unwrap();
+        if let Some(entries) = origins.get_mut(&origin) {
+            entries.retain(|(v, _)| v != alt);
+        }
+    }
+}
+
+/// The cache key of `uri`, `None` unless it's an `https` URI. Host names are
+/// case-insensitive.
+fn origin(uri: &Uri) -> Option<String> {
+    if uri.scheme() != Some(&http::uri::Scheme::HTTPS) {
+        return None;
+    }
+    let host = uri.host()?.to_ascii_lowercase();
+    Some(format!("{host}:{}", uri.port_u16().unwrap_or(443)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use http::{HeaderMap, HeaderValue, Uri, Version, header};
+
+    use super::{AltSvc, AltSvcCache, parse};
+    use crate::{client::Client, stats::StatsRecorder, test_util::TestServer};
+
+    #[test]
+    fn test_parse() {
+        let parsed =
+            parse(r#"h3=":443"; ma=60, http%2F1.1="alt.example.com:8443"; persist=1"#).unwrap();
+        assert_eq!(
+            parsed,
+            vec![
+                AltSvc {
+                    protocol: "h3".into(),
+                    host: None,
+                    port: 443,
+                    max_age: Duration::from_secs(60),
+                },
+                AltSvc {
+                    protocol: "http/1.1".into(),
+                    host: Some("alt.example.com".into()),
+                    port: 8443,
+                    max_age: Duration::from_secs(24 * 60 * 60),
+                },
+            ]
+        );
+        assert_eq!(
+            parse(r#"h2="[::1]:443""#).unwrap()[0].host.as_deref(),
+            Some("::1")
+        );
+        assert_eq!(parse("clear"), Some(vec![]));
+        assert_eq!(parse("h2=443"), None);
+        assert_eq!(
+            parse(r#"h2=":443"; ma=18446744073709551615"#).unwrap()[0].max_age,
+            Duration::from_secs(365 * 24 * 60 * 60)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cache() {
+        let cache = AltSvcCache::default();
+        let uri = Uri::from_static("https://example.com/");
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::ALT_SVC,
+            HeaderValue::from_static(r#"h3=":443", h2="alt.example.com:443"; ma=10"#),
+        );
+        cache.update(&uri, &headers);
+
+        let decision = cache.decide(&uri).unwrap();
+        assert_eq!(
+            decision.used.unwrap().host.as_deref(),
+            Some("alt.example.com")
+        );
+        assert_eq!(decision.skipped[0].protocol, "h3");
+        let upper = Uri::from_static("https://EXAMPLE.com/");
+        assert_eq!(cache.decide(&upper), cache.decide(&uri));
+        // Plain origins are left alone.
+        cache.update(&Uri::from_static("http://example.com/"), &headers);
+        assert!(
+            cache
+                .decide(&Uri::from_static("http://example.com/"))
+                .is_none()
+        );
+
+        tokio::time::advance(Duration::from_secs(11)).await;
+        let decision = cache.decide(&uri).unwrap();
+        assert!(decision.used.is_none());
+
+        headers.insert(header::ALT_SVC, HeaderValue::from_static("clear"));
+        cache.update(&uri, &headers);
+        assert!(cache.decide(&uri).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_alt_svc() {
+        let alternative = TestServer::builder()
+            .tls()
+            .body("alt")
+            .start()
+            .await
+            .unwrap();
+        let alt_svc = format!("h3=\":443\", h2=\":{}\"", alternative.addr().port());
+        let origin = TestServer::builder()
+            .tls()
+            .header("alt-svc", alt_svc.as_str())
+            .body("origin")
+            .start()
+            .await
+            .unwrap();
+        let client = Client::builder()
+            .skip_tls_verify()
+            .alt_svc()
+            .build()
+            .unwrap();
+
+        let recorder = StatsRecorder::new();
+        let send = || {
+            client
+                .get(origin.url("/"))
+                .recorder(Box::new(recorder.clone()))
+                .send()
+        };
+        let response = send().await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "origin");
+        assert!(recorder.finish().alt_svc.is_none());
+
+        let response = send().await.unwrap();
+        assert_eq!(response.version(), Version::HTTP_2);
+        assert_eq!(response.text().await.unwrap(), "alt");
+        let decision = recorder.finish().alt_svc.unwrap();
+        assert_eq!(decision.used.unwrap().port, alternative.addr().port());
+        assert_eq!(decision.skipped[0].protocol, "h3");
+        assert_eq!(alternative.hits(), 1);
+    }
+}
@@ -0,0 +1,218 @@
+//! Track the availability of a target from the results of repeated probes.
+//!
+//! ```
+//! use std::time::{Duration, SystemTime};
+//!
+//! use httptrace::availability::{AvailabilityTracker, Probe};
+//!
+//! let start = SystemTime::now();
+//! let mut tracker = AvailabilityTracker::new(Duration::from_secs(3600));
+//! for (minute, up) in [true, false, false, true].into_iter().enumerate() {
+//!     let at = start + Duration::from_secs(60 * minute as u64);
+//!     tracker.record(Probe::new(at, up));
+//! }
+//! let snapshot = tracker.snapshot();
+//! assert_eq!(snapshot.uptime, 50.0);
+//! assert_eq!(snapshot.mean_time_to_recovery, Some(Duration::from_secs(120)));
+//! ```
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    time::{Duration, SystemTime},
+};
+
+use crate::check::Report;
+
+/// The result of one probe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Probe {
+    pub at: SystemTime,
+    pub up: bool,
+}
+
+impl Probe {
+    pub fn new(at: SystemTime, up: bool) -> Self {
+        Self { at, up }
+    }
+}
+
+impl From<&Report> for Probe {
+    /// Up if every expectation of the check passed.
+    fn from(report: &Report) -> Self {
+        let at = report.stats.started_at.unwrap_or_else(SystemTime::now);
+        Self::new(at, report.passed())
+    }
+}
+
+/// The state of an [`AvailabilityTracker`] at some point.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AvailabilitySnapshot {
+    /// The percentage of probes up within the window, 100 without probes.
+    pub uptime: f64,
+    /// The number of probes within the window.
+    pub probes: usize,
+    /// The failed probes since the last successful one.
+    pub consecutive_failures: u64,
+    /// The time of the first failed probe of the current outage.
+    pub down_since: Option<SystemTime>,
+    /// The number of outages recovered from since tracking started.
+    pub outages: u64,
+    /// The mean time from the first failed probe to the next successful one.
+    pub mean_time_to_recovery: Option<Duration>,
+    pub longest_outage: Option<Duration>,
+}
+
+/// Consumes probe results in time order and maintains the rolling uptime,
+/// the current outage and recovery times.
+#[derive(Debug, Clone)]
+pub struct AvailabilityTracker {
+    window: Duration,
+    probes: VecDeque<Probe>,
+    up: usize,
+    consecutive_failures: u64,
+    down_since: Option<SystemTime>,
+    outages: u64,
+    downtime: Duration,
+    longest_outage: Option<Duration>,
+}
+
+impl AvailabilityTracker {
+    /// Track the uptime over the last `window`, measured from the latest
+    /// probe.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            probes: VecDeque::new(),
+            up: 0,
+            consecutive_failures: 0,
+            down_since: None,
+            outages: 0,
+            downtime: Duration::ZERO,
+            longest_outage: None,
+        }
+    }
+
+    pub fn record(&mut self, probe: Probe) {
+        if probe.up {
+            self.up += 1;
+            self.consecutive_failures = 0;
+            if let Some(down_since) = self.down_since.take() {
+                let outage = probe.at.duration_since(down_since).unwrap_or_default();
+                self.outages += 1;
+                self.downtime += outage;
+                self.longest_outage = self.longest_outage.max(Some(outage));
+            }
+        } else {
+            self.consecutive_failures += 1;
+            self.down_since.get_or_insert(probe.at);
+        }
+        self.probes.push_back(probe);
+
+        while let Some(oldest) = self.probes.front()
+            && probe.at.duration_since(oldest.at).unwrap_or_default() > self.window
+        {
+            if oldest.up {
+                self.up -= 1;
+            }
+            self.probes.pop_front();
+        }
+    }
+
+    pub fn snapshot(&self) -> AvailabilitySnapshot {
+        AvailabilitySnapshot {
+            uptime: match self.probes.len() {
+                0 => 100.0,
+                len => self.up as f64 / len as f64 * 100.0,
+            },
+            probes: self.probes.len(),
+            consecutive_failures: self.consecutive_failures,
+            down_since: self.down_since,
+            outages: self.outages,
+            mean_time_to_recovery: (self.outages > 0).then(|| self.downtime / self.outages as u32),
+            longest_outage: self.longest_outage,
+        }
+    }
+}
+
+impl Extend<Probe> for AvailabilityTracker {
+    fn extend<T: IntoIterator<Item = Probe>>(&mut self, probes: T) {
+        for probe in probes {
+            self.record(probe);
+        }
+    }
+}
+
+#[cfg(any(test, feature = "json"))]
+impl AvailabilitySnapshot {
+    /// The snapshot as JSON, durations in milliseconds and timestamps in
+    /// milliseconds since the Unix epoch. Requires the `json` feature.
+    pub fn to_json(&self) -> serde_json::Value {
+        let ms = |v: Duration| v.as_secs_f64() * 1000.0;
+        serde_json::json!({
+            "uptime": self.uptime,
+            "probes": self.probes,
+            "consecutive_failures": self.consecutive_failures,
+            "down_since": self.down_since
+                .and_then(|v| v.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .map(|v| v.as_millis() as u64),
+            "outages": self.outages,
+            "mean_time_to_recovery_ms": self.mean_time_to_recovery.map(ms),
+            "longest_outage_ms": self.longest_outage.map(ms),
+        })
+    }
+}
+
+impl fmt::Display for AvailabilitySnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "uptime {:.3}% over {} probes", self.uptime, self.probes)?;
+        if self.consecutive_failures > 0 {
+            write!(f, ", down for {} probes", self.consecutive_failures)?;
+        }
+        if let Some(mttr) = self.mean_time_to_recovery {
+            write!(f, ", {} outages, mttr {:?}", self.outages, mttr)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::{AvailabilityTracker, Probe};
+
+    #[test]
+    fn test_availability() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let at = |minute: u64| start + Duration::from_secs(60 * minute);
+        let mut tracker = AvailabilityTracker::new(Duration::from_secs(300));
+
+        let ups = [
+            true, false, true, true, false, false, false, true, true, false,
+        ];
+        tracker.extend(
+            ups.iter()
+                .enumerate()
+                .map(|(i, up)| Probe::new(at(i as u64), *up)),
+        );
+
+        let snapshot = tracker.snapshot();
+        // Minutes 4 to 9 are within the window.
+        assert_eq!(snapshot.probes, 6);
+        assert!((snapshot.uptime - 100.0 / 3.0).abs() < 1e-9);
+        assert_eq!(snapshot.consecutive_failures, 1);
+        assert_eq!(snapshot.down_since, Some(at(9)));
+        assert_eq!(snapshot.outages, 2);
+        assert_eq!(
+            snapshot.mean_time_to_recovery,
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(snapshot.longest_outage, Some(Duration::from_secs(180)));
+        assert_eq!(snapshot.to_json()["down_since"], 1_700_000_540_000u64);
+        assert_eq!(
+            snapshot.to_string(),
+            "uptime 33.333% over 6 probes, down for 1 probes, 2 outages, mttr 120s"
+        );
+    }
+}
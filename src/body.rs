@@ -5,16 +5,23 @@ use std::task::{Context, Poll, ready};
 use std::time::Duration;
 
 use bytes::Bytes;
+use http::HeaderMap;
 use http_body::{Body as HttpBody, Frame};
 use http_body_util::combinators::BoxBody;
 use pin_project_lite::pin_project;
+use sync_wrapper::SyncFuture;
 use tokio::fs::File;
+use tokio::io::AsyncWrite;
 use tokio::time::Sleep;
 use tokio_util::io::ReaderStream;
 
+use crate::{request::Request, stats::BodyInfo};
+
 /// An asynchronous request body.
 pub struct Body {
     inner: Inner,
+    // Sent after the data, see `Body::with_trailers`.
+    trailers: Option<HeaderMap>,
 }
 
 enum Inner {
@@ -44,7 +51,52 @@ pin_project! {
     }
 }
 
+pin_project! {
+    /// A response body reporting to the recorder once read to the end.
+    pub(crate) struct RecordedBody<B> {
+        #[pin]
+        inner: B,
+        request: Request,
+        digest: Option<ring::digest::Context>,
+        len: u64,
+        done: bool,
+    }
+}
+
+pin_project! {
+    /// A body copying its data to a writer before handing it out.
+    pub(crate) struct TeeBody<B, W> {
+        #[pin]
+        inner: B,
+        #[pin]
+        sink: W,
+        // The frame being copied, with the number of bytes already written.
+        pending: Option<(Frame<Bytes>, usize)>,
+        flushed: bool,
+    }
+}
+
+pin_project! {
+    /// A body driving its connection while it is read.
+    pub(crate) struct DrivenBody<B> {
+        #[pin]
+        inner: B,
+        // `None` once the connection is done.
+        conn: Option<SyncFuture<Pin<Box<dyn Future<Output = hyper::Result<()>> + Send>>>>,
+    }
+}
+
+pin_project! {
+    /// A body handing out the frames read ahead before the rest of it.
+    pub(crate) struct PeekedBody<B> {
+        frames: std::collections::VecDeque<Frame<Bytes>>,
+        #[pin]
+        inner: B,
+    }
+}
+
 /// Converts any `impl Body` into a `impl Stream` of just its DATA frames.
+#[allow(dead_code)]
 pub(crate) struct DataStream<B>(pub(crate) B);
 
 impl Body {
@@ -63,7 +115,7 @@ impl Body {
     /// # Example
     ///
     /// ```
-    /// # use rquest::Body;
+    /// # use httptrace::Body;
     /// # use futures_util;
     /// # fn main() {
     /// let chunks: Vec<Result<_, ::std::io::Error>> = vec![
@@ -107,6 +159,25 @@ impl Body {
         )));
         Body {
             inner: Inner::Streaming(body),
+            trailers: None,
+        }
+    }
+
+    /// A body of constant data, handed to the connection without being
+    /// copied.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use httptrace::Body;
+    /// const PAYLOAD: Body = Body::from_static(b"{\"ping\":true}");
+    ///
+    /// assert_eq!(PAYLOAD.as_bytes(), Some(&b"{\"ping\":true}"[..]));
+    /// ```
+    pub const fn from_static(bytes: &'static [u8]) -> Body {
+        Body {
+            inner: Inner::Reusable(Bytes::from_static(bytes)),
+            trailers: None,
         }
     }
 
@@ -117,6 +188,7 @@ impl Body {
     pub(crate) fn reusable(chunk: Bytes) -> Body {
         Body {
             inner: Inner::Reusable(chunk),
+            trailers: None,
         }
     }
 
@@ -125,7 +197,7 @@ impl Body {
     /// # Example
     ///
     /// ```
-    /// # use rquest::Body;
+    /// # use httptrace::Body;
     /// # use futures_util;
     /// # fn main() {
     /// let content = "hello,world!".to_string();
@@ -145,9 +217,37 @@ impl Body {
 
         Body {
             inner: Inner::Streaming(boxed),
+            trailers: None,
         }
     }
 
+    /// Send `trailers` after the data, e.g. the `grpc-status` of a gRPC
+    /// call.
+    ///
+    /// On HTTP/1.1 the body is then sent chunked, with a `Trailer` header
+    /// naming the fields unless the request has one.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use httptrace::Body;
+    /// # use http::{HeaderMap, HeaderValue};
+    /// let mut trailers = HeaderMap::new();
+    /// trailers.insert("checksum", HeaderValue::from_static("2cf24dba"));
+    ///
+    /// let body = Body::from("hello").with_trailers(trailers);
+    /// ```
+    pub fn with_trailers(mut self, trailers: HeaderMap) -> Body {
+        self.trailers = Some(trailers);
+        self
+    }
+
+    /// The trailers sent after the data, if any.
+    pub(crate) fn trailers(&self) -> Option<&HeaderMap> {
+        self.trailers.as_ref()
+    }
+
+    #[allow(dead_code)]
     pub(crate) fn try_reuse(self) -> (Option<Bytes>, Self) {
         let reuse = match self.inner {
             Inner::Reusable(ref chunk) => Some(chunk.clone()),
@@ -159,7 +259,10 @@ impl Body {
 
     pub(crate) fn try_clone(&self) -> Option<Body> {
         match self.inner {
-            Inner::Reusable(ref chunk) => Some(Body::reusable(chunk.clone())),
+            Inner::Reusable(ref chunk) => Some(Body {
+                inner: Inner::Reusable(chunk.clone()),
+                trailers: self.trailers.clone(),
+            }),
             Inner::Streaming { .. } => None,
         }
     }
@@ -177,6 +280,7 @@ impl From<BoxBody<Bytes, Box<dyn std::error::Error + Send + Sync>>> for Body {
     fn from(body: BoxBody<Bytes, Box<dyn std::error::Error + Send + Sync>>) -> Self {
         Self {
             inner: Inner::Streaming(body),
+            trailers: None,
         }
     }
 }
@@ -198,7 +302,7 @@ impl From<Vec<u8>> for Body {
 impl From<&'static [u8]> for Body {
     #[inline]
     fn from(s: &'static [u8]) -> Body {
-        Body::reusable(Bytes::from_static(s))
+        Body::from_static(s)
     }
 }
 
@@ -237,23 +341,30 @@ impl HttpBody for Body {
         mut self: Pin<&mut Self>,
         cx: &mut Context,
     ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
-        match self.inner {
+        let frame = match self.inner {
             Inner::Reusable(ref mut bytes) => {
                 let out = bytes.split_off(0);
                 if out.is_empty() {
-                    Poll::Ready(None)
+                    None
                 } else {
-                    Poll::Ready(Some(Ok(Frame::data(out))))
+                    Some(Ok(Frame::data(out)))
                 }
             }
-            Inner::Streaming(ref mut body) => Poll::Ready(
-                ready!(Pin::new(body).poll_frame(cx))
-                    .map(|opt_chunk| opt_chunk.map_err(Into::into)),
-            ),
+            Inner::Streaming(ref mut body) => {
+                ready!(Pin::new(body).poll_frame(cx)).map(|opt_chunk| opt_chunk.map_err(Into::into))
+            }
+        };
+        match frame {
+            Some(frame) => Poll::Ready(Some(frame)),
+            None => Poll::Ready(self.trailers.take().map(|v| Ok(Frame::trailers(v)))),
         }
     }
 
     fn size_hint(&self) -> http_body::SizeHint {
+        // Unknown with trailers, for HTTP/1.1 to send the body chunked.
+        if self.trailers.is_some() {
+            return http_body::SizeHint::default();
+        }
         match self.inner {
             Inner::Reusable(ref bytes) => http_body::SizeHint::with_exact(bytes.len() as u64),
             Inner::Streaming(ref body) => body.size_hint(),
@@ -261,6 +372,9 @@ impl HttpBody for Body {
     }
 
     fn is_end_stream(&self) -> bool {
+        if self.trailers.is_some() {
+            return false;
+        }
         match self.inner {
             Inner::Reusable(ref bytes) => bytes.is_empty(),
             Inner::Streaming(ref body) => body.is_end_stream(),
@@ -270,6 +384,7 @@ impl HttpBody for Body {
 
 // ===== impl TotalTimeoutBody =====
 
+#[allow(dead_code)]
 pub(crate) fn total_timeout<B>(body: B, timeout: Pin<Box<Sleep>>) -> TotalTimeoutBody<B> {
     TotalTimeoutBody {
         inner: body,
@@ -277,6 +392,7 @@ pub(crate) fn total_timeout<B>(body: B, timeout: Pin<Box<Sleep>>) -> TotalTimeou
     }
 }
 
+#[allow(dead_code)]
 pub(crate) fn with_read_timeout<B>(body: B, timeout: Duration) -> ReadTimeoutBody<B> {
     ReadTimeoutBody {
         inner: body,
@@ -363,6 +479,234 @@ where
     }
 }
 
+// ===== impl DrivenBody =====
+
+pub(crate) fn driven<B, C>(body: B, conn: Pin<Box<C>>) -> DrivenBody<B>
+where
+    C: Future<Output = hyper::Result<()>> + Send + 'static,
+{
+    DrivenBody {
+        inner: body,
+        conn: Some(SyncFuture::new(conn)),
+    }
+}
+
+impl<B: HttpBody> HttpBody for DrivenBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        // A connection error fails the body as well, no need to report it.
+        if let Some(conn) = this.conn.as_mut()
+            && Pin::new(conn).poll(cx).is_ready()
+        {
+            *this.conn = None;
+        }
+        this.inner.poll_frame(cx)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+
+    #[inline]
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+}
+
+// ===== impl RecordedBody =====
+
+/// Report the body to the recorder of `request`, hashing it if `digest` is set.
+pub(crate) fn recorded<B>(body: B, request: Request, digest: bool) -> RecordedBody<B> {
+    RecordedBody {
+        inner: body,
+        request,
+        digest: digest.then(|| ring::digest::Context::new(&ring::digest::SHA256)),
+        len: 0,
+        done: false,
+    }
+}
+
+impl<B> RecordedBody<B> {
+    fn report(self: Pin<&mut Self>, result: Result<(), &dyn std::error::Error>) {
+        let this = self.project();
+        if std::mem::replace(this.done, true) {
+            return;
+        }
+        let Some(recorder) = this.request.recorder() else {
+            return;
+        };
+        let info = result.map(|_| BodyInfo {
+            len: *this.len,
+            sha256: this.digest.take().map(|digest| {
+                let mut sha256 = [0u8; 32];
+                sha256.copy_from_slice(digest.finish().as_ref());
+                sha256
+            }),
+        });
+        recorder.on_body_done(this.request, info.as_ref().map_err(|e| *e));
+    }
+}
+
+impl<B> HttpBody for RecordedBody<B>
+where
+    B: HttpBody<Data = Bytes, Error = Box<dyn std::error::Error + Send + Sync>>,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.as_mut().project();
+        let ret = ready!(this.inner.poll_frame(cx));
+        match ret.as_ref() {
+            Some(Ok(frame)) => {
+                if let Some(data) = frame.data_ref() {
+                    *this.len += data.len() as u64;
+                    if let Some(digest) = this.digest.as_mut() {
+                        digest.update(data);
+                    }
+                }
+            }
+            Some(Err(e)) => self.report(Err(&**e)),
+            None => self.report(Ok(())),
+        }
+        Poll::Ready(ret)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+
+    #[inline]
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+}
+
+// ===== impl TeeBody =====
+
+pub(crate) fn tee<B, W>(body: B, sink: W) -> TeeBody<B, W> {
+    TeeBody {
+        inner: body,
+        sink,
+        pending: None,
+        flushed: false,
+    }
+}
+
+impl<B, W> HttpBody for TeeBody<B, W>
+where
+    B: HttpBody<Data = Bytes>,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    W: AsyncWrite,
+{
+    type Data = Bytes;
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let mut this = self.project();
+        loop {
+            if let Some((frame, written)) = this.pending.as_mut() {
+                let data = frame.data_ref().map_or(&[][..], |v| &v[..]);
+                if *written < data.len() {
+                    let n = ready!(this.sink.as_mut().poll_write(cx, &data[*written..]))?;
+                    if n == 0 {
+                        return Poll::Ready(Some(Err(std::io::Error::from(
+                            std::io::ErrorKind::WriteZero,
+                        )
+                        .into())));
+                    }
+                    *written += n;
+                    continue;
+                }
+                let (frame, _) = this.pending.take().unwrap();
+                return Poll::Ready(Some(Ok(frame)));
+            }
+
+            match ready!(this.inner.as_mut().poll_frame(cx)) {
+                Some(Ok(frame)) => *this.pending = Some((frame, 0)),
+                Some(Err(e)) => return Poll::Ready(Some(Err(e.into()))),
+                None => {
+                    if !*this.flushed {
+                        ready!(this.sink.as_mut().poll_flush(cx))?;
+                        *this.flushed = true;
+                    }
+                    return Poll::Ready(None);
+                }
+            }
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+
+    #[inline]
+    fn is_end_stream(&self) -> bool {
+        self.pending.is_none() && self.inner.is_end_stream()
+    }
+}
+
+// ===== impl PeekedBody =====
+
+pub(crate) fn peeked<B>(frames: Vec<Frame<Bytes>>, body: B) -> PeekedBody<B> {
+    PeekedBody {
+        frames: frames.into(),
+        inner: body,
+    }
+}
+
+impl<B: HttpBody<Data = Bytes>> HttpBody for PeekedBody<B> {
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        match this.frames.pop_front() {
+            Some(frame) => Poll::Ready(Some(Ok(frame))),
+            None => this.inner.poll_frame(cx),
+        }
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        let peeked = self
+            .frames
+            .iter()
+            .filter_map(|frame| frame.data_ref())
+            .map(|data| data.len() as u64)
+            .sum::<u64>();
+        let inner = self.inner.size_hint();
+        let mut hint = http_body::SizeHint::new();
+        hint.set_lower(inner.lower() + peeked);
+        if let Some(upper) = inner.upper() {
+            hint.set_upper(upper + peeked);
+        }
+        hint
+    }
+
+    #[inline]
+    fn is_end_stream(&self) -> bool {
+        self.frames.is_empty() && self.inner.is_end_stream()
+    }
+}
+
 pub(crate) type ResponseBody =
     http_body_util::combinators::BoxBody<Bytes, Box<dyn std::error::Error + Send + Sync>>;
 
@@ -376,6 +720,7 @@ where
     body.map_err(box_err).boxed()
 }
 
+#[allow(dead_code)]
 pub(crate) fn response<B>(
     body: B,
     deadline: Option<Pin<Box<Sleep>>>,
@@ -469,9 +814,16 @@ where
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
     use http_body::Body as _;
 
     use super::Body;
+    use crate::{
+        client::Client,
+        stats::StatsRecorder,
+        test_util::{MockResponse, MockTransport},
+    };
 
     #[test]
     fn test_as_bytes() {
@@ -495,4 +847,108 @@ mod tests {
         assert!(stream_body.is_end_stream());
         assert_eq!(stream_body.size_hint().exact(), Some(0));
     }
+
+    #[tokio::test]
+    async fn test_from_static() {
+        use http_body_util::BodyExt;
+
+        static PAYLOAD: &[u8] = b"payload";
+        let mut body = Body::from_static(PAYLOAD);
+        let data = body.frame().await.unwrap().unwrap().into_data().unwrap();
+        assert_eq!(data.as_ptr(), PAYLOAD.as_ptr());
+        assert!(body.is_end_stream());
+        assert!(body.frame().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_with_trailers() {
+        use http::{HeaderMap, HeaderValue};
+        use http_body_util::BodyExt;
+        use hyper::service::service_fn;
+        use hyper_util::{
+            rt::{TokioExecutor, TokioIo},
+            server::conn::auto,
+        };
+
+        use crate::client::Client;
+
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", HeaderValue::from_static("0"));
+        let body = Body::from("hello").with_trailers(trailers.clone());
+        assert_eq!(body.size_hint().exact(), None);
+        let collected = body.collect().await.unwrap();
+        assert_eq!(collected.trailers(), Some(&trailers));
+        assert_eq!(collected.to_bytes(), "hello");
+
+        // Echoes the trailer it received.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let service = service_fn(|request: http::Request<hyper::body::Incoming>| async {
+                    let declared = request.headers().get(http::header::TRAILER).cloned();
+                    let collected = request.into_body().collect().await?;
+                    let status = collected
+                        .trailers()
+                        .and_then(|v| v.get("grpc-status"))
+                        .cloned();
+                    let mut response =
+                        http::Response::new(http_body_util::Full::new(collected.to_bytes()));
+                    if let Some(status) = status {
+                        response.headers_mut().insert("grpc-status", status);
+                    }
+                    if let Some(declared) = declared {
+                        response.headers_mut().insert("declared", declared);
+                    }
+                    Ok::<_, hyper::Error>(response)
+                });
+                tokio::spawn(async move {
+                    _ = auto::Builder::new(TokioExecutor::new())
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+
+        for client in [
+            Client::builder().build().unwrap(),
+            Client::builder().http2_prior_knowledge().build().unwrap(),
+        ] {
+            let response = client
+                .post(format!("http://{addr}/"))
+                .body(Body::from("hello").with_trailers(trailers.clone()))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.headers()["grpc-status"], "0");
+            assert_eq!(response.headers()["declared"], "grpc-status");
+            assert_eq!(response.text().await.unwrap(), "hello");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_body_digest() {
+        let mock = MockTransport::new();
+        mock.push(MockResponse::new(200).body("hello"));
+        let client = Client::builder()
+            .mock_transport(mock)
+            .body_digest()
+            .build()
+            .unwrap();
+
+        let recorder = StatsRecorder::new();
+        let mut request = client.get("https://example.com").build().unwrap();
+        *request.recorder_mut() = Some(Arc::new(recorder.clone()));
+        let response = client.execute(request).await.unwrap();
+        assert!(recorder.finish().body_info.is_none());
+        response.bytes().await.unwrap();
+
+        let body = recorder.finish().body_info.unwrap();
+        assert_eq!(body.len, 5);
+        let hex = body.sha256.unwrap().map(|v| format!("{v:02x}")).concat();
+        assert_eq!(
+            hex,
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+    }
 }
@@ -0,0 +1,944 @@
+use std::{
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll, ready},
+    time::{Duration, SystemTime},
+};
+
+use bytes::Bytes;
+use http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode, header};
+use http_body::{Body as HttpBody, Frame};
+use pin_project_lite::pin_project;
+use tokio::{fs::File, io::AsyncWriteExt, sync::mpsc};
+
+use crate::{Body, client::Client, request::Request, response::Response};
+
+const META_MAGIC: &str = "httptrace-cache 4";
+
+/// A disk cache for responses, keyed by URL and revalidated with the
+/// `ETag` / `Last-Modified` validators the origin handed out.
+///
+/// An entry is served straight from disk while it is fresh according to
+/// `Cache-Control: max-age`, unless the request asks for revalidation with
+/// its own `no-cache` or `max-age`. Past that, it is revalidated: the request is sent
+/// with `If-None-Match` / `If-Modified-Since`, and on a `304 Not Modified` the
+/// body is streamed from disk instead of the network. Within the
+/// `stale-while-revalidate` window (RFC 5861) the stale entry is served right
+/// away and the revalidation runs in background.
+///
+/// A stored entry is only used for requests carrying the same values for the
+/// headers listed in its `Vary` response header, and `Vary: *` responses are
+/// never stored. Headers given to [`DiskCache::vary_headers`] are part of the
+/// key itself, so that their variants are stored side by side.
+///
+/// Disk access is reported through the `on_cache_*` hooks of the recorder,
+/// so the time spent on disk can be told apart from the time spent on the
+/// network.
+#[derive(Debug, Clone)]
+pub struct DiskCache {
+    dir: PathBuf,
+    vary_headers: Vec<HeaderName>,
+}
+
+/// How a response was served, available in the response extensions when a
+/// cache is configured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheStatus {
+    /// The response came from the network.
+    Miss,
+    /// The entry was fresh, the origin was not contacted.
+    Hit,
+    /// The entry was stale but within its `stale-while-revalidate` window, it
+    /// is being revalidated in background.
+    Stale,
+    /// The origin answered `304 Not Modified`, the body comes from disk.
+    Revalidated,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    status: StatusCode,
+    stored_at: SystemTime,
+    /// The request headers nominated by `Vary`, `None` when absent.
+    vary: Vec<(HeaderName, Option<HeaderValue>)>,
+    headers: HeaderMap,
+    /// The file name of the body, next to the meta. Each stored body gets
+    /// a new one, so that a reader never pairs a meta with another body.
+    body: String,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Freshness {
+    Fresh,
+    StaleWhileRevalidate,
+    Expired,
+}
+
+enum Chunk {
+    Data(Bytes),
+    End,
+}
+
+pin_project! {
+    struct CacheWriteBody<B> {
+        #[pin]
+        inner: B,
+        tx: Option<mpsc::UnboundedSender<Chunk>>,
+    }
+}
+
+pin_project! {
+    /// The body of a stored entry, ending the cache read once read to the
+    /// end, failed or dropped.
+    struct CacheReadBody {
+        #[pin]
+        inner: Body,
+        done: Option<(Request, String)>,
+    }
+
+    impl PinnedDrop for CacheReadBody {
+        fn drop(this: Pin<&mut Self>) {
+            if let Some((request, key)) = this.project().done.take() {
+                read_done(&request, &key, Ok(true));
+            }
+        }
+    }
+}
+
+impl DiskCache {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self {
+            dir: dir.into(),
+            vary_headers: Vec::new(),
+        }
+    }
+
+    /// Make the given request headers part of the cache key, whatever the
+    /// `Vary` header of the response says.
+    pub fn vary_headers<I>(mut self, headers: I) -> Self
+    where
+        I: IntoIterator<Item = HeaderName>,
+    {
+        self.vary_headers = headers.into_iter().collect();
+        self
+    }
+
+    /// Get the directory the entries are stored in.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub(crate) async fn execute(
+        &self,
+        client: &Client,
+        mut request: Request,
+    ) -> crate::Result<Response> {
+        if !is_cacheable_request(&request) {
+            return client.execute_uncached(request).await;
+        }
+
+        let key = self.key(&request);
+        let cached = self.read(&request, &key).await;
+        if let Some((entry, file)) = cached {
+            match entry.freshness(SystemTime::now(), request.headers()) {
+                Freshness::Fresh => return Ok(serve(entry, file, CacheStatus::Hit)),
+                Freshness::StaleWhileRevalidate => {
+                    self.revalidate_in_background(client.clone(), request.head(), key, &entry);
+                    return Ok(serve(entry, file, CacheStatus::Stale));
+                }
+                Freshness::Expired => {
+                    entry.add_conditions(&mut request);
+
+                    let head = request.head();
+                    let response = client.execute_uncached(request).await?;
+                    return Ok(self.handle(head, key, response, Some((entry, file))).await);
+                }
+            }
+        }
+
+        let head = request.head();
+        let response = client.execute_uncached(request).await?;
+        Ok(self.handle(head, key, response, None).await)
+    }
+
+    async fn handle(
+        &self,
+        head: Request,
+        key: String,
+        mut response: Response,
+        cached: Option<(Entry, CacheReadBody)>,
+    ) -> Response {
+        match cached {
+            Some((mut entry, file)) if response.status() == StatusCode::NOT_MODIFIED => {
+                entry.freshen(response.headers());
+                _ = self.write_meta(&key, &entry).await;
+                let mut served = serve(entry, file, CacheStatus::Revalidated);
+                *served.res.version_mut() = response.version();
+                served
+            }
+            _ if is_storable(&response) => self.store(head, key, response),
+            _ => {
+                response.extensions_mut().insert(CacheStatus::Miss);
+                response
+            }
+        }
+    }
+
+    fn revalidate_in_background(
+        &self,
+        client: Client,
+        mut request: Request,
+        key: String,
+        entry: &Entry,
+    ) {
+        entry.add_conditions(&mut request);
+
+        let cache = self.clone();
+        let mut entry = entry.clone();
+        tokio::spawn(async move {
+            if let Some(recorder) = request.recorder() {
+                recorder.on_cache_revalidate_start(&request, &key);
+            }
+
+            let head = request.head();
+            let timeout = request.timeout().copied();
+            let ret = async {
+                let execute = client.execute_uncached(request);
+                let response = match timeout {
+                    Some(timeout) => tokio::time::timeout(timeout, execute).await??,
+                    None => execute.await?,
+                };
+                let status = response.status();
+                if status == StatusCode::NOT_MODIFIED {
+                    entry.freshen(response.headers());
+                    cache.write_meta(&key, &entry).await?;
+                } else if is_storable(&response) {
+                    // Drain the body so that it reaches the disk.
+                    let mut response = cache.store(head.head(), key.clone(), response);
+                    while response.chunk().await?.is_some() {}
+                }
+                crate::Result::Ok(status)
+            }
+            .await;
+
+            if let Some(recorder) = head.recorder() {
+                recorder.on_cache_revalidate_done(
+                    &head,
+                    &key,
+                    ret.as_ref().copied().map_err(|e| e as _),
+                );
+            }
+        });
+    }
+
+    /// Look up the entry of `key`. On a hit, the read goes on until its body
+    /// is read.
+    async fn read(&self, request: &Request, key: &str) -> Option<(Entry, CacheReadBody)> {
+        if let Some(recorder) = request.recorder() {
+            recorder.on_cache_read_start(request, key);
+        }
+
+        match self._read(request, key).await {
+            Ok(Some((entry, file))) => {
+                let body = CacheReadBody {
+                    inner: Body::from(file),
+                    done: request.recorder().map(|_| (request.head(), key.to_owned())),
+                };
+                Some((entry, body))
+            }
+            ret => {
+                read_done(
+                    request,
+                    key,
+                    ret.as_ref().map(|_| false).map_err(|e| e as _),
+                );
+                None
+            }
+        }
+    }
+
+    async fn _read(&self, request: &Request, key: &str) -> crate::Result<Option<(Entry, File)>> {
+        let meta = match tokio::fs::read(self.meta_path(key)).await {
+            Ok(meta) => meta,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let Some(entry) = decode_meta(&meta, key) else {
+            return Ok(None);
+        };
+        if !entry.matches(request.headers()) {
+            return Ok(None);
+        }
+        match File::open(self.dir.join(&entry.body)).await {
+            Ok(file) => Ok(Some((entry, file))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn store(&self, head: Request, key: String, response: Response) -> Response {
+        if let Some(recorder) = head.recorder() {
+            recorder.on_cache_write_start(&head, &key);
+        }
+
+        let (mut parts, body) = response.res.into_parts();
+        let entry = Entry {
+            status: parts.status,
+            stored_at: SystemTime::now(),
+            vary: vary_names(&parts.headers)
+                .map(|name| {
+                    let value = joined_value(head.headers(), &name);
+                    (name, value)
+                })
+                .collect(),
+            headers: parts.headers.clone(),
+            body: format!("{:016x}.{}.body", fnv1a(key.as_bytes()), unique()),
+        };
+        let meta = encode_meta(&key, &entry);
+        let (tx, rx) = mpsc::unbounded_channel();
+        let meta_path = self.meta_path(&key);
+        let body_path = self.dir.join(&entry.body);
+        tokio::spawn(async move {
+            let ret = write_entry(&key, meta_path, body_path, meta, rx).await;
+            if let Some(recorder) = head.recorder() {
+                recorder.on_cache_write_done(
+                    &head,
+                    &key,
+                    ret.as_ref().copied().map_err(|e| e as _),
+                );
+            }
+        });
+
+        parts.extensions.insert(CacheStatus::Miss);
+        let body = crate::body::boxed(CacheWriteBody {
+            inner: body,
+            tx: Some(tx),
+        });
+        Response::new(http::Response::from_parts(parts, body))
+    }
+
+    async fn write_meta(&self, key: &str, entry: &Entry) -> crate::Result<()> {
+        let meta_path = self.meta_path(key);
+        let meta_tmp = tmp_path(&meta_path);
+        tokio::fs::write(&meta_tmp, encode_meta(key, entry)).await?;
+        tokio::fs::rename(&meta_tmp, &meta_path).await?;
+        Ok(())
+    }
+
+    fn key(&self, request: &Request) -> String {
+        let mut key = request.uri().to_string();
+        for name in self.vary_headers.iter() {
+            key.push(' ');
+            key.push_str(name.as_str());
+            if let Some(value) = joined_value(request.headers(), name) {
+                key.push('=');
+                key.push_str(&String::from_utf8_lossy(value.as_bytes()));
+            }
+        }
+        key
+    }
+
+    fn meta_path(&self, key: &str) -> PathBuf {
+        self.dir
+            .join(format!("{:016x}.meta", fnv1a(key.as_bytes())))
+    }
+}
+
+impl Entry {
+    /// How fresh the entry is for a request with `request` headers, which
+    /// may ask for revalidation with `no-cache` or a `max-age`.
+    fn freshness(&self, now: SystemTime, request: &HeaderMap) -> Freshness {
+        if cache_directive(&self.headers, "no-cache").is_some()
+            || cache_directive(request, "no-cache").is_some()
+        {
+            return Freshness::Expired;
+        }
+        let Some(max_age) = cache_directive(&self.headers, "max-age").flatten() else {
+            return Freshness::Expired;
+        };
+        let age_header = self
+            .headers
+            .get(header::AGE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_default();
+        let age = now
+            .duration_since(self.stored_at)
+            .unwrap_or_default()
+            .saturating_add(Duration::from_secs(age_header));
+        if let Some(Some(max_age)) = cache_directive(request, "max-age")
+            && age >= Duration::from_secs(max_age)
+        {
+            return Freshness::Expired;
+        }
+        if age < Duration::from_secs(max_age) {
+            return Freshness::Fresh;
+        }
+        let window = cache_directive(&self.headers, "stale-while-revalidate")
+            .flatten()
+            .unwrap_or_default();
+        if age < Duration::from_secs(max_age.saturating_add(window)) {
+            return Freshness::StaleWhileRevalidate;
+        }
+        Freshness::Expired
+    }
+
+    /// Whether the request carries the same nominated headers as the one
+    /// that got this entry stored.
+    fn matches(&self, headers: &HeaderMap) -> bool {
+        self.vary
+            .iter()
+            .all(|(name, value)| joined_value(headers, name) == *value)
+    }
+
+    fn add_conditions(&self, request: &mut Request) {
+        if let Some(etag) = self.headers.get(header::ETAG) {
+            request
+                .headers_mut()
+                .insert(header::IF_NONE_MATCH, etag.clone());
+        }
+        if let Some(last_modified) = self.headers.get(header::LAST_MODIFIED) {
+            request
+                .headers_mut()
+                .insert(header::IF_MODIFIED_SINCE, last_modified.clone());
+        }
+    }
+
+    /// Freshen the stored headers with the ones sent along a 304.
+    fn freshen(&mut self, headers: &HeaderMap) {
+        for (name, value) in headers.iter() {
+            if name != header::CONTENT_LENGTH {
+                self.headers.insert(name.clone(), value.clone());
+            }
+        }
+        // The age of a revalidated entry starts over.
+        self.headers.remove(header::AGE);
+        self.stored_at = SystemTime::now();
+    }
+}
+
+fn serve(entry: Entry, body: CacheReadBody, status: CacheStatus) -> Response {
+    let mut res = http::Response::new(crate::body::boxed(body));
+    *res.status_mut() = entry.status;
+    *res.headers_mut() = entry.headers;
+    res.extensions_mut().insert(status);
+    Response::new(res)
+}
+
+/// Write the body under its own name, then switch the entry over to it by
+/// renaming the meta into place, and remove the body it replaces.
+async fn write_entry(
+    key: &str,
+    meta_path: PathBuf,
+    body_path: PathBuf,
+    meta: Vec<u8>,
+    mut rx: mpsc::UnboundedReceiver<Chunk>,
+) -> crate::Result<u64> {
+    if let Some(dir) = body_path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let meta_tmp = tmp_path(&meta_path);
+
+    let mut file = File::create(&body_path).await?;
+    let mut written = 0u64;
+    loop {
+        match rx.recv().await {
+            Some(Chunk::Data(data)) => {
+                file.write_all(&data).await?;
+                written += data.len() as u64;
+            }
+            Some(Chunk::End) => break,
+            None => {
+                drop(file);
+                _ = tokio::fs::remove_file(&body_path).await;
+                return Err(crate::Error::CacheBodyIncomplete);
+            }
+        }
+    }
+    file.flush().await?;
+    drop(file);
+
+    let replaced = tokio::fs::read(&meta_path)
+        .await
+        .ok()
+        .and_then(|meta| decode_meta(&meta, key));
+    tokio::fs::write(&meta_tmp, meta).await?;
+    tokio::fs::rename(&meta_tmp, &meta_path).await?;
+    if let (Some(replaced), Some(dir)) = (replaced, meta_path.parent()) {
+        _ = tokio::fs::remove_file(dir.join(replaced.body)).await;
+    }
+    Ok(written)
+}
+
+/// A temporary path next to `path`, unique to this write so that concurrent
+/// writers of the same entry don't share it.
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}.tmp", unique()));
+    name.into()
+}
+
+/// A name part no other write uses, in this process or another one.
+fn unique() -> String {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    let n = NEXT.fetch_add(1, Ordering::Relaxed);
+    format!("{}-{:x}-{n}", std::process::id(), now.as_nanos())
+}
+
+fn read_done(request: &Request, key: &str, hit: Result<bool, &dyn std::error::Error>) {
+    if let Some(recorder) = request.recorder() {
+        recorder.on_cache_read_done(request, key, hit);
+    }
+}
+
+fn is_cacheable_request(request: &Request) -> bool {
+    request.method() == Method::GET
+        && !request.headers().contains_key(header::IF_NONE_MATCH)
+        && !request.headers().contains_key(header::IF_MODIFIED_SINCE)
+        && cache_directive(request.headers(), "no-store").is_none()
+}
+
+fn is_storable(response: &Response) -> bool {
+    response.status() == StatusCode::OK
+        && (response.headers().contains_key(header::ETAG)
+            || response.headers().contains_key(header::LAST_MODIFIED))
+        && cache_directive(response.headers(), "no-store").is_none()
+        && vary_names(response.headers()).all(|name| name != "*")
+}
+
+fn vary_names(headers: &HeaderMap) -> impl Iterator<Item = HeaderName> + '_ {
+    headers
+        .get_all(header::VARY)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|v| HeaderName::from_bytes(v.trim().as_bytes()).ok())
+}
+
+/// All the values of a header, joined the way they would be on a single line.
+fn joined_value(headers: &HeaderMap, name: &HeaderName) -> Option<HeaderValue> {
+    let mut values = headers.get_all(name).iter();
+    let first = values.next()?;
+    let mut joined = first.as_bytes().to_vec();
+    for value in values {
+        joined.extend_from_slice(b", ");
+        joined.extend_from_slice(value.as_bytes());
+    }
+    HeaderValue::from_bytes(&joined).ok()
+}
+
+/// Look up a `Cache-Control` directive, along with its numeric argument if any.
+fn cache_directive(headers: &HeaderMap, name: &str) -> Option<Option<u64>> {
+    headers
+        .get_all(header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .find_map(|v| {
+            let mut kv = v.trim().splitn(2, '=');
+            match kv.next() {
+                Some(k) if k.eq_ignore_ascii_case(name) => {
+                    Some(kv.next().and_then(|v| v.trim_matches('"').parse().ok()))
+                }
+                _ => None,
+            }
+        })
+}
+
+fn encode_meta(key: &str, entry: &Entry) -> Vec<u8> {
+    let stored_at = entry
+        .stored_at
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    let mut buf = Vec::new();
+    buf.extend_from_slice(META_MAGIC.as_bytes());
+    buf.push(b'\n');
+    buf.extend_from_slice(key.as_bytes());
+    buf.push(b'\n');
+    buf.extend_from_slice(entry.status.as_str().as_bytes());
+    buf.push(b'\n');
+    buf.extend_from_slice(stored_at.to_string().as_bytes());
+    buf.push(b'\n');
+    buf.extend_from_slice(entry.body.as_bytes());
+    buf.push(b'\n');
+    buf.extend_from_slice(entry.vary.len().to_string().as_bytes());
+    buf.push(b'\n');
+    for (name, value) in entry.vary.iter() {
+        buf.extend_from_slice(name.as_str().as_bytes());
+        if let Some(value) = value {
+            buf.extend_from_slice(b": ");
+            buf.extend_from_slice(value.as_bytes());
+        }
+        buf.push(b'\n');
+    }
+    for (name, value) in entry.headers.iter() {
+        buf.extend_from_slice(name.as_str().as_bytes());
+        buf.extend_from_slice(b": ");
+        buf.extend_from_slice(value.as_bytes());
+        buf.push(b'\n');
+    }
+    buf
+}
+
+fn decode_meta(buf: &[u8], key: &str) -> Option<Entry> {
+    let mut lines = buf.split(|b| *b == b'\n');
+    if lines.next()? != META_MAGIC.as_bytes() || lines.next()? != key.as_bytes() {
+        return None;
+    }
+    let status = StatusCode::from_bytes(lines.next()?).ok()?;
+    let stored_at = std::str::from_utf8(lines.next()?).ok()?.parse().ok()?;
+    let body = std::str::from_utf8(lines.next()?).ok()?.to_owned();
+    let vary_len: usize = std::str::from_utf8(lines.next()?).ok()?.parse().ok()?;
+
+    let mut vary = Vec::with_capacity(vary_len);
+    for _ in 0..vary_len {
+        let line = lines.next()?;
+        match line.windows(2).position(|v| v == b": ") {
+            Some(pos) => vary.push((
+                HeaderName::from_bytes(&line[..pos]).ok()?,
+                Some(HeaderValue::from_bytes(&line[pos + 2..]).ok()?),
+            )),
+            None => vary.push((HeaderName::from_bytes(line).ok()?, None)),
+        }
+    }
+
+    let mut headers = HeaderMap::new();
+    for line in lines.filter(|line| !line.is_empty()) {
+        let pos = line.windows(2).position(|v| v == b": ")?;
+        let name = HeaderName::from_bytes(&line[..pos]).ok()?;
+        let value = HeaderValue::from_bytes(&line[pos + 2..]).ok()?;
+        headers.append(name, value);
+    }
+    Some(Entry {
+        status,
+        stored_at: SystemTime::UNIX_EPOCH + Duration::from_secs(stored_at),
+        vary,
+        headers,
+        body,
+    })
+}
+
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+impl<B> HttpBody for CacheWriteBody<B>
+where
+    B: HttpBody<Data = Bytes>,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let frame = ready!(this.inner.poll_frame(cx));
+        match frame.as_ref() {
+            Some(Ok(frame)) => {
+                if let (Some(data), Some(tx)) = (frame.data_ref(), this.tx.as_ref()) {
+                    _ = tx.send(Chunk::Data(data.clone()));
+                }
+            }
+            // The entry is dropped along with the sender.
+            Some(Err(_)) => _ = this.tx.take(),
+            None => {
+                if let Some(tx) = this.tx.take() {
+                    _ = tx.send(Chunk::End);
+                }
+            }
+        }
+        Poll::Ready(frame)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+
+    #[inline]
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+}
+
+impl HttpBody for CacheReadBody {
+    type Data = Bytes;
+    type Error = crate::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let frame = ready!(this.inner.poll_frame(cx));
+        let ret = match frame.as_ref() {
+            Some(Ok(_)) => return Poll::Ready(frame),
+            Some(Err(e)) => Err(e as _),
+            None => Ok(true),
+        };
+        if let Some((request, key)) = this.done.take() {
+            read_done(&request, &key, ret);
+        }
+        Poll::Ready(frame)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+
+    #[inline]
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use http::{HeaderMap, StatusCode, header};
+
+    use super::{CacheStatus, DiskCache, Entry, Freshness, decode_meta, encode_meta};
+    use crate::{
+        client::Client,
+        events::{ChannelRecorder, EventKind, EventReceiver, Overflow},
+        test_util::{MockResponse, MockTransport},
+    };
+
+    #[test]
+    fn test_meta_roundtrip() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ETAG, "\"abc\"".parse().unwrap());
+        headers.append(header::SET_COOKIE, "a=1".parse().unwrap());
+        headers.append(header::SET_COOKIE, "b=2".parse().unwrap());
+        let entry = Entry {
+            status: StatusCode::OK,
+            stored_at: SystemTime::UNIX_EPOCH + Duration::from_secs(1700000000),
+            vary: vec![
+                (header::ACCEPT_ENCODING, Some("gzip".parse().unwrap())),
+                (header::ACCEPT_LANGUAGE, None),
+            ],
+            headers,
+            body: "0123456789abcdef.1.body".to_owned(),
+        };
+
+        let meta = encode_meta("https://example.com/a", &entry);
+        let decoded = decode_meta(&meta, "https://example.com/a").unwrap();
+        assert_eq!(decoded.status, entry.status);
+        assert_eq!(decoded.stored_at, entry.stored_at);
+        assert_eq!(decoded.vary, entry.vary);
+        assert_eq!(decoded.headers, entry.headers);
+        assert_eq!(decoded.body, entry.body);
+
+        assert!(decode_meta(&meta, "https://example.com/b").is_none());
+    }
+
+    #[test]
+    fn test_freshness() {
+        let stored_at = SystemTime::UNIX_EPOCH + Duration::from_secs(1700000000);
+        let entry = |cache_control: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::CACHE_CONTROL, cache_control.parse().unwrap());
+            Entry {
+                status: StatusCode::OK,
+                stored_at,
+                vary: Vec::new(),
+                headers,
+                body: String::new(),
+            }
+        };
+        let at = |secs| stored_at + Duration::from_secs(secs);
+        let none = HeaderMap::new();
+
+        let swr = entry("max-age=60, stale-while-revalidate=30");
+        assert_eq!(swr.freshness(at(10), &none), Freshness::Fresh);
+        assert_eq!(
+            swr.freshness(at(70), &none),
+            Freshness::StaleWhileRevalidate
+        );
+        assert_eq!(swr.freshness(at(100), &none), Freshness::Expired);
+
+        assert_eq!(
+            entry("max-age=60").freshness(at(70), &none),
+            Freshness::Expired
+        );
+        assert_eq!(
+            entry("no-cache, max-age=60").freshness(at(10), &none),
+            Freshness::Expired
+        );
+
+        // Huge values saturate.
+        let max = u64::MAX;
+        let huge = entry(&format!("max-age={max}, stale-while-revalidate={max}"));
+        assert_eq!(huge.freshness(at(10), &none), Freshness::Fresh);
+        let mut aged = entry("max-age=60, stale-while-revalidate=30");
+        aged.headers.insert(header::AGE, max.into());
+        assert_eq!(aged.freshness(at(10), &none), Freshness::Expired);
+
+        // The request may ask for revalidation.
+        let request = |cache_control: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::CACHE_CONTROL, cache_control.parse().unwrap());
+            headers
+        };
+        assert_eq!(
+            swr.freshness(at(10), &request("no-cache")),
+            Freshness::Expired
+        );
+        assert_eq!(
+            swr.freshness(at(10), &request("max-age=0")),
+            Freshness::Expired
+        );
+        assert_eq!(
+            swr.freshness(at(10), &request("max-age=20")),
+            Freshness::Fresh
+        );
+        assert_eq!(
+            swr.freshness(at(70), &request("max-age=20")),
+            Freshness::Expired
+        );
+    }
+
+    #[tokio::test]
+    async fn test_hit_and_revalidate() {
+        let dir = std::env::temp_dir().join(format!("httptrace-cache-{}", std::process::id()));
+        _ = std::fs::remove_dir_all(&dir);
+        let mock = MockTransport::new();
+        mock.push(
+            MockResponse::new(200)
+                .header(header::ETAG, "\"a\"")
+                .header(header::CACHE_CONTROL, "max-age=60")
+                .body("fresh"),
+        );
+        mock.push(
+            MockResponse::new(200)
+                .header(header::ETAG, "\"b\"")
+                .header(header::CACHE_CONTROL, "max-age=0")
+                .body("stale"),
+        );
+        mock.push(MockResponse::new(304).header(header::ETAG, "\"b\""));
+        mock.push(
+            MockResponse::new(200)
+                .header(header::ETAG, "\"c\"")
+                .header(header::CACHE_CONTROL, "max-age=60")
+                .body("fresher"),
+        );
+        let client = Client::builder()
+            .cache(DiskCache::new(&dir))
+            .mock_transport(mock.clone())
+            .build()
+            .unwrap();
+
+        for (url, body) in [
+            ("http://example.com/a", "fresh"),
+            ("http://example.com/b", "stale"),
+        ] {
+            let (recorder, mut received) = ChannelRecorder::new(64, Overflow::Block);
+            let response = client
+                .get(url)
+                .recorder(Box::new(recorder))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.extensions().get(), Some(&CacheStatus::Miss));
+            assert_eq!(response.text().await.unwrap(), body);
+            assert_eq!(written(&mut received).await, Some(Ok(body.len() as u64)));
+        }
+
+        let (recorder, mut received) = ChannelRecorder::new(64, Overflow::Block);
+        let response = client
+            .get("http://example.com/a")
+            .recorder(Box::new(recorder))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.extensions().get(), Some(&CacheStatus::Hit));
+        // The read goes on until the body is read.
+        let read_done = |received: &mut EventReceiver| {
+            std::iter::from_fn(|| received.try_recv()).find_map(|event| match event.kind {
+                EventKind::CacheReadDone { hit, .. } => Some(hit),
+                _ => None,
+            })
+        };
+        assert_eq!(read_done(&mut received), None);
+        assert_eq!(response.text().await.unwrap(), "fresh");
+        assert_eq!(read_done(&mut received), Some(Ok(true)));
+        assert_eq!(mock.requests().len(), 2);
+
+        let response = client.get("http://example.com/b").send().await.unwrap();
+        assert_eq!(response.extensions().get(), Some(&CacheStatus::Revalidated));
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), "stale");
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[2].headers[header::IF_NONE_MATCH], "\"b\"");
+
+        // Revalidated on request, and replaced along with its body.
+        let (recorder, mut received) = ChannelRecorder::new(64, Overflow::Block);
+        let response = client
+            .get("http://example.com/a")
+            .header(header::CACHE_CONTROL, "no-cache")
+            .recorder(Box::new(recorder))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.extensions().get(), Some(&CacheStatus::Miss));
+        assert_eq!(response.text().await.unwrap(), "fresher");
+        assert_eq!(written(&mut received).await, Some(Ok(7)));
+        assert_eq!(mock.requests()[3].headers[header::IF_NONE_MATCH], "\"a\"");
+        let bodies = std::fs::read_dir(&dir)
+            .unwrap()
+            .filter(|v| v.as_ref().unwrap().path().extension() == Some("body".as_ref()))
+            .count();
+        assert_eq!(bodies, 2);
+
+        _ = std::fs::remove_dir_all(&dir);
+    }
+
+    /// The result of the next cache write, once its body is on disk.
+    async fn written(received: &mut EventReceiver) -> Option<Result<u64, String>> {
+        while let Some(event) = received.recv().await {
+            if let EventKind::CacheWriteDone { written, .. } = event.kind {
+                return Some(written);
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn test_vary_matches() {
+        let entry = Entry {
+            status: StatusCode::OK,
+            stored_at: SystemTime::now(),
+            vary: vec![
+                (header::ACCEPT_ENCODING, Some("gzip, br".parse().unwrap())),
+                (header::ACCEPT_LANGUAGE, None),
+            ],
+            headers: HeaderMap::new(),
+            body: String::new(),
+        };
+
+        let mut headers = HeaderMap::new();
+        headers.append(header::ACCEPT_ENCODING, "gzip".parse().unwrap());
+        headers.append(header::ACCEPT_ENCODING, "br".parse().unwrap());
+        assert!(entry.matches(&headers));
+
+        headers.insert(header::ACCEPT_LANGUAGE, "en".parse().unwrap());
+        assert!(!entry.matches(&headers));
+
+        headers.remove(header::ACCEPT_LANGUAGE);
+        headers.insert(header::ACCEPT_ENCODING, "identity".parse().unwrap());
+        assert!(!entry.matches(&headers));
+    }
+}
@@ -0,0 +1,210 @@
+use std::{
+    fmt,
+    io::IoSlice,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, ready},
+};
+
+use bytes::{Bytes, BytesMut};
+use pin_project_lite::pin_project;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// The raw bytes exchanged on a connection, as seen by the HTTP layer.
+///
+/// For `https` requests the bytes are captured after decryption. Once
+/// enabled with [`ClientBuilder::wire_capture`](crate::client::ClientBuilder::wire_capture),
+/// it is available in the response extensions and keeps growing while the
+/// body is read.
+#[derive(Clone, Default)]
+pub struct WireCapture {
+    inner: Arc<Mutex<WireCaptureInner>>,
+}
+
+#[derive(Default)]
+struct WireCaptureInner {
+    limit: usize,
+    sent: BytesMut,
+    received: BytesMut,
+    sent_total: u64,
+    received_total: u64,
+}
+
+impl WireCapture {
+    pub(crate) fn new(limit: usize) -> Self {
+        let capture = WireCapture::default();
+        capture.inner.lock().unwrap().limit = limit;
+        capture
+    }
+
+    /// The bytes written to the connection, up to the capture limit.
+    pub fn sent(&self) -> Bytes {
+        Bytes::copy_from_slice(&self.inner.lock().unwrap().sent)
+    }
+
+    /// The bytes read from the connection, up to the capture limit.
+    pub fn received(&self) -> Bytes {
+        Bytes::copy_from_slice(&self.inner.lock().unwrap().received)
+    }
+
+    /// The number of bytes written, including the ones past the limit.
+    pub fn sent_total(&self) -> u64 {
+        self.inner.lock().unwrap().sent_total
+    }
+
+    /// The number of bytes read, including the ones past the limit.
+    pub fn received_total(&self) -> u64 {
+        self.inner.lock().unwrap().received_total
+    }
+
+    /// Whether some bytes were dropped because of the limit.
+    pub fn is_truncated(&self) -> bool {
+        let inner = self.inner.lock().unwrap();
+        inner.sent_total > inner.sent.len() as u64
+            || inner.received_total > inner.received.len() as u64
+    }
+}
+
+/// Receives the bytes going through a [`CaptureIo`].
+pub(crate) trait Tap: Send + Sync {
+    fn on_sent(&self, data: &[u8]);
+
+    fn on_received(&self, data: &[u8]);
+}
+
+impl Tap for WireCapture {
+    fn on_sent(&self, data: &[u8]) {
+        let mut inner = self.inner.lock().unwrap();
+        let n = data.len().min(inner.limit.saturating_sub(inner.sent.len()));
+        inner.sent.extend_from_slice(&data[..n]);
+        inner.sent_total += data.len() as u64;
+    }
+
+    fn on_received(&self, data: &[u8]) {
+        let mut inner = self.inner.lock().unwrap();
+        let n = data
+            .len()
+            .min(inner.limit.saturating_sub(inner.received.len()));
+        inner.received.extend_from_slice(&data[..n]);
+        inner.received_total += data.len() as u64;
+    }
+}
+
+impl fmt::Debug for WireCapture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WireCapture")
+            .field("sent_total", &self.sent_total())
+            .field("received_total", &self.received_total())
+            .finish()
+    }
+}
+
+pin_project! {
+    /// An IO reporting the bytes going through it to a [`Tap`], if any.
+    pub(crate) struct CaptureIo<T> {
+        #[pin]
+        inner: T,
+        tap: Option<Arc<dyn Tap>>,
+    }
+}
+
+impl<T> CaptureIo<T> {
+    pub(crate) fn new(inner: T, tap: Option<Arc<dyn Tap>>) -> Self {
+        CaptureIo { inner, tap }
+    }
+
+    pub(crate) fn get_ref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for CaptureIo<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.project();
+        let filled = buf.filled().len();
+        ready!(this.inner.poll_read(cx, buf))?;
+        if let Some(tap) = this.tap.as_ref() {
+            tap.on_received(&buf.filled()[filled..]);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: AsyncWrite> AsyncWrite for CaptureIo<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.project();
+        let n = ready!(this.inner.poll_write(cx, buf))?;
+        if let Some(tap) = this.tap.as_ref() {
+            tap.on_sent(&buf[..n]);
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.project();
+        let mut left = ready!(this.inner.poll_write_vectored(cx, bufs))?;
+        let n = left;
+        if let Some(tap) = this.tap.as_ref() {
+            for buf in bufs {
+                if left == 0 {
+                    break;
+                }
+                let len = buf.len().min(left);
+                tap.on_sent(&buf[..len]);
+                left -= len;
+            }
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.project().inner.poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.project().inner.poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::{CaptureIo, WireCapture};
+
+    #[tokio::test]
+    async fn test_capture_truncated() {
+        let (client, mut server) = tokio::io::duplex(64);
+        let capture = WireCapture::new(4);
+        let mut io = CaptureIo::new(client, Some(Arc::new(capture.clone())));
+
+        io.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+        server.write_all(b"HTTP").await.unwrap();
+        let mut buf = [0u8; 4];
+        io.read_exact(&mut buf).await.unwrap();
+
+        assert_eq!(capture.sent(), "GET ");
+        assert_eq!(capture.sent_total(), 16);
+        assert_eq!(capture.received(), "HTTP");
+        assert_eq!(capture.received_total(), 4);
+        assert!(capture.is_truncated());
+    }
+}
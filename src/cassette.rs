@@ -0,0 +1,279 @@
+//! Record exchanges to a file and replay them through a [`MockTransport`].
+//!
+//! Requires the `test-util` feature.
+
+use std::{net::SocketAddr, path::Path, sync::Arc, time::Duration};
+
+use base64::prelude::{BASE64_STANDARD, Engine};
+use bytes::Bytes;
+use http::{HeaderMap, Version};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Body,
+    client::Client,
+    request::Request,
+    response::Response,
+    stats::{Stats, StatsRecorder},
+    test_util::{MockResponse, MockTransport},
+};
+
+/// A list of recorded request/response exchanges, along with their timings.
+///
+/// Replaying a cassette serves the recorded responses in order, whatever the
+/// requests are, with the recorded delays.
+///
+/// ```no_run
+/// # use httptrace::{cassette::Cassette, client::Client};
+/// # async fn run() {
+/// let mut cassette = Cassette::new();
+/// let client = Client::builder().build().unwrap();
+/// let request = client.get("https://www.example.com").build().unwrap();
+/// let response = cassette.record(&client, request).await.unwrap();
+/// cassette.save("example.json").await.unwrap();
+///
+/// let cassette = Cassette::load("example.json").await.unwrap();
+/// let client = Client::builder()
+///     .mock_transport(cassette.mock_transport())
+///     .build()
+///     .unwrap();
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Cassette {
+    pub interactions: Vec<Interaction>,
+}
+
+/// A recorded exchange.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    pub request: RecordedRequest,
+    pub response: RecordedResponse,
+    pub timings: RecordedTimings,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub uri: String,
+    pub headers: Vec<(String, String)>,
+    /// Base64 encoded, `None` if the body is a stream.
+    pub body: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    pub status: u16,
+    pub version: String,
+    pub headers: Vec<(String, String)>,
+    /// Base64 encoded.
+    pub body: String,
+}
+
+/// The phase durations of a recorded exchange.
+///
+/// `response` spans from sending the request to reading the whole body.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RecordedTimings {
+    pub peer_addr: Option<SocketAddr>,
+    pub dns: Duration,
+    pub tcp: Duration,
+    pub tls: Duration,
+    pub response: Duration,
+}
+
+impl Cassette {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn load<P: AsRef<Path>>(path: P) -> crate::Result<Self> {
+        let data = tokio::fs::read(path).await?;
+        Ok(serde_json::from_slice(&data)?)
+    }
+
+    pub async fn save<P: AsRef<Path>>(&self, path: P) -> crate::Result<()> {
+        let data = serde_json::to_vec_pretty(self)?;
+        tokio::fs::write(path, data).await?;
+        Ok(())
+    }
+
+    /// Execute the request, reading the whole body, and append the exchange.
+    ///
+    /// Timings are only recorded for requests without a recorder of their own.
+    pub async fn record(
+        &mut self,
+        client: &Client,
+        mut request: Request,
+    ) -> crate::Result<Response> {
+        let recorder = match request.recorder() {
+            Some(_) => None,
+            None => {
+                let recorder = StatsRecorder::new();
+                *request.recorder_mut() = Some(Arc::new(recorder.clone()));
+                Some(recorder)
+            }
+        };
+        let recorded_request = RecordedRequest {
+            method: request.method().to_string(),
+            uri: request.uri().to_string(),
+            headers: header_pairs(request.headers()),
+            body: match request.body() {
+                Some(body) => body.as_bytes().map(|v| BASE64_STANDARD.encode(v)),
+                None => Some(String::new()),
+            },
+        };
+
+        let response = client.execute(request).await?;
+        let (mut parts, body) = response.res.into_parts();
+        let body = Response::new(http::Response::new(body)).bytes().await?;
+
+        let timings = recorder
+            .map(|recorder| RecordedTimings::from_stats(&recorder.finish()))
+            .unwrap_or_default();
+        self.interactions.push(Interaction {
+            request: recorded_request,
+            response: RecordedResponse {
+                status: parts.status.as_u16(),
+                version: format!("{:?}", parts.version),
+                headers: header_pairs(&parts.headers),
+                body: BASE64_STANDARD.encode(&body),
+            },
+            timings,
+        });
+
+        parts.extensions.clear();
+        Ok(Response::new(http::Response::from_parts(
+            parts,
+            crate::body::boxed(Body::from(body)),
+        )))
+    }
+
+    /// A mock transport serving the recorded responses in order.
+    pub fn mock_transport(&self) -> MockTransport {
+        let mock = MockTransport::new();
+        for interaction in &self.interactions {
+            mock.push(interaction.mock_response());
+        }
+        mock
+    }
+}
+
+impl Interaction {
+    fn mock_response(&self) -> MockResponse {
+        let response = &self.response;
+        let timings = &self.timings;
+
+        let mut mock = MockResponse::new(response.status)
+            .version(parse_version(&response.version))
+            .body(Bytes::from(
+                BASE64_STANDARD.decode(&response.body).unwrap_or_default(),
+            ))
+            .dns_delay(timings.dns)
+            .tcp_delay(timings.tcp)
+            .tls_delay(timings.tls)
+            .response_delay(timings.response);
+        for (name, value) in &response.headers {
+            mock = mock.header(name.as_str(), value.as_str());
+        }
+        if let Some(addr) = timings.peer_addr {
+            mock = mock.addr(addr.ip());
+        }
+        mock
+    }
+}
+
+impl RecordedTimings {
+    fn from_stats(stats: &Stats) -> Self {
+        let tcp = stats
+            .tcp_stats
+            .iter()
+            .flatten()
+            .find(|stat| stat.error.is_none());
+        RecordedTimings {
+            peer_addr: tcp.map(|stat| stat.peer_addr),
+            dns: stats.dns_stats.duration,
+            tcp: tcp.map(|stat| stat.duration).unwrap_or_default(),
+            tls: stats
+                .tls_stats
+                .as_ref()
+                .map(|stat| stat.duration)
+                .unwrap_or_default(),
+            response: stats
+                .request_stats
+                .as_ref()
+                .map(|stat| stat.duration)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+fn header_pairs(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            (
+                name.to_string(),
+                String::from_utf8_lossy(value.as_bytes()).into_owned(),
+            )
+        })
+        .collect()
+}
+
+fn parse_version(version: &str) -> Version {
+    match version {
+        "HTTP/0.9" => Version::HTTP_09,
+        "HTTP/1.0" => Version::HTTP_10,
+        "HTTP/2.0" => Version::HTTP_2,
+        "HTTP/3.0" => Version::HTTP_3,
+        _ => Version::HTTP_11,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Cassette;
+    use crate::{
+        client::Client,
+        test_util::{MockResponse, MockTransport},
+    };
+
+    #[tokio::test(start_paused = true)]
+    async fn test_record_replay() {
+        let mock = MockTransport::new();
+        mock.push(
+            MockResponse::new(200)
+                .header("content-type", "text/plain")
+                .body("recorded")
+                .tls_delay(Duration::from_millis(30))
+                .response_delay(Duration::from_millis(50)),
+        );
+        let client = Client::builder().mock_transport(mock).build().unwrap();
+
+        let mut cassette = Cassette::new();
+        let request = client.get("https://example.com/a").build().unwrap();
+        let response = cassette.record(&client, request).await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "recorded");
+
+        let path =
+            std::env::temp_dir().join(format!("httptrace-cassette-{}.json", std::process::id()));
+        cassette.save(&path).await.unwrap();
+        let cassette = Cassette::load(&path).await.unwrap();
+        _ = std::fs::remove_file(&path);
+
+        let interaction = &cassette.interactions[0];
+        assert_eq!(interaction.request.uri, "https://example.com/a");
+        assert_eq!(interaction.timings.tls, Duration::from_millis(30));
+        assert_eq!(interaction.timings.response, Duration::from_millis(50));
+
+        let client = Client::builder()
+            .mock_transport(cassette.mock_transport())
+            .build()
+            .unwrap();
+        let response = client.get("https://example.com/a").send().await.unwrap();
+        assert_eq!(response.headers()["content-type"], "text/plain");
+        assert_eq!(response.text().await.unwrap(), "recorded");
+    }
+}
@@ -0,0 +1,294 @@
+//! Monitoring checks: run a request and compare what was measured against a
+//! list of expectations.
+//!
+//! ```no_run
+//! # async fn run() -> httptrace::Result<()> {
+//! use std::time::Duration;
+//!
+//! use httptrace::{check::Check, client::Client};
+//!
+//! let client = Client::builder().build()?;
+//! let request = client.get("https://example.com").build()?;
+//! let report = Check::new()
+//!     .status(200)
+//!     .max_ttfb(Duration::from_millis(300))
+//!     .body_contains("Example Domain")
+//!     .cert_valid_for(Duration::from_secs(14 * 86400))
+//!     .run(&client, request)
+//!     .await;
+//! if !report.passed() {
+//!     eprintln!("{report}");
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use bytes::Bytes;
+use http::StatusCode;
+
+use crate::{
+    client::Client,
+    request::Request,
+    stats::{Stats, StatsRecorder},
+};
+
+/// Something a check expects from the response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expectation {
+    Status(StatusCode),
+    /// The time from sending the request to receiving the response head is
+    /// below the limit.
+    MaxTtfb(Duration),
+    /// The whole request, body included, takes less than the limit.
+    MaxTotal(Duration),
+    BodyContains(String),
+    /// The server certificate is still valid for at least this long. Fails
+    /// for plain HTTP.
+    CertValidFor(Duration),
+}
+
+/// The result of one expectation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub expectation: Expectation,
+    pub passed: bool,
+    /// What was measured, `None` if it couldn't be, e.g. when the request
+    /// failed before the response.
+    pub actual: Option<String>,
+}
+
+/// The outcome of every expectation of a check, in declaration order.
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub outcomes: Vec<Outcome>,
+    /// The error the request failed with, if it did.
+    pub error: Option<String>,
+    pub stats: Stats,
+}
+
+/// A list of expectations to run against requests.
+#[derive(Debug, Clone, Default)]
+pub struct Check {
+    expectations: Vec<Expectation>,
+}
+
+impl Check {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn expect(mut self, expectation: Expectation) -> Self {
+        self.expectations.push(expectation);
+        self
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the status is not between 100 and 999.
+    pub fn status(self, status: u16) -> Self {
+        let status = StatusCode::from_u16(status).expect("invalid status code");
+        self.expect(Expectation::Status(status))
+    }
+
+    pub fn max_ttfb(self, limit: Duration) -> Self {
+        self.expect(Expectation::MaxTtfb(limit))
+    }
+
+    pub fn max_total(self, limit: Duration) -> Self {
+        self.expect(Expectation::MaxTotal(limit))
+    }
+
+    pub fn body_contains<S: Into<String>>(self, needle: S) -> Self {
+        self.expect(Expectation::BodyContains(needle.into()))
+    }
+
+    pub fn cert_valid_for(self, validity: Duration) -> Self {
+        self.expect(Expectation::CertValidFor(validity))
+    }
+
+    /// Send the request, read the whole body and evaluate every expectation.
+    ///
+    /// The request's recorder is replaced by the one collecting the stats. A
+    /// failed request is reported, every expectation then fails.
+    pub async fn run(&self, client: &Client, mut request: Request) -> Report {
+        let recorder = StatsRecorder::new();
+        *request.recorder_mut() = Some(Arc::new(recorder.clone()));
+
+        let ret = match client.execute(request).await {
+            Ok(response) => {
+                let status = response.status();
+                response.bytes().await.map(|body| (status, body))
+            }
+            Err(e) => Err(e),
+        };
+        let stats = recorder.finish();
+        let (response, error) = match ret {
+            Ok(response) => (Some(response), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+
+        let outcomes = self
+            .expectations
+            .iter()
+            .map(|expectation| evaluate(expectation, response.as_ref(), &stats))
+            .collect();
+        Report {
+            outcomes,
+            error,
+            stats,
+        }
+    }
+}
+
+fn evaluate(
+    expectation: &Expectation,
+    response: Option<&(StatusCode, Bytes)>,
+    stats: &Stats,
+) -> Outcome {
+    let (passed, actual) = match (expectation, response) {
+        (_, None) => (false, None),
+        (Expectation::Status(expected), Some((status, _))) => {
+            (status == expected, Some(status.to_string()))
+        }
+        (Expectation::MaxTtfb(limit), Some(_)) => match stats.request_stats.as_ref() {
+            Some(stat) => (stat.duration < *limit, Some(format!("{:?}", stat.duration))),
+            None => (false, None),
+        },
+        (Expectation::MaxTotal(limit), Some(_)) => (
+            stats.total_duration < *limit,
+            Some(format!("{:?}", stats.total_duration)),
+        ),
+        (Expectation::BodyContains(needle), Some((_, body))) => {
+            let found = needle.is_empty()
+                || body
+                    .windows(needle.len())
+                    .any(|window| window == needle.as_bytes());
+            let actual = format!(
+                "{} in {} bytes",
+                if found { "found" } else { "not found" },
+                body.len()
+            );
+            (found, Some(actual))
+        }
+        (Expectation::CertValidFor(validity), Some(_)) => {
+            match stats.tls_info.as_ref().and_then(|v| v.cert_not_after) {
+                Some(not_after) => {
+                    let left = not_after
+                        .duration_since(SystemTime::now())
+                        .unwrap_or_default();
+                    (
+                        left >= *validity,
+                        Some(format!("expires in {}", days(left))),
+                    )
+                }
+                None => (false, None),
+            }
+        }
+    };
+    Outcome {
+        expectation: expectation.clone(),
+        passed,
+        actual,
+    }
+}
+
+fn days(duration: Duration) -> String {
+    format!("{:.1} days", duration.as_secs_f64() / 86400.0)
+}
+
+impl Report {
+    /// Whether the request succeeded and every expectation passed.
+    pub fn passed(&self) -> bool {
+        self.error.is_none() && self.outcomes.iter().all(|v| v.passed)
+    }
+
+    /// The expectations that failed.
+    pub fn failures(&self) -> impl Iterator<Item = &Outcome> {
+        self.outcomes.iter().filter(|v| !v.passed)
+    }
+}
+
+impl fmt::Display for Expectation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expectation::Status(status) => write!(f, "status == {}", status.as_u16()),
+            Expectation::MaxTtfb(limit) => write!(f, "ttfb < {:?}", limit),
+            Expectation::MaxTotal(limit) => write!(f, "total < {:?}", limit),
+            Expectation::BodyContains(needle) => write!(f, "body contains {:?}", needle),
+            Expectation::CertValidFor(validity) => {
+                write!(f, "cert valid for {}", days(*validity))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(error) = &self.error {
+            writeln!(f, "ERROR {}", error)?;
+        }
+        for outcome in &self.outcomes {
+            let verdict = if outcome.passed { "PASS" } else { "FAIL" };
+            write!(f, "{} {}", verdict, outcome.expectation)?;
+            match &outcome.actual {
+                Some(actual) => writeln!(f, " ({})", actual)?,
+                None => writeln!(f, " (not measured)")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Check;
+    use crate::{
+        client::Client,
+        test_util::{MockFailure, MockResponse, MockTransport},
+    };
+
+    #[tokio::test(start_paused = true)]
+    async fn test_check() {
+        let mock = MockTransport::new();
+        mock.push(
+            MockResponse::new(200)
+                .body("hello world")
+                .response_delay(Duration::from_millis(400))
+                .cert_validity(Duration::from_secs(10 * 86400)),
+        );
+        mock.push(MockResponse::failure(MockFailure::Tcp));
+        let client = Client::builder().mock_transport(mock).build().unwrap();
+        let check = Check::new()
+            .status(200)
+            .max_ttfb(Duration::from_millis(300))
+            .body_contains("world")
+            .cert_valid_for(Duration::from_secs(14 * 86400));
+
+        let request = client.get("https://example.com").build().unwrap();
+        let report = check.run(&client, request).await;
+        assert!(!report.passed());
+        let passed = report.outcomes.iter().map(|v| v.passed).collect::<Vec<_>>();
+        assert_eq!(passed, [true, false, true, false]);
+        assert_eq!(report.outcomes[1].actual.as_deref(), Some("400ms"));
+        assert_eq!(report.failures().count(), 2);
+        assert!(report.to_string().contains("FAIL ttfb < 300ms (400ms)"));
+
+        let request = client.get("https://example.com").build().unwrap();
+        let report = check.run(&client, request).await;
+        assert!(report.error.is_some());
+        assert!(
+            report
+                .outcomes
+                .iter()
+                .all(|v| !v.passed && v.actual.is_none())
+        );
+    }
+}
@@ -1,30 +1,63 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     net::{IpAddr, SocketAddr},
-    sync::{Arc, Once},
+    sync::{Arc, Mutex, Once, OnceLock},
     time::Duration,
 };
 
+use futures_util::{
+    FutureExt, StreamExt,
+    future::BoxFuture,
+    stream::{BoxStream, FuturesUnordered},
+};
 use hickory_resolver::{
-    Resolver, TokioResolver,
-    config::{LookupIpStrategy, NameServerConfig, ResolverConfig},
-    name_server::{GenericConnector, TokioConnectionProvider},
-    proto::runtime::TokioRuntimeProvider,
+    TokioResolver,
+    config::{LookupIpStrategy, NameServerConfig},
 };
-use http::{HeaderValue, Method};
+use http::{HeaderName, HeaderValue, Method, StatusCode, Version};
+use hyper::{client::conn::http2, upgrade::Upgraded};
 use hyper_util::rt::{TokioExecutor, TokioIo};
-use rustls::{ClientConfig, RootCertStore};
+use rustls::{
+    CipherSuite, ClientConfig, ConfigBuilder, KeyLog, NamedGroup, ProtocolVersion, RootCertStore,
+    WantsVerifier,
+    client::{EchConfig, EchMode, Resumption, WebPkiServerVerifier, danger::ServerCertVerifier},
+    crypto::{CryptoProvider, aws_lc_rs::hpke},
+    pki_types::{CertificateDer, CertificateRevocationListDer, EchConfigListBytes, ServerName},
+};
 use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
     net::{TcpSocket, TcpStream},
+    sync::{OwnedSemaphorePermit, Semaphore},
     time::Instant,
 };
 use tokio_rustls::{TlsConnector, client::TlsStream};
 
 use crate::{
+    Body,
+    alt_svc::{AltSvc, AltSvcCache, AltSvcDecision},
+    cache::DiskCache,
+    capture::{CaptureIo, Tap, WireCapture},
+    dns::{DnsInfo, DnsSource, HickoryResolver, Resolve},
+    early_data::EarlyDataIo,
+    expect_continue,
+    h2c::{self, H2cIo},
+    haproxy,
+    head_io::HeadIo,
     into_uri::IntoUri,
-    request::{Request, RequestBuilder},
+    key_log::KeyLogWriter,
+    mirror::MirrorBuilder,
+    pool::{Http2Sender, Pool, PoolKey, Sender},
+    probe::CertificateProbe,
+    proxy::{Connect, Proxies, Proxy, ProxyKind},
+    redirect::{self, Policy},
+    request::{Request, RequestBuilder, RequestHead, TracedRequest},
     response::Response,
-    skip_verify::SkipVerifier,
+    revocation::{RevocationChecker, RevocationStatus},
+    skip_verify::{SkipHostnameVerifier, SkipVerifier},
+    stats::{ConnectionInfo, Negotiation, StatsRecorder, TcpInfo, TlsInfo},
+    tunnel::{Tunnel, TunnelBuilder},
+    util::Rng,
+    verifier::{self, ConnectionVerifier},
 };
 
 const DEFAULT_DNS_TIMEOUT: Duration = Duration::from_secs(5);
@@ -33,10 +66,23 @@ const DEFAULT_TCP_TIMEOUT: Duration = Duration::from_secs(30);
 
 const DEFAULT_TLS_TIMEOUT: Duration = Duration::from_secs(10);
 
-const FALLBACK_INTERVAL: Duration = Duration::from_secs(3);
+const DEFAULT_CONNECT_ATTEMPT_DELAY: Duration = Duration::from_secs(3);
+
+// The shortest delay between connect attempts, however close the host.
+const MIN_CONNECT_ATTEMPT_DELAY: Duration = Duration::from_millis(100);
+
+// The hosts whose connect time is remembered.
+const MAX_CONNECT_RTT_HOSTS: usize = 1024;
+
+// The ECH config lists whose TLS config is kept for the next handshakes.
+const MAX_ECH_CONFIGS: usize = 64;
 
 const FAR_INTERVAL: Duration = Duration::from_secs(86400 * 365 * 30);
 
+const DEFAULT_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
 // Initialize crypto provider once
 static INIT: Once = Once::new();
 
@@ -67,20 +113,229 @@ impl Client {
         RequestBuilder::new(self.clone(), req)
     }
 
-    pub async fn execute(&self, request: Request) -> crate::Result<Response> {
+    /// Start building a request that fails over across an ordered list of mirrors.
+    ///
+    /// See [`MirrorBuilder`] for how the mirrors are tried.
+    pub fn mirrors<I, U>(&self, method: Method, uris: I) -> MirrorBuilder
+    where
+        I: IntoIterator<Item = U>,
+        U: IntoUri,
+    {
+        MirrorBuilder::new(self.clone(), method, uris)
+    }
+
+    /// Start building a tunnel to `authority`, a `host:port`, handing back
+    /// the raw byte stream once open.
+    ///
+    /// The tunnel goes through the proxy of the client with `CONNECT`, or a
+    /// SOCKS handshake, and straight to the target without one. The proxy is
+    /// the `https` one for port 443, the `http` one otherwise. The handshake
+    /// is reported to
+    /// [`Recorder::on_proxy_start`](crate::stats::Recorder::on_proxy_start)
+    /// like the tunnel of a request.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async fn run() -> httptrace::Result<()> {
+    /// use tokio::io::AsyncWriteExt;
+    ///
+    /// let client = httptrace::client::Client::builder().build()?;
+    /// let mut tunnel = client.connect_tunnel("example.com:25").send().await?;
+    /// tunnel.write_all(b"EHLO localhost\r\n").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn connect_tunnel(&self, authority: &str) -> TunnelBuilder {
+        TunnelBuilder::new(self.clone(), authority)
+    }
+
+    /// Connect to the origin of `uri` and complete the TLS handshake without
+    /// sending a request, to read the certificates of the server, e.g. to
+    /// watch their expiry. The certificates are verified as for a request,
+    /// see [`ClientBuilder::skip_tls_verify`] to read invalid ones.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async fn run() -> httptrace::Result<()> {
+    /// let client = httptrace::client::Client::builder().build()?;
+    /// let probe = client.probe_certificate("https://example.com").await?;
+    /// for cert in &probe.certificates {
+    ///     println!("{} issued by {}, until {:?}", cert.subject, cert.issuer, cert.not_after);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn probe_certificate<U: IntoUri>(&self, uri: U) -> crate::Result<CertificateProbe> {
+        let mut request = Request::new(Method::CONNECT, uri.into_uri()?);
+        let recorder = StatsRecorder::new();
+        *request.recorder_mut() = Some(Arc::new(recorder.clone()));
+        request.start();
+        let tls = self.inner.probe_certificate(&request).await?;
+        Ok(CertificateProbe::new(tls, recorder.finish()))
+    }
+
+    /// Whether a connection to the origin of `uri` is idle in the pool and
+    /// still open, so the next request to it can skip DNS, TCP and TLS.
+    ///
+    /// A server may still close it before the request is sent, which is then
+    /// sent on a new connection and told by
+    /// [`Stats::dead_connections`](crate::stats::Stats::dead_connections).
+    pub fn is_alive<U: IntoUri>(&self, uri: U) -> crate::Result<bool> {
+        let request = Request::new(Method::GET, uri.into_uri()?);
+        Ok(self.inner.pool.is_alive(&PoolKey::new(&request)))
+    }
+
+    pub(crate) async fn open_tunnel(&self, mut request: Request) -> crate::Result<Tunnel> {
+        request.start();
+        let timeout = *request.timeout().unwrap_or(&FAR_INTERVAL);
+        tokio::time::timeout(timeout, self.inner.open_tunnel(&request)).await?
+    }
+
+    pub async fn execute(&self, mut request: Request) -> crate::Result<Response> {
+        request.start();
+        // One deadline for the whole redirect chain.
+        let deadline = Instant::now() + *request.timeout().unwrap_or(&FAR_INTERVAL);
+        tokio::time::timeout_at(deadline, self.execute_chain(request)).await?
+    }
+
+    async fn execute_chain(&self, mut request: Request) -> crate::Result<Response> {
+        if self.inner.redirect.is_none() {
+            return self.execute_hop(request).await;
+        }
+
+        let mut previous = vec![request.uri().clone()];
+        loop {
+            // Kept to build the next hop, the request goes to the connection.
+            let (current, body_lost) = match request.redirected(request.uri().clone(), true) {
+                Some(current) => (current, false),
+                None => (request.head(), true),
+            };
+            let response = self.execute_hop(request).await?;
+            let next = redirect::next_request(
+                &self.inner.redirect,
+                &current,
+                body_lost,
+                response.status(),
+                response.headers(),
+                &previous,
+            )?;
+            let Some(next) = next else {
+                return Ok(response);
+            };
+            if let Some(recorder) = next.recorder() {
+                recorder.on_redirect(&next, response.status(), next.uri());
+            }
+            // Release the connection and the concurrency slot of the hop.
+            drop(response);
+            previous.push(next.uri().clone());
+            request = next;
+        }
+    }
+
+    async fn execute_hop(&self, request: Request) -> crate::Result<Response> {
+        #[cfg(any(
+            feature = "gzip",
+            feature = "deflate",
+            feature = "brotli",
+            feature = "zstd"
+        ))]
+        let (no_decompress, method) = (request.no_decompress(), request.method().clone());
+        let response = match self.inner.cache.as_ref() {
+            Some(cache) => cache.execute(self, request).await?,
+            None => self.inner.execute(request).await?,
+        };
+        // Above the cache, which keeps the body as received.
+        #[cfg(any(
+            feature = "gzip",
+            feature = "deflate",
+            feature = "brotli",
+            feature = "zstd"
+        ))]
+        if !no_decompress {
+            return Ok(crate::decompress::decompress(response, &method));
+        }
+        Ok(response)
+    }
+
+    /// Execute a request on the network, bypassing the cache. The timeout of
+    /// the request is left to the caller.
+    pub(crate) async fn execute_uncached(&self, request: Request) -> crate::Result<Response> {
         self.inner.execute(request).await
     }
 }
 
+/// The HTTP/2 settings of a client, hyper's defaults when unset.
+#[derive(Debug, Clone, Copy, Default)]
+struct Http2Settings {
+    initial_stream_window_size: Option<u32>,
+    initial_connection_window_size: Option<u32>,
+    adaptive_window: bool,
+    max_concurrent_streams: Option<u32>,
+    max_frame_size: Option<u32>,
+}
+
+/// Holds a slot of [`ClientBuilder::max_concurrent_requests`] for as long as
+/// the response lives.
+#[derive(Clone)]
+struct ConcurrencySlot {
+    _permit: Arc<OwnedSemaphorePermit>,
+}
+
 #[derive(Clone, Debug)]
 pub(crate) struct ClientRef {
     local_addr: Option<IpAddr>,
-    resolver: Resolver<GenericConnector<TokioRuntimeProvider>>,
+    resolver: Arc<dyn Resolve>,
     dns_overrides: HashMap<String, Vec<IpAddr>>,
     skip_tls_verify: bool,
+    skip_hostname_verify: bool,
+    root_certificates: Vec<CertificateDer<'static>>,
+    built_in_root_certs: bool,
+    min_tls_version: Option<ProtocolVersion>,
+    max_tls_version: Option<ProtocolVersion>,
+    cipher_suites: Option<Vec<CipherSuite>>,
+    kx_groups: Option<Vec<NamedGroup>>,
+    no_sni: bool,
+    tls_session_cache: Option<usize>,
+    tls_capture_rejected_chain: bool,
+    tls_enforce_crls: bool,
+    tls_early_data: bool,
+    tls_ech: bool,
+    key_log: Option<Arc<dyn KeyLog>>,
+    mptcp: bool,
     alpn_protocols: Option<Vec<Alpn>>,
+    // Built with the client and shared by the handshakes, only the server
+    // name varies per request.
+    tls_config: OnceLock<Arc<ClientConfig>>,
+    // The verifier of `tls_config`, shared with the configs offering ECH.
+    tls_verifier: OnceLock<Arc<dyn ServerCertVerifier>>,
+    // The configs offering ECH, by the published config list.
+    tls_ech_configs: Arc<Mutex<HashMap<Vec<u8>, Arc<ClientConfig>>>>,
+    revocation: Option<Arc<RevocationChecker>>,
+    proxies: Proxies,
+    redirect: Policy,
+    pool: Pool,
     disable_auto_set_header: bool,
+    request_id_header: bool,
+    body_digest: bool,
+    drive_in_task: bool,
+    title_case_headers: bool,
+    http2_prior_knowledge: bool,
+    http2_upgrade: bool,
+    http2: Http2Settings,
+    haproxy_protocol: Option<haproxy::Version>,
     prefer_ipv6: bool,
+    addr_rng: Option<Arc<Mutex<Rng>>>,
+    wire_capture: Option<usize>,
+    cache: Option<DiskCache>,
+    alt_svc: Option<Arc<AltSvcCache>>,
+    concurrency: Option<Arc<Semaphore>>,
+    connect_rtt: ConnectRtt,
+    #[cfg(feature = "pcap")]
+    pcap: Option<Arc<crate::pcap::PcapWriter>>,
+    #[cfg(any(test, feature = "test-util"))]
+    mock: Option<crate::test_util::MockTransport>,
 
     dns_timeout: Duration,
     tcp_timeout: Duration,
@@ -92,11 +347,53 @@ pub struct ClientBuilder {
     local_addr: Option<IpAddr>,
     lookup_ip_strategy: Option<LookupIpStrategy>,
     name_servers: Option<Vec<NameServerConfig>>,
+    resolver: Option<Arc<dyn Resolve>>,
     headers: Option<http::HeaderMap>,
     skip_tls_verify: bool,
+    skip_hostname_verify: bool,
+    root_certificates: Vec<CertificateDer<'static>>,
+    no_built_in_root_certs: bool,
+    min_tls_version: Option<ProtocolVersion>,
+    max_tls_version: Option<ProtocolVersion>,
+    cipher_suites: Option<Vec<CipherSuite>>,
+    kx_groups: Option<Vec<NamedGroup>>,
+    no_sni: bool,
+    tls_session_cache: Option<usize>,
+    tls_capture_rejected_chain: bool,
+    tls_enforce_crls: bool,
+    tls_early_data: bool,
+    tls_ech: bool,
+    tls_key_log: bool,
+    tls_key_log_file: Option<std::path::PathBuf>,
+    mptcp: bool,
     disable_auto_set_header: bool,
+    request_id_header: bool,
+    body_digest: bool,
     alpn_protocols: Option<Vec<Alpn>>,
+    crls: Option<Vec<CertificateRevocationListDer<'static>>>,
+    proxy: Option<Proxy>,
+    proxy_from_env: bool,
+    redirect: Policy,
+    pool_idle_timeout: Option<Duration>,
+    pool_max_idle_per_host: Option<usize>,
     dns_overrides: HashMap<String, Vec<IpAddr>>,
+    shuffle_addrs: bool,
+    shuffle_seed: Option<u64>,
+    wire_capture: Option<usize>,
+    cache: Option<DiskCache>,
+    alt_svc: bool,
+    max_concurrent_requests: Option<usize>,
+    drive_in_task: bool,
+    title_case_headers: bool,
+    http2_prior_knowledge: bool,
+    http2_upgrade: bool,
+    http2: Http2Settings,
+    haproxy_protocol: Option<haproxy::Version>,
+    connect_attempt_delay: Option<Duration>,
+    #[cfg(feature = "pcap")]
+    pcap: Option<std::path::PathBuf>,
+    #[cfg(any(test, feature = "test-util"))]
+    mock: Option<crate::test_util::MockTransport>,
 
     dns_timeout: Option<Duration>,
     tcp_timeout: Option<Duration>,
@@ -109,36 +406,110 @@ impl ClientBuilder {
     }
 
     pub fn build(self) -> crate::error::Result<Client> {
-        let mut resolver_builder = {
-            let provider = TokioConnectionProvider::default();
-            if self.name_servers.as_ref().is_some_and(|v| !v.is_empty()) {
-                let mut config = ResolverConfig::new();
-                for ns in self.name_servers.unwrap() {
-                    config.add_name_server(ns);
-                }
-                TokioResolver::builder_with_config(config, provider)
-            } else {
-                TokioResolver::builder(provider)?
-            }
+        if let Some(size) = self.http2.max_frame_size
+            && !(16_384..=16_777_215).contains(&size)
+        {
+            let msg = format!("HTTP/2 max frame size {size} not within 16384..=16777215");
+            return Err(crate::Error::InvalidConfig(msg));
+        }
+        if self.max_concurrent_requests == Some(0) {
+            let msg = "max concurrent requests of 0".to_string();
+            return Err(crate::Error::InvalidConfig(msg));
+        }
+        let resolver = match self.resolver {
+            Some(resolver) => resolver,
+            None => Arc::new(HickoryResolver::new(
+                self.name_servers,
+                self.lookup_ip_strategy.unwrap_or_default(),
+            )?),
         };
 
-        resolver_builder.options_mut().ip_strategy = self.lookup_ip_strategy.unwrap_or_default();
+        let proxies = match self.proxy {
+            Some(proxy) => Proxies::all(proxy),
+            None if self.proxy_from_env => Proxies::from_env()?,
+            None => Proxies::default(),
+        };
 
-        Ok(Client {
-            inner: Arc::new(ClientRef {
-                resolver: resolver_builder.build(),
-                local_addr: self.local_addr,
-                skip_tls_verify: self.skip_tls_verify,
-                alpn_protocols: self.alpn_protocols,
-                disable_auto_set_header: self.disable_auto_set_header,
-                dns_overrides: self.dns_overrides,
-                dns_timeout: self.dns_timeout.unwrap_or(DEFAULT_DNS_TIMEOUT), 
-                tcp_timeout: self.tcp_timeout.unwrap_or(DEFAULT_TCP_TIMEOUT),  
-                tls_timeout: self.tls_timeout.unwrap_or(DEFAULT_TLS_TIMEOUT),  
-                prefer_ipv6: self.lookup_ip_strategy.is_some_and(|v| {
-                    v == LookupIpStrategy::Ipv6Only || v == LookupIpStrategy::Ipv6thenIpv4
-                }),
+        let inner = ClientRef {
+            resolver,
+            local_addr: self.local_addr,
+            skip_tls_verify: self.skip_tls_verify,
+            skip_hostname_verify: self.skip_hostname_verify,
+            root_certificates: self.root_certificates,
+            built_in_root_certs: !self.no_built_in_root_certs,
+            min_tls_version: self.min_tls_version,
+            max_tls_version: self.max_tls_version,
+            cipher_suites: self.cipher_suites,
+            kx_groups: self.kx_groups,
+            no_sni: self.no_sni,
+            tls_session_cache: self.tls_session_cache,
+            tls_capture_rejected_chain: self.tls_capture_rejected_chain,
+            tls_enforce_crls: self.tls_enforce_crls,
+            tls_early_data: self.tls_early_data,
+            tls_ech: self.tls_ech,
+            key_log: match self.tls_key_log_file {
+                Some(path) => Some(Arc::new(KeyLogWriter::open(path)?)),
+                #[cfg(feature = "pcap")]
+                None if self.pcap.is_some() => Some(Arc::new(rustls::KeyLogFile::new())),
+                None if self.tls_key_log => Some(Arc::new(rustls::KeyLogFile::new())),
+                None => None,
+            },
+            mptcp: self.mptcp,
+            alpn_protocols: Some(
+                self.alpn_protocols
+                    .unwrap_or_else(|| VersionPolicy::PreferHttp2.alpn_protocols()),
+            ),
+            tls_config: OnceLock::new(),
+            tls_verifier: OnceLock::new(),
+            tls_ech_configs: Default::default(),
+            revocation: self.crls.map(|crls| Arc::new(RevocationChecker::new(crls))),
+            proxies,
+            redirect: self.redirect,
+            pool: Pool::new(
+                Some(self.pool_idle_timeout.unwrap_or(DEFAULT_POOL_IDLE_TIMEOUT)),
+                self.pool_max_idle_per_host.unwrap_or(0),
+            ),
+            disable_auto_set_header: self.disable_auto_set_header,
+            request_id_header: self.request_id_header,
+            body_digest: self.body_digest,
+            drive_in_task: self.drive_in_task,
+            title_case_headers: self.title_case_headers,
+            http2_prior_knowledge: self.http2_prior_knowledge,
+            http2_upgrade: self.http2_upgrade,
+            http2: self.http2,
+            haproxy_protocol: self.haproxy_protocol,
+            dns_overrides: self.dns_overrides,
+            addr_rng: self.shuffle_addrs.then(|| {
+                let rng = self.shuffle_seed.map_or_else(Rng::from_time, Rng::new);
+                Arc::new(Mutex::new(rng))
             }),
+            wire_capture: self.wire_capture,
+            cache: self.cache,
+            alt_svc: self.alt_svc.then(Arc::default),
+            concurrency: self
+                .max_concurrent_requests
+                .map(|v| Arc::new(Semaphore::new(v))),
+            connect_rtt: ConnectRtt::new(
+                self.connect_attempt_delay
+                    .unwrap_or(DEFAULT_CONNECT_ATTEMPT_DELAY),
+            ),
+            #[cfg(feature = "pcap")]
+            pcap: match self.pcap {
+                Some(path) => Some(Arc::new(crate::pcap::PcapWriter::create(path)?)),
+                None => None,
+            },
+            #[cfg(any(test, feature = "test-util"))]
+            mock: self.mock,
+            dns_timeout: self.dns_timeout.unwrap_or(DEFAULT_DNS_TIMEOUT),
+            tcp_timeout: self.tcp_timeout.unwrap_or(DEFAULT_TCP_TIMEOUT),
+            tls_timeout: self.tls_timeout.unwrap_or(DEFAULT_TLS_TIMEOUT),
+            prefer_ipv6: self.lookup_ip_strategy.is_some_and(|v| {
+                v == LookupIpStrategy::Ipv6Only || v == LookupIpStrategy::Ipv6thenIpv4
+            }),
+        };
+        inner.init_tls_config()?;
+        Ok(Client {
+            inner: Arc::new(inner),
         })
     }
 
@@ -153,16 +524,38 @@ impl ClientBuilder {
         self
     }
 
+    /// Shuffle the resolved addresses before connecting to them.
+    pub fn shuffle_addrs(mut self) -> Self {
+        self.shuffle_addrs = true;
+        self
+    }
+
+    /// Shuffle the resolved addresses with a seeded generator, so the connect
+    /// order of a sequence of requests is reproducible.
+    pub fn shuffle_addrs_with_seed(mut self, seed: u64) -> Self {
+        self.shuffle_addrs = true;
+        self.shuffle_seed = Some(seed);
+        self
+    }
+
     pub fn lookup_ip_strategy(mut self, strategy: LookupIpStrategy) -> Self {
         self.lookup_ip_strategy = Some(strategy);
         self
     }
 
+    /// The protocols offered by ALPN, h2 then HTTP/1.1 by default. An empty
+    /// list offers none, which the server answers with HTTP/1.1.
     pub fn alpn_protocols(mut self, alpn: Vec<Alpn>) -> Self {
         self.alpn_protocols = Some(alpn);
         self
     }
 
+    /// Offer the protocols of `policy`, overridden per request with
+    /// [`RequestBuilder::version_policy`](crate::request::RequestBuilder::version_policy).
+    pub fn version_policy(self, policy: VersionPolicy) -> Self {
+        self.alpn_protocols(policy.alpn_protocols())
+    }
+
     pub fn name_servers<I>(mut self, addr: I) -> Self
     where
         I: IntoIterator<Item = NameServerConfig>,
@@ -171,6 +564,32 @@ impl ClientBuilder {
         self
     }
 
+    /// Resolve host names with a custom resolver.
+    ///
+    /// `name_servers` and `lookup_ip_strategy` only apply to the default resolver.
+    pub fn dns_resolver<R: Resolve + 'static>(mut self, resolver: R) -> Self {
+        self.resolver = Some(Arc::new(resolver));
+        self
+    }
+
+    /// Resolve host names with a resolver shared with other clients, along
+    /// with its cache.
+    ///
+    /// `name_servers` and `lookup_ip_strategy` don't apply.
+    pub fn shared_dns_resolver(mut self, resolver: Arc<dyn Resolve>) -> Self {
+        self.resolver = Some(resolver);
+        self
+    }
+
+    /// Resolve host names with an existing hickory resolver. Clients given
+    /// clones of the same resolver share its cache.
+    ///
+    /// `name_servers` and `lookup_ip_strategy` don't apply, the resolver's own
+    /// configuration does.
+    pub fn hickory_resolver(self, resolver: TokioResolver) -> Self {
+        self.dns_resolver(HickoryResolver::from(resolver))
+    }
+
     pub fn headers(mut self, headers: http::HeaderMap) -> Self {
         self.headers = Some(headers);
         self
@@ -191,318 +610,1819 @@ impl ClientBuilder {
         self
     }
 
-    pub fn skip_tls_verify(mut self) -> Self {
-        self.skip_tls_verify = true;
+    /// The delay before trying the next address of a host while the previous
+    /// attempts are pending, 3s by default.
+    ///
+    /// Once connected to a host, the delay to its addresses adapts to twice
+    /// its smoothed connect time, at least 100ms and at most this one.
+    pub fn connect_attempt_delay(mut self, delay: Duration) -> Self {
+        self.connect_attempt_delay = Some(delay);
         self
     }
 
-    pub fn disable_auto_set_header(mut self) -> Self {
-        self.disable_auto_set_header = true;
+    /// Check the server certificates against these revocation lists after
+    /// the handshake, the outcome is reported to
+    /// [`Recorder::on_revocation_done`](crate::stats::Recorder::on_revocation_done).
+    ///
+    /// Only the end entity is checked, its status is unknown if no list of
+    /// its issuer is given. A revoked certificate fails the request with
+    /// [`Error::CertificateRevoked`](crate::Error::CertificateRevoked), the
+    /// chain must then be valid against the platform roots even with
+    /// `skip_tls_verify`.
+    pub fn crls<I>(mut self, crls: I) -> Self
+    where
+        I: IntoIterator<Item = CertificateRevocationListDer<'static>>,
+    {
+        self.crls = Some(crls.into_iter().collect());
         self
     }
-}
 
-impl ClientRef {
-    pub(crate) async fn execute(&self, mut request: Request) -> crate::Result<Response> {
-        let timeout = *request.timeout().unwrap_or(&FAR_INTERVAL);
+    /// Check the [`crls`](Self::crls) in the handshake rather than after it,
+    /// for every certificate of the chain: the handshake fails if one is
+    /// revoked, or if no list of its issuer is given. Passing is told by
+    /// [`TlsInfo::revocation`], a failure by the error of the TLS phase.
+    pub fn tls_enforce_crls(mut self, enable: bool) -> Self {
+        self.tls_enforce_crls = enable;
+        self
+    }
 
-        tokio::time::timeout(timeout, async {
-            let (addrs, _) = self.dns_resolve(&request).await?;
+    /// Tunnel every request through `proxy`, the tunnel setup is reported
+    /// to [`Recorder::on_proxy_done`](crate::stats::Recorder::on_proxy_done),
+    /// or [`Recorder::on_socks_done`](crate::stats::Recorder::on_socks_done)
+    /// for a SOCKS proxy, and the TCP phase is the connection to the proxy.
+    /// Basic credentials go with every `CONNECT`, a bearer token once an
+    /// HTTP proxy answers `407`, that round trip is reported to
+    /// [`Recorder::on_proxy_auth_done`](crate::stats::Recorder::on_proxy_auth_done).
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
 
-            let is_https = request.uri().scheme() == Some(&http::uri::Scheme::HTTPS);
+    /// Take the proxies from `HTTP_PROXY`, `HTTPS_PROXY` and `ALL_PROXY`,
+    /// read when building the client, requests to the hosts of `NO_PROXY`
+    /// are sent directly. An `https://` proxy isn't supported and is left
+    /// out. A proxy set with [`proxy`](Self::proxy) wins.
+    pub fn proxy_from_env(mut self) -> Self {
+        self.proxy_from_env = true;
+        self
+    }
 
-            let stream = self.tcp_connect(&request, addrs).await?;
+    /// Follow redirects as `policy` decides, none by default. Every hop is
+    /// reported to the recorder of the request, see [`crate::redirect`].
+    pub fn redirect(mut self, policy: Policy) -> Self {
+        self.redirect = policy;
+        self
+    }
 
-            if !self.disable_auto_set_header {
-                let host = request.uri().host().ok_or(crate::Error::EmptyResolveResult)?.to_string();
-                if request.headers().get(http::header::HOST).is_none() {
-                    request
-                        .headers_mut()
-                        .insert(http::header::HOST, host.parse()?);
-                }
-                if request.headers().get(http::header::USER_AGENT).is_none() {
-                    request.headers_mut().insert(http::header::USER_AGENT, HeaderValue::from_static("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/137.0.0.0 Safari/537.36"));
-                }
-            }
+    /// Close the connections idle in the pool for longer than `timeout`, 90
+    /// seconds by default.
+    pub fn pool_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.pool_idle_timeout = Some(timeout);
+        self
+    }
 
-            
- 
-            if is_https {
-                let tls_stream = self.tls_handshake(stream, &request).await?;
+    /// Keep up to `max` idle connections per origin for later requests to
+    /// reuse. Pooling is off by default, every request opens its connection.
+    ///
+    /// A request on a pooled connection is reported to
+    /// [`Recorder::on_connection_reused`](crate::stats::Recorder::on_connection_reused)
+    /// instead of the DNS, TCP and TLS phases.
+    pub fn pool_max_idle_per_host(mut self, max: usize) -> Self {
+        self.pool_max_idle_per_host = Some(max);
+        self
+    }
 
-                self.tls_send_request(tls_stream, request).await
-            } else {
-                self.tcp_send_h1_request(stream, request).await
-            }
-        })
-        .await?
+    pub fn skip_tls_verify(mut self) -> Self {
+        self.skip_tls_verify = true;
+        self
     }
 
-    pub(crate) async fn dns_resolve(
-        &self,
-        request: &Request,
-    ) -> crate::Result<(Vec<SocketAddr>, bool)> {
-        let host = request.uri().host().ok_or(crate::Error::HostRequired)?;
-        if let Some(recorder) = request.recorder() {
-            recorder.on_dns_start(request, self.resolver.config().name_servers(), host);
-        }
+    #[deprecated(note = "use `danger_accept_invalid_hostnames(true)` instead")]
+    pub fn danger_skip_hostname_verification(self) -> Self {
+        self.danger_accept_invalid_hostnames(true)
+    }
 
-        let ret = self._dns_resolve(request).await;
+    /// Whether to accept a server certificate presented under another name,
+    /// e.g. when connecting by IP address. Unlike `skip_tls_verify`, the
+    /// chain is still verified against the root store, so a failure with it
+    /// on points at the certificate itself rather than its name.
+    pub fn danger_accept_invalid_hostnames(mut self, accept: bool) -> Self {
+        self.skip_hostname_verify = accept;
+        self
+    }
 
-        if let Some(recorder) = request.recorder() {
-            recorder.on_dns_done(
-                request,
-                self.resolver.config().name_servers(),
-                host,
-                ret.as_ref()
-                    .map(|(ips, hit_cache)| (ips.as_slice(), *hit_cache))
-                    .map_err(|e| e.to_string()),
-            );
-        }
-        ret
+    /// Trust `certificate` as a root, e.g. a private CA, on top of the
+    /// built-in roots.
+    pub fn add_root_certificate(mut self, certificate: CertificateDer<'static>) -> Self {
+        self.root_certificates.push(certificate);
+        self
     }
 
-    pub(crate) async fn tcp_connect(
-        &self,
-        request: &Request,
-        addrs: Vec<SocketAddr>,
-    ) -> crate::Result<TcpStream> {
-        let (tx, mut rx) = tokio::sync::mpsc::channel::<(SocketAddr, crate::Result<TcpStream>)>(1);
-        let (cancel, _) = tokio::sync::broadcast::channel::<()>(1);
+    /// Trust the built-in roots, the platform store or the bundled Mozilla
+    /// roots with the `webpki-roots` feature. On by default, turn it off to
+    /// only trust the roots given with
+    /// [`add_root_certificate`](Self::add_root_certificate), no root at all
+    /// fails [`build`](Self::build).
+    pub fn tls_built_in_root_certs(mut self, enable: bool) -> Self {
+        self.no_built_in_root_certs = !enable;
+        self
+    }
 
-        let mut addrs = addrs.into_iter();
+    /// The oldest TLS version to negotiate, e.g. `ProtocolVersion::TLSv1_3`
+    /// for TLS 1.3 only. TLS 1.2 and 1.3 are supported.
+    pub fn min_tls_version(mut self, version: ProtocolVersion) -> Self {
+        self.min_tls_version = Some(version);
+        self
+    }
 
-        let mut result: crate::Result<TcpStream> = Err(crate::Error::Unknown);
-        let mut timer = Instant::now();
-        let mut tx_opt = Some(tx);
-        let deadline = timer + self.tcp_timeout;
+    /// The newest TLS version to negotiate, e.g. `ProtocolVersion::TLSv1_2`
+    /// for TLS 1.2 only. No version left between the bounds fails
+    /// [`build`](Self::build).
+    pub fn max_tls_version(mut self, version: ProtocolVersion) -> Self {
+        self.max_tls_version = Some(version);
+        self
+    }
 
-        'outer: loop {
-            tokio::select! {
-                _ = tokio::time::sleep_until(deadline) => {
-                    result = Err(crate::Error::TcpDeadlineExceeded);
-                    break 'outer;
-                }
-                _ = tokio::time::sleep_until(timer) => {
-                    match addrs.next() {
-                        Some(addr) => {
-                            if let Some(recorder) = request.recorder() {
-                                recorder.on_tcp_start(request, &addr);
-                            }
-                            if let Some(tx) = tx_opt.clone() {
-                                let local_addr = self.local_addr;
-                                let prefer_ipv6 = self.prefer_ipv6;
-                                let cancel_rx = cancel.subscribe();
-                                tokio::spawn(async move {
-                                    let ret = Self::_tcp_connect(local_addr, addr, cancel_rx, prefer_ipv6).await;
-                                    _ = tx.send((addr, ret)).await;
-                                });
-                            }
-                            timer += FALLBACK_INTERVAL;
-                        }
-                        None => {
-                            let tx = tx_opt.take();
-                            drop(tx);
-                            timer = Instant::now() + FAR_INTERVAL;
-                        }
-                    }
-                }
-                conn_ret = rx.recv() => match conn_ret {
-                    Some((addr, ret)) => {
-                        if let Some(recorder) = request.recorder() {
-                            recorder.on_tcp_done(request, &addr, ret.as_ref().map_err(|e|e.to_string()));
-                        }
-                        if let Ok(ret) = ret {
-                            result = Ok(ret);
-                            break 'outer;
-                        }
-                    }
-                    None => {
-                        result = Err(crate::Error::AllTcpConnectFailed);
-                        break 'outer;
-                    },
-                }
-            }
-        }
-        _ = cancel.send(());
-        result
+    /// Offer only `suites`, in this order of preference, e.g. to check
+    /// whether a server accepts ChaCha20. The suites the crypto provider
+    /// doesn't implement are dropped, none left fails [`build`](Self::build).
+    pub fn tls_cipher_suites(mut self, suites: Vec<CipherSuite>) -> Self {
+        self.cipher_suites = Some(suites);
+        self
     }
 
-    pub(crate) async fn tls_handshake(
-        &self,
-        stream: TcpStream,
-        request: &Request,
-    ) -> crate::Result<TlsStream<TcpStream>> {
-        ensure_crypto_provider();
-        if let Some(recorder) = request.recorder() {
-            recorder.on_tls_start(request, &stream);
-        }
+    /// Offer only the key exchange `groups`, in this order of preference,
+    /// e.g. `X25519` alone. The groups the crypto provider doesn't implement
+    /// are dropped, none left fails [`build`](Self::build).
+    pub fn tls_kx_groups(mut self, groups: Vec<NamedGroup>) -> Self {
+        self.kx_groups = Some(groups);
+        self
+    }
 
-        let ret = self._tls_handshake(stream, request).await;
+    /// Send no TLS server name, as for an IP address. The certificate is
+    /// still verified against the host of the URI, or the name given with
+    /// [`RequestBuilder::sni`].
+    pub fn no_sni(mut self) -> Self {
+        self.no_sni = true;
+        self
+    }
 
-        if let Some(recorder) = request.recorder() {
-            recorder.on_tls_done(request, ret.as_ref().map_err(|e| e.to_string()));
-        }
-        ret
+    pub fn disable_auto_set_header(mut self) -> Self {
+        self.disable_auto_set_header = true;
+        self
     }
 
-    async fn _dns_resolve(&self, request: &Request) -> crate::Result<(Vec<SocketAddr>, bool)> {
-        let host = request.uri().host().ok_or(crate::Error::HostRequired)?;
-        let port = request.port();
+    /// Write header names in title case on HTTP/1, e.g. `Content-Type`,
+    /// instead of lowercase. Names added with
+    /// [`RequestBuilder::header_with_case`] keep their own case.
+    pub fn title_case_headers(mut self) -> Self {
+        self.title_case_headers = true;
+        self
+    }
 
-        if let Some(ips) = self.dns_overrides.get(host) {
-            if !ips.is_empty() {
-                return Ok((
-                    ips.iter().map(|ip| SocketAddr::new(*ip, port)).collect(),
-                    true,
-                ));
-            }
-        }
+    /// Speak HTTP/2 right away on plain `http://` connections (h2c), for
+    /// servers known to support it, instead of HTTP/1.1.
+    pub fn http2_prior_knowledge(mut self) -> Self {
+        self.http2_prior_knowledge = true;
+        self
+    }
 
-        let ips = tokio::time::timeout(self.dns_timeout, self.resolver.lookup_ip(host)).await??;
+    /// Ask plain `http://` servers to switch to HTTP/2 with `Upgrade: h2c`,
+    /// on requests without a body. The round trip until the `101` is
+    /// reported to
+    /// [`Recorder::on_h2c_upgrade_start`](crate::stats::Recorder::on_h2c_upgrade_start).
+    pub fn http2_upgrade(mut self) -> Self {
+        self.http2_upgrade = true;
+        self
+    }
 
-        let addrs: Vec<_> = ips
-            .into_iter()
-            .map(|ip| SocketAddr::new(ip, port))
-            .collect();
-        if addrs.is_empty() {
-            return Err(crate::Error::EmptyResolveResult);
-        }
-        Ok((addrs, false))
+    /// The HTTP/2 flow-control window of each stream, 64KiB by default.
+    pub fn http2_initial_stream_window_size(mut self, size: u32) -> Self {
+        self.http2.initial_stream_window_size = Some(size);
+        self
     }
 
-    async fn _tcp_connect(
-        local_addr: Option<IpAddr>,
-        dest: SocketAddr,
-        mut cancel_rx: tokio::sync::broadcast::Receiver<()>,
-        prefer_ipv6: bool,
-    ) -> crate::Result<TcpStream> {
-        let socket = {
-            match local_addr {
-                Some(local_addr) => match local_addr.is_ipv4() {
-                    true => {
-                        let socket = TcpSocket::new_v4()?;
-                        socket.bind(SocketAddr::new(local_addr, 0))?;
-                        socket
-                    }
-                    false => {
-                        let socket = TcpSocket::new_v6()?;
-                        socket.bind(SocketAddr::new(local_addr, 0))?;
-                        socket
-                    }
-                },
-                None => match prefer_ipv6 {
-                    true => TcpSocket::new_v6()?,
-                    false => TcpSocket::new_v4()?,
-                },
-            }
-        };
+    /// The HTTP/2 flow-control window of the whole connection, 64KiB by
+    /// default.
+    pub fn http2_initial_connection_window_size(mut self, size: u32) -> Self {
+        self.http2.initial_connection_window_size = Some(size);
+        self
+    }
 
-        tokio::select! {
-            _ = cancel_rx.recv() => Err(crate::Error::TcpDeadlineExceeded),
-            stream = socket.connect(dest) => Ok(stream?),
-        }
+    /// Grow the HTTP/2 windows after the measured bandwidth-delay product,
+    /// overriding the initial window sizes.
+    pub fn http2_adaptive_window(mut self, enabled: bool) -> Self {
+        self.http2.adaptive_window = enabled;
+        self
     }
 
-    async fn _tls_handshake(
-        &self,
-        stream: TcpStream,
-        request: &Request,
-    ) -> crate::Result<TlsStream<TcpStream>> {
-        // Add root certificates
-        let mut root_store = RootCertStore::empty();
-        let certs = rustls_native_certs::load_native_certs().certs;
-        for cert in certs {
-            root_store.add(cert)?;
-        }
-
-        // Configure TLS client
-        let mut config = ClientConfig::builder()
-            .with_root_certificates(root_store)
-            .with_no_client_auth();
-        if self.skip_tls_verify {
-            config
-                .dangerous()
-                .set_certificate_verifier(Arc::new(SkipVerifier));
-        }
-
-        // Set ALPN protocols
-        if let Some(alpn) = self.alpn_protocols.as_ref() {
-            config.alpn_protocols = alpn
-                .iter()
-                .map(|v| v.to_string().as_bytes().to_vec())
-                .collect::<Vec<_>>();
-        }
+    /// The `SETTINGS_MAX_CONCURRENT_STREAMS` advertised to the server.
+    pub fn http2_max_concurrent_streams(mut self, max: u32) -> Self {
+        self.http2.max_concurrent_streams = Some(max);
+        self
+    }
 
-        let connector = TlsConnector::from(Arc::new(config));
+    /// The largest HTTP/2 frame payload accepted, 16KiB by default. The
+    /// protocol allows 16KiB to 16MiB - 1, [`build`](Self::build) fails on
+    /// others.
+    pub fn http2_max_frame_size(mut self, size: u32) -> Self {
+        self.http2.max_frame_size = Some(size);
+        self
+    }
 
-        let domain = request
-            .uri()
-            .host()
-            .unwrap_or_default()
-            .to_string()
-            .try_into()?;
+    /// Start every connection with a PROXY protocol header of `version`,
+    /// carrying its own addresses, for load balancers requiring one.
+    pub fn haproxy_protocol(mut self, version: haproxy::Version) -> Self {
+        self.haproxy_protocol = Some(version);
+        self
+    }
 
-        let tls_stream =
-            tokio::time::timeout(self.tls_timeout, connector.connect(domain, stream)).await??;
+    /// Send the [`Request::id`] in an `X-Request-Id` header, unless the request
+    /// already has one.
+    pub fn request_id_header(mut self) -> Self {
+        self.request_id_header = true;
+        self
+    }
 
-        Ok(tls_stream)
+    /// Hash the response bodies while they are read, the SHA-256 is reported in
+    /// [`BodyInfo::sha256`](crate::stats::BodyInfo::sha256).
+    pub fn body_digest(mut self) -> Self {
+        self.body_digest = true;
+        self
     }
 
-    async fn tcp_send_h1_request(
-        &self,
-        stream: TcpStream,
-        request: Request,
-    ) -> crate::Result<Response> {
-        if let Some(recorder) = request.recorder() {
-            recorder.on_request_start(&request);
-        }
+    /// Record up to `limit` bytes sent and received on each connection.
+    ///
+    /// The capture is inserted into the response extensions as a [`WireCapture`].
+    pub fn wire_capture(mut self, limit: usize) -> Self {
+        self.wire_capture = Some(limit);
+        self
+    }
 
-        let (mut tx, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+    /// Limit the number of requests in flight, the others wait for a slot.
+    ///
+    /// A slot is released once the response is dropped. The time spent waiting
+    /// is reported as [`Stats::blocked_stats`](crate::stats::Stats::blocked_stats).
+    /// Building the client fails with
+    /// [`Error::InvalidConfig`](crate::Error::InvalidConfig) if `max` is `0`.
+    pub fn max_concurrent_requests(mut self, max: usize) -> Self {
+        self.max_concurrent_requests = Some(max);
+        self
+    }
 
-        tokio::spawn(async move {
-            _ = conn.await;
-        });
+    /// Drive connections in the task executing the request instead of
+    /// spawning a task per connect attempt and per connection.
+    ///
+    /// This cuts the spawn overhead and keeps single request measurements on
+    /// one task. The connection then only makes progress while the request
+    /// or its response body is polled.
+    pub fn drive_in_task(mut self) -> Self {
+        self.drive_in_task = true;
+        self
+    }
 
-        let resp = tx.send_request(request.try_into()?).await?;
-        Ok(Response::new(resp.map(super::body::boxed)))
+    /// Cache responses on disk, see [`DiskCache`].
+    pub fn cache(mut self, cache: DiskCache) -> Self {
+        self.cache = Some(cache);
+        self
     }
 
-    async fn tls_send_request(
-        &self,
-        stream: TlsStream<TcpStream>,
-        request: Request,
-    ) -> crate::Result<Response> {
-        if let Some(recorder) = request.recorder() {
-            recorder.on_request_start(&request);
-        }
+    /// Send the request as TLS 1.3 early data (0-RTT) when resuming a
+    /// session, saving a round trip. Whether the server accepted it is
+    /// reported to
+    /// [`Recorder::on_tls_early_data`](crate::stats::Recorder::on_tls_early_data).
+    ///
+    /// Only attempted when a single protocol is offered by ALPN, e.g. with
+    /// [`RequestBuilder::version`], and without revocation lists. The
+    /// connection isn't pooled. Early data can be replayed by an attacker,
+    /// so only idempotent requests are sent early.
+    pub fn tls_early_data(mut self, enable: bool) -> Self {
+        self.tls_early_data = enable;
+        self
+    }
 
-        let is_h2 = {
-            if let Some(alpn) = stream.get_ref().1.alpn_protocol() {
-                String::from_utf8_lossy(alpn) == "h2"
-            } else {
-                false
-            }
-        };
+    /// Encrypt the ClientHello (ECH) with the config published in the
+    /// `HTTPS` record of the host, looked up by the resolver along with its
+    /// addresses. Told by [`TlsInfo::ech`].
+    ///
+    /// A handshake offering ECH is TLS 1.3 only. One without a config the
+    /// client supports falls back to a ClientHello in the clear, one
+    /// rejected by the server fails with
+    /// [`PeerIncompatible::ServerRejectedEncryptedClientHello`](rustls::PeerIncompatible::ServerRejectedEncryptedClientHello)
+    /// after checking the certificate against the public name of the config.
+    pub fn tls_ech(mut self, enable: bool) -> Self {
+        self.tls_ech = enable;
+        self
+    }
 
-        let resp = if is_h2 {
-            let (mut tx, conn) =
-                hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(stream))
-                    .await?;
+    /// Keep the TLS sessions of up to `size` servers, 256 by default, for the
+    /// following connections to resume with an abbreviated handshake as told
+    /// by [`TlsInfo::resumed`]. `0` makes every handshake a full one.
+    pub fn tls_session_cache(mut self, size: usize) -> Self {
+        self.tls_session_cache = Some(size);
+        self
+    }
+
+    /// Keep the chain presented by a server whose certificate is rejected,
+    /// the handshake then fails with
+    /// [`Error::CertificateRejected`](crate::Error::CertificateRejected)
+    /// carrying it, as reported to
+    /// [`Recorder::on_tls_done`](crate::stats::Recorder::on_tls_done) and
+    /// found in [`Stats::rejected_certificates`](crate::stats::Stats::rejected_certificates).
+    pub fn tls_capture_rejected_chain(mut self, enable: bool) -> Self {
+        self.tls_capture_rejected_chain = enable;
+        self
+    }
+
+    /// Log the TLS secrets to the file named by `SSLKEYLOGFILE`, if set, so
+    /// `https` traffic captured alongside a trace can be decrypted, e.g. by
+    /// Wireshark.
+    pub fn tls_key_log(mut self) -> Self {
+        self.tls_key_log = true;
+        self
+    }
+
+    /// Append the TLS secrets to the key log file at `path`, whatever
+    /// `SSLKEYLOGFILE` says. Failing to open it fails [`build`](Self::build).
+    pub fn tls_key_log_file<P: AsRef<std::path::Path>>(mut self, path: P) -> Self {
+        self.tls_key_log_file = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Connect with Multipath TCP sockets on Linux kernels supporting it,
+    /// plain TCP sockets otherwise, as told by [`TcpInfo::mptcp`]. The kernel
+    /// still falls back to TCP with servers not speaking MPTCP.
+    pub fn mptcp(mut self, enable: bool) -> Self {
+        self.mptcp = enable;
+        self
+    }
+
+    /// Remember the alternative services `https` origins advertise with
+    /// `Alt-Svc`, and connect to them on the following requests to these
+    /// origins. The decision is reported to
+    /// [`Recorder::on_alt_svc`](crate::stats::Recorder::on_alt_svc).
+    ///
+    /// Alternatives are skipped for requests going through a proxy or
+    /// constraining the protocol, and those speaking `h3`.
+    pub fn alt_svc(mut self) -> Self {
+        self.alt_svc = true;
+        self
+    }
+
+    /// Write the TCP byte streams of every connection to a pcap file.
+    ///
+    /// TLS secrets are logged to the file named by `SSLKEYLOGFILE`, if set, so
+    /// the captured `https` traffic can be decrypted, unless
+    /// [`tls_key_log_file`](Self::tls_key_log_file) names another one.
+    #[cfg(feature = "pcap")]
+    pub fn pcap<P: AsRef<std::path::Path>>(mut self, path: P) -> Self {
+        self.pcap = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Serve every request from a [`MockTransport`](crate::test_util::MockTransport)
+    /// instead of the network.
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn mock_transport(mut self, mock: crate::test_util::MockTransport) -> Self {
+        self.mock = Some(mock);
+        self
+    }
+}
+
+/// A connection to the host of a request, or tunneled to it, with the ECH
+/// config list of the host.
+type Connected = (
+    CaptureIo<TcpStream>,
+    TcpInfo,
+    Option<EchConfigListBytes<'static>>,
+);
+
+/// What became of a request sent on a connection of the pool.
+enum Sent {
+    Response(Response),
+    /// The connection was closed before the request was written, or before
+    /// any response to an idempotent request without a body, given back to
+    /// send again.
+    Closed(Box<Request>, hyper::Error),
+}
+
+impl ClientRef {
+    pub(crate) async fn execute(&self, request: Request) -> crate::Result<Response> {
+        let permit = self.acquire_slot(&request).await;
+        let head = request.recorder().map(|_| request.head());
+        let uri = self.alt_svc.as_ref().map(|_| request.uri().clone());
+        let fallback = h2_fallback(&request);
+        let mut response = match self._execute(request).await {
+            Ok(response) => response,
+            Err(e) => match fallback.filter(|retry| is_h2_refusal(&e, retry.method())) {
+                Some(retry) => {
+                    if let Some(recorder) = retry.recorder() {
+                        recorder.on_h2_fallback(&retry, &e);
+                    }
+                    self._execute(retry).await?
+                }
+                None => return Err(e),
+            },
+        };
+        if let Some(cache) = self.alt_svc.as_ref()
+            && let Some(uri) = uri.as_ref()
+        {
+            cache.update(uri, response.headers());
+        }
+        if let Some(permit) = permit {
+            response.extensions_mut().insert(permit);
+        }
+        if let Some(head) = head {
+            let traced = TracedRequest(Arc::new(head.head()));
+            response.extensions_mut().insert(traced);
+            let digest = self.body_digest;
+            response.res = response
+                .res
+                .map(|body| crate::body::boxed(crate::body::recorded(body, head, digest)));
+        }
+        Ok(response)
+    }
+
+    async fn open_tunnel(&self, request: &Request) -> crate::Result<Tunnel> {
+        let (stream, tcp, _) = self.connect(request).await?;
+        Ok(Tunnel::new(stream, tcp))
+    }
+
+    /// Connect to the host of the request, or through its proxy, again if
+    /// the proxy closes the connection after asking for credentials. The
+    /// ECH config of the host is looked up along with its addresses.
+    async fn connect(&self, request: &Request) -> crate::Result<Connected> {
+        let mut auth = false;
+        let mut ech = None;
+        loop {
+            let connect = match auth {
+                true => self.dns_connect(request).await,
+                false => {
+                    let ret;
+                    (ret, ech) = tokio::join!(self.dns_connect(request), self.ech_config(request));
+                    ret
+                }
+            };
+            let stream = match connect {
+                Ok(stream) => stream,
+                Err(e) => {
+                    // Back to the origin on the next request.
+                    if let Some(cache) = self.alt_svc.as_ref()
+                        && let Some(alt) = request.alt_svc()
+                    {
+                        cache.remove(request.uri(), alt);
+                    }
+                    return Err(e);
+                }
+            };
+            let tcp = TcpInfo::new(&stream, stream.peer_addr()?);
+            let tap = self.connection_tap(&stream);
+            let mut stream = CaptureIo::new(stream, tap);
+            if let Some(version) = self.haproxy_protocol {
+                let header = version.header(stream.get_ref().local_addr()?, tcp.peer_addr);
+                stream.write_all(&header).await?;
+            }
+            if self.proxy_connect(&mut stream, request, auth).await? {
+                return Ok((stream, tcp, ech));
+            }
+            auth = true;
+        }
+    }
+
+    async fn probe_certificate(&self, request: &Request) -> crate::Result<TlsInfo> {
+        let (stream, _, ech) = self.connect(request).await?;
+        let (_, tls) = self.tls_handshake(stream, request, ech).await?;
+        Ok(tls)
+    }
+
+    /// Wait for a slot if the number of requests in flight is limited.
+    async fn acquire_slot(&self, request: &Request) -> Option<ConcurrencySlot> {
+        let semaphore = self.concurrency.clone()?;
+        if let Ok(permit) = semaphore.clone().try_acquire_owned() {
+            return Some(ConcurrencySlot {
+                _permit: Arc::new(permit),
+            });
+        }
+
+        if let Some(recorder) = request.recorder() {
+            recorder.on_blocked_start(request);
+        }
+        // The semaphore is never closed.
+        let permit = semaphore.acquire_owned().await.ok()?;
+        if let Some(recorder) = request.recorder() {
+            recorder.on_blocked_done(request);
+        }
+        Some(ConcurrencySlot {
+            _permit: Arc::new(permit),
+        })
+    }
+
+    async fn _execute(&self, mut request: Request) -> crate::Result<Response> {
+        #[cfg(any(test, feature = "test-util"))]
+        if let Some(mock) = self.mock.as_ref() {
+            self.set_default_headers(&mut request)?;
+            let head = RequestHead::new(&request);
+            let mut response = mock.execute(request).await?;
+            response.extensions_mut().insert(head);
+            return Ok(response);
+        }
+
+        let pinned = pinned_alpn(&request)?;
+        if let Some(decision) = self.alt_svc_decision(&request) {
+            if let Some(recorder) = request.recorder() {
+                recorder.on_alt_svc(&request, &decision);
+            }
+            request.set_alt_svc(decision.used);
+        }
+
+        let key = self.pool_key(&request);
+        if let Some(key) = key.as_ref() {
+            let (idle, dead) = self.pool.checkout(key);
+            if let Some(recorder) = request.recorder() {
+                for conn in &dead {
+                    recorder.on_connection_dead(&request, conn);
+                }
+            }
+            if let Some((sender, conn)) = idle {
+                match self
+                    .execute_reused(request, key.clone(), sender, conn)
+                    .await
+                {
+                    Ok(ret) => return ret,
+                    // Found closed, on a new connection then.
+                    Err(closed) => request = closed,
+                }
+            }
+        }
+
+        let is_https = request.uri().scheme() == Some(&http::uri::Scheme::HTTPS);
+
+        let (stream, tcp, ech) = self.connect(&request).await?;
+
+        self.set_default_headers(&mut request)?;
+        let head = RequestHead::new(&request);
+
+        let h2c_upgrade = !is_https
+            && self.http2_upgrade
+            && !self.http2_prior_knowledge
+            && pinned.is_none()
+            && h2c::can_upgrade(&request);
+        let (mut response, tls) = if is_https {
+            let (tls_stream, tls) = self.tls_handshake(stream, &request, ech).await?;
+            // Still handshaking while sending early data.
+            let early = tls_stream.get_ref().1.is_handshaking();
+            if !early {
+                self.check_revocation(&tls_stream, &request)?;
+            }
+
+            let offered = self.offered_alpn(&request);
+            let alpn = match early {
+                // The one offered, which the resumed session agreed on.
+                true => offered.as_deref().and_then(|alpn| alpn_ids(alpn).pop()),
+                false => tls_stream.get_ref().1.alpn_protocol().map(<[u8]>::to_vec),
+            };
+            let alpn = alpn.as_deref();
+            if let Some(recorder) = request.recorder() {
+                let offered = offered.is_some_and(|v| !v.is_empty());
+                recorder.on_negotiated(&request, &Negotiation::tls(alpn, offered));
+            }
+            check_protocol(&request, alpn)?;
+            let is_h2 = alpn.is_some_and(|alpn| alpn == b"h2");
+
+            if early {
+                // Not pooled, its info is only complete after the handshake.
+                let done = Arc::new(OnceLock::new());
+                let head = request.recorder().map(|_| request.head());
+                let sni = self.tls_sni(&request);
+                let io = EarlyDataIo::new(tls_stream, head, sni, done.clone());
+                let response = self.send_request(io, is_h2, request, None).await?;
+                (response, Some(done.get().cloned().unwrap_or(tls)))
+            } else {
+                let version = if is_h2 {
+                    Version::HTTP_2
+                } else {
+                    Version::HTTP_11
+                };
+                let checkin = key.map(|key| {
+                    (
+                        key,
+                        ConnectionInfo::new(tcp.clone(), Some(tls.clone()), version),
+                    )
+                });
+                let response = self
+                    .send_request(tls_stream, is_h2, request, checkin)
+                    .await?;
+                (response, Some(tls))
+            }
+        } else if h2c_upgrade {
+            (self.send_h2c_upgrade(stream, request).await?, None)
+        } else {
+            let is_h2 = match pinned {
+                Some(alpn) => matches!(alpn, Alpn::Http2),
+                None => self.http2_prior_knowledge,
+            };
+            if let Some(recorder) = request.recorder() {
+                recorder.on_negotiated(&request, &Negotiation::cleartext(is_h2));
+            }
+            check_protocol(&request, is_h2.then_some(b"h2"))?;
+
+            let version = if is_h2 {
+                Version::HTTP_2
+            } else {
+                Version::HTTP_11
+            };
+            let checkin = key.map(|key| (key, ConnectionInfo::new(tcp.clone(), None, version)));
+            (
+                self.send_request(stream, is_h2, request, checkin).await?,
+                None,
+            )
+        };
+        // A pooled connection already has its info.
+        if response.extensions().get::<ConnectionInfo>().is_none() {
+            let conn = ConnectionInfo::new(tcp, tls, response.version());
+            response.extensions_mut().insert(conn);
+        }
+        response.extensions_mut().insert(head);
+        Ok(response)
+    }
+
+    /// Send `request` on a connection of the pool, skipping DNS, TCP and TLS.
+    /// The request is given back if the connection turns out closed before
+    /// it's written, to send on a new one.
+    async fn execute_reused(
+        &self,
+        mut request: Request,
+        key: PoolKey,
+        sender: Sender,
+        conn: ConnectionInfo,
+    ) -> Result<crate::Result<Response>, Request> {
+        if sender.is_closed() {
+            if let Some(recorder) = request.recorder() {
+                recorder.on_connection_dead(&request, &conn);
+            }
+            return Err(request);
+        }
+        if let Some(recorder) = request.recorder() {
+            recorder.on_connection_reused(&request, &conn);
+        }
+        if let Err(e) = self.set_default_headers(&mut request) {
+            return Ok(Err(e));
+        }
+        let head = RequestHead::new(&request);
+        let started = request.recorder().map(|recorder| {
+            recorder.on_request_start(&request);
+            request.head()
+        });
+        let dead = conn.clone();
+        match self.send_on(key, conn, sender, request).await {
+            Ok(Sent::Response(mut response)) => {
+                if let Some(started) = started.as_ref()
+                    && let Some(recorder) = started.recorder()
+                {
+                    recorder.on_response(started, &response);
+                }
+                response.extensions_mut().insert(head);
+                Ok(Ok(response))
+            }
+            Ok(Sent::Closed(request, _)) => {
+                if let Some(recorder) = request.recorder() {
+                    recorder.on_connection_dead(&request, &dead);
+                }
+                Err(*request)
+            }
+            Err(e) => Ok(Err(e)),
+        }
+    }
+
+    /// What to make of the alternatives of the origin of `request`, `None`
+    /// if it has none or the request can't use them.
+    fn alt_svc_decision(&self, request: &Request) -> Option<AltSvcDecision> {
+        let cache = self.alt_svc.as_ref()?;
+        let usable = self.proxies.get(request.uri()).is_none()
+            && request.connect_ip().is_none()
+            && request.required_protocol().is_none()
+            && request.version_policy().is_none()
+            && request.pinned_version().is_none();
+        if !usable {
+            return None;
+        }
+        cache.decide(request.uri())
+    }
+
+    /// The pool key of `request`, `None` if its connection can't be shared
+    /// with other requests.
+    fn pool_key(&self, request: &Request) -> Option<PoolKey> {
+        let shared = self.pool.is_enabled()
+            && !self.drive_in_task
+            && self.wire_capture.is_none()
+            && request.raw_head().is_none()
+            && request.header_case().is_empty()
+            && request.required_protocol().is_none()
+            && request.version_policy().is_none()
+            && request.pinned_version().is_none()
+            && !request.headers().contains_key(http::header::UPGRADE)
+            && !request.expect_continue();
+        shared.then(|| PoolKey::new(request))
+    }
+
+    fn set_default_headers(&self, request: &mut Request) -> crate::Result<()> {
+        if !self.disable_auto_set_header {
+            let host = request
+                .uri()
+                .host()
+                .ok_or(crate::Error::EmptyResolveResult)?
+                .to_string();
+            if request.headers().get(http::header::HOST).is_none() {
+                request
+                    .headers_mut()
+                    .insert(http::header::HOST, host.parse()?);
+            }
+            #[cfg(any(
+                feature = "gzip",
+                feature = "deflate",
+                feature = "brotli",
+                feature = "zstd"
+            ))]
+            if request
+                .headers()
+                .get(http::header::ACCEPT_ENCODING)
+                .is_none()
+            {
+                request.headers_mut().insert(
+                    http::header::ACCEPT_ENCODING,
+                    crate::decompress::accept_encoding(),
+                );
+            }
+            if request.headers().get(http::header::USER_AGENT).is_none() {
+                request.headers_mut().insert(http::header::USER_AGENT, HeaderValue::from_static("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/137.0.0.0 Safari/537.36"));
+            }
+        }
+        if self.request_id_header && request.headers().get(X_REQUEST_ID).is_none() {
+            let id = HeaderValue::from(request.id().as_u64());
+            request.headers_mut().insert(X_REQUEST_ID, id);
+        }
+        Ok(())
+    }
+
+    /// The tap of a new connection, if the client captures them.
+    fn connection_tap(&self, _stream: &TcpStream) -> Option<Arc<dyn Tap>> {
+        #[cfg(feature = "pcap")]
+        if let Some(writer) = self.pcap.as_ref() {
+            let local = _stream.local_addr().ok()?;
+            let peer = _stream.peer_addr().ok()?;
+            return Some(Arc::new(crate::pcap::PcapConn::new(
+                writer.clone(),
+                local,
+                peer,
+            )));
+        }
+        None
+    }
+
+    /// Open a tunnel to the host of the request, if the client has a proxy,
+    /// sending a held bearer token with `auth`. `false` if the proxy closes
+    /// the connection after asking for credentials, to send on a new one.
+    async fn proxy_connect<S>(
+        &self,
+        stream: &mut S,
+        request: &Request,
+        auth: bool,
+    ) -> crate::Result<bool>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let Some(proxy) = self.proxies.get(request.uri()) else {
+            return Ok(true);
+        };
+        let is_socks = proxy.kind() != ProxyKind::Http;
+        if let Some(recorder) = request.recorder() {
+            if is_socks {
+                recorder.on_socks_start(request, proxy.uri());
+            } else {
+                recorder.on_proxy_start(request, proxy.uri());
+            }
+        }
+
+        let host = match request.connect_ip() {
+            Some(ip) => ip.to_string(),
+            // Without the brackets of an IPv6 literal.
+            None => {
+                let host = request.uri().host().unwrap_or_default();
+                host.trim_start_matches('[')
+                    .trim_end_matches(']')
+                    .to_string()
+            }
+        };
+        let connect = async {
+            let mut ret = proxy.connect(stream, &host, request.port(), auth).await;
+            // Challenged on a connection that stays open.
+            let challenged = matches!(ret, Ok(Connect::Challenged { reusable: true }));
+            if (auth || challenged)
+                && let Some(recorder) = request.recorder()
+            {
+                recorder.on_proxy_auth_start(request, proxy.uri());
+            }
+            if challenged {
+                ret = proxy.connect(stream, &host, request.port(), true).await;
+            }
+            if (auth || challenged)
+                && let Some(recorder) = request.recorder()
+            {
+                let result = ret.as_ref().map(|_| ()).map_err(|e| e as _);
+                recorder.on_proxy_auth_done(request, proxy.uri(), result);
+            }
+            ret
+        };
+        let ret = tokio::time::timeout(self.tcp_timeout, connect)
+            .await
+            .map_err(crate::Error::from)
+            .and_then(|ret| ret);
+        let reconnect = matches!(ret, Ok(Connect::Challenged { .. }));
+        let ret = match ret {
+            Ok(Connect::Challenged { .. }) => Err(crate::Error::ProxyConnect(
+                StatusCode::PROXY_AUTHENTICATION_REQUIRED,
+            )),
+            ret => ret.map(|_| ()),
+        };
+
+        if let Some(recorder) = request.recorder() {
+            let result = ret.as_ref().map_err(|e| e as _).copied();
+            if is_socks {
+                recorder.on_socks_done(request, proxy.uri(), result);
+            } else {
+                recorder.on_proxy_done(request, proxy.uri(), result);
+            }
+        }
+        match reconnect {
+            true => Ok(false),
+            false => ret.map(|_| true),
+        }
+    }
+
+    /// Resolve the host, or the one of the proxy, and connect to it, starting
+    /// with the first answer while the other lookups complete.
+    pub(crate) async fn dns_connect(&self, request: &Request) -> crate::Result<TcpStream> {
+        let (host, port) = match self.proxies.get(request.uri()) {
+            Some(proxy) => (proxy.host(), proxy.port()),
+            None => {
+                let host = request.uri().host().ok_or(crate::Error::HostRequired)?;
+                match request.alt_svc() {
+                    Some(alt) => (alt.host.as_deref().unwrap_or(host), alt.port),
+                    None => (host, request.port()),
+                }
+            }
+        };
+        if let Some(recorder) = request.recorder() {
+            recorder.on_dns_start(request, self.resolver.name_servers(), host);
+        }
+
+        let mut lookups = Lookups {
+            request,
+            name_servers: self.resolver.name_servers(),
+            host,
+            stream: Some(self.dns_lookups(request, host)),
+            deadline: Instant::now() + self.dns_timeout,
+            info: None,
+            error: None,
+        };
+        let mut addrs = loop {
+            match lookups.next().await {
+                Some(addrs) if !addrs.is_empty() => break addrs,
+                Some(_) => continue,
+                None => return Err(lookups.error.unwrap_or(crate::Error::EmptyResolveResult)),
+            }
+        };
+        // Take the answers already there, only the slower lookups overlap.
+        while let Some(more) = lookups.next().now_or_never() {
+            match more {
+                Some(more) => addrs.extend(more),
+                None => break,
+            }
+        }
+        if let Some(rng) = self.addr_rng.as_ref() {
+            rng.lock().unwrap().shuffle(&mut addrs);
+        }
+
+        let ret = self.tcp_connect(request, addrs, port, &mut lookups).await;
+        // Connected before the other lookups completed.
+        lookups.finish();
+        ret
+    }
+
+    fn dns_lookups<'a>(
+        &'a self,
+        request: &Request,
+        host: &'a str,
+    ) -> BoxStream<'a, crate::Result<DnsInfo>> {
+        // The address of the host is for the proxy to connect to.
+        let connect_ip = request
+            .connect_ip()
+            .filter(|_| self.proxies.get(request.uri()).is_none());
+        let ips = match connect_ip {
+            Some(ip) => Some(vec![ip]),
+            None => self
+                .dns_overrides
+                .get(host)
+                .filter(|ips| !ips.is_empty())
+                .cloned(),
+        };
+        if let Some(ips) = ips {
+            let info = DnsInfo {
+                source: DnsSource::Override,
+                ..DnsInfo::new(ips)
+            };
+            return Box::pin(futures_util::stream::once(async { Ok(info) }));
+        }
+        self.resolver.resolve_partial(host)
+    }
+
+    /// Connect to the first address that accepts, trying the next one after
+    /// the connect attempt delay of the host and the ones of `lookups` as they
+    /// come.
+    async fn tcp_connect(
+        &self,
+        request: &Request,
+        addrs: Vec<IpAddr>,
+        port: u16,
+        lookups: &mut Lookups<'_>,
+    ) -> crate::Result<TcpStream> {
+        let (tx, mut rx) = tokio::sync::mpsc::channel::<(SocketAddr, crate::Result<TcpStream>)>(1);
+        let (cancel, _) = tokio::sync::broadcast::channel::<()>(1);
+
+        let mut addrs = addrs
+            .into_iter()
+            .map(|ip| SocketAddr::new(ip, port))
+            .collect::<VecDeque<_>>();
+
+        let host = lookups.host;
+        let delay = self.connect_rtt.delay(host);
+        if let Some(recorder) = request.recorder() {
+            recorder.on_connect_delay(request, delay);
+        }
+
+        let result: crate::Result<TcpStream>;
+        let mut timer = Instant::now();
+        let mut tx_opt = Some(tx);
+        let deadline = timer + self.tcp_timeout;
+        // The attempts polled by this loop with `drive_in_task`.
+        let mut attempts = FuturesUnordered::<BoxFuture<'static, ()>>::new();
+        // Every address was tried while lookups are still running.
+        let mut idle = false;
+        // The start of each attempt, to learn the connect time of the host.
+        let mut started = HashMap::new();
+
+        'outer: loop {
+            tokio::select! {
+                _ = attempts.next(), if !attempts.is_empty() => {}
+                more = lookups.next(), if !lookups.is_done() => {
+                    if let Some(mut more) = more {
+                        if let Some(rng) = self.addr_rng.as_ref() {
+                            rng.lock().unwrap().shuffle(&mut more);
+                        }
+                        addrs.extend(more.into_iter().map(|ip| SocketAddr::new(ip, port)));
+                    }
+                    if std::mem::take(&mut idle) {
+                        timer = Instant::now();
+                    }
+                }
+                _ = tokio::time::sleep_until(deadline) => {
+                    result = Err(crate::Error::TcpDeadlineExceeded);
+                    break 'outer;
+                }
+                _ = tokio::time::sleep_until(timer) => {
+                    match addrs.pop_front() {
+                        Some(addr) => {
+                            if let Some(recorder) = request.recorder() {
+                                recorder.on_tcp_start(request, &addr);
+                            }
+                            started.insert(addr, Instant::now());
+                            if let Some(tx) = tx_opt.clone() {
+                                let local_addr = self.local_addr;
+                                let prefer_ipv6 = self.prefer_ipv6;
+                                let mptcp = self.mptcp;
+                                let cancel_rx = cancel.subscribe();
+                                let attempt = async move {
+                                    let ret = Self::_tcp_connect(local_addr, addr, cancel_rx, prefer_ipv6, mptcp).await;
+                                    _ = tx.send((addr, ret)).await;
+                                };
+                                match self.drive_in_task {
+                                    true => attempts.push(Box::pin(attempt)),
+                                    false => _ = tokio::spawn(attempt),
+                                }
+                            }
+                            timer += delay;
+                        }
+                        None if !lookups.is_done() => {
+                            idle = true;
+                            timer = Instant::now() + FAR_INTERVAL;
+                        }
+                        None => {
+                            let tx = tx_opt.take();
+                            drop(tx);
+                            timer = Instant::now() + FAR_INTERVAL;
+                        }
+                    }
+                }
+                conn_ret = rx.recv() => match conn_ret {
+                    Some((addr, ret)) => {
+                        if let Some(recorder) = request.recorder() {
+                            recorder.on_tcp_done(
+                                request,
+                                &addr,
+                                ret.as_ref()
+                                    .map(|stream| TcpInfo::new(stream, addr))
+                                    .as_ref()
+                                    .map_err(|e| e as _),
+                            );
+                        }
+                        if let Ok(ret) = ret {
+                            if let Some(started) = started.get(&addr) {
+                                self.connect_rtt.update(host, started.elapsed());
+                            }
+                            result = Ok(ret);
+                            break 'outer;
+                        }
+                    }
+                    None => {
+                        result = Err(crate::Error::AllTcpConnectFailed);
+                        break 'outer;
+                    },
+                }
+            }
+        }
+        _ = cancel.send(());
+        result
+    }
+
+    pub(crate) async fn tls_handshake(
+        &self,
+        stream: CaptureIo<TcpStream>,
+        request: &Request,
+        ech: Option<EchConfigListBytes<'static>>,
+    ) -> crate::Result<(TlsStream<CaptureIo<TcpStream>>, TlsInfo)> {
+        ensure_crypto_provider();
+        if let Some(recorder) = request.recorder() {
+            let tcp = stream.get_ref();
+            recorder.on_tls_start(request, &TcpInfo::new(tcp, tcp.peer_addr()?));
+        }
+
+        let ret = self
+            ._tls_handshake(stream, request, ech)
+            .await
+            .map(|(stream, ocsp_response)| {
+                let tls = TlsInfo {
+                    ocsp_response,
+                    ..self.tls_info(&stream, request)
+                };
+                (stream, tls)
+            });
+
+        // Reported by `EarlyDataIo` once the early data is answered.
+        let early = ret
+            .as_ref()
+            .is_ok_and(|(stream, _)| stream.get_ref().1.is_handshaking());
+        if let Some(recorder) = request.recorder()
+            && let Err(crate::Error::CertificateRejected { chain, .. }) = &ret
+        {
+            recorder.on_tls_rejected_chain(request, chain);
+        }
+        if let Some(recorder) = request.recorder().filter(|_| !early) {
+            recorder.on_tls_done(
+                request,
+                ret.as_ref().map(|(_, tls)| tls).map_err(|e| e as _),
+            );
+        }
+        ret
+    }
+
+    async fn _tcp_connect(
+        local_addr: Option<IpAddr>,
+        dest: SocketAddr,
+        mut cancel_rx: tokio::sync::broadcast::Receiver<()>,
+        prefer_ipv6: bool,
+        mptcp: bool,
+    ) -> crate::Result<TcpStream> {
+        let socket = {
+            match local_addr {
+                Some(local_addr) => {
+                    let socket = tcp_socket(local_addr.is_ipv6(), mptcp)?;
+                    socket.bind(SocketAddr::new(local_addr, 0))?;
+                    socket
+                }
+                None => tcp_socket(prefer_ipv6, mptcp)?,
+            }
+        };
+
+        tokio::select! {
+            _ = cancel_rx.recv() => Err(crate::Error::TcpDeadlineExceeded),
+            stream = socket.connect(dest) => Ok(stream?),
+        }
+    }
+
+    fn tls_config(&self) -> Arc<ClientConfig> {
+        self.tls_config
+            .get()
+            .expect("built with the client")
+            .clone()
+    }
+
+    /// Build the TLS config and its verifier, once with the client so that
+    /// a bad setting fails [`ClientBuilder::build`] rather than a request.
+    fn init_tls_config(&self) -> crate::Result<()> {
+        let builder = self.tls_builder(None)?;
+        let verifier = tls_verifier(
+            self.skip_tls_verify,
+            self.skip_hostname_verify,
+            builder.crypto_provider(),
+            || self.root_store(),
+            self.handshake_crls(),
+        )?;
+        // Shared by the handshakes so sessions resume, rustls only resumes
+        // those verified by the same one.
+        let verifier: Arc<dyn ServerCertVerifier> = Arc::new(ConnectionVerifier::new(
+            verifier,
+            self.tls_capture_rejected_chain,
+        ));
+        let mut config = tls_config(
+            builder,
+            verifier.clone(),
+            self.tls_early_data,
+            self.alpn_protocols.as_deref(),
+        )?;
+        config.enable_sni = !self.no_sni;
+        config.resumption = match self.tls_session_cache {
+            Some(0) => Resumption::disabled(),
+            Some(size) => Resumption::in_memory_sessions(size),
+            None => Resumption::default(),
+        };
+        if let Some(key_log) = &self.key_log {
+            config.key_log = key_log.clone();
+        }
+        _ = self.tls_verifier.set(verifier);
+        _ = self.tls_config.set(Arc::new(config));
+        Ok(())
+    }
+
+    /// The config offering ECH with the config list `ech`, sharing its
+    /// verifier and session cache with [`tls_config`](Self::tls_config),
+    /// which is used instead if the client supports none of the list.
+    fn tls_ech_config(&self, ech: EchConfigListBytes<'static>) -> crate::Result<Arc<ClientConfig>> {
+        if let Some(config) = self.tls_ech_configs.lock().unwrap().get(&*ech) {
+            return Ok(config.clone());
+        }
+        let shared = self.tls_config();
+        let config = match EchConfig::new(ech.clone(), hpke::ALL_SUPPORTED_SUITES) {
+            Ok(config) => self.new_tls_ech_config(&shared, config)?,
+            Err(_) => shared,
+        };
+        let mut configs = self.tls_ech_configs.lock().unwrap();
+        if configs.len() >= MAX_ECH_CONFIGS {
+            configs.clear();
+        }
+        configs.insert(ech.to_vec(), config.clone());
+        Ok(config)
+    }
+
+    fn new_tls_ech_config(
+        &self,
+        shared: &ClientConfig,
+        ech: EchConfig,
+    ) -> crate::Result<Arc<ClientConfig>> {
+        let verifier = self.tls_verifier.get().expect("set with tls_config");
+        let builder = self.tls_builder(Some(EchMode::from(ech)))?;
+        let mut config = tls_config(
+            builder,
+            verifier.clone(),
+            self.tls_early_data,
+            self.alpn_protocols.as_deref(),
+        )?;
+        config.enable_sni = shared.enable_sni;
+        config.resumption = shared.resumption.clone();
+        config.key_log = shared.key_log.clone();
+        Ok(Arc::new(config))
+    }
+
+    /// The ECH config list published by the server name of an HTTPS
+    /// request, `None` without [`ClientBuilder::tls_ech`] or if none is
+    /// found.
+    async fn ech_config(&self, request: &Request) -> Option<EchConfigListBytes<'static>> {
+        if !self.tls_ech || request.uri().scheme() != Some(&http::uri::Scheme::HTTPS) {
+            return None;
+        }
+        let ServerName::DnsName(host) = server_name(request).ok()? else {
+            return None;
+        };
+        let lookup = self.resolver.ech_config_list(host.as_ref());
+        let list = tokio::time::timeout(self.dns_timeout, lookup)
+            .await
+            .ok()?
+            .ok()??;
+        Some(EchConfigListBytes::from(list))
+    }
+
+    /// Whether to send `request` as early data on a resumed session: the
+    /// protocol must be known before the server picks one, the certificate
+    /// checked before sending, and a replay harmless.
+    fn early_data(&self, request: &Request) -> bool {
+        // Not `CONNECT` either, nothing to send with a probe.
+        self.tls_early_data
+            && request.method().is_idempotent()
+            && self.revocation.is_none()
+            && self
+                .offered_alpn(request)
+                .is_none_or(|alpn| alpn.len() <= 1)
+    }
+
+    /// The roots server certificates are verified against, the built-in ones
+    /// and those added to the builder.
+    fn root_store(&self) -> crate::Result<Arc<RootCertStore>> {
+        if self.root_certificates.is_empty() && self.built_in_root_certs {
+            return root_store();
+        }
+        let mut roots = match self.built_in_root_certs {
+            true => (*root_store()?).clone(),
+            false => RootCertStore::empty(),
+        };
+        for certificate in &self.root_certificates {
+            roots.add(certificate.clone())?;
+        }
+        Ok(Arc::new(roots))
+    }
+
+    /// A TLS config with the versions, cipher suites and key exchange groups
+    /// given to the builder, or offering `ech` with TLS 1.3 only.
+    fn tls_builder(
+        &self,
+        ech: Option<EchMode>,
+    ) -> crate::Result<ConfigBuilder<ClientConfig, WantsVerifier>> {
+        ensure_crypto_provider();
+
+        let mut provider = match CryptoProvider::get_default() {
+            Some(provider) => (**provider).clone(),
+            None => rustls::crypto::ring::default_provider(),
+        };
+        if let Some(suites) = self.cipher_suites.as_ref() {
+            provider.cipher_suites = suites
+                .iter()
+                .filter_map(|suite| {
+                    let supported = provider.cipher_suites.iter();
+                    supported.copied().find(|v| v.suite() == *suite)
+                })
+                .collect();
+        }
+        if let Some(groups) = self.kx_groups.as_ref() {
+            provider.kx_groups = groups
+                .iter()
+                .filter_map(|group| {
+                    let supported = provider.kx_groups.iter();
+                    supported.copied().find(|v| v.name() == *group)
+                })
+                .collect();
+        }
+
+        let min = self.min_tls_version.map_or(0, u16::from);
+        let max = self.max_tls_version.map_or(u16::MAX, u16::from);
+        let versions: Vec<_> = rustls::ALL_VERSIONS
+            .iter()
+            .copied()
+            .filter(|v| (min..=max).contains(&u16::from(v.version)))
+            .collect();
+        let builder = ClientConfig::builder_with_provider(Arc::new(provider));
+        match ech {
+            Some(ech) => Ok(builder.with_ech(ech)?),
+            None => Ok(builder.with_protocol_versions(&versions)?),
+        }
+    }
+
+    /// The server name to send, `None` with `no_sni` or when connecting to
+    /// an IP address.
+    fn tls_sni(&self, request: &Request) -> Option<String> {
+        if self.no_sni {
+            return None;
+        }
+        if let Some(sni) = request.sni() {
+            return Some(sni.to_string());
+        }
+        request
+            .uri()
+            .host()
+            .filter(|host| host.trim_matches(['[', ']']).parse::<IpAddr>().is_err())
+            .map(|host| host.to_string())
+    }
+
+    /// The protocols offered by ALPN for the request, `None` if none.
+    fn offered_alpn(&self, request: &Request) -> Option<Vec<Alpn>> {
+        request_alpn(request).or_else(|| self.alpn_protocols.clone())
+    }
+
+    /// The revocation lists checked by the verifier of the handshake.
+    fn handshake_crls(&self) -> &[CertificateRevocationListDer<'static>] {
+        match self.revocation.as_ref() {
+            Some(checker) if self.tls_enforce_crls => checker.crls(),
+            _ => &[],
+        }
+    }
+
+    /// The facts about the handshake of `stream`.
+    fn tls_info<S>(&self, stream: &TlsStream<S>, request: &Request) -> TlsInfo {
+        let mut tls = TlsInfo::new(stream, self.tls_sni(request));
+        // Passing the verifier means no list names a certificate of the chain.
+        if !self.handshake_crls().is_empty() && !self.skip_tls_verify {
+            tls.revocation = Some(RevocationStatus::Good);
+        }
+        if self.tls_ech {
+            tls.ech = Some(stream.get_ref().1.ech_status());
+        }
+        tls
+    }
+
+    /// Check the revocation of the server certificate, if the client was
+    /// given revocation lists and doesn't check them in the handshake.
+    fn check_revocation<S>(&self, stream: &TlsStream<S>, request: &Request) -> crate::Result<()> {
+        let Some(checker) = self.revocation.as_ref().filter(|_| !self.tls_enforce_crls) else {
+            return Ok(());
+        };
+        if let Some(recorder) = request.recorder() {
+            recorder.on_revocation_start(request);
+        }
+
+        let chain = stream.get_ref().1.peer_certificates().unwrap_or_default();
+        let host = request
+            .sni()
+            .or(request.uri().host())
+            .unwrap_or_default()
+            .trim_matches(['[', ']']);
+        let ret = ServerName::try_from(host)
+            .map_err(crate::Error::from)
+            .and_then(|name| checker.check(|| self.root_store(), chain, &name));
+
+        if let Some(recorder) = request.recorder() {
+            recorder.on_revocation_done(request, ret.as_ref().copied().map_err(|e| e as _));
+        }
+        match ret? {
+            RevocationStatus::Revoked => Err(crate::Error::CertificateRevoked),
+            _ => Ok(()),
+        }
+    }
+
+    /// The handshake on `stream`, with the OCSP response stapled by the
+    /// server.
+    async fn _tls_handshake(
+        &self,
+        stream: CaptureIo<TcpStream>,
+        request: &Request,
+        ech: Option<EchConfigListBytes<'static>>,
+    ) -> crate::Result<(TlsStream<CaptureIo<TcpStream>>, Option<Vec<u8>>)> {
+        let domain = server_name(request)?;
+        let mut config = match ech {
+            Some(ech) => self.tls_ech_config(ech)?,
+            None => self.tls_config(),
+        };
+        if let Some(alpn) = request_alpn(request) {
+            let mut custom = (*config).clone();
+            custom.alpn_protocols = alpn_ids(&alpn);
+            config = Arc::new(custom);
+        }
+        let connector = TlsConnector::from(config).early_data(self.early_data(request));
+
+        let handshake = verifier::scope(connector.connect(domain, stream));
+        let (ret, verified) = tokio::time::timeout(self.tls_timeout, handshake).await?;
+        match ret {
+            Ok(tls_stream) => Ok((tls_stream, verified.ocsp_response)),
+            Err(e) => {
+                let chain = verified.rejected_chain;
+                let rustls_error = e.get_ref().and_then(|e| e.downcast_ref::<rustls::Error>());
+                match rustls_error {
+                    Some(error) if !chain.is_empty() => Err(crate::Error::CertificateRejected {
+                        error: error.clone(),
+                        chain,
+                    }),
+                    _ => Err(e.into()),
+                }
+            }
+        }
+    }
+
+    /// Send `request` on a new connection, handed to the pool under the key of
+    /// `checkin` if any.
+    async fn send_request<T>(
+        &self,
+        io: T,
+        is_h2: bool,
+        request: Request,
+        checkin: Option<(PoolKey, ConnectionInfo)>,
+    ) -> crate::Result<Response>
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        self.record_send(request, |request| async move {
+            if let Some((key, conn)) = checkin {
+                return self.send_pooled(io, is_h2, request, key, conn).await;
+            }
+            match self.wire_capture {
+                Some(limit) => {
+                    let capture = WireCapture::new(limit);
+                    let tap: Arc<dyn Tap> = Arc::new(capture.clone());
+                    let mut resp = self
+                        ._send_request(CaptureIo::new(io, Some(tap)), is_h2, request)
+                        .await?;
+                    resp.extensions_mut().insert(capture);
+                    Ok(resp)
+                }
+                None => self._send_request(io, is_h2, request).await,
+            }
+        })
+        .await
+    }
+
+    /// Send `request` over HTTP/1.1 asking to switch to h2c, the response
+    /// comes over HTTP/2 if the server agrees.
+    async fn send_h2c_upgrade<T>(&self, io: T, mut request: Request) -> crate::Result<Response>
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        h2c::set_upgrade_headers(request.headers_mut());
+        self.record_send(request, |request| async move {
+            let traced = request.recorder().map(|_| request.head());
+            if let Some(traced) = traced.as_ref()
+                && let Some(recorder) = traced.recorder()
+            {
+                recorder.on_h2c_upgrade_start(traced);
+            }
+            let ret = self.h2c_round_trip(io, request).await;
+            if let Some(traced) = traced.as_ref()
+                && let Some(recorder) = traced.recorder()
+            {
+                let switched = ret.as_ref().map(|ret| ret.is_ok());
+                recorder.on_h2c_upgrade_done(traced, switched.map_err(|e| e as _));
+                let switched = matches!(ret, Ok(Ok(_)));
+                recorder.on_negotiated(traced, &Negotiation::h2c_upgrade(switched));
+            }
+
+            let upgraded = match ret? {
+                Ok(upgraded) => upgraded,
+                Err(refused) => return Ok(refused),
+            };
+            let (mut tx, conn) = self
+                .http2_handshake(H2cIo::new(TokioIo::new(upgraded)))
+                .await?;
+            tokio::spawn(async move {
+                _ = conn.await;
+            });
+            let response = tx.send_request(h2c::stand_in()).await?;
+            Ok(Response::new(response.map(super::body::boxed)))
+        })
+        .await
+    }
+
+    /// The upgraded connection, or the response if the server didn't switch.
+    async fn h2c_round_trip<T>(
+        &self,
+        io: T,
+        request: Request,
+    ) -> crate::Result<Result<Upgraded, Response>>
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let mut response = self.send_http1(io, request).await?;
+        if response.status() != StatusCode::SWITCHING_PROTOCOLS {
+            return Ok(Err(response));
+        }
+        Ok(Ok(hyper::upgrade::on(&mut response.res).await?))
+    }
+
+    /// Send `request` with `send`, reporting it to the recorder.
+    async fn record_send<F, Fut>(&self, request: Request, send: F) -> crate::Result<Response>
+    where
+        F: FnOnce(Request) -> Fut,
+        Fut: Future<Output = crate::Result<Response>>,
+    {
+        let head = request.recorder().map(|recorder| {
+            recorder.on_request_start(&request);
+            request.head()
+        });
+
+        let resp = send(request).await?;
+
+        if let Some(head) = head.as_ref()
+            && let Some(recorder) = head.recorder()
+        {
+            recorder.on_response(head, &resp);
+        }
+        Ok(resp)
+    }
+
+    /// Open a connection driven in its own task, which goes to the pool once
+    /// `request` is sent on it.
+    async fn send_pooled<T>(
+        &self,
+        io: T,
+        is_h2: bool,
+        request: Request,
+        key: PoolKey,
+        conn: ConnectionInfo,
+    ) -> crate::Result<Response>
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let sender = if is_h2 {
+            let (tx, driver) = self.http2_handshake(io).await?;
+            tokio::spawn(async move {
+                _ = driver.await;
+            });
+            // Pooled before the first request for the next ones to share it.
+            let h2 = Http2Sender::new(tx);
+            self.pool
+                .insert(key.clone(), Sender::Http2(h2.clone()), conn.clone());
+            Sender::Http2(h2)
+        } else {
+            let (tx, conn) = hyper::client::conn::http1::Builder::new()
+                .title_case_headers(self.title_case_headers)
+                .handshake(TokioIo::new(io))
+                .await?;
             tokio::spawn(async move {
                 _ = conn.await;
             });
-            tx.send_request(request.try_into()?).await?
+            Sender::Http1(tx)
+        };
+        match self.send_on(key, conn, sender, request).await? {
+            Sent::Response(response) => Ok(response),
+            Sent::Closed(_, e) => Err(e.into()),
+        }
+    }
+
+    async fn send_on(
+        &self,
+        key: PoolKey,
+        conn: ConnectionInfo,
+        sender: Sender,
+        mut request: Request,
+    ) -> crate::Result<Sent> {
+        let head = request.recorder().map(|_| request.head());
+        let has_body = request.body().is_some();
+        let attempt = request.take_attempt().try_into()?;
+        let (stream_id, send) = self.pool.send(key, conn.clone(), sender, attempt);
+        if let Some(head) = head.as_ref()
+            && let Some(recorder) = head.recorder()
+            && let Some(stream_id) = stream_id
+        {
+            recorder.on_http2_stream(head, stream_id);
+        }
+        let resp = match send.await {
+            Ok(resp) => resp,
+            Err(mut e) => {
+                let attempt = e.take_message();
+                let error = e.into_error();
+                match attempt {
+                    Some(attempt) if has_body => *request.body_mut() = Some(attempt.into_body()),
+                    Some(_) => {}
+                    // Written, but the connection closed before any response:
+                    // only safe to send again without a body to replay.
+                    None if !has_body
+                        && request.method().is_idempotent()
+                        && (error.is_incomplete_message() || error.is_canceled()) => {}
+                    None => return Err(error.into()),
+                }
+                return Ok(Sent::Closed(Box::new(request), error));
+            }
+        };
+        let mut resp = Response::new(resp.map(super::body::boxed));
+        resp.extensions_mut().insert(conn);
+        Ok(Sent::Response(resp))
+    }
+
+    async fn _send_request<T>(
+        &self,
+        io: T,
+        is_h2: bool,
+        request: Request,
+    ) -> crate::Result<Response>
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        if is_h2 {
+            if request.raw_head().is_some() {
+                return Err(crate::Error::RawHeadNotHttp1);
+            }
+            if let Some(recorder) = request.recorder() {
+                recorder.on_http2_stream(&request, 1);
+            }
+            let (mut tx, conn) = self.http2_handshake(io).await?;
+            self.drive(conn, tx.send_request(request.try_into()?)).await
+        } else if let Some(head) = request.raw_head() {
+            let io = HeadIo::raw(io, head.clone());
+            self.send_http1(io, request).await
+        } else if !request.header_case().is_empty() {
+            let io = HeadIo::header_case(io, request.header_case());
+            self.send_http1(io, request).await
         } else {
-            let (mut tx, conn) =
-                hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+            self.send_http1(io, request).await
+        }
+    }
+
+    async fn http2_handshake<T>(
+        &self,
+        io: T,
+    ) -> hyper::Result<(
+        http2::SendRequest<Body>,
+        http2::Connection<TokioIo<T>, Body, TokioExecutor>,
+    )>
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let settings = &self.http2;
+        http2::Builder::new(TokioExecutor::new())
+            .initial_stream_window_size(settings.initial_stream_window_size)
+            .initial_connection_window_size(settings.initial_connection_window_size)
+            .adaptive_window(settings.adaptive_window)
+            .max_concurrent_streams(settings.max_concurrent_streams)
+            .max_frame_size(settings.max_frame_size)
+            .handshake(TokioIo::new(io))
+            .await
+    }
+
+    async fn send_http1<T>(&self, io: T, request: Request) -> crate::Result<Response>
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        if request.expect_continue() && request.body().is_some() {
+            let head = request.recorder().map(|_| request.head());
+            let mut request = http::Request::try_from(request)?;
+            let body = std::mem::take(request.body_mut());
+            let (io, body, guard) = expect_continue::wrap(io, body, head);
+            *request.body_mut() = Body::wrap(body);
+            let response = self.http1_round_trip(io, request).await?;
+            let res = response
+                .res
+                .map(|body| crate::body::boxed(expect_continue::guarded(body, guard)));
+            return Ok(Response::new(res));
+        }
+        self.http1_round_trip(io, request.try_into()?).await
+    }
+
+    async fn http1_round_trip<T>(
+        &self,
+        io: T,
+        request: http::Request<Body>,
+    ) -> crate::Result<Response>
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let (mut tx, conn) = hyper::client::conn::http1::Builder::new()
+            .title_case_headers(self.title_case_headers)
+            .handshake(TokioIo::new(io))
+            .await?;
+        self.drive(conn.with_upgrades(), tx.send_request(request))
+            .await
+    }
+
+    /// Wait for the response while the connection is driven, in a spawned
+    /// task or, with `drive_in_task`, alongside the request and then its body.
+    async fn drive<C, F>(&self, conn: C, send: F) -> crate::Result<Response>
+    where
+        C: Future<Output = hyper::Result<()>> + Send + 'static,
+        F: Future<Output = hyper::Result<http::Response<hyper::body::Incoming>>>,
+    {
+        if !self.drive_in_task {
             tokio::spawn(async move {
                 _ = conn.await;
             });
-            tx.send_request(request.try_into()?).await?
+            return Ok(Response::new(send.await?.map(super::body::boxed)));
+        }
+
+        let mut conn = Box::pin(conn);
+        tokio::pin!(send);
+        let resp = tokio::select! {
+            resp = &mut send => resp?,
+            // The request fails along, unless the connection closed cleanly
+            // after the response was written.
+            ret = &mut conn => {
+                ret?;
+                return Ok(Response::new(send.await?.map(super::body::boxed)));
+            }
         };
+        // Without a body to drive it along, the connection must run until it
+        // hands over the upgraded stream.
+        if resp.status() == StatusCode::SWITCHING_PROTOCOLS {
+            tokio::spawn(async move {
+                _ = conn.await;
+            });
+            return Ok(Response::new(resp.map(super::body::boxed)));
+        }
+        Ok(Response::new(resp.map(|body| {
+            super::body::boxed(super::body::driven(body, conn))
+        })))
+    }
+}
+
+/// The smoothed connect time of the hosts connected to, pacing the attempts
+/// to their addresses.
+#[derive(Clone, Debug)]
+struct ConnectRtt {
+    default_delay: Duration,
+    hosts: Arc<Mutex<HashMap<String, Duration>>>,
+}
+
+impl ConnectRtt {
+    fn new(default_delay: Duration) -> Self {
+        Self {
+            default_delay,
+            hosts: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn delay(&self, host: &str) -> Duration {
+        match self.hosts.lock().unwrap().get(host) {
+            Some(rtt) => (*rtt * 2)
+                .max(MIN_CONNECT_ATTEMPT_DELAY)
+                .min(self.default_delay),
+            None => self.default_delay,
+        }
+    }
+
+    fn update(&self, host: &str, sample: Duration) {
+        let mut hosts = self.hosts.lock().unwrap();
+        match hosts.get_mut(host) {
+            // As TCP smooths its RTT, RFC 6298.
+            Some(rtt) => *rtt = *rtt * 7 / 8 + sample / 8,
+            None => {
+                if hosts.len() >= MAX_CONNECT_RTT_HOSTS {
+                    hosts.clear();
+                }
+                hosts.insert(host.to_string(), sample);
+            }
+        }
+    }
+}
+
+/// The lookups of a host, possibly still running while connecting.
+struct Lookups<'a> {
+    request: &'a Request,
+    name_servers: &'a [NameServerConfig],
+    host: &'a str,
+    // `None` once done and reported.
+    stream: Option<BoxStream<'a, crate::Result<DnsInfo>>>,
+    deadline: Instant,
+    // The answers so far, merged.
+    info: Option<DnsInfo>,
+    // The first failed lookup.
+    error: Option<crate::Error>,
+}
+
+impl Lookups<'_> {
+    fn is_done(&self) -> bool {
+        self.stream.is_none()
+    }
+
+    /// The addresses of the next completed lookup, `None` once all are done
+    /// or the DNS timeout expired.
+    async fn next(&mut self) -> Option<Vec<IpAddr>> {
+        let stream = self.stream.as_mut()?;
+        match tokio::time::timeout_at(self.deadline, stream.next()).await {
+            Ok(Some(Ok(info))) => {
+                let addrs = info.addrs.clone();
+                match self.info.as_mut() {
+                    Some(merged) => merged.merge(info),
+                    None => self.info = Some(info),
+                }
+                Some(addrs)
+            }
+            Ok(Some(Err(e))) => {
+                self.error.get_or_insert(e);
+                Some(Vec::new())
+            }
+            Ok(None) => {
+                self.finish();
+                None
+            }
+            Err(e) => {
+                self.error.get_or_insert(e.into());
+                self.finish();
+                None
+            }
+        }
+    }
 
-        Ok(Response::new(resp.map(super::body::boxed)))
+    /// Stop the lookups still running and report the answers so far.
+    fn finish(&mut self) {
+        if self.stream.take().is_none() {
+            return;
+        }
+        let Some(recorder) = self.request.recorder() else {
+            return;
+        };
+        let empty = crate::Error::EmptyResolveResult;
+        let result = match (self.info.as_ref(), self.error.as_ref()) {
+            (Some(info), _) if !info.addrs.is_empty() => Ok(info),
+            (_, Some(e)) => Err(e),
+            _ => Err(&empty),
+        };
+        recorder.on_dns_done(
+            self.request,
+            self.name_servers,
+            self.host,
+            result.map_err(|e| e as _),
+        );
     }
 }
 
@@ -523,7 +2443,236 @@ impl std::fmt::Display for Alpn {
     }
 }
 
-fn ensure_crypto_provider() {
+/// Which protocols to offer, and so which one to fall back to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VersionPolicy {
+    /// Offer h2, falling back to HTTP/1.1 if the server doesn't pick it.
+    PreferHttp2,
+    /// Only offer HTTP/1.1.
+    Http1Only,
+}
+
+impl VersionPolicy {
+    fn alpn_protocols(self) -> Vec<Alpn> {
+        match self {
+            VersionPolicy::PreferHttp2 => vec![Alpn::Http2, Alpn::Http1],
+            VersionPolicy::Http1Only => vec![Alpn::Http1],
+        }
+    }
+}
+
+/// An IPv6 socket if `v6`, MPTCP if asked and the kernel supports it.
+fn tcp_socket(v6: bool, _mptcp: bool) -> std::io::Result<TcpSocket> {
+    #[cfg(target_os = "linux")]
+    if _mptcp {
+        use socket2::{Domain, Protocol, Socket, Type};
+
+        let domain = if v6 { Domain::IPV6 } else { Domain::IPV4 };
+        // Plain TCP without MPTCP support.
+        if let Ok(socket) = Socket::new(domain, Type::STREAM, Some(Protocol::MPTCP)) {
+            socket.set_nonblocking(true)?;
+            return Ok(TcpSocket::from_std_stream(socket.into()));
+        }
+    }
+    match v6 {
+        true => TcpSocket::new_v6(),
+        false => TcpSocket::new_v4(),
+    }
+}
+
+/// The name the server of `request` is sent and verified against.
+fn server_name(request: &Request) -> crate::Result<ServerName<'static>> {
+    let name = match request.sni() {
+        Some(sni) => sni,
+        None => request.uri().host().unwrap_or_default(),
+    };
+    Ok(name.to_string().try_into()?)
+}
+
+fn alpn_ids(alpn: &[Alpn]) -> Vec<Vec<u8>> {
+    alpn.iter().map(|v| v.to_string().into_bytes()).collect()
+}
+
+/// The copy of `request` to send over HTTP/1.1 if HTTP/2 refuses it, `None`
+/// if it must be sent with a given protocol.
+fn h2_fallback(request: &Request) -> Option<Request> {
+    if request.pinned_version().is_some() || request.required_protocol().is_some() {
+        return None;
+    }
+    request.downgraded()
+}
+
+/// Whether `e` is an HTTP/2 failure before the response, the server not
+/// having processed the request: a refused stream or HTTP/1.1 required. A
+/// protocol error or a `GOAWAY` may follow the processing, only `method`
+/// requests that are idempotent are sent again then.
+fn is_h2_refusal(e: &crate::Error, method: &Method) -> bool {
+    let crate::Error::Hyper(e) = e else {
+        return false;
+    };
+    let source = std::error::Error::source(e);
+    let Some(e) = source.and_then(|e| e.downcast_ref::<h2::Error>()) else {
+        return false;
+    };
+    let refused = e.reason().is_some_and(|reason| {
+        [h2::Reason::REFUSED_STREAM, h2::Reason::HTTP_1_1_REQUIRED].contains(&reason)
+    });
+    refused
+        || method.is_idempotent()
+            && (e.is_go_away() || e.reason() == Some(h2::Reason::PROTOCOL_ERROR))
+}
+
+/// The protocol of the version pinned by [`RequestBuilder::version`], if any,
+/// else HTTP/1.1 for the requests only sent as such: with a raw head, header
+/// names with case or `Expect: 100-continue`.
+fn pinned_alpn(request: &Request) -> crate::Result<Option<Alpn>> {
+    match request.pinned_version() {
+        None if request.raw_head().is_some()
+            || !request.header_case().is_empty()
+            || request.expect_continue() =>
+        {
+            Ok(Some(Alpn::Http1))
+        }
+        None => Ok(None),
+        Some(Version::HTTP_09 | Version::HTTP_10 | Version::HTTP_11) => Ok(Some(Alpn::Http1)),
+        Some(Version::HTTP_2) => Ok(Some(Alpn::Http2)),
+        Some(version) => Err(crate::Error::UnsupportedVersion(version)),
+    }
+}
+
+/// The protocols to offer instead of the client's: the pinned one, else
+/// the one of the alternative service, else those of the version policy.
+fn request_alpn(request: &Request) -> Option<Vec<Alpn>> {
+    match pinned_alpn(request) {
+        Ok(Some(alpn)) => Some(vec![alpn]),
+        _ => match request.alt_svc() {
+            Some(alt) => Some(vec![alt_svc_alpn(alt)]),
+            None => request.version_policy().map(VersionPolicy::alpn_protocols),
+        },
+    }
+}
+
+/// The protocol to offer an alternative service, which is a supported one.
+fn alt_svc_alpn(alt: &AltSvc) -> Alpn {
+    match alt.protocol.as_str() {
+        "h2" => Alpn::Http2,
+        _ => Alpn::Http1,
+    }
+}
+
+/// Fail unless the negotiated protocol is the one the request requires or
+/// pinned, no ALPN meaning HTTP/1.1.
+fn check_protocol(request: &Request, alpn: Option<&[u8]>) -> crate::Result<()> {
+    let Some(required) = request
+        .required_protocol()
+        .cloned()
+        .or(pinned_alpn(request)?)
+    else {
+        return Ok(());
+    };
+    let required = required.to_string();
+    let negotiated = alpn.map_or("http/1.1".into(), String::from_utf8_lossy);
+    if required != negotiated {
+        return Err(crate::Error::ProtocolNotNegotiated {
+            required,
+            negotiated: negotiated.into_owned(),
+        });
+    }
+    Ok(())
+}
+
+/// The verifier of server certificates, the roots are not needed without
+/// verification.
+fn tls_verifier(
+    skip_verify: bool,
+    skip_hostname_verify: bool,
+    provider: &Arc<CryptoProvider>,
+    roots: impl FnOnce() -> crate::Result<Arc<RootCertStore>>,
+    crls: &[CertificateRevocationListDer<'static>],
+) -> crate::Result<Arc<dyn ServerCertVerifier>> {
+    let webpki = || -> crate::Result<_> {
+        let builder = WebPkiServerVerifier::builder_with_provider(roots()?, provider.clone())
+            .with_crls(crls.iter().cloned());
+        Ok(builder
+            .build()
+            .map_err(|e| rustls::Error::General(e.to_string()))?)
+    };
+    let verifier: Arc<dyn ServerCertVerifier> = match skip_verify {
+        true => Arc::new(SkipVerifier),
+        false if skip_hostname_verify => Arc::new(SkipHostnameVerifier::new(webpki()?)),
+        false => webpki()?,
+    };
+    Ok(verifier)
+}
+
+/// The TLS config of a client, built once as loading the root certificates
+/// takes a while.
+fn tls_config(
+    builder: ConfigBuilder<ClientConfig, WantsVerifier>,
+    verifier: Arc<dyn ServerCertVerifier>,
+    early_data: bool,
+    alpn_protocols: Option<&[Alpn]>,
+) -> crate::Result<ClientConfig> {
+    let mut config = builder
+        .dangerous()
+        .with_custom_certificate_verifier(verifier)
+        .with_no_client_auth();
+
+    // Set ALPN protocols
+    if let Some(alpn) = alpn_protocols {
+        config.alpn_protocols = alpn_ids(alpn);
+    }
+    config.enable_early_data = early_data;
+
+    Ok(config)
+}
+
+/// The bundled Mozilla roots, used instead of the platform store with the
+/// `webpki-roots` feature.
+#[cfg(feature = "webpki-roots")]
+fn root_store() -> crate::Result<Arc<RootCertStore>> {
+    static ROOTS: OnceLock<Arc<RootCertStore>> = OnceLock::new();
+    let roots = ROOTS.get_or_init(|| {
+        Arc::new(RootCertStore::from_iter(
+            webpki_roots::TLS_SERVER_ROOTS.iter().cloned(),
+        ))
+    });
+    Ok(roots.clone())
+}
+
+/// The roots of the platform store, loaded on first use and kept for the
+/// lifetime of the process.
+///
+/// Certificates that fail to parse are skipped, it's an error only if none
+/// is usable.
+#[cfg(not(feature = "webpki-roots"))]
+fn root_store() -> crate::Result<Arc<RootCertStore>> {
+    static ROOTS: OnceLock<Result<Arc<RootCertStore>, String>> = OnceLock::new();
+    let roots = ROOTS.get_or_init(|| native_root_store(rustls_native_certs::load_native_certs()));
+    roots.clone().map_err(crate::Error::NativeCerts)
+}
+
+#[cfg(not(feature = "webpki-roots"))]
+fn native_root_store(
+    native: rustls_native_certs::CertificateResult,
+) -> Result<Arc<RootCertStore>, String> {
+    let mut root_store = RootCertStore::empty();
+    root_store.add_parsable_certificates(native.certs);
+    if !root_store.is_empty() {
+        return Ok(Arc::new(root_store));
+    }
+    match native.errors.is_empty() {
+        true => Err("the platform store is empty".to_string()),
+        false => Err(native
+            .errors
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>()
+            .join("; ")),
+    }
+}
+
+pub(crate) fn ensure_crypto_provider() {
     INIT.call_once(|| {
         let _ = tokio_rustls::rustls::crypto::ring::default_provider().install_default();
     });
@@ -531,9 +2680,40 @@ fn ensure_crypto_provider() {
 
 #[cfg(test)]
 mod tests {
-    use std::time::Duration;
-
-    use tokio::time::Instant;
+    use std::{sync::Arc, time::Duration};
+
+    use rustls::{CipherSuite, NamedGroup, ProtocolVersion};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        time::Instant,
+    };
+
+    use super::{Alpn, Client, ClientBuilder, ConnectRtt, VersionPolicy};
+    use crate::{
+        aggregate::StatsAggregate,
+        stats::StatsRecorder,
+        test_util::{
+            MockLookup, MockResolver, MockResponse, MockTransport, TestServer, self_signed_acceptor,
+        },
+    };
+
+    #[test]
+    fn test_connect_rtt() {
+        let rtt = ConnectRtt::new(Duration::from_secs(3));
+        assert_eq!(rtt.delay("a.test"), Duration::from_secs(3));
+
+        rtt.update("a.test", Duration::from_millis(200));
+        assert_eq!(rtt.delay("a.test"), Duration::from_millis(400));
+        rtt.update("a.test", Duration::from_millis(600));
+        assert_eq!(rtt.delay("a.test"), Duration::from_millis(500));
+
+        // Bounded by the minimum and the default.
+        rtt.update("b.test", Duration::from_millis(1));
+        assert_eq!(rtt.delay("b.test"), Duration::from_millis(100));
+        rtt.update("c.test", Duration::from_secs(10));
+        assert_eq!(rtt.delay("c.test"), Duration::from_secs(3));
+    }
 
     #[tokio::test]
     async fn test_worker() {
@@ -573,4 +2753,494 @@ mod tests {
             }
         }
     }
+
+    #[tokio::test]
+    async fn test_request_id_header() {
+        let mock = MockTransport::new();
+        mock.push(MockResponse::new(200))
+            .push(MockResponse::new(200));
+        let client = Client::builder()
+            .mock_transport(mock.clone())
+            .request_id_header()
+            .build()
+            .unwrap();
+
+        let request = client.get("https://example.com").build().unwrap();
+        let id = request.id();
+        client.execute(request).await.unwrap();
+        client
+            .get("https://example.com")
+            .header("x-request-id", "custom")
+            .send()
+            .await
+            .unwrap();
+
+        let requests = mock.requests();
+        assert_eq!(requests[0].headers["x-request-id"], id.to_string());
+        assert_eq!(requests[1].headers["x-request-id"], "custom");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_blocked_stats() {
+        let mock = MockTransport::new();
+        mock.push(MockResponse::new(200).response_delay(Duration::from_millis(30)));
+        mock.push(MockResponse::new(200).response_delay(Duration::from_millis(30)));
+        let client = Client::builder()
+            .mock_transport(mock)
+            .max_concurrent_requests(1)
+            .build()
+            .unwrap();
+
+        let recorder = StatsRecorder::new();
+        let request = || {
+            let mut request = client.get("https://example.com").build().unwrap();
+            *request.recorder_mut() = Some(Arc::new(recorder.clone()));
+            request
+        };
+        let (a, b) = (request(), request());
+        let (a_id, b_id) = (a.id(), b.id());
+        // The slot is held until the first response is dropped.
+        tokio::join!(
+            async {
+                let response = client.execute(a).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                drop(response);
+            },
+            async { client.execute(b).await.unwrap() },
+        );
+
+        assert!(recorder.finish_for(a_id).unwrap().blocked_stats.is_none());
+        let stats = recorder.finish_for(b_id).unwrap();
+        assert_eq!(
+            stats.blocked_stats.unwrap().duration,
+            Duration::from_millis(50)
+        );
+        assert_eq!(stats.total_duration, Duration::from_millis(80));
+
+        // No request would ever get a slot.
+        let err = Client::builder()
+            .max_concurrent_requests(0)
+            .build()
+            .unwrap_err();
+        assert!(matches!(err, crate::Error::InvalidConfig(_)));
+    }
+
+    #[tokio::test]
+    async fn test_h2_fallback() {
+        let (acceptor, _) = self_signed_acceptor().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let mut stream = acceptor.accept(stream).await.unwrap();
+                    if stream.get_ref().1.alpn_protocol() == Some(b"h2") {
+                        let mut preface = [0; 24];
+                        stream.read_exact(&mut preface).await.unwrap();
+                        // SETTINGS, then GOAWAY before any stream.
+                        stream
+                            .write_all(&[0, 0, 0, 4, 0, 0, 0, 0, 0])
+                            .await
+                            .unwrap();
+                        let goaway = [0, 0, 8, 7, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+                        stream.write_all(&goaway).await.unwrap();
+                        stream.flush().await.unwrap();
+                        _ = stream.read_to_end(&mut Vec::new()).await;
+                        return;
+                    }
+                    let mut head = Vec::new();
+                    while !head.ends_with(b"\r\n\r\n") {
+                        head.push(stream.read_u8().await.unwrap());
+                    }
+                    stream
+                        .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok")
+                        .await
+                        .unwrap();
+                    stream.flush().await.unwrap();
+                });
+            }
+        });
+
+        let client = Client::builder().skip_tls_verify().build().unwrap();
+        let recorder = StatsRecorder::new();
+        let response = client
+            .get(format!("https://{addr}/"))
+            .recorder(Box::new(recorder.clone()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.version(), http::Version::HTTP_11);
+        assert_eq!(response.text().await.unwrap(), "ok");
+        assert!(recorder.finish().h2_fallback.is_some());
+
+        // Not after a GOAWAY for a request that isn't idempotent.
+        let err = client
+            .post(format!("https://{addr}/"))
+            .send()
+            .await
+            .unwrap_err();
+        assert!(matches!(err, crate::Error::Hyper(_)));
+
+        // Not when the request asks for HTTP/2.
+        let err = client
+            .get(format!("https://{addr}/"))
+            .version(http::Version::HTTP_2)
+            .send()
+            .await
+            .unwrap_err();
+        assert!(matches!(err, crate::Error::Hyper(_)));
+    }
+
+    #[tokio::test]
+    async fn test_sni() {
+        let (acceptor, _) = self_signed_acceptor().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let mut names = Vec::new();
+            for _ in 0..2 {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut stream = acceptor.accept(stream).await.unwrap();
+                names.push(stream.get_ref().1.server_name().map(str::to_string));
+                let mut head = Vec::new();
+                while !head.ends_with(b"\r\n\r\n") {
+                    head.push(stream.read_u8().await.unwrap());
+                }
+                stream
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                    .await
+                    .unwrap();
+                stream.flush().await.unwrap();
+            }
+            names
+        });
+        let url = format!("https://localhost:{}/", addr.port());
+        let send = |client: Client, sni: Option<&'static str>| {
+            let url = url.clone();
+            async move {
+                let mut request = client.get(url).version(http::Version::HTTP_11);
+                if let Some(sni) = sni {
+                    request = request.sni(sni);
+                }
+                let response = request.send().await.unwrap();
+                response.connection_info().unwrap().tls.clone().unwrap().sni
+            }
+        };
+
+        let client = Client::builder().skip_tls_verify().build().unwrap();
+        assert_eq!(
+            send(client, Some("cdn.test")).await.as_deref(),
+            Some("cdn.test")
+        );
+        let client = Client::builder()
+            .skip_tls_verify()
+            .no_sni()
+            .build()
+            .unwrap();
+        assert_eq!(send(client, None).await, None);
+        assert_eq!(server.await.unwrap(), [Some("cdn.test".to_string()), None]);
+    }
+
+    #[tokio::test]
+    async fn test_add_root_certificate() {
+        let server = TestServer::builder().tls().start().await.unwrap();
+        let url = format!("https://localhost:{}/", server.addr().port());
+
+        let client = Client::builder()
+            .add_root_certificate(server.certificate().unwrap().clone())
+            .tls_built_in_root_certs(false)
+            .build()
+            .unwrap();
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+
+        // Unknown to the built-in roots.
+        let client = Client::builder().build().unwrap();
+        let err = client.get(&url).send().await.unwrap_err();
+        assert!(matches!(err, crate::Error::Io(_) | crate::Error::Rustls(_)));
+
+        // Nothing to trust fails the build rather than the request.
+        let err = Client::builder()
+            .tls_built_in_root_certs(false)
+            .build()
+            .unwrap_err();
+        assert!(matches!(err, crate::Error::Rustls(_)));
+    }
+
+    #[tokio::test]
+    async fn test_tls_version_bounds() {
+        let server = TestServer::builder().tls().start().await.unwrap();
+        let url = format!("https://localhost:{}/", server.addr().port());
+        let negotiated = |builder: ClientBuilder| {
+            let url = url.clone();
+            async move {
+                let client = builder.skip_tls_verify().build()?;
+                let recorder = StatsRecorder::new();
+                client
+                    .get(url)
+                    .recorder(Box::new(recorder.clone()))
+                    .send()
+                    .await?;
+                Ok::<_, crate::Error>(recorder.finish().tls_info.unwrap().protocol_version)
+            }
+        };
+
+        let max_12 = Client::builder().max_tls_version(ProtocolVersion::TLSv1_2);
+        assert_eq!(
+            negotiated(max_12).await.unwrap(),
+            Some(ProtocolVersion::TLSv1_2)
+        );
+        let min_13 = Client::builder().min_tls_version(ProtocolVersion::TLSv1_3);
+        assert_eq!(
+            negotiated(min_13).await.unwrap(),
+            Some(ProtocolVersion::TLSv1_3)
+        );
+
+        let none = Client::builder()
+            .min_tls_version(ProtocolVersion::TLSv1_3)
+            .max_tls_version(ProtocolVersion::TLSv1_2);
+        assert!(negotiated(none).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tls_cipher_suites() {
+        let server = TestServer::builder().tls().start().await.unwrap();
+        let url = format!("https://localhost:{}/", server.addr().port());
+
+        let client = Client::builder()
+            .skip_tls_verify()
+            .tls_cipher_suites(vec![CipherSuite::TLS13_CHACHA20_POLY1305_SHA256])
+            .tls_kx_groups(vec![NamedGroup::secp256r1])
+            .build()
+            .unwrap();
+        let response = client.get(&url).send().await.unwrap();
+        let tls = response.connection_info().unwrap().tls.clone().unwrap();
+        assert_eq!(
+            tls.cipher_suite,
+            Some(CipherSuite::TLS13_CHACHA20_POLY1305_SHA256)
+        );
+        assert_eq!(tls.kx_group, Some(NamedGroup::secp256r1));
+
+        // Not implemented by the provider.
+        let client = Client::builder()
+            .skip_tls_verify()
+            .tls_cipher_suites(vec![CipherSuite::TLS_RSA_WITH_AES_128_CBC_SHA])
+            .build();
+        assert!(client.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_danger_accept_invalid_hostnames() {
+        let server = TestServer::builder().tls().start().await.unwrap();
+        let url = format!("https://other.test:{}/", server.addr().port());
+        let builder = || {
+            Client::builder()
+                .tls_built_in_root_certs(false)
+                .resolve_to_addrs("other.test", &[server.addr().ip()])
+        };
+        let trusted = || builder().add_root_certificate(server.certificate().unwrap().clone());
+
+        let client = trusted().build().unwrap();
+        let err = client.get(&url).send().await.unwrap_err();
+        assert!(matches!(err, crate::Error::Io(_) | crate::Error::Rustls(_)));
+
+        let client = trusted()
+            .danger_accept_invalid_hostnames(true)
+            .build()
+            .unwrap();
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+
+        // The chain is still verified.
+        let other = TestServer::builder().tls().start().await.unwrap();
+        let client = builder()
+            .add_root_certificate(other.certificate().unwrap().clone())
+            .danger_accept_invalid_hostnames(true)
+            .build()
+            .unwrap();
+        let err = client.get(&url).send().await.unwrap_err();
+        assert!(matches!(err, crate::Error::Io(_) | crate::Error::Rustls(_)));
+    }
+
+    #[tokio::test]
+    async fn test_tls_session_cache() {
+        let server = TestServer::builder().tls().start().await.unwrap();
+        let url = format!("https://localhost:{}/", server.addr().port());
+        let resumed = |builder: ClientBuilder| {
+            let url = url.clone();
+            async move {
+                let client = builder.skip_tls_verify().build().unwrap();
+                let mut aggregate = StatsAggregate::new();
+                let mut resumed = Vec::new();
+                for _ in 0..2 {
+                    let recorder = StatsRecorder::new();
+                    let response = client
+                        .get(&url)
+                        .recorder(Box::new(recorder.clone()))
+                        .send()
+                        .await
+                        .unwrap();
+                    response.text().await.unwrap();
+                    let stats = recorder.finish();
+                    aggregate.add(&stats);
+                    resumed.push(stats.tls_info.unwrap().resumed);
+                }
+                assert_eq!(aggregate.tls.count(), 2);
+                (resumed, aggregate.tls_resumed.count())
+            }
+        };
+
+        assert_eq!(resumed(Client::builder()).await, (vec![false, true], 1));
+        // The verifier capturing the chain is shared by the handshakes too.
+        let capture = Client::builder().tls_capture_rejected_chain(true);
+        assert_eq!(resumed(capture).await, (vec![false, true], 1));
+        let disabled = Client::builder().tls_session_cache(0);
+        assert_eq!(resumed(disabled).await, (vec![false, false], 0));
+    }
+
+    #[tokio::test]
+    async fn test_tls_ech() {
+        // An ECHConfigList of one draft-18 config: X25519, HKDF-SHA256 and
+        // AES-128-GCM, public name `localhost`.
+        let mut config = vec![0x01, 0x00, 0x20, 0x00, 0x20];
+        config.extend([0x42; 32]);
+        config.extend([0x00, 0x04, 0x00, 0x01, 0x00, 0x01, 0x00, 0x09]);
+        config.extend(b"localhost");
+        config.extend([0x00, 0x00]);
+        let mut list = vec![0xfe, 0x0d, 0x00, config.len() as u8];
+        list.extend(config);
+        list.splice(0..0, [0x00, list.len() as u8]);
+
+        let server = TestServer::builder().tls().start().await.unwrap();
+        let url = format!("https://localhost:{}/", server.addr().port());
+        let client = |resolver: MockResolver, ech| {
+            for _ in 0..2 {
+                let lookup = MockLookup::addrs(&["127.0.0.1".parse().unwrap()]);
+                resolver.push("localhost", lookup);
+            }
+            Client::builder()
+                .dns_resolver(resolver)
+                .add_root_certificate(server.certificate().unwrap().clone())
+                .tls_built_in_root_certs(false)
+                .tls_ech(ech)
+                .build()
+                .unwrap()
+        };
+
+        // The test server doesn't support ECH.
+        let resolver = MockResolver::new();
+        resolver.publish_ech("localhost", list.clone());
+        let ech_client = client(resolver, true);
+        // Again with the config kept for the list.
+        for _ in 0..2 {
+            let err = ech_client.probe_certificate(&url).await.unwrap_err();
+            let crate::Error::Io(e) = err else {
+                panic!("{err:?}");
+            };
+            let e = e.get_ref().and_then(|e| e.downcast_ref::<rustls::Error>());
+            assert!(matches!(
+                e,
+                Some(rustls::Error::PeerIncompatible(
+                    rustls::PeerIncompatible::ServerRejectedEncryptedClientHello(None)
+                ))
+            ));
+        }
+
+        let probe = client(MockResolver::new(), true)
+            .probe_certificate(&url)
+            .await
+            .unwrap();
+        assert_eq!(probe.tls.ech, Some(rustls::client::EchStatus::NotOffered));
+
+        let resolver = MockResolver::new();
+        resolver.publish_ech("localhost", list);
+        let probe = client(resolver, false)
+            .probe_certificate(&url)
+            .await
+            .unwrap();
+        assert_eq!(probe.tls.ech, None);
+    }
+
+    #[cfg(not(feature = "webpki-roots"))]
+    #[test]
+    fn test_native_root_store() {
+        use rustls_native_certs::{CertificateResult, Error, ErrorKind};
+
+        let error = || Error {
+            context: "failed to read PEM from file",
+            kind: ErrorKind::Io {
+                inner: std::io::ErrorKind::PermissionDenied.into(),
+                path: "/etc/ssl/cert.pem".into(),
+            },
+        };
+        let (_, certificate) = self_signed_acceptor().unwrap();
+
+        let err = super::native_root_store(CertificateResult::default()).unwrap_err();
+        assert_eq!(err, "the platform store is empty");
+        let mut native = CertificateResult::default();
+        native.certs.push(b"garbage".to_vec().into());
+        native.errors.extend([error(), error()]);
+        let err = super::native_root_store(native).unwrap_err();
+        assert_eq!(err.matches("/etc/ssl/cert.pem").count(), 2);
+        assert_eq!(
+            crate::Error::NativeCerts(err)
+                .to_string()
+                .split(": ")
+                .next(),
+            Some("no native root certificates")
+        );
+
+        // The errors don't matter as long as a root is usable.
+        let mut native = CertificateResult::default();
+        native
+            .certs
+            .extend([b"garbage".to_vec().into(), certificate]);
+        native.errors.push(error());
+        assert_eq!(super::native_root_store(native).unwrap().len(), 1);
+    }
+
+    #[cfg(feature = "webpki-roots")]
+    #[test]
+    fn test_webpki_roots() {
+        let roots = super::root_store().unwrap();
+        assert_eq!(roots.len(), webpki_roots::TLS_SERVER_ROOTS.len());
+        assert!(Arc::ptr_eq(&roots, &super::root_store().unwrap()));
+    }
+    #[tokio::test]
+    async fn test_default_alpn() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let acceptor = rustls::server::Acceptor::default();
+                let start = tokio_rustls::LazyConfigAcceptor::new(acceptor, stream)
+                    .await
+                    .unwrap();
+                let offered = start.client_hello().alpn().map(|v| {
+                    v.map(|v| String::from_utf8_lossy(v).into_owned())
+                        .collect::<Vec<_>>()
+                });
+                _ = tx.send(offered);
+            }
+        });
+
+        let http1_only = Client::builder().version_policy(VersionPolicy::Http1Only);
+        let custom = Client::builder().alpn_protocols(vec![Alpn::Http1, Alpn::Http2]);
+        for (builder, expected) in [
+            (Client::builder(), &["h2", "http/1.1"][..]),
+            (http1_only, &["http/1.1"]),
+            (custom, &["http/1.1", "h2"]),
+        ] {
+            let client = builder.skip_tls_verify().build().unwrap();
+            // The handshake stops at the ClientHello.
+            client
+                .get(format!("https://{addr}/"))
+                .send()
+                .await
+                .unwrap_err();
+            assert_eq!(rx.recv().await.unwrap().unwrap(), expected);
+        }
+    }
 }
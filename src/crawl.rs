@@ -0,0 +1,459 @@
+//! Load a page along with its first-level subresources, in the spirit of
+//! WebPageTest.
+//!
+//! ```no_run
+//! # use httptrace::{client::Client, crawl::PageCrawl};
+//! # async fn run() -> httptrace::Result<()> {
+//! let client = Client::builder().build()?;
+//! let page = PageCrawl::new("https://example.com").concurrency(6).run(&client).await?;
+//! print!("{page}");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{fmt, sync::Arc, time::Duration};
+
+use futures_util::{StreamExt, stream};
+use http::{StatusCode, Uri};
+use tokio::time::Instant;
+
+use crate::{
+    client::Client,
+    into_uri::IntoUri,
+    stats::{Stats, StatsRecorder},
+};
+
+const DEFAULT_CONCURRENCY: usize = 6;
+
+// `<link>` relations a browser fetches while loading the page, unlike e.g.
+// `canonical` or `preconnect`.
+const FETCHED_LINKS: &[&str] = &[
+    "icon",
+    "apple-touch-icon",
+    "manifest",
+    "preload",
+    "modulepreload",
+];
+
+/// The kind of a subresource, from the tag referencing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ResourceKind {
+    Document,
+    Stylesheet,
+    Script,
+    Image,
+    /// Another fetched `<link>`, e.g. `icon` or `preload`.
+    Link,
+}
+
+/// The trace of the page or of one of its subresources.
+#[derive(Debug, Clone)]
+pub struct ResourceTrace {
+    pub uri: Uri,
+    pub kind: ResourceKind,
+    pub status: Option<StatusCode>,
+    pub error: Option<String>,
+    /// The number of body bytes read.
+    pub size: u64,
+    /// When the request started, from the start of the page request.
+    pub offset: Duration,
+    pub stats: Stats,
+}
+
+/// A page and its subresources, see [`PageCrawl`].
+#[derive(Debug, Clone)]
+pub struct PageLoad {
+    pub page: ResourceTrace,
+    /// In document order, duplicates removed.
+    pub resources: Vec<ResourceTrace>,
+    /// From the start of the page request to the end of the last resource.
+    pub total_duration: Duration,
+}
+
+/// Fetches an HTML page, then the stylesheets, scripts, images and other
+/// linked resources it references, through the same client.
+///
+/// Only the page itself is parsed, resources referenced by stylesheets or
+/// added by scripts are not fetched.
+pub struct PageCrawl {
+    uri: crate::Result<Uri>,
+    concurrency: usize,
+}
+
+impl PageCrawl {
+    pub fn new<U: IntoUri>(uri: U) -> Self {
+        Self {
+            uri: uri.into_uri(),
+            concurrency: DEFAULT_CONCURRENCY,
+        }
+    }
+
+    /// The number of resources fetched at once, 6 by default as in browsers.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Fails only if the page itself can't be fetched.
+    pub async fn run(self, client: &Client) -> crate::Result<PageLoad> {
+        let uri = self.uri?;
+        let start = Instant::now();
+
+        let recorder = StatsRecorder::new();
+        let mut request = client.get(uri.clone()).build()?;
+        *request.recorder_mut() = Some(Arc::new(recorder.clone()));
+        let response = client.execute(request).await?;
+        let status = response.status();
+        let body = response.bytes().await?;
+        let page = ResourceTrace {
+            uri: uri.clone(),
+            kind: ResourceKind::Document,
+            status: Some(status),
+            error: None,
+            size: body.len() as u64,
+            offset: Duration::ZERO,
+            stats: recorder.finish(),
+        };
+
+        let html = String::from_utf8_lossy(&body);
+        let mut links: Vec<(ResourceKind, Uri)> = Vec::new();
+        for (kind, reference) in subresources(&html) {
+            if let Some(uri) = resolve(&uri, &reference)
+                && !links.iter().any(|(_, v)| *v == uri)
+            {
+                links.push((kind, uri));
+            }
+        }
+
+        let resources: Vec<ResourceTrace> = stream::iter(links)
+            .map(|(kind, uri)| fetch(client, kind, uri, start))
+            .buffered(self.concurrency)
+            .collect()
+            .await;
+        Ok(PageLoad {
+            page,
+            resources,
+            total_duration: start.elapsed(),
+        })
+    }
+}
+
+async fn fetch(client: &Client, kind: ResourceKind, uri: Uri, start: Instant) -> ResourceTrace {
+    let offset = start.elapsed();
+    let recorder = StatsRecorder::new();
+    let ret = match client.get(uri.clone()).build() {
+        Ok(mut request) => {
+            *request.recorder_mut() = Some(Arc::new(recorder.clone()));
+            client.execute(request).await
+        }
+        Err(e) => Err(e),
+    };
+    let (status, size, error) = match ret {
+        Ok(response) => {
+            let status = response.status();
+            match response.bytes().await {
+                Ok(body) => (Some(status), body.len() as u64, None),
+                Err(e) => (Some(status), 0, Some(e.to_string())),
+            }
+        }
+        Err(e) => (None, 0, Some(e.to_string())),
+    };
+    ResourceTrace {
+        uri,
+        kind,
+        status,
+        error,
+        size,
+        offset,
+        stats: recorder.finish(),
+    }
+}
+
+/// The subresources referenced by the tags of the document, in order.
+fn subresources(html: &str) -> Vec<(ResourceKind, String)> {
+    let mut found = Vec::new();
+    let mut rest = html;
+    while let Some(index) = rest.find('<') {
+        rest = &rest[index + 1..];
+        if let Some(comment) = rest.strip_prefix("!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        let end = rest.find('>').unwrap_or(rest.len());
+        let tag = &rest[..end];
+        rest = &rest[end..];
+
+        let name_end = tag
+            .find(|c: char| c.is_ascii_whitespace() || c == '/')
+            .unwrap_or(tag.len());
+        let name = tag[..name_end].to_ascii_lowercase();
+        let attrs = attributes(&tag[name_end..]);
+        let attr = |key: &str| {
+            attrs
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(key))
+                .map(|(_, value)| value.clone())
+        };
+        let resource = match name.as_str() {
+            "script" => attr("src").map(|src| (ResourceKind::Script, src)),
+            "img" => attr("src").map(|src| (ResourceKind::Image, src)),
+            "link" => {
+                let rel = attr("rel").unwrap_or_default().to_ascii_lowercase();
+                let rel = rel.split_ascii_whitespace().collect::<Vec<_>>();
+                let kind = if rel.contains(&"stylesheet") {
+                    Some(ResourceKind::Stylesheet)
+                } else if rel.iter().any(|v| FETCHED_LINKS.contains(v)) {
+                    Some(ResourceKind::Link)
+                } else {
+                    None
+                };
+                kind.zip(attr("href"))
+            }
+            _ => None,
+        };
+        if let Some((kind, reference)) = resource
+            && !reference.trim().is_empty()
+        {
+            found.push((kind, reference.trim().to_string()));
+        }
+    }
+    found
+}
+
+/// The `name=value` pairs of a tag, values unquoted. Entities are not decoded
+/// but `&amp;`.
+fn attributes(mut input: &str) -> Vec<(String, String)> {
+    let mut attrs = Vec::new();
+    loop {
+        input = input.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == '/');
+        if input.is_empty() {
+            return attrs;
+        }
+        let name_end = input
+            .find(|c: char| c.is_ascii_whitespace() || c == '=' || c == '/')
+            .unwrap_or(input.len());
+        let name = input[..name_end].to_string();
+        input = input[name_end..].trim_start();
+        let Some(value) = input.strip_prefix('=') else {
+            attrs.push((name, String::new()));
+            continue;
+        };
+        let value = value.trim_start();
+        let (value, rest) = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let value = &value[1..];
+                let end = value.find(quote).unwrap_or(value.len());
+                (&value[..end], value.get(end + 1..).unwrap_or(""))
+            }
+            _ => {
+                let end = value
+                    .find(|c: char| c.is_ascii_whitespace())
+                    .unwrap_or(value.len());
+                (&value[..end], &value[end..])
+            }
+        };
+        attrs.push((name, value.replace("&amp;", "&")));
+        input = rest;
+    }
+}
+
+/// Resolve a reference found in the page against its URI, `None` for
+/// references that aren't http(s), e.g. `data:` URIs.
+pub(crate) fn resolve(base: &Uri, reference: &str) -> Option<Uri> {
+    let reference = reference.split('#').next().unwrap_or_default();
+    let scheme = base.scheme_str()?;
+    let authority = base.authority()?.as_str();
+
+    let resolved = if let Some((ref_scheme, _)) = reference.split_once(':')
+        && !ref_scheme.contains(['/', '?'])
+    {
+        if !ref_scheme.eq_ignore_ascii_case("http") && !ref_scheme.eq_ignore_ascii_case("https") {
+            return None;
+        }
+        reference.to_string()
+    } else if reference.starts_with("//") {
+        format!("{}:{}", scheme, reference)
+    } else if reference.starts_with('/') {
+        format!(
+            "{}://{}{}",
+            scheme,
+            authority,
+            remove_dot_segments(reference)
+        )
+    } else if reference.starts_with('?') {
+        format!("{}://{}{}{}", scheme, authority, base.path(), reference)
+    } else {
+        let (path, query) = match reference.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (reference, None),
+        };
+        let directory = &base.path()[..=base.path().rfind('/').unwrap_or(0)];
+        let mut resolved = format!(
+            "{}://{}{}",
+            scheme,
+            authority,
+            remove_dot_segments(&format!("{}{}", directory, path))
+        );
+        if let Some(query) = query {
+            resolved.push('?');
+            resolved.push_str(query);
+        }
+        resolved
+    };
+    resolved.parse().ok()
+}
+
+fn remove_dot_segments(path: &str) -> String {
+    let mut segments: Vec<&str> = Vec::new();
+    let mut parts = path.split('/').skip(1).peekable();
+    while let Some(part) = parts.next() {
+        let last = parts.peek().is_none();
+        match part {
+            "." => {}
+            ".." => {
+                segments.pop();
+            }
+            _ => segments.push(part),
+        }
+        if last && matches!(part, "." | "..") {
+            segments.push("");
+        }
+    }
+    format!("/{}", segments.join("/"))
+}
+
+impl fmt::Display for PageLoad {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const WIDTH: usize = 40;
+        let total = self.total_duration.as_secs_f64().max(f64::EPSILON);
+        let column = |duration: Duration| (duration.as_secs_f64() / total * WIDTH as f64) as usize;
+
+        for resource in std::iter::once(&self.page).chain(&self.resources) {
+            let start = column(resource.offset).min(WIDTH - 1);
+            let len = column(resource.stats.total_duration).clamp(1, WIDTH - start);
+            let bar = format!("{}{}", " ".repeat(start), "=".repeat(len));
+            write!(
+                f,
+                "{:>5}ms {:>5}ms |{:<WIDTH$}| ",
+                resource.offset.as_millis(),
+                resource.stats.total_duration.as_millis(),
+                bar
+            )?;
+            match (resource.status, &resource.error) {
+                (_, Some(error)) => writeln!(f, "{} failed: {}", resource.uri, error)?,
+                (Some(status), None) => writeln!(
+                    f,
+                    "{} {} {} bytes",
+                    status.as_u16(),
+                    resource.uri,
+                    resource.size
+                )?,
+                (None, None) => writeln!(f, "{}", resource.uri)?,
+            }
+        }
+        writeln!(f, "total: {}ms", self.total_duration.as_millis())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use http::Uri;
+
+    use super::{PageCrawl, ResourceKind, resolve, subresources};
+    use crate::{
+        client::Client,
+        test_util::{MockFailure, MockResponse, MockTransport},
+    };
+
+    #[test]
+    fn test_subresources() {
+        let html = r#"<html><head>
+            <link rel="stylesheet" href="/css/site.css">
+            <LINK REL=icon HREF=favicon.ico>
+            <link rel="preconnect" href="https://fonts.example.com">
+            <!-- <script src="commented.js"></script> -->
+            <script src='js/app.js?v=1&amp;x=2'></script>
+            <script>let a = 1;</script>
+        </head><body><img alt="logo" src="data:image/png;base64,AAAA"><img src=//cdn.example.com/a.png /></body></html>"#;
+        let found = subresources(html);
+        assert_eq!(
+            found,
+            [
+                (ResourceKind::Stylesheet, "/css/site.css".to_string()),
+                (ResourceKind::Link, "favicon.ico".to_string()),
+                (ResourceKind::Script, "js/app.js?v=1&x=2".to_string()),
+                (
+                    ResourceKind::Image,
+                    "data:image/png;base64,AAAA".to_string()
+                ),
+                (ResourceKind::Image, "//cdn.example.com/a.png".to_string()),
+            ]
+        );
+
+        let base: Uri = "https://example.com/a/b/page.html?q=1".parse().unwrap();
+        for (reference, expected) in [
+            ("/css/site.css", Some("https://example.com/css/site.css")),
+            ("img.png", Some("https://example.com/a/b/img.png")),
+            ("../up.js?v=2#frag", Some("https://example.com/a/up.js?v=2")),
+            ("./x/./y/../z", Some("https://example.com/a/b/x/z")),
+            ("?page=2", Some("https://example.com/a/b/page.html?page=2")),
+            (
+                "//cdn.example.com/a.png",
+                Some("https://cdn.example.com/a.png"),
+            ),
+            ("http://other.com/", Some("http://other.com/")),
+            ("data:image/png;base64,AAAA", None),
+            ("mailto:a@example.com", None),
+        ] {
+            let resolved = resolve(&base, reference).map(|v| v.to_string());
+            assert_eq!(resolved.as_deref(), expected, "{reference}");
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_page_crawl() {
+        let html = r#"<link rel=stylesheet href="a.css"><script src="a.js"></script>
+            <img src="a.css"><img src="missing.png">"#;
+        let mock = MockTransport::new();
+        mock.push(
+            MockResponse::new(200)
+                .body(html)
+                .response_delay(Duration::from_millis(100)),
+        );
+        mock.push(
+            MockResponse::new(200)
+                .body("body {}")
+                .response_delay(Duration::from_millis(50)),
+        );
+        mock.push(
+            MockResponse::new(200)
+                .body("let a;")
+                .response_delay(Duration::from_millis(50)),
+        );
+        mock.push(MockResponse::failure(MockFailure::Tcp));
+        let client = Client::builder()
+            .mock_transport(mock.clone())
+            .build()
+            .unwrap();
+
+        let page = PageCrawl::new("http://example.com/index.html")
+            .concurrency(2)
+            .run(&client)
+            .await
+            .unwrap();
+        assert_eq!(page.page.size, html.len() as u64);
+        assert_eq!(page.resources.len(), 3);
+        assert_eq!(page.resources[0].kind, ResourceKind::Stylesheet);
+        assert_eq!(page.resources[0].uri, "http://example.com/a.css");
+        assert_eq!(page.resources[1].offset, Duration::from_millis(100));
+        assert_eq!(page.resources[1].size, 6);
+        assert!(page.resources[2].error.is_some());
+        assert_eq!(page.total_duration, Duration::from_millis(150));
+        assert!(
+            page.to_string()
+                .contains("200 http://example.com/a.js 6 bytes")
+        );
+    }
+}
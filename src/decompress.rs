@@ -0,0 +1,201 @@
+//! Decoding of response bodies after their `Content-Encoding`, with the
+//! `gzip`, `deflate`, `brotli` and `zstd` features.
+
+use futures_util::TryStreamExt;
+use http::{HeaderValue, Method, StatusCode, header};
+use http_body::{Body as _, Frame};
+use http_body_util::StreamBody;
+use tokio::io::AsyncRead;
+use tokio_util::io::{ReaderStream, StreamReader};
+
+use crate::{
+    body::{DataStream, ResponseBody},
+    response::Response,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Coding {
+    #[cfg(feature = "gzip")]
+    Gzip,
+    #[cfg(feature = "deflate")]
+    Deflate,
+    #[cfg(feature = "brotli")]
+    Brotli,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl Coding {
+    const ENABLED: &[Coding] = &[
+        #[cfg(feature = "gzip")]
+        Coding::Gzip,
+        #[cfg(feature = "deflate")]
+        Coding::Deflate,
+        #[cfg(feature = "brotli")]
+        Coding::Brotli,
+        #[cfg(feature = "zstd")]
+        Coding::Zstd,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            #[cfg(feature = "gzip")]
+            Coding::Gzip => "gzip",
+            #[cfg(feature = "deflate")]
+            Coding::Deflate => "deflate",
+            #[cfg(feature = "brotli")]
+            Coding::Brotli => "br",
+            #[cfg(feature = "zstd")]
+            Coding::Zstd => "zstd",
+        }
+    }
+}
+
+/// The `Accept-Encoding` sent unless the request has one, listing the
+/// enabled codings.
+pub(crate) fn accept_encoding() -> HeaderValue {
+    let names = Coding::ENABLED.iter().map(|coding| coding.name());
+    HeaderValue::from_str(&names.collect::<Vec<_>>().join(", "))
+        .expect("coding names are valid header values")
+}
+
+/// Decode the body of `response` to a `method` request if it has a single
+/// enabled `Content-Encoding`, dropping the headers describing the encoded
+/// body. A response without a body is left as is.
+pub(crate) fn decompress(mut response: Response, method: &Method) -> Response {
+    let status = response.status();
+    if method == Method::HEAD
+        || status.is_informational()
+        || status == StatusCode::NO_CONTENT
+        || status == StatusCode::NOT_MODIFIED
+        || response.res.body().is_end_stream()
+    {
+        return response;
+    }
+    let coding = response
+        .headers()
+        .get(header::CONTENT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| {
+            let v = v.trim();
+            Coding::ENABLED
+                .iter()
+                .copied()
+                .find(|coding| v.eq_ignore_ascii_case(coding.name()))
+        });
+    let Some(coding) = coding else {
+        return response;
+    };
+
+    let headers = response.headers_mut();
+    headers.remove(header::CONTENT_ENCODING);
+    headers.remove(header::CONTENT_LENGTH);
+    response.res = response.res.map(|body| decode(coding, body));
+    response
+}
+
+fn decode(coding: Coding, body: ResponseBody) -> ResponseBody {
+    use async_compression::tokio::bufread;
+
+    let reader = StreamReader::new(DataStream(body).map_err(std::io::Error::other));
+    match coding {
+        #[cfg(feature = "gzip")]
+        Coding::Gzip => {
+            let mut decoder = bufread::GzipDecoder::new(reader);
+            decoder.multiple_members(true);
+            reader_body(decoder)
+        }
+        #[cfg(feature = "deflate")]
+        Coding::Deflate => reader_body(bufread::ZlibDecoder::new(reader)),
+        #[cfg(feature = "brotli")]
+        Coding::Brotli => reader_body(bufread::BrotliDecoder::new(reader)),
+        #[cfg(feature = "zstd")]
+        Coding::Zstd => reader_body(bufread::ZstdDecoder::new(reader)),
+    }
+}
+
+fn reader_body<R: AsyncRead + Send + 'static>(reader: R) -> ResponseBody {
+    let stream = ReaderStream::new(reader)
+        .map_ok(Frame::data)
+        .map_err(Into::into);
+    http_body_util::BodyExt::boxed(StreamBody::new(sync_wrapper::SyncStream::new(stream)))
+}
+
+#[cfg(all(test, feature = "gzip"))]
+mod tests {
+    use async_compression::tokio::bufread::GzipEncoder;
+    use tokio::io::AsyncReadExt;
+
+    use crate::{
+        client::Client,
+        test_util::{MockResponse, MockTransport},
+    };
+
+    #[tokio::test]
+    async fn test_gzip() {
+        let mut encoded = Vec::new();
+        GzipEncoder::new(&b"hello world"[..])
+            .read_to_end(&mut encoded)
+            .await
+            .unwrap();
+
+        let mock = MockTransport::new();
+        for _ in 0..2 {
+            mock.push(
+                MockResponse::new(200)
+                    .header("content-encoding", "gzip")
+                    .header("content-length", encoded.len().to_string())
+                    .body(encoded.clone()),
+            );
+        }
+        let client = Client::builder()
+            .mock_transport(mock.clone())
+            .build()
+            .unwrap();
+
+        let response = client.get("https://example.com/").send().await.unwrap();
+        assert!(response.headers().get("content-encoding").is_none());
+        assert!(response.headers().get("content-length").is_none());
+        assert_eq!(response.text().await.unwrap(), "hello world");
+        let accept = mock.requests()[0].headers["accept-encoding"].clone();
+        assert!(accept.to_str().unwrap().contains("gzip"));
+
+        // The raw transfer, still asking for a compressed body.
+        let response = client
+            .get("https://example.com/")
+            .no_decompress()
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.headers()["content-encoding"], "gzip");
+        assert_eq!(response.bytes().await.unwrap(), encoded);
+        assert!(mock.requests()[1].headers.contains_key("accept-encoding"));
+    }
+
+    #[tokio::test]
+    async fn test_no_body() {
+        let mock = MockTransport::new();
+        for status in [200, 200, 204, 304] {
+            mock.push(
+                MockResponse::new(status)
+                    .header("content-encoding", "gzip")
+                    .header("content-length", "31"),
+            );
+        }
+        let client = Client::builder()
+            .mock_transport(mock.clone())
+            .build()
+            .unwrap();
+
+        // Nothing to decode, the headers describe the body a GET would get.
+        let mut responses = vec![client.head("https://example.com/").send().await.unwrap()];
+        for _ in 0..3 {
+            responses.push(client.get("https://example.com/").send().await.unwrap());
+        }
+        for response in responses {
+            assert_eq!(response.headers()["content-encoding"], "gzip");
+            assert_eq!(response.headers()["content-length"], "31");
+            assert_eq!(response.text().await.unwrap(), "");
+        }
+    }
+}
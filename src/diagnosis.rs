@@ -0,0 +1,234 @@
+//! Explain where the time of a request went, see [`Stats::analyze`].
+
+use std::{fmt, time::Duration};
+
+use rustls::ProtocolVersion;
+
+use crate::{aggregate::Phase, stats::Stats};
+
+// The share of the total above which a phase gets a hint even if it is
+// within its baseline.
+const DOMINANT_SHARE: f64 = 50.0;
+
+/// A phase of the request, compared to a typical duration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PhaseDiagnosis {
+    pub phase: Phase,
+    pub duration: Duration,
+    /// The percentage of the total duration.
+    pub share: f64,
+    /// A typical duration for the phase on a healthy connection over the
+    /// internet.
+    pub baseline: Duration,
+}
+
+/// Where the time of a request went, with hints for the slow parts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnosis {
+    pub total: Duration,
+    /// The phases the request went through, in order.
+    pub phases: Vec<PhaseDiagnosis>,
+    /// The time left after the phases, mostly reading the body.
+    pub transfer: Duration,
+    /// The phase taking the most time, `None` if reading the body did.
+    pub dominant: Option<Phase>,
+    /// Human readable advice, most important first.
+    pub hints: Vec<String>,
+}
+
+impl PhaseDiagnosis {
+    /// Whether the phase took longer than its baseline.
+    pub fn is_slow(&self) -> bool {
+        self.duration > self.baseline
+    }
+}
+
+fn baseline(phase: Phase) -> Duration {
+    Duration::from_millis(match phase {
+        Phase::Blocked => 10,
+        Phase::Dns => 50,
+        Phase::Tcp => 100,
+        Phase::Tls => 200,
+        Phase::Request => 500,
+        Phase::Total => 1000,
+    })
+}
+
+impl Stats {
+    /// Diagnose the request: the share of each phase, how it compares to
+    /// typical durations and what could be done about the slow ones.
+    pub fn analyze(&self) -> Diagnosis {
+        let total = self.total_duration;
+        let percent = |duration: Duration| match total.is_zero() {
+            true => 0.0,
+            false => duration.as_secs_f64() / total.as_secs_f64() * 100.0,
+        };
+
+        let phases = Phase::ALL
+            .into_iter()
+            .filter(|phase| *phase != Phase::Total)
+            .filter_map(|phase| {
+                let duration = phase.duration(self)?;
+                Some(PhaseDiagnosis {
+                    phase,
+                    duration,
+                    share: percent(duration),
+                    baseline: baseline(phase),
+                })
+            })
+            .collect::<Vec<_>>();
+        let transfer = total.saturating_sub(phases.iter().map(|v| v.duration).sum());
+        let dominant = phases
+            .iter()
+            .max_by_key(|v| v.duration)
+            .filter(|v| v.duration >= transfer)
+            .map(|v| v.phase);
+
+        let mut hints = Vec::new();
+        if let Some(error) = self.error() {
+            hints.push(format!("The request failed: {}", error));
+        }
+        let mut sorted = phases.iter().collect::<Vec<_>>();
+        sorted.sort_by_key(|v| std::cmp::Reverse(v.duration));
+        for phase in sorted {
+            if phase.is_slow() || phase.share >= DOMINANT_SHARE {
+                hints.push(self.hint(phase));
+            }
+        }
+        if percent(transfer) >= DOMINANT_SHARE && transfer > Duration::from_millis(100) {
+            let size = self
+                .body_info
+                .as_ref()
+                .map(|v| format!(" {} bytes", v.len))
+                .unwrap_or_default();
+            hints.push(format!(
+                "Reading the body{} took {:.0}% of total ({:?}) — the response is large or \
+                 the bandwidth low, consider compression or a CDN",
+                size,
+                percent(transfer),
+                transfer
+            ));
+        }
+        let failed_attempts = self
+            .tcp_stats
+            .iter()
+            .flatten()
+            .filter(|stat| stat.error.is_some())
+            .count();
+        if failed_attempts > 0 && self.error().is_none() {
+            hints.push(format!(
+                "{} connect attempt(s) failed before one succeeded — some resolved \
+                 addresses are unreachable, e.g. IPv6 without a route",
+                failed_attempts
+            ));
+        }
+
+        Diagnosis {
+            total,
+            phases,
+            transfer,
+            dominant,
+            hints,
+        }
+    }
+
+    fn hint(&self, phase: &PhaseDiagnosis) -> String {
+        let measured = format!(
+            "{} took {:.0}% of total ({:?}, typically under {:?})",
+            match phase.phase {
+                Phase::Blocked => "Waiting for a free slot",
+                Phase::Dns => "DNS",
+                Phase::Tcp => "TCP connect",
+                Phase::Tls => "TLS handshake",
+                Phase::Request => "Waiting for the server",
+                Phase::Total => "The request",
+            },
+            phase.share,
+            phase.duration,
+            phase.baseline
+        );
+        let advice = match phase.phase {
+            Phase::Blocked => "raise the concurrency limit or send fewer requests at once",
+            Phase::Dns => "consider caching or a closer resolver",
+            Phase::Tcp => "the server is far away or the network congested, reuse connections",
+            Phase::Tls => match self.tls_info.as_ref().and_then(|v| v.protocol_version) {
+                Some(ProtocolVersion::TLSv1_2) => {
+                    "TLS 1.2 needs one more round trip than TLS 1.3, enable TLS 1.3 on the server"
+                }
+                _ => "reuse connections or enable session resumption",
+            },
+            Phase::Request => "the time is spent by the server, look at the backend",
+            Phase::Total => "",
+        };
+        format!("{} — {}", measured, advice)
+    }
+}
+
+impl fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for phase in self.phases.iter() {
+            writeln!(
+                f,
+                "{:<8} {:>5}ms {:>5.1}%{}",
+                phase.phase.to_string(),
+                phase.duration.as_millis(),
+                phase.share,
+                if phase.is_slow() { " slow" } else { "" }
+            )?;
+        }
+        writeln!(f, "{:<8} {:>5}ms", "transfer", self.transfer.as_millis())?;
+        for hint in self.hints.iter() {
+            writeln!(f, "- {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        aggregate::Phase,
+        stats::{Stat, Stats},
+    };
+
+    #[test]
+    fn test_analyze() {
+        let stat = |ms| Stat {
+            duration: Duration::from_millis(ms),
+            started_at: Some(std::time::SystemTime::now()),
+            ..Default::default()
+        };
+        let stats = Stats {
+            dns_stats: stat(780),
+            tls_stats: Some(stat(100)),
+            request_stats: Some(stat(80)),
+            total_duration: Duration::from_millis(1000),
+            ..Default::default()
+        };
+
+        let diagnosis = stats.analyze();
+        assert_eq!(diagnosis.dominant, Some(Phase::Dns));
+        assert_eq!(diagnosis.transfer, Duration::from_millis(40));
+        assert_eq!(diagnosis.phases.len(), 3);
+        assert!(diagnosis.phases[0].is_slow());
+        assert!(!diagnosis.phases[1].is_slow());
+        assert_eq!(
+            diagnosis.hints,
+            [
+                "DNS took 78% of total (780ms, typically under 50ms) — consider caching or a \
+              closer resolver"
+            ]
+        );
+
+        let stats = Stats {
+            request_stats: Some(stat(100)),
+            total_duration: Duration::from_millis(900),
+            ..Default::default()
+        };
+        let diagnosis = stats.analyze();
+        assert_eq!(diagnosis.dominant, None);
+        assert!(diagnosis.hints[0].starts_with("Reading the body took 89% of total"));
+    }
+}
@@ -0,0 +1,309 @@
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use futures_util::{
+    future::BoxFuture,
+    stream::{self, BoxStream},
+};
+use hickory_resolver::{
+    Resolver, TokioResolver,
+    config::{LookupIpStrategy, NameServerConfig, ResolverConfig},
+    name_server::{GenericConnector, TokioConnectionProvider},
+    proto::{
+        rr::{
+            RData, RecordType,
+            rdata::svcb::{EchConfigList, SvcParamValue},
+        },
+        runtime::TokioRuntimeProvider,
+    },
+};
+
+/// Resolves a host name to its addresses.
+///
+/// The client applies its DNS timeout and overrides around the resolver, and
+/// reports the lookup to the request's recorder.
+pub trait Resolve: Send + Sync + fmt::Debug {
+    /// Resolve the host, an empty [`DnsInfo::addrs`] fails the request.
+    fn resolve<'a>(&'a self, host: &'a str) -> BoxFuture<'a, crate::Result<DnsInfo>>;
+
+    /// Resolve the host as partial lookups, e.g. `A` and `AAAA`, yielded as
+    /// they complete so that connecting can start with the first answer.
+    ///
+    /// The host fails to resolve only if no lookup yields an address.
+    /// Defaults to a single [`Resolve::resolve`].
+    fn resolve_partial<'a>(&'a self, host: &'a str) -> BoxStream<'a, crate::Result<DnsInfo>> {
+        Box::pin(stream::once(self.resolve(host)))
+    }
+
+    /// The name servers reported to the recorder.
+    fn name_servers(&self) -> &[NameServerConfig] {
+        &[]
+    }
+
+    /// The ECH config list published in the `HTTPS` record of the host, see
+    /// [`ClientBuilder::tls_ech`](crate::client::ClientBuilder::tls_ech).
+    /// Defaults to none.
+    fn ech_config_list<'a>(
+        &'a self,
+        _host: &'a str,
+    ) -> BoxFuture<'a, crate::Result<Option<Vec<u8>>>> {
+        Box::pin(async { Ok(None) })
+    }
+}
+
+/// Where the addresses of a lookup come from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DnsSource {
+    /// Set with [`ClientBuilder::resolve_to_addrs`](crate::client::ClientBuilder::resolve_to_addrs).
+    Override,
+    /// Answered from the resolver's cache.
+    Cache,
+    #[default]
+    Network,
+}
+
+/// The result of a lookup.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DnsInfo {
+    pub addrs: Vec<IpAddr>,
+    pub source: DnsSource,
+    /// The time the records stay valid.
+    pub ttl: Option<Duration>,
+    pub query_types: Vec<RecordType>,
+    /// The name server that answered, `None` if the resolver doesn't tell.
+    pub name_server: Option<SocketAddr>,
+}
+
+impl DnsInfo {
+    pub fn new(addrs: Vec<IpAddr>) -> Self {
+        DnsInfo {
+            addrs,
+            ..Default::default()
+        }
+    }
+
+    /// Add the answer of another partial lookup of the same host.
+    pub(crate) fn merge(&mut self, other: DnsInfo) {
+        self.addrs.extend(other.addrs);
+        self.ttl = match (self.ttl, other.ttl) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        for query_type in other.query_types {
+            if !self.query_types.contains(&query_type) {
+                self.query_types.push(query_type);
+            }
+        }
+        if other.source != DnsSource::Cache {
+            self.source = other.source;
+        }
+        self.name_server = self.name_server.or(other.name_server);
+    }
+}
+
+/// The default resolver, backed by hickory.
+///
+/// Hickory doesn't tell whether an answer comes from its cache, nor which name
+/// server answered, so lookups are always reported from the network.
+#[derive(Debug, Clone)]
+pub struct HickoryResolver {
+    resolver: Resolver<GenericConnector<TokioRuntimeProvider>>,
+    strategy: LookupIpStrategy,
+}
+
+impl HickoryResolver {
+    /// Use the system configuration, or the given name servers if any.
+    pub fn new(
+        name_servers: Option<Vec<NameServerConfig>>,
+        strategy: LookupIpStrategy,
+    ) -> crate::Result<Self> {
+        let provider = TokioConnectionProvider::default();
+        let mut builder = match name_servers.filter(|v| !v.is_empty()) {
+            Some(name_servers) => {
+                let mut config = ResolverConfig::new();
+                for ns in name_servers {
+                    config.add_name_server(ns);
+                }
+                TokioResolver::builder_with_config(config, provider)
+            }
+            None => TokioResolver::builder(provider)?,
+        };
+        builder.options_mut().ip_strategy = strategy;
+        Ok(HickoryResolver {
+            resolver: builder.build(),
+            strategy,
+        })
+    }
+}
+
+/// Wrap an existing resolver, keeping its lookup strategy. Clones of a
+/// hickory resolver share its cache.
+impl From<TokioResolver> for HickoryResolver {
+    fn from(resolver: TokioResolver) -> Self {
+        HickoryResolver {
+            strategy: resolver.options().ip_strategy,
+            resolver,
+        }
+    }
+}
+
+impl Resolve for HickoryResolver {
+    fn resolve<'a>(&'a self, host: &'a str) -> BoxFuture<'a, crate::Result<DnsInfo>> {
+        Box::pin(async move {
+            let lookup = self.resolver.lookup_ip(host).await?;
+            Ok(DnsInfo {
+                ttl: Some(
+                    lookup
+                        .valid_until()
+                        .saturating_duration_since(Instant::now()),
+                ),
+                addrs: lookup.into_iter().collect(),
+                source: DnsSource::Network,
+                query_types: match self.strategy {
+                    LookupIpStrategy::Ipv4Only => vec![RecordType::A],
+                    LookupIpStrategy::Ipv6Only => vec![RecordType::AAAA],
+                    _ => vec![RecordType::A, RecordType::AAAA],
+                },
+                name_server: None,
+            })
+        })
+    }
+
+    /// With both address families queried at once, `A` and `AAAA` are
+    /// looked up separately and yielded in the order they complete.
+    fn resolve_partial<'a>(&'a self, host: &'a str) -> BoxStream<'a, crate::Result<DnsInfo>> {
+        if self.strategy != LookupIpStrategy::Ipv4AndIpv6 || host.parse::<IpAddr>().is_ok() {
+            return Box::pin(stream::once(self.resolve(host)));
+        }
+        let lookup = |record_type| async move {
+            let lookup = self.resolver.lookup(host, record_type).await?;
+            Ok(DnsInfo {
+                ttl: Some(
+                    lookup
+                        .valid_until()
+                        .saturating_duration_since(Instant::now()),
+                ),
+                addrs: lookup.iter().filter_map(|v| v.ip_addr()).collect(),
+                source: DnsSource::Network,
+                query_types: vec![record_type],
+                name_server: None,
+            })
+        };
+        Box::pin(stream::select(
+            stream::once(lookup(RecordType::AAAA)),
+            stream::once(lookup(RecordType::A)),
+        ))
+    }
+
+    fn name_servers(&self) -> &[NameServerConfig] {
+        self.resolver.config().name_servers()
+    }
+
+    /// The one of the service binding with the highest priority that has one.
+    fn ech_config_list<'a>(
+        &'a self,
+        host: &'a str,
+    ) -> BoxFuture<'a, crate::Result<Option<Vec<u8>>>> {
+        Box::pin(async move {
+            let lookup = match self.resolver.lookup(host, RecordType::HTTPS).await {
+                Ok(lookup) => lookup,
+                Err(e) if e.is_no_records_found() => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+            let list = lookup
+                .iter()
+                .filter_map(|v| match v {
+                    RData::HTTPS(https) => Some(&https.0),
+                    _ => None,
+                })
+                .filter_map(|svcb| {
+                    let list = svcb.svc_params().iter().find_map(|(_, v)| match v {
+                        SvcParamValue::EchConfigList(EchConfigList(list)) => Some(list),
+                        _ => None,
+                    })?;
+                    Some((svcb.svc_priority(), list))
+                })
+                .min_by_key(|(priority, _)| *priority)
+                .map(|(_, list)| list.clone());
+            Ok(list)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::IpAddr, time::Duration};
+
+    use hickory_resolver::proto::rr::RecordType;
+
+    use super::{DnsInfo, DnsSource};
+    use crate::{
+        client::Client,
+        stats::StatsRecorder,
+        test_util::{MockLookup, MockResolver, TestServer},
+    };
+
+    #[tokio::test]
+    async fn test_dns_info() {
+        let server = TestServer::builder().start().await.unwrap();
+        let localhost: IpAddr = "127.0.0.1".parse().unwrap();
+        let name_server = "127.0.0.53:53".parse().unwrap();
+        let resolver = MockResolver::new();
+        resolver.push_partial(
+            "a.test",
+            [
+                MockLookup::info(DnsInfo {
+                    ttl: Some(Duration::from_secs(60)),
+                    query_types: vec![RecordType::AAAA],
+                    name_server: Some(name_server),
+                    ..Default::default()
+                }),
+                // The address comes last, so both parts are merged.
+                MockLookup::info(DnsInfo {
+                    source: DnsSource::Cache,
+                    ttl: Some(Duration::from_secs(30)),
+                    query_types: vec![RecordType::A],
+                    ..DnsInfo::new(vec![localhost])
+                })
+                .delay(Duration::from_millis(10)),
+            ],
+        );
+        let client = Client::builder()
+            .dns_resolver(resolver)
+            .resolve_to_addrs("b.test", &[localhost])
+            .build()
+            .unwrap();
+
+        let recorder = StatsRecorder::new();
+        let send = |host: &str| {
+            client
+                .get(format!("http://{host}:{}/", server.addr().port()))
+                .recorder(Box::new(recorder.clone()))
+                .send()
+        };
+        send("a.test").await.unwrap();
+        assert_eq!(
+            recorder.finish().dns_info.unwrap(),
+            DnsInfo {
+                addrs: vec![localhost],
+                // Not from the cache as long as a part isn't.
+                source: DnsSource::Network,
+                ttl: Some(Duration::from_secs(30)),
+                query_types: vec![RecordType::AAAA, RecordType::A],
+                name_server: Some(name_server),
+            }
+        );
+
+        send("b.test").await.unwrap();
+        assert_eq!(
+            recorder.finish().dns_info.unwrap(),
+            DnsInfo {
+                source: DnsSource::Override,
+                ..DnsInfo::new(vec![localhost])
+            }
+        );
+    }
+}
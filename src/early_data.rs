@@ -0,0 +1,210 @@
+//! TLS 1.3 early data, see
+//! [`ClientBuilder::tls_early_data`](crate::client::ClientBuilder::tls_early_data).
+//!
+//! With 0-RTT the connection is handed over while the handshake is still
+//! going on, the request being written as early data. [`EarlyDataIo`]
+//! reports the end of the handshake once the server's answer is read.
+
+use std::{
+    io::IoSlice,
+    pin::Pin,
+    sync::{Arc, OnceLock},
+    task::{Context, Poll},
+};
+
+use pin_project_lite::pin_project;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_rustls::client::TlsStream;
+
+use crate::{request::Request, stats::TlsInfo};
+
+pin_project! {
+    /// A connection sending early data, reporting the handshake to the
+    /// recorder of `request` when it completes.
+    pub(crate) struct EarlyDataIo<S> {
+        #[pin]
+        inner: TlsStream<S>,
+        request: Option<Request>,
+        sni: Option<String>,
+        tls: Arc<OnceLock<TlsInfo>>,
+    }
+}
+
+impl<S> EarlyDataIo<S> {
+    /// `tls` is set once the handshake completes.
+    pub(crate) fn new(
+        inner: TlsStream<S>,
+        request: Option<Request>,
+        sni: Option<String>,
+        tls: Arc<OnceLock<TlsInfo>>,
+    ) -> Self {
+        EarlyDataIo {
+            inner,
+            request,
+            sni,
+            tls,
+        }
+    }
+
+    fn check_handshake(self: Pin<&mut Self>) {
+        let this = self.project();
+        let session = this.inner.get_ref().1;
+        if this.tls.get().is_some() || session.is_handshaking() {
+            return;
+        }
+
+        let info = TlsInfo::new(&this.inner, this.sni.clone());
+        let accepted = session.is_early_data_accepted();
+        if let Some(request) = this.request.take()
+            && let Some(recorder) = request.recorder()
+        {
+            recorder.on_tls_done(&request, Ok(&info));
+            recorder.on_tls_early_data(&request, accepted);
+        }
+        _ = this.tls.set(info);
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for EarlyDataIo<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let ret = self.as_mut().project().inner.poll_read(cx, buf);
+        self.check_handshake();
+        ret
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for EarlyDataIo<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let ret = self.as_mut().project().inner.poll_write(cx, buf);
+        self.check_handshake();
+        ret
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        let ret = self.as_mut().project().inner.poll_write_vectored(cx, bufs);
+        self.check_handshake();
+        ret
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let ret = self.as_mut().project().inner.poll_flush(cx);
+        self.check_handshake();
+        ret
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.project().inner.poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, sync::Arc};
+
+    use http::Method;
+    use rustls::{
+        ServerConfig,
+        pki_types::{CertificateDer, PrivateKeyDer},
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+    use tokio_rustls::TlsAcceptor;
+
+    use crate::{client::Client, stats::StatsRecorder};
+
+    fn acceptor() -> TlsAcceptor {
+        crate::client::ensure_crypto_provider();
+        let rcgen::CertifiedKey { cert, key_pair } =
+            rcgen::generate_simple_self_signed(["localhost".to_string()]).unwrap();
+        let certificate = CertificateDer::from(cert.der().to_vec());
+        let key = PrivateKeyDer::Pkcs8(key_pair.serialize_der().into());
+        let mut config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(vec![certificate], key)
+            .unwrap();
+        config.max_early_data_size = 16384;
+        TlsAcceptor::from(Arc::new(config))
+    }
+
+    #[tokio::test]
+    async fn test_tls_early_data() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let acceptor = acceptor();
+        let server = tokio::spawn(async move {
+            let mut early = Vec::new();
+            for _ in 0..3 {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut stream = acceptor.accept(stream).await.unwrap();
+                let mut head = Vec::new();
+                if let Some(mut data) = stream.get_mut().1.early_data() {
+                    data.read_to_end(&mut head).unwrap();
+                }
+                early.push(!head.is_empty());
+                while !head.ends_with(b"\r\n\r\n") {
+                    head.push(stream.read_u8().await.unwrap());
+                }
+                stream
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok")
+                    .await
+                    .unwrap();
+                stream.flush().await.unwrap();
+            }
+            early
+        });
+
+        let client = Client::builder()
+            .skip_tls_verify()
+            .tls_early_data(true)
+            .resolve_to_addrs("localhost", &[addr.ip()])
+            .build()
+            .unwrap();
+        let send = |method| async {
+            let recorder = StatsRecorder::new();
+            let response = client
+                .request(method, format!("https://localhost:{}/", addr.port()))
+                .version(http::Version::HTTP_11)
+                .recorder(Box::new(recorder.clone()))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.text().await.unwrap(), "ok");
+            recorder.finish()
+        };
+
+        // A full handshake first, for the session ticket.
+        let stats = send(Method::GET).await;
+        assert_eq!(stats.tls_early_data, None);
+        assert!(!stats.tls_info.unwrap().resumed);
+
+        let stats = send(Method::GET).await;
+        assert_eq!(stats.tls_early_data, Some(true));
+        let tls = stats.tls_info.unwrap();
+        assert!(tls.resumed);
+        assert!(tls.protocol_version.is_some());
+        assert!(stats.tls_stats.is_some());
+
+        // Could be replayed.
+        let stats = send(Method::POST).await;
+        assert_eq!(stats.tls_early_data, None);
+        assert_eq!(server.await.unwrap(), [false, true, false]);
+    }
+}
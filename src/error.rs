@@ -1,6 +1,7 @@
 use thiserror::Error;
 
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum Error {
     #[error("unknown error")]
     Unknown,
@@ -49,6 +50,112 @@ pub enum Error {
 
     #[error("body timeout")]
     BodyTimeout,
+
+    #[error("body is not reusable")]
+    BodyNotReusable,
+
+    #[error("no mirrors given")]
+    NoMirrors,
+
+    #[error("cache body incomplete")]
+    CacheBodyIncomplete,
+
+    #[cfg(any(test, feature = "test-util"))]
+    #[error("mock transport has no response left")]
+    MockExhausted,
+
+    #[cfg(any(test, feature = "json"))]
+    #[error("json error {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[cfg(feature = "msgpack")]
+    #[error("msgpack error {0}")]
+    Msgpack(#[from] rmp_serde::decode::Error),
+
+    #[cfg(feature = "cbor")]
+    #[error("cbor error {0}")]
+    Cbor(#[from] ciborium::de::Error<std::io::Error>),
+
+    #[error("unsupported content type {0:?}")]
+    UnsupportedContentType(Option<String>),
+
+    #[error("all mirrors failed")]
+    AllMirrorsFailed(Vec<crate::mirror::MirrorAttempt>),
+
+    #[error("template variable {0} has no value")]
+    MissingVariable(String),
+
+    #[error("invalid template {0:?}")]
+    InvalidTemplate(String),
+
+    #[error("invalid csv at line {0}")]
+    InvalidCsv(usize),
+
+    #[error("no native root certificates: {0}")]
+    NativeCerts(String),
+
+    #[error("raw request head needs an HTTP/1 connection")]
+    RawHeadNotHttp1,
+
+    #[error("required protocol {required} not negotiated, got {negotiated}")]
+    ProtocolNotNegotiated {
+        required: String,
+        negotiated: String,
+    },
+
+    #[error("unsupported HTTP version {0:?}")]
+    UnsupportedVersion(http::Version),
+
+    #[error("revocation check error {0}")]
+    Revocation(String),
+
+    #[error("unsupported proxy {0}")]
+    UnsupportedProxy(String),
+
+    #[error("proxy refused to connect: {0}")]
+    ProxyConnect(http::StatusCode),
+
+    #[error("socks proxy refused to connect: {0:#04x}")]
+    SocksConnect(u8),
+
+    #[error("socks proxy authentication failed")]
+    SocksAuth,
+
+    #[error("invalid proxy response")]
+    InvalidProxyResponse,
+
+    #[error("too many redirects, {0} followed")]
+    TooManyRedirects(usize),
+
+    #[error("redirect error {0}")]
+    Redirect(String),
+
+    #[error("server certificate revoked")]
+    CertificateRevoked,
+
+    /// The server certificate failed verification, with the chain it
+    /// presented, see
+    /// [`ClientBuilder::tls_capture_rejected_chain`](crate::client::ClientBuilder::tls_capture_rejected_chain).
+    #[error("server certificate rejected: {error}")]
+    CertificateRejected {
+        error: tokio_rustls::rustls::Error,
+        chain: Vec<rustls::pki_types::CertificateDer<'static>>,
+    },
+
+    #[error("websocket handshake failed: {0}")]
+    WebSocketHandshake(String),
+
+    #[error("websocket protocol error: {0}")]
+    WebSocket(String),
+
+    #[error("upgrade failed: {0}")]
+    Upgrade(String),
+
+    #[error("tunnel target {0} has no port")]
+    InvalidTunnelTarget(String),
+
+    #[error("invalid client config: {0}")]
+    InvalidConfig(String),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
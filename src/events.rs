@@ -0,0 +1,631 @@
+//! Stream the events of requests to a consumer, e.g. to aggregate a load test
+//! while it runs.
+//!
+//! The channel is bounded: when the consumer falls behind, the
+//! [`Overflow`] policy decides between slowing the requests down and losing
+//! events, lost events are counted.
+//!
+//! ```no_run
+//! # use httptrace::{client::Client, events::{ChannelRecorder, Overflow}};
+//! # async fn run() -> httptrace::Result<()> {
+//! let (recorder, mut events) = ChannelRecorder::new(1024, Overflow::DropOldest);
+//! tokio::spawn(async move {
+//!     while let Some(event) = events.recv().await {
+//!         println!("{} {:?}", event.request_id.as_u64(), event.kind);
+//!     }
+//! });
+//!
+//! let client = Client::builder().build()?;
+//! client
+//!     .get("https://www.example.com")
+//!     .recorder(Box::new(recorder))
+//!     .send()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    collections::VecDeque,
+    error::Error,
+    net::SocketAddr,
+    sync::{
+        Arc, Condvar, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime},
+};
+
+use hickory_resolver::config::NameServerConfig;
+use http::{HeaderMap, StatusCode, Uri};
+use rustls::pki_types::CertificateDer;
+use tokio::{
+    runtime::{Handle, RuntimeFlavor},
+    sync::Notify,
+};
+
+use crate::{
+    alt_svc::AltSvcDecision,
+    dns::DnsInfo,
+    request::{Request, RequestId},
+    response::Response,
+    revocation::RevocationStatus,
+    sse::SseEvent,
+    stats::{BodyInfo, ConnectionInfo, Negotiation, Recorder, TcpInfo, TlsInfo},
+};
+
+/// What to do with an event when the channel is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Overflow {
+    /// Wait for the consumer, blocking the thread of the request.
+    ///
+    /// **The hooks are synchronous, so this blocks a runtime thread, not just
+    /// the task.** On a multi-threaded runtime the other tasks of that thread
+    /// are handed off first, with [`tokio::task::block_in_place`]. On a
+    /// current-thread runtime the consumer can't run while the request
+    /// waits, and a full channel deadlocks: only use it there with the
+    /// request on another thread, e.g. with
+    /// [`spawn_blocking`](tokio::task::spawn_blocking).
+    Block,
+    /// Make room by dropping the oldest queued event.
+    #[default]
+    DropOldest,
+    /// Drop the new event.
+    DropNewest,
+}
+
+/// An event of a request, as passed to the [`Recorder`] hooks.
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub request_id: RequestId,
+    /// The wall-clock time of the event.
+    pub at: SystemTime,
+    pub kind: EventKind,
+}
+
+/// The hook an [`Event`] comes from, failures formatted.
+#[derive(Debug, Clone)]
+pub enum EventKind {
+    BlockedStart,
+    BlockedDone,
+    DnsStart {
+        host: String,
+    },
+    DnsDone {
+        host: String,
+        result: Result<DnsInfo, String>,
+    },
+    ConnectDelay(Duration),
+    TcpStart {
+        dest: SocketAddr,
+    },
+    TcpDone {
+        dest: SocketAddr,
+        result: Result<TcpInfo, String>,
+    },
+    /// The request goes on the connection `id` of the pool.
+    ConnectionReused {
+        id: u64,
+    },
+    /// The connection `id` of the pool was found closed, the request goes on
+    /// another one.
+    ConnectionDead {
+        id: u64,
+    },
+    ProxyStart {
+        proxy: Uri,
+    },
+    ProxyDone {
+        proxy: Uri,
+        result: Result<(), String>,
+    },
+    ProxyAuthStart {
+        proxy: Uri,
+    },
+    ProxyAuthDone {
+        proxy: Uri,
+        result: Result<(), String>,
+    },
+    SocksStart {
+        proxy: Uri,
+    },
+    SocksDone {
+        proxy: Uri,
+        result: Result<(), String>,
+    },
+    H2cUpgradeStart,
+    H2cUpgradeDone(Result<bool, String>),
+    TlsStart,
+    TlsDone(Result<TlsInfo, String>),
+    TlsEarlyData(bool),
+    TlsRejectedChain(Vec<CertificateDer<'static>>),
+    RevocationStart,
+    RevocationDone(Result<RevocationStatus, String>),
+    Negotiated(Negotiation),
+    Http2Stream(u32),
+    H2Fallback(String),
+    AltSvc(AltSvcDecision),
+    RequestStart,
+    ExpectContinueStart,
+    /// `true` if the body was sent on `100 Continue`.
+    ExpectContinueDone(bool),
+    Informational {
+        status: StatusCode,
+        headers: HeaderMap,
+    },
+    Response {
+        status: StatusCode,
+    },
+    BodyDone(Result<BodyInfo, String>),
+    SseEvent(SseEvent),
+    Redirect {
+        status: StatusCode,
+        location: Uri,
+    },
+    CacheReadStart {
+        key: String,
+    },
+    CacheReadDone {
+        key: String,
+        hit: Result<bool, String>,
+    },
+    CacheWriteStart {
+        key: String,
+    },
+    CacheWriteDone {
+        key: String,
+        written: Result<u64, String>,
+    },
+    CacheRevalidateStart {
+        key: String,
+    },
+    CacheRevalidateDone {
+        key: String,
+        status: Result<StatusCode, String>,
+    },
+}
+
+/// A recorder sending every event to an [`EventReceiver`].
+///
+/// Clones send to the same channel, which is closed once all of them are
+/// dropped.
+#[derive(Clone)]
+pub struct ChannelRecorder {
+    sender: Arc<Sender>,
+}
+
+/// The receiving side of a [`ChannelRecorder`].
+pub struct EventReceiver {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    queue: Mutex<VecDeque<Event>>,
+    capacity: usize,
+    overflow: Overflow,
+    dropped: AtomicU64,
+    // Wakes the receiver on a new event or once the senders are gone.
+    notify: Notify,
+    // Wakes the blocked senders once an event is taken.
+    not_full: Condvar,
+    senders_closed: AtomicBool,
+    receiver_closed: AtomicBool,
+}
+
+struct Sender {
+    shared: Arc<Shared>,
+}
+
+impl ChannelRecorder {
+    /// A channel holding at most `capacity` events, at least one.
+    pub fn new(capacity: usize, overflow: Overflow) -> (Self, EventReceiver) {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(VecDeque::with_capacity(capacity.max(1))),
+            capacity: capacity.max(1),
+            overflow,
+            dropped: AtomicU64::new(0),
+            notify: Notify::new(),
+            not_full: Condvar::new(),
+            senders_closed: AtomicBool::new(false),
+            receiver_closed: AtomicBool::new(false),
+        });
+        let recorder = Self {
+            sender: Arc::new(Sender {
+                shared: shared.clone(),
+            }),
+        };
+        (recorder, EventReceiver { shared })
+    }
+
+    fn send(&self, request: &Request, kind: EventKind) {
+        let shared = &self.sender.shared;
+        if shared.receiver_closed.load(Ordering::Acquire) {
+            return;
+        }
+        let event = Event {
+            request_id: request.id(),
+            at: SystemTime::now(),
+            kind,
+        };
+
+        let mut queue = shared.queue.lock().unwrap();
+        if queue.len() >= shared.capacity {
+            match shared.overflow {
+                Overflow::Block => {
+                    let wait = || {
+                        shared.not_full.wait_while(queue, |queue| {
+                            queue.len() >= shared.capacity
+                                && !shared.receiver_closed.load(Ordering::Acquire)
+                        })
+                    };
+                    let flavor = Handle::try_current().map(|v| v.runtime_flavor());
+                    queue = match flavor {
+                        Ok(RuntimeFlavor::MultiThread) => tokio::task::block_in_place(wait),
+                        _ => wait(),
+                    }
+                    .unwrap();
+                    if shared.receiver_closed.load(Ordering::Acquire) {
+                        return;
+                    }
+                }
+                Overflow::DropOldest => {
+                    queue.pop_front();
+                    shared.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                Overflow::DropNewest => {
+                    shared.dropped.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+            }
+        }
+        queue.push_back(event);
+        drop(queue);
+        shared.notify.notify_one();
+    }
+}
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        self.shared.senders_closed.store(true, Ordering::Release);
+        self.shared.notify.notify_one();
+    }
+}
+
+impl EventReceiver {
+    /// The next event, `None` once every recorder is dropped and the queued
+    /// events are taken.
+    pub async fn recv(&mut self) -> Option<Event> {
+        loop {
+            if let Some(event) = self.try_recv() {
+                return Some(event);
+            }
+            if self.shared.senders_closed.load(Ordering::Acquire) {
+                // An event may have been queued before the close.
+                return self.try_recv();
+            }
+            self.shared.notify.notified().await;
+        }
+    }
+
+    /// The next event if one is queued.
+    pub fn try_recv(&mut self) -> Option<Event> {
+        let event = self.shared.queue.lock().unwrap().pop_front()?;
+        self.shared.not_full.notify_one();
+        Some(event)
+    }
+
+    /// The number of events lost to [`Overflow::DropOldest`] and
+    /// [`Overflow::DropNewest`] so far.
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for EventReceiver {
+    fn drop(&mut self) {
+        self.shared.receiver_closed.store(true, Ordering::Release);
+        // Take the lock so a sender can't miss the wake-up between its check
+        // and its wait.
+        drop(self.shared.queue.lock().unwrap());
+        self.shared.not_full.notify_all();
+    }
+}
+
+fn format_err<T>(result: Result<T, &dyn Error>) -> Result<T, String> {
+    result.map_err(|e| e.to_string())
+}
+
+impl Recorder for ChannelRecorder {
+    fn on_blocked_start(&self, request: &Request) {
+        self.send(request, EventKind::BlockedStart);
+    }
+
+    fn on_blocked_done(&self, request: &Request) {
+        self.send(request, EventKind::BlockedDone);
+    }
+
+    fn on_dns_start(&self, request: &Request, _name_servers: &[NameServerConfig], host: &str) {
+        let host = host.to_string();
+        self.send(request, EventKind::DnsStart { host });
+    }
+
+    fn on_dns_done(
+        &self,
+        request: &Request,
+        _name_servers: &[NameServerConfig],
+        host: &str,
+        result: Result<&DnsInfo, &dyn Error>,
+    ) {
+        let host = host.to_string();
+        let result = format_err(result.cloned());
+        self.send(request, EventKind::DnsDone { host, result });
+    }
+
+    fn on_connect_delay(&self, request: &Request, delay: Duration) {
+        self.send(request, EventKind::ConnectDelay(delay));
+    }
+
+    fn on_tcp_start(&self, request: &Request, dest: &SocketAddr) {
+        self.send(request, EventKind::TcpStart { dest: *dest });
+    }
+
+    fn on_tcp_done(&self, request: &Request, dest: &SocketAddr, tcp: Result<&TcpInfo, &dyn Error>) {
+        let result = format_err(tcp.cloned());
+        self.send(
+            request,
+            EventKind::TcpDone {
+                dest: *dest,
+                result,
+            },
+        );
+    }
+
+    fn on_connection_reused(&self, request: &Request, conn: &ConnectionInfo) {
+        self.send(request, EventKind::ConnectionReused { id: conn.id });
+    }
+
+    fn on_connection_dead(&self, request: &Request, conn: &ConnectionInfo) {
+        self.send(request, EventKind::ConnectionDead { id: conn.id });
+    }
+
+    fn on_tls_start(&self, request: &Request, _tcp: &TcpInfo) {
+        self.send(request, EventKind::TlsStart);
+    }
+
+    fn on_proxy_start(&self, request: &Request, proxy: &Uri) {
+        let proxy = proxy.clone();
+        self.send(request, EventKind::ProxyStart { proxy });
+    }
+
+    fn on_proxy_done(&self, request: &Request, proxy: &Uri, result: Result<(), &dyn Error>) {
+        let proxy = proxy.clone();
+        let result = format_err(result);
+        self.send(request, EventKind::ProxyDone { proxy, result });
+    }
+
+    fn on_proxy_auth_start(&self, request: &Request, proxy: &Uri) {
+        let proxy = proxy.clone();
+        self.send(request, EventKind::ProxyAuthStart { proxy });
+    }
+
+    fn on_proxy_auth_done(&self, request: &Request, proxy: &Uri, result: Result<(), &dyn Error>) {
+        let proxy = proxy.clone();
+        let result = format_err(result);
+        self.send(request, EventKind::ProxyAuthDone { proxy, result });
+    }
+
+    fn on_socks_start(&self, request: &Request, proxy: &Uri) {
+        let proxy = proxy.clone();
+        self.send(request, EventKind::SocksStart { proxy });
+    }
+
+    fn on_socks_done(&self, request: &Request, proxy: &Uri, result: Result<(), &dyn Error>) {
+        let proxy = proxy.clone();
+        let result = format_err(result);
+        self.send(request, EventKind::SocksDone { proxy, result });
+    }
+
+    fn on_h2c_upgrade_start(&self, request: &Request) {
+        self.send(request, EventKind::H2cUpgradeStart);
+    }
+
+    fn on_h2c_upgrade_done(&self, request: &Request, switched: Result<bool, &dyn Error>) {
+        self.send(request, EventKind::H2cUpgradeDone(format_err(switched)));
+    }
+
+    fn on_tls_done(&self, request: &Request, tls: Result<&TlsInfo, &dyn Error>) {
+        self.send(request, EventKind::TlsDone(format_err(tls.cloned())));
+    }
+
+    fn on_tls_early_data(&self, request: &Request, accepted: bool) {
+        self.send(request, EventKind::TlsEarlyData(accepted));
+    }
+
+    fn on_tls_rejected_chain(&self, request: &Request, chain: &[CertificateDer<'static>]) {
+        self.send(request, EventKind::TlsRejectedChain(chain.to_vec()));
+    }
+
+    fn on_revocation_start(&self, request: &Request) {
+        self.send(request, EventKind::RevocationStart);
+    }
+
+    fn on_revocation_done(&self, request: &Request, status: Result<RevocationStatus, &dyn Error>) {
+        self.send(request, EventKind::RevocationDone(format_err(status)));
+    }
+
+    fn on_negotiated(&self, request: &Request, negotiation: &Negotiation) {
+        self.send(request, EventKind::Negotiated(negotiation.clone()));
+    }
+
+    fn on_http2_stream(&self, request: &Request, stream_id: u32) {
+        self.send(request, EventKind::Http2Stream(stream_id));
+    }
+
+    fn on_h2_fallback(&self, request: &Request, error: &dyn Error) {
+        self.send(request, EventKind::H2Fallback(error.to_string()));
+    }
+
+    fn on_alt_svc(&self, request: &Request, decision: &AltSvcDecision) {
+        self.send(request, EventKind::AltSvc(decision.clone()));
+    }
+
+    fn on_request_start(&self, request: &Request) {
+        self.send(request, EventKind::RequestStart);
+    }
+
+    fn on_expect_continue_start(&self, request: &Request) {
+        self.send(request, EventKind::ExpectContinueStart);
+    }
+
+    fn on_expect_continue_done(&self, request: &Request, received: bool) {
+        self.send(request, EventKind::ExpectContinueDone(received));
+    }
+
+    fn on_informational(&self, request: &Request, status: StatusCode, headers: &HeaderMap) {
+        let headers = headers.clone();
+        self.send(request, EventKind::Informational { status, headers });
+    }
+
+    fn on_response(&self, request: &Request, response: &Response) {
+        let status = response.status();
+        self.send(request, EventKind::Response { status });
+    }
+
+    fn on_body_done(&self, request: &Request, body: Result<&BodyInfo, &dyn Error>) {
+        self.send(request, EventKind::BodyDone(format_err(body.cloned())));
+    }
+
+    fn on_sse_event(&self, request: &Request, event: &SseEvent) {
+        self.send(request, EventKind::SseEvent(event.clone()));
+    }
+
+    fn on_redirect(&self, request: &Request, status: StatusCode, location: &Uri) {
+        let location = location.clone();
+        self.send(request, EventKind::Redirect { status, location });
+    }
+
+    fn on_cache_read_start(&self, request: &Request, key: &str) {
+        let key = key.to_string();
+        self.send(request, EventKind::CacheReadStart { key });
+    }
+
+    fn on_cache_read_done(&self, request: &Request, key: &str, hit: Result<bool, &dyn Error>) {
+        let key = key.to_string();
+        let hit = format_err(hit);
+        self.send(request, EventKind::CacheReadDone { key, hit });
+    }
+
+    fn on_cache_write_start(&self, request: &Request, key: &str) {
+        let key = key.to_string();
+        self.send(request, EventKind::CacheWriteStart { key });
+    }
+
+    fn on_cache_write_done(&self, request: &Request, key: &str, written: Result<u64, &dyn Error>) {
+        let key = key.to_string();
+        let written = format_err(written);
+        self.send(request, EventKind::CacheWriteDone { key, written });
+    }
+
+    fn on_cache_revalidate_start(&self, request: &Request, key: &str) {
+        let key = key.to_string();
+        self.send(request, EventKind::CacheRevalidateStart { key });
+    }
+
+    fn on_cache_revalidate_done(
+        &self,
+        request: &Request,
+        key: &str,
+        status: Result<StatusCode, &dyn Error>,
+    ) {
+        let key = key.to_string();
+        let status = format_err(status);
+        self.send(request, EventKind::CacheRevalidateDone { key, status });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use super::{ChannelRecorder, EventKind, Overflow};
+    use crate::{
+        client::Client,
+        test_util::{MockResponse, MockTransport},
+    };
+
+    #[tokio::test]
+    async fn test_channel_recorder() {
+        let mock = MockTransport::new();
+        for _ in 0..3 {
+            mock.push(MockResponse::new(200));
+        }
+        let client = Client::builder().mock_transport(mock).build().unwrap();
+
+        let (recorder, mut events) = ChannelRecorder::new(2, Overflow::DropNewest);
+        let mut request = client.get("http://example.com").build().unwrap();
+        *request.recorder_mut() = Some(Arc::new(recorder.clone()));
+        client.execute(request).await.unwrap();
+        let first = events.try_recv().unwrap();
+        assert!(matches!(first.kind, EventKind::DnsStart { ref host } if host == "example.com"));
+        assert!(events.dropped() > 0);
+
+        let (recorder, mut events) = ChannelRecorder::new(1, Overflow::DropOldest);
+        let mut request = client.get("http://example.com").build().unwrap();
+        *request.recorder_mut() = Some(Arc::new(recorder));
+        client
+            .execute(request)
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        let last = events.recv().await.unwrap();
+        assert!(matches!(last.kind, EventKind::BodyDone(Ok(_))));
+        // Every recorder is dropped with the request.
+        assert!(events.recv().await.is_none());
+
+        let (recorder, mut events) = ChannelRecorder::new(1, Overflow::Block);
+        let consumer = tokio::spawn(async move {
+            let mut count = 0;
+            while events.recv().await.is_some() {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+                count += 1;
+            }
+            (count, events.dropped())
+        });
+        let mut request = client.get("http://example.com").build().unwrap();
+        *request.recorder_mut() = Some(Arc::new(recorder));
+        tokio::task::spawn_blocking(move || {
+            tokio::runtime::Handle::current().block_on(client.execute(request))
+        })
+        .await
+        .unwrap()
+        .unwrap();
+        let (count, dropped) = consumer.await.unwrap();
+        assert!(count > 2);
+        assert_eq!(dropped, 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_block_multi_thread() {
+        let mock = MockTransport::new();
+        mock.push(MockResponse::new(200));
+        let client = Client::builder().mock_transport(mock).build().unwrap();
+
+        // The only worker waits for the consumer, which runs on it once handed
+        // off.
+        let (recorder, mut events) = ChannelRecorder::new(1, Overflow::Block);
+        let consumer = tokio::spawn(async move {
+            let mut count = 0;
+            while events.recv().await.is_some() {
+                count += 1;
+            }
+            count
+        });
+        let mut request = client.get("http://example.com").build().unwrap();
+        *request.recorder_mut() = Some(Arc::new(recorder));
+        let send = tokio::spawn(async move { client.execute(request).await.map(drop) });
+        send.await.unwrap().unwrap();
+        assert!(consumer.await.unwrap() > 2);
+    }
+}
@@ -0,0 +1,350 @@
+//! `Expect: 100-continue`, see
+//! [`RequestBuilder::expect_continue`](crate::request::RequestBuilder::expect_continue).
+//!
+//! hyper skips the interim response, so [`ContinueIo`] spots it in the bytes
+//! read from the connection and releases the body held by [`ContinueBody`].
+//! After a final response instead, the body is withheld until the response
+//! held by [`ResponseGuard`] is dropped.
+
+use std::{
+    pin::Pin,
+    task::{Context, Poll, ready},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use http_body::{Body as HttpBody, Frame, SizeHint};
+use pin_project_lite::pin_project;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    sync::oneshot,
+    time::Sleep,
+};
+
+use crate::{Body, request::Request};
+
+/// How long to hold the body without an answer, as curl does.
+const CONTINUE_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Enough of the status line to tell a `100`.
+const STATUS_PREFIX_LEN: usize = b"HTTP/1.1 100".len();
+
+/// The pair waiting for `100 Continue`: the connection and the body to hold
+/// until it's read on it, with the guard to put on the response. The wait is
+/// reported to the recorder of `request`.
+pub(crate) fn wrap<T>(
+    io: T,
+    body: Body,
+    request: Option<Request>,
+) -> (ContinueIo<T>, ContinueBody, ResponseGuard) {
+    let (tx, rx) = oneshot::channel();
+    let (guard, response_dropped) = oneshot::channel();
+    let io = ContinueIo {
+        inner: io,
+        status: Vec::with_capacity(STATUS_PREFIX_LEN),
+        tx: Some(tx),
+    };
+    let body = ContinueBody {
+        inner: body,
+        rx: Some(rx),
+        timeout: None,
+        withheld: false,
+        response_dropped: Some(response_dropped),
+        request,
+    };
+    (io, body, ResponseGuard(guard))
+}
+
+/// Dropped with the response, ending a withheld body.
+pub(crate) struct ResponseGuard(#[allow(dead_code)] oneshot::Sender<()>);
+
+/// `body`, keeping `guard` until it's dropped.
+pub(crate) fn guarded<B>(body: B, guard: ResponseGuard) -> GuardedBody<B> {
+    GuardedBody {
+        inner: body,
+        _guard: guard,
+    }
+}
+
+pin_project! {
+    /// A connection telling the body whether the response starts with
+    /// `100 Continue`.
+    pub(crate) struct ContinueIo<T> {
+        #[pin]
+        inner: T,
+        status: Vec<u8>,
+        // `None` once the start of the response is read.
+        tx: Option<oneshot::Sender<()>>,
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for ContinueIo<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.project();
+        let filled = buf.filled().len();
+        let ret = this.inner.poll_read(cx, buf);
+        if this.tx.is_some() {
+            let read = &buf.filled()[filled..];
+            let n = read.len().min(STATUS_PREFIX_LEN - this.status.len());
+            this.status.extend_from_slice(&read[..n]);
+            if this.status.len() == STATUS_PREFIX_LEN {
+                let tx = this.tx.take();
+                if this.status.starts_with(b"HTTP/1.") && this.status.ends_with(b" 100") {
+                    _ = tx.map(|tx| tx.send(()));
+                }
+            }
+        }
+        ret
+    }
+}
+
+impl<T: AsyncWrite> AsyncWrite for ContinueIo<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.project().inner.poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[std::io::IoSlice<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        self.project().inner.poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.project().inner.poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.project().inner.poll_shutdown(cx)
+    }
+}
+
+pin_project! {
+    /// A request body held until `100 Continue` or the timeout. If the
+    /// server answers with a final response instead, it's never sent.
+    pub(crate) struct ContinueBody {
+        #[pin]
+        inner: Body,
+        // `None` once released or withheld.
+        rx: Option<oneshot::Receiver<()>>,
+        timeout: Option<Pin<Box<Sleep>>>,
+        // Whether a final response came first, the body then ends empty.
+        withheld: bool,
+        // Ready once the response is dropped, `None` after.
+        response_dropped: Option<oneshot::Receiver<()>>,
+        request: Option<Request>,
+    }
+}
+
+pin_project! {
+    /// A response body holding the [`ResponseGuard`] of its request.
+    pub(crate) struct GuardedBody<B> {
+        #[pin]
+        inner: B,
+        _guard: ResponseGuard,
+    }
+}
+
+impl HttpBody for ContinueBody {
+    type Data = Bytes;
+    type Error = crate::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        if let Some(rx) = this.rx.as_mut() {
+            // Polled once the head is written.
+            let timeout = this.timeout.get_or_insert_with(|| {
+                if let Some(request) = this.request.as_ref()
+                    && let Some(recorder) = request.recorder()
+                {
+                    recorder.on_expect_continue_start(request);
+                }
+                Box::pin(tokio::time::sleep(CONTINUE_TIMEOUT))
+            });
+            let received = match Pin::new(rx).poll(cx) {
+                Poll::Ready(Ok(())) => true,
+                // A final response, the server doesn't want the body.
+                Poll::Ready(Err(_)) => {
+                    *this.withheld = true;
+                    false
+                }
+                Poll::Pending => match timeout.as_mut().poll(cx) {
+                    Poll::Ready(()) => false,
+                    Poll::Pending => return Poll::Pending,
+                },
+            };
+            *this.rx = None;
+            if let Some(request) = this.request.take()
+                && let Some(recorder) = request.recorder()
+            {
+                recorder.on_expect_continue_done(&request, received);
+            }
+        }
+        if *this.withheld {
+            // Ending the body short fails the connection, not before the
+            // response is read then.
+            if let Some(rx) = this.response_dropped.as_mut() {
+                _ = ready!(Pin::new(rx).poll(cx));
+                *this.response_dropped = None;
+            }
+            return Poll::Ready(None);
+        }
+        this.inner.poll_frame(cx)
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match self.withheld {
+            true => self.response_dropped.is_none(),
+            false => self.inner.is_end_stream(),
+        }
+    }
+}
+
+impl<B: HttpBody> HttpBody for GuardedBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        self.project().inner.poll_frame(cx)
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use crate::{client::Client, stats::StatsRecorder};
+
+    /// Answer one request, with `100 Continue` first if `interim`, and return
+    /// its head and body.
+    async fn serve(listener: TcpListener, interim: bool) -> (String, String) {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            head.push(stream.read_u8().await.unwrap());
+        }
+        if interim {
+            stream
+                .write_all(b"HTTP/1.1 100 Continue\r\n\r\n")
+                .await
+                .unwrap();
+        }
+        let mut body = [0; 5];
+        stream.read_exact(&mut body).await.unwrap();
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok")
+            .await
+            .unwrap();
+        (
+            String::from_utf8(head).unwrap(),
+            String::from_utf8(body.to_vec()).unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_expect_continue() {
+        for interim in [true, false] {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let server = tokio::spawn(serve(listener, interim));
+
+            let recorder = StatsRecorder::new();
+            let response = Client::builder()
+                .build()
+                .unwrap()
+                .post(format!("http://{addr}/"))
+                .body("hello")
+                .expect_continue()
+                .recorder(Box::new(recorder.clone()))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.text().await.unwrap(), "ok");
+
+            let (head, body) = server.await.unwrap();
+            assert!(head.contains("expect: 100-continue\r\n"));
+            assert_eq!(body, "hello");
+            let stat = recorder.finish().expect_continue_stats.unwrap();
+            if interim {
+                assert_eq!(stat.extend.as_deref(), Some("100 Continue"));
+            } else {
+                assert!(stat.extend.is_none());
+                assert!(stat.duration >= super::CONTINUE_TIMEOUT);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_final_response() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut head = Vec::new();
+            while !head.ends_with(b"\r\n\r\n") {
+                head.push(stream.read_u8().await.unwrap());
+            }
+            stream
+                .write_all(b"HTTP/1.1 417 Expectation Failed\r\ncontent-length: 4\r\n\r\nnope")
+                .await
+                .unwrap();
+            let mut rest = Vec::new();
+            _ = stream.read_to_end(&mut rest).await;
+            rest
+        });
+
+        let recorder = StatsRecorder::new();
+        let client = Client::builder().build().unwrap();
+        let response = client
+            .post(format!("http://{addr}/"))
+            .body("hello")
+            .expect_continue()
+            .recorder(Box::new(recorder.clone()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 417);
+        assert_eq!(response.text().await.unwrap(), "nope");
+        drop(client);
+
+        // Neither sent nor waited for.
+        assert_eq!(server.await.unwrap(), b"");
+        let stat = recorder.finish().expect_continue_stats.unwrap();
+        assert!(stat.extend.is_none());
+        assert!(stat.duration < super::CONTINUE_TIMEOUT);
+    }
+}
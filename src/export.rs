@@ -0,0 +1,256 @@
+//! Publish the [`Stats`] of requests to existing telemetry stacks, through the
+//! `metrics` facade or straight to a StatsD agent.
+
+use std::{
+    io,
+    net::{ToSocketAddrs, UdpSocket},
+};
+
+use crate::{aggregate::Phase, stats::Stats};
+
+/// Record the stats through the [`metrics`] facade, with `labels` on every
+/// metric. Requires the `metrics` feature.
+///
+/// Emits the `httptrace_requests_total` and `httptrace_errors_total`
+/// counters, the `httptrace_body_bytes_total` counter once the body was read,
+/// and the `httptrace_phase_duration_seconds` histogram with a `phase` label
+/// for every phase the request went through.
+#[cfg(feature = "metrics")]
+pub fn record_metrics(stats: &Stats, labels: &[(&'static str, String)]) {
+    use metrics::Label;
+
+    let labels = labels
+        .iter()
+        .map(|(key, value)| Label::new(*key, value.clone()))
+        .collect::<Vec<_>>();
+
+    metrics::counter!("httptrace_requests_total", labels.clone()).increment(1);
+    if stats.error().is_some() {
+        metrics::counter!("httptrace_errors_total", labels.clone()).increment(1);
+    }
+    if let Some(body) = stats.body_info.as_ref() {
+        metrics::counter!("httptrace_body_bytes_total", labels.clone()).increment(body.len);
+    }
+    for phase in Phase::ALL {
+        if let Some(duration) = phase.duration(stats) {
+            let mut labels = labels.clone();
+            labels.push(Label::new("phase", phase.to_string()));
+            metrics::histogram!("httptrace_phase_duration_seconds", labels)
+                .record(duration.as_secs_f64());
+        }
+    }
+}
+
+/// Sends the stats of each request to a StatsD agent over UDP, with DogStatsD
+/// tags if any are set.
+#[derive(Debug)]
+pub struct StatsdExporter {
+    socket: UdpSocket,
+    prefix: String,
+    tags: Vec<(String, String)>,
+}
+
+impl StatsdExporter {
+    /// Send to the agent at `addr`, e.g. `127.0.0.1:8125`.
+    pub fn new<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let addr = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::AddrNotAvailable))?;
+        let local = match addr {
+            std::net::SocketAddr::V4(_) => "0.0.0.0:0",
+            std::net::SocketAddr::V6(_) => "[::]:0",
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(addr)?;
+        Ok(Self {
+            socket,
+            prefix: "httptrace".to_string(),
+            tags: Vec::new(),
+        })
+    }
+
+    /// The prefix of the metric names, `httptrace` by default.
+    pub fn prefix<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Add a DogStatsD tag to every metric.
+    pub fn tag<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.tags.push((key.into(), value.into()));
+        self
+    }
+
+    /// Send the stats of a request, all metrics in one datagram.
+    pub fn send(&self, stats: &Stats) -> io::Result<()> {
+        self.socket.send(self.lines(stats).join("\n").as_bytes())?;
+        Ok(())
+    }
+
+    fn lines(&self, stats: &Stats) -> Vec<String> {
+        let tags = match self.tags.is_empty() {
+            true => String::new(),
+            false => {
+                let tags = self
+                    .tags
+                    .iter()
+                    .map(|(key, value)| format!("{}:{}", key, value))
+                    .collect::<Vec<_>>();
+                format!("|#{}", tags.join(","))
+            }
+        };
+
+        let mut lines = vec![format!("{}.requests:1|c{}", self.prefix, tags)];
+        if stats.error().is_some() {
+            lines.push(format!("{}.errors:1|c{}", self.prefix, tags));
+        }
+        if let Some(body) = stats.body_info.as_ref() {
+            lines.push(format!("{}.body_bytes:{}|c{}", self.prefix, body.len, tags));
+        }
+        for phase in Phase::ALL {
+            if let Some(duration) = phase.duration(stats) {
+                let ms = duration.as_secs_f64() * 1000.0;
+                lines.push(format!("{}.{}:{}|ms{}", self.prefix, phase, ms, tags));
+            }
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::UdpSocket, time::Duration};
+
+    use super::StatsdExporter;
+    use crate::stats::{Stat, Stats};
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_record_metrics() {
+        use std::sync::{Arc, Mutex};
+
+        use metrics::{
+            Counter, CounterFn, Gauge, Histogram, HistogramFn, Key, KeyName, Metadata, Recorder,
+            SharedString, Unit,
+        };
+
+        use crate::stats::BodyInfo;
+
+        // Every value recorded, as `name{labels} value`.
+        #[derive(Default)]
+        struct Captured(Arc<Mutex<Vec<String>>>);
+
+        struct Handle {
+            key: String,
+            values: Arc<Mutex<Vec<String>>>,
+        }
+
+        impl CounterFn for Handle {
+            fn increment(&self, value: u64) {
+                self.values
+                    .lock()
+                    .unwrap()
+                    .push(format!("{} {value}", self.key));
+            }
+
+            fn absolute(&self, _value: u64) {}
+        }
+
+        impl HistogramFn for Handle {
+            fn record(&self, value: f64) {
+                self.values
+                    .lock()
+                    .unwrap()
+                    .push(format!("{} {value}", self.key));
+            }
+        }
+
+        impl Captured {
+            fn handle(&self, key: &Key) -> Arc<Handle> {
+                let labels = key
+                    .labels()
+                    .map(|v| format!("{}={}", v.key(), v.value()))
+                    .collect::<Vec<_>>();
+                Arc::new(Handle {
+                    key: format!("{}{{{}}}", key.name(), labels.join(",")),
+                    values: self.0.clone(),
+                })
+            }
+        }
+
+        impl Recorder for Captured {
+            fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+            fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+            fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+            fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+                Counter::from_arc(self.handle(key))
+            }
+
+            fn register_gauge(&self, _: &Key, _: &Metadata<'_>) -> Gauge {
+                Gauge::noop()
+            }
+
+            fn register_histogram(&self, key: &Key, _: &Metadata<'_>) -> Histogram {
+                Histogram::from_arc(self.handle(key))
+            }
+        }
+
+        let stats = Stats {
+            request_stats: Some(Stat {
+                duration: Duration::from_micros(1500),
+                error: Some("reset".to_string()),
+                ..Default::default()
+            }),
+            body_info: Some(BodyInfo {
+                len: 5,
+                sha256: None,
+            }),
+            total_duration: Duration::from_millis(20),
+            ..Default::default()
+        };
+        let recorder = Captured::default();
+        metrics::with_local_recorder(&recorder, || {
+            super::record_metrics(&stats, &[("probe", "home".to_string())]);
+        });
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            [
+                "httptrace_requests_total{probe=home} 1",
+                "httptrace_errors_total{probe=home} 1",
+                "httptrace_body_bytes_total{probe=home} 5",
+                "httptrace_phase_duration_seconds{probe=home,phase=total} 0.02",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_statsd() {
+        let agent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let exporter = StatsdExporter::new(agent.local_addr().unwrap())
+            .unwrap()
+            .prefix("probe")
+            .tag("env", "test");
+
+        let stats = Stats {
+            request_stats: Some(Stat {
+                duration: Duration::from_micros(1500),
+                error: Some("reset".to_string()),
+                ..Default::default()
+            }),
+            total_duration: Duration::from_millis(20),
+            ..Default::default()
+        };
+        exporter.send(&stats).unwrap();
+
+        let mut buf = [0; 1024];
+        let len = agent.recv(&mut buf).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&buf[..len]).unwrap(),
+            "probe.requests:1|c|#env:test\n\
+             probe.errors:1|c|#env:test\n\
+             probe.total:20|ms|#env:test"
+        );
+    }
+}
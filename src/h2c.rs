@@ -0,0 +1,349 @@
+//! Switching a plain HTTP/1.1 connection to HTTP/2 with `Upgrade: h2c`, see
+//! [`ClientBuilder::http2_upgrade`](crate::client::ClientBuilder::http2_upgrade).
+//!
+//! After the `101`, the server answers the upgrade request on stream 1 over
+//! HTTP/2, which hyper's client can't adopt. So [`H2cIo`] lets the client
+//! open stream 1 with a stand-in request but never writes its frames: the
+//! response on stream 1 then goes to the stand-in. The stand-in only uses
+//! static HPACK entries, so dropping its header block leaves the server's
+//! table in sync.
+
+use std::{
+    io::IoSlice,
+    pin::Pin,
+    task::{Context, Poll, Waker, ready},
+};
+
+use http::{HeaderMap, HeaderValue, Method, header};
+use pin_project_lite::pin_project;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::{Body, request::Request};
+
+const PREFACE_LEN: usize = 24;
+const FRAME_HEAD_LEN: usize = 9;
+
+const FRAME_DATA: u8 = 0x0;
+const FRAME_HEADERS: u8 = 0x1;
+const FRAME_CONTINUATION: u8 = 0x9;
+const FLAG_END_HEADERS: u8 = 0x4;
+
+// SETTINGS_ENABLE_PUSH = 0, base64url encoded.
+const HTTP2_SETTINGS: &str = "AAIAAAAA";
+
+/// Whether `request` can carry an upgrade: a body would have to be sent
+/// before switching, so only requests without one do.
+pub(crate) fn can_upgrade(request: &Request) -> bool {
+    request.method() != Method::CONNECT
+        && request.raw_head().is_none()
+        && !request.headers().contains_key(header::UPGRADE)
+        && request
+            .body()
+            .is_none_or(|body| body.as_bytes() == Some(&[]) && body.trailers().is_none())
+}
+
+pub(crate) fn set_upgrade_headers(headers: &mut HeaderMap) {
+    headers.insert(
+        header::CONNECTION,
+        HeaderValue::from_static("Upgrade, HTTP2-Settings"),
+    );
+    headers.insert(header::UPGRADE, HeaderValue::from_static("h2c"));
+    headers.insert("http2-settings", HeaderValue::from_static(HTTP2_SETTINGS));
+}
+
+/// The request standing in for the upgrade request on stream 1, encoded
+/// with static HPACK entries only.
+pub(crate) fn stand_in() -> http::Request<Body> {
+    let mut request = http::Request::new(Body::empty());
+    *request.uri_mut() = http::Uri::from_static("/");
+    // A relative URI is only allowed for requests forwarded from HTTP/1.
+    *request.version_mut() = http::Version::HTTP_11;
+    request
+}
+
+pin_project! {
+    /// The upgraded connection, dropping the frames of the stand-in request
+    /// and holding the reads until they are dropped so the response can't
+    /// arrive before the client opened stream 1.
+    pub(crate) struct H2cIo<T> {
+        #[pin]
+        inner: T,
+        preface_left: usize,
+        head: Vec<u8>,
+        // The payload bytes left of the current frame, and whether to write
+        // them.
+        payload_left: usize,
+        forward: bool,
+        // Parsed bytes waiting to be written.
+        out: Vec<u8>,
+        stand_in_dropped: bool,
+        read_waker: Option<Waker>,
+    }
+}
+
+impl<T> H2cIo<T> {
+    pub(crate) fn new(inner: T) -> Self {
+        H2cIo {
+            inner,
+            preface_left: PREFACE_LEN,
+            head: Vec::with_capacity(FRAME_HEAD_LEN),
+            payload_left: 0,
+            forward: true,
+            out: Vec::new(),
+            stand_in_dropped: false,
+            read_waker: None,
+        }
+    }
+}
+
+impl<T: AsyncWrite> H2cIo<T> {
+    fn poll_write_out(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let mut this = self.project();
+        while !this.out.is_empty() {
+            let n = ready!(this.inner.as_mut().poll_write(cx, this.out))?;
+            if n == 0 {
+                return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()));
+            }
+            this.out.drain(..n);
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Sort `buf` into frames, keeping those to write in `out`.
+    fn parse(self: Pin<&mut Self>, mut buf: &[u8]) {
+        let this = self.project();
+        while !buf.is_empty() {
+            if *this.preface_left > 0 {
+                let n = buf.len().min(*this.preface_left);
+                this.out.extend_from_slice(&buf[..n]);
+                *this.preface_left -= n;
+                buf = &buf[n..];
+            } else if *this.payload_left > 0 {
+                let n = buf.len().min(*this.payload_left);
+                if *this.forward {
+                    this.out.extend_from_slice(&buf[..n]);
+                }
+                *this.payload_left -= n;
+                buf = &buf[n..];
+            } else {
+                let n = buf.len().min(FRAME_HEAD_LEN - this.head.len());
+                this.head.extend_from_slice(&buf[..n]);
+                buf = &buf[n..];
+                if this.head.len() < FRAME_HEAD_LEN {
+                    continue;
+                }
+
+                let head = std::mem::take(this.head);
+                let len = u32::from_be_bytes([0, head[0], head[1], head[2]]) as usize;
+                let (kind, flags) = (head[3], head[4]);
+                let stream = u32::from_be_bytes([head[5], head[6], head[7], head[8]]) & 0x7fff_ffff;
+                let stand_in =
+                    stream == 1 && matches!(kind, FRAME_DATA | FRAME_HEADERS | FRAME_CONTINUATION);
+                *this.forward = !stand_in;
+                *this.payload_left = len;
+                if *this.forward {
+                    this.out.extend_from_slice(&head);
+                } else if kind != FRAME_DATA && flags & FLAG_END_HEADERS != 0 {
+                    *this.stand_in_dropped = true;
+                    if let Some(waker) = this.read_waker.take() {
+                        waker.wake();
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for H2cIo<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.project();
+        if !*this.stand_in_dropped {
+            *this.read_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        this.inner.poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite> AsyncWrite for H2cIo<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        ready!(self.as_mut().poll_write_out(cx))?;
+        self.as_mut().parse(buf);
+        // Written on the next call if the inner IO isn't ready.
+        if let Poll::Ready(Err(e)) = self.poll_write_out(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        ready!(self.as_mut().poll_write_out(cx))?;
+        for buf in bufs {
+            self.as_mut().parse(buf);
+        }
+        if let Poll::Ready(Err(e)) = self.poll_write_out(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Poll::Ready(Ok(bufs.iter().map(|buf| buf.len()).sum()))
+    }
+
+    // The buffers are gathered in `out`, then written at once.
+    fn is_write_vectored(&self) -> bool {
+        true
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        ready!(self.as_mut().poll_write_out(cx))?;
+        self.project().inner.poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        ready!(self.as_mut().poll_write_out(cx))?;
+        self.project().inner.poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::IoSlice;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::H2cIo;
+    use crate::{
+        client::Client,
+        stats::{NegotiationReason, StatsRecorder},
+        test_util::TestServer,
+    };
+
+    fn frame(kind: u8, flags: u8, stream: u32, payload: &[u8]) -> Vec<u8> {
+        let mut frame = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+        frame.extend_from_slice(&[kind, flags]);
+        frame.extend_from_slice(&stream.to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[tokio::test]
+    async fn test_drop_stand_in() {
+        let (client, mut server) = tokio::io::duplex(1024);
+        let mut io = H2cIo::new(client);
+
+        let preface = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+        let settings = frame(0x4, 0, 0, &[]);
+        let stand_in = frame(0x1, 0x5, 1, &[0x82, 0x86, 0x84]);
+        let request = frame(0x1, 0x5, 3, &[0x82]);
+        let mut sent = preface.to_vec();
+        sent.extend_from_slice(&settings);
+        sent.extend_from_slice(&stand_in);
+        sent.extend_from_slice(&request);
+        // Cut anywhere, the end in one vectored write.
+        let (start, end) = sent.split_at(30);
+        for chunk in start.chunks(5) {
+            io.write_all(chunk).await.unwrap();
+        }
+        let bufs = end.chunks(7).map(IoSlice::new).collect::<Vec<_>>();
+        assert_eq!(io.write_vectored(&bufs).await.unwrap(), end.len());
+        io.flush().await.unwrap();
+
+        let mut expected = preface.to_vec();
+        expected.extend_from_slice(&settings);
+        expected.extend_from_slice(&request);
+        let mut received = vec![0; expected.len()];
+        server.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, expected);
+
+        // Reading waits for the stand-in, now dropped.
+        server.write_all(b"response").await.unwrap();
+        let mut buf = [0; 8];
+        io.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"response");
+    }
+
+    #[tokio::test]
+    async fn test_http2_upgrade() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut head = Vec::new();
+            while !head.ends_with(b"\r\n\r\n") {
+                head.push(stream.read_u8().await.unwrap());
+            }
+            stream
+                .write_all(b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: h2c\r\n\r\n")
+                .await
+                .unwrap();
+            stream.write_all(&frame(0x4, 0, 0, &[])).await.unwrap();
+            let mut preface = [0; 24];
+            stream.read_exact(&mut preface).await.unwrap();
+            // `:status: 200` answering the upgrade request on stream 1.
+            stream
+                .write_all(&frame(0x1, 0x4, 1, &[0x88]))
+                .await
+                .unwrap();
+            stream.write_all(&frame(0x0, 0x1, 1, b"ok")).await.unwrap();
+            // Until the client is done.
+            _ = stream.read_to_end(&mut Vec::new()).await;
+            (String::from_utf8(head).unwrap(), preface)
+        });
+
+        let client = Client::builder().http2_upgrade().build().unwrap();
+        let recorder = StatsRecorder::new();
+        let response = client
+            .get(format!("http://{addr}/"))
+            .recorder(Box::new(recorder.clone()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.version(), http::Version::HTTP_2);
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.text().await.unwrap(), "ok");
+        drop(client);
+        let stats = recorder.finish();
+        assert!(stats.h2c_upgrade_stats.unwrap().error.is_none());
+        assert_eq!(
+            stats.negotiation.unwrap().reason,
+            NegotiationReason::Upgrade
+        );
+
+        let (head, preface) = server.await.unwrap();
+        assert!(head.contains("upgrade: h2c\r\n"));
+        assert!(head.contains("http2-settings: AAIAAAAA\r\n"));
+        assert_eq!(&preface, b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n");
+
+        // Servers ignoring the upgrade answer over HTTP/1.1.
+        let server = TestServer::builder().body("ok").start().await.unwrap();
+        let client = Client::builder().http2_upgrade().build().unwrap();
+        let recorder = StatsRecorder::new();
+        let response = client
+            .get(server.url("/"))
+            .recorder(Box::new(recorder.clone()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.version(), http::Version::HTTP_11);
+        assert_eq!(response.text().await.unwrap(), "ok");
+        let stats = recorder.finish();
+        assert!(stats.h2c_upgrade_stats.is_some());
+        assert_eq!(
+            stats.negotiation.unwrap().reason,
+            NegotiationReason::UpgradeRefused
+        );
+    }
+}
@@ -0,0 +1,143 @@
+//! The PROXY protocol header of HAProxy, see
+//! [`ClientBuilder::haproxy_protocol`](crate::client::ClientBuilder::haproxy_protocol).
+//!
+//! Load balancers requiring it read the address of the original client from
+//! it before the request; here it's the address of the connection itself.
+
+use std::net::{IpAddr, SocketAddr};
+
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// The version of the PROXY protocol header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    /// The human-readable header, e.g. `PROXY TCP4 10.0.0.1 10.0.0.2 5000 80`.
+    V1,
+    /// The binary header.
+    V2,
+}
+
+impl Version {
+    /// The header of a TCP connection from `src` to `dst`, IPv4 addresses
+    /// being mapped to IPv6 if the other one is IPv6.
+    pub(crate) fn header(self, src: SocketAddr, dst: SocketAddr) -> Vec<u8> {
+        let (src_ip, dst_ip) = match (src.ip(), dst.ip()) {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => (IpAddr::V4(src), IpAddr::V4(dst)),
+            (src, dst) => (IpAddr::V6(to_v6(src)), IpAddr::V6(to_v6(dst))),
+        };
+        match self {
+            Version::V1 => {
+                let family = match src_ip {
+                    IpAddr::V4(_) => "TCP4",
+                    IpAddr::V6(_) => "TCP6",
+                };
+                format!(
+                    "PROXY {family} {src_ip} {dst_ip} {} {}\r\n",
+                    src.port(),
+                    dst.port()
+                )
+                .into_bytes()
+            }
+            Version::V2 => {
+                let mut header = V2_SIGNATURE.to_vec();
+                // Version 2, PROXY command.
+                header.push(0x21);
+                match (src_ip, dst_ip) {
+                    (IpAddr::V4(src_ip), IpAddr::V4(dst_ip)) => {
+                        // TCP over IPv4.
+                        header.push(0x11);
+                        header.extend_from_slice(&12u16.to_be_bytes());
+                        header.extend_from_slice(&src_ip.octets());
+                        header.extend_from_slice(&dst_ip.octets());
+                    }
+                    (src_ip, dst_ip) => {
+                        // TCP over IPv6.
+                        header.push(0x21);
+                        header.extend_from_slice(&36u16.to_be_bytes());
+                        header.extend_from_slice(&to_v6(src_ip).octets());
+                        header.extend_from_slice(&to_v6(dst_ip).octets());
+                    }
+                }
+                header.extend_from_slice(&src.port().to_be_bytes());
+                header.extend_from_slice(&dst.port().to_be_bytes());
+                header
+            }
+        }
+    }
+}
+
+fn to_v6(ip: IpAddr) -> std::net::Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+
+    use super::Version;
+    use crate::client::Client;
+
+    #[test]
+    fn test_header() {
+        let src = "10.0.0.1:5000".parse().unwrap();
+        let dst = "10.0.0.2:80".parse().unwrap();
+        assert_eq!(
+            Version::V1.header(src, dst),
+            b"PROXY TCP4 10.0.0.1 10.0.0.2 5000 80\r\n"
+        );
+        let header = Version::V2.header(src, dst);
+        assert_eq!(header.len(), 16 + 12);
+        assert_eq!(&header[12..16], [0x21, 0x11, 0, 12]);
+        assert_eq!(&header[16..], [10, 0, 0, 1, 10, 0, 0, 2, 0x13, 0x88, 0, 80]);
+
+        let v6 = "[::1]:443".parse().unwrap();
+        assert_eq!(
+            Version::V1.header(src, v6),
+            b"PROXY TCP6 ::ffff:10.0.0.1 ::1 5000 443\r\n"
+        );
+        assert_eq!(Version::V2.header(src, v6).len(), 16 + 36);
+    }
+
+    #[tokio::test]
+    async fn test_haproxy_protocol() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (stream, peer) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+            let mut line = String::new();
+            stream.read_line(&mut line).await.unwrap();
+            let mut head = [0u8; 4];
+            stream.read_exact(&mut head).await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .await
+                .unwrap();
+            (line, peer, head)
+        });
+
+        let client = Client::builder()
+            .haproxy_protocol(Version::V1)
+            .build()
+            .unwrap();
+        let response = client.get(format!("http://{addr}/")).send().await.unwrap();
+        assert_eq!(response.status(), 204);
+        let (line, peer, head) = server.await.unwrap();
+        assert_eq!(
+            line,
+            format!(
+                "PROXY TCP4 127.0.0.1 127.0.0.1 {} {}\r\n",
+                peer.port(),
+                addr.port()
+            )
+        );
+        // The request follows.
+        assert_eq!(&head, b"GET ");
+    }
+}
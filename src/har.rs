@@ -0,0 +1,300 @@
+//! Replay the requests of a HAR (HTTP Archive) file, e.g. one exported from
+//! browser developer tools, and compare the fresh timings to the recorded
+//! ones.
+//!
+//! Requires the `json` feature.
+//!
+//! ```no_run
+//! # use httptrace::{aggregate::Phase, client::Client, har::Har};
+//! # async fn run() -> httptrace::Result<()> {
+//! let har = Har::load("session.har").await?;
+//! let client = Client::builder().build()?;
+//! for result in har.replay(&client, true).await {
+//!     println!(
+//!         "{} recorded {:?} replayed {:?}",
+//!         result.url,
+//!         result.recorded.duration(Phase::Total),
+//!         result.stats.total_duration
+//!     );
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{path::Path, sync::Arc, time::Duration};
+
+use http::{Method, StatusCode};
+use serde::Deserialize;
+use tokio::time::Instant;
+
+use crate::{
+    aggregate::Phase,
+    client::Client,
+    request::Request,
+    stats::{Stats, StatsRecorder},
+};
+
+/// A parsed HAR file, only the parts needed to replay it.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Har {
+    pub log: HarLog,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct HarLog {
+    pub entries: Vec<HarEntry>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarEntry {
+    /// An RFC 3339 timestamp.
+    pub started_date_time: String,
+    pub request: HarRequest,
+    pub timings: HarTimings,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarRequest {
+    pub method: String,
+    pub url: String,
+    #[serde(default)]
+    pub headers: Vec<HarHeader>,
+    pub post_data: Option<HarPostData>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct HarHeader {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarPostData {
+    pub mime_type: Option<String>,
+    pub text: Option<String>,
+}
+
+/// The recorded phase durations in milliseconds, `-1` or missing when they
+/// don't apply.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct HarTimings {
+    pub blocked: Option<f64>,
+    pub dns: Option<f64>,
+    /// Includes `ssl`.
+    pub connect: Option<f64>,
+    pub ssl: Option<f64>,
+    pub send: f64,
+    pub wait: f64,
+    pub receive: f64,
+}
+
+/// A replayed entry.
+#[derive(Debug, Clone)]
+pub struct ReplayResult {
+    pub url: String,
+    pub recorded: HarTimings,
+    pub status: Option<StatusCode>,
+    pub error: Option<String>,
+    pub stats: Stats,
+}
+
+impl Har {
+    pub async fn load<P: AsRef<Path>>(path: P) -> crate::Result<Self> {
+        let data = tokio::fs::read(path).await?;
+        Self::parse(&data)
+    }
+
+    pub fn parse(data: &[u8]) -> crate::Result<Self> {
+        Ok(serde_json::from_slice(data)?)
+    }
+
+    /// Send the requests of the entries one after another, reading the whole
+    /// bodies. With `think_time`, each request starts at the same offset from
+    /// the first one as when recorded, if the previous ones are done by then.
+    ///
+    /// HTTP/2 pseudo-headers and `Content-Length` are not replayed.
+    pub async fn replay(&self, client: &Client, think_time: bool) -> Vec<ReplayResult> {
+        let start = Instant::now();
+        let first = self
+            .log
+            .entries
+            .first()
+            .and_then(|entry| crate::util::parse_rfc3339(&entry.started_date_time));
+
+        let mut results = Vec::with_capacity(self.log.entries.len());
+        for entry in self.log.entries.iter() {
+            if think_time {
+                let started = crate::util::parse_rfc3339(&entry.started_date_time);
+                if let (Some(first), Some(started)) = (first, started) {
+                    let offset = started.duration_since(first).unwrap_or_default();
+                    tokio::time::sleep_until(start + offset).await;
+                }
+            }
+            results.push(replay_entry(client, entry).await);
+        }
+        results
+    }
+}
+
+async fn replay_entry(client: &Client, entry: &HarEntry) -> ReplayResult {
+    let recorder = StatsRecorder::new();
+    let ret = async {
+        let mut request = entry_request(client, &entry.request)?;
+        *request.recorder_mut() = Some(Arc::new(recorder.clone()));
+        let response = client.execute(request).await?;
+        let status = response.status();
+        response.bytes().await?;
+        Ok::<_, crate::Error>(status)
+    }
+    .await;
+
+    let (status, error) = match ret {
+        Ok(status) => (Some(status), None),
+        Err(e) => (None, Some(e.to_string())),
+    };
+    ReplayResult {
+        url: entry.request.url.clone(),
+        recorded: entry.timings.clone(),
+        status,
+        error,
+        stats: recorder.finish(),
+    }
+}
+
+fn entry_request(client: &Client, request: &HarRequest) -> crate::Result<Request> {
+    let method = Method::from_bytes(request.method.as_bytes()).map_err(http::Error::from)?;
+    let mut builder = client.request(method, request.url.as_str());
+    for header in request.headers.iter() {
+        if header.name.starts_with(':') || header.name.eq_ignore_ascii_case("content-length") {
+            continue;
+        }
+        builder = builder.header(header.name.as_str(), header.value.as_str());
+    }
+    if let Some(text) = request.post_data.as_ref().and_then(|v| v.text.clone()) {
+        builder = builder.body(text);
+    }
+    builder.build()
+}
+
+impl HarTimings {
+    /// The recorded duration of a phase, to compare with
+    /// [`Phase::duration`] of the replayed stats.
+    ///
+    /// `Request` spans `send` and `wait`, `Total` every phase.
+    pub fn duration(&self, phase: Phase) -> Option<Duration> {
+        fn ms(value: f64) -> Option<Duration> {
+            // Also `None` when too large for a `Duration`, or not a number.
+            (value >= 0.0)
+                .then(|| Duration::try_from_secs_f64(value / 1000.0).ok())
+                .flatten()
+        }
+        match phase {
+            Phase::Blocked => ms(self.blocked?),
+            Phase::Dns => ms(self.dns?),
+            Phase::Tcp => ms(self.connect? - self.ssl.filter(|v| *v > 0.0).unwrap_or(0.0)),
+            Phase::Tls => ms(self.ssl?),
+            Phase::Request => ms(self.send.max(0.0) + self.wait.max(0.0)),
+            Phase::Total => {
+                let total = [self.blocked, self.dns, self.connect]
+                    .into_iter()
+                    .flatten()
+                    .chain([self.send, self.wait, self.receive])
+                    .filter(|v| *v > 0.0)
+                    .sum();
+                ms(total)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::Instant;
+
+    use super::{Har, HarTimings};
+    use crate::{
+        aggregate::Phase,
+        client::Client,
+        test_util::{MockResponse, MockTransport},
+    };
+
+    const HAR: &str = r#"{"log": {"version": "1.2", "entries": [
+        {
+            "startedDateTime": "2024-05-01T12:30:00.000Z",
+            "time": 120,
+            "request": {
+                "method": "POST",
+                "url": "http://example.com/login",
+                "httpVersion": "HTTP/2",
+                "headers": [
+                    {"name": ":authority", "value": "example.com"},
+                    {"name": "content-type", "value": "application/json"},
+                    {"name": "content-length", "value": "13"}
+                ],
+                "postData": {"mimeType": "application/json", "text": "{\"user\":\"a\"}"}
+            },
+            "response": {"status": 200},
+            "timings": {"blocked": 1, "dns": 10, "connect": 30, "ssl": 20, "send": 1, "wait": 70, "receive": 8}
+        },
+        {
+            "startedDateTime": "2024-05-01T14:30:00.250+02:00",
+            "time": 50,
+            "request": {"method": "GET", "url": "http://example.com/me", "headers": []},
+            "response": {"status": 200},
+            "timings": {"blocked": -1, "dns": -1, "connect": -1, "send": 1, "wait": 40, "receive": 9}
+        }
+    ]}}"#;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_har_replay() {
+        let har = Har::parse(HAR.as_bytes()).unwrap();
+        let timings = &har.log.entries[0].timings;
+        assert_eq!(
+            timings.duration(Phase::Tcp),
+            Some(Duration::from_millis(10))
+        );
+        assert_eq!(
+            timings.duration(Phase::Total),
+            Some(Duration::from_millis(120))
+        );
+        assert_eq!(har.log.entries[1].timings.duration(Phase::Dns), None);
+        let huge = HarTimings {
+            dns: Some(f64::INFINITY),
+            blocked: Some(f64::MAX),
+            ..Default::default()
+        };
+        assert_eq!(huge.duration(Phase::Dns), None);
+        assert_eq!(huge.duration(Phase::Blocked), None);
+
+        let mock = MockTransport::new();
+        mock.push(MockResponse::new(200).response_delay(Duration::from_millis(30)));
+        mock.push(MockResponse::new(404));
+        let client = Client::builder()
+            .mock_transport(mock.clone())
+            .build()
+            .unwrap();
+
+        let start = Instant::now();
+        let results = har.replay(&client, true).await;
+        assert_eq!(start.elapsed(), Duration::from_millis(250));
+        assert_eq!(results[0].stats.total_duration, Duration::from_millis(30));
+        assert_eq!(results[1].status.unwrap(), 404);
+
+        let requests = mock.requests();
+        assert_eq!(requests[0].method, "POST");
+        // One from the HAR, the others set by the client.
+        let defaults = ["host", "user-agent", "accept-encoding"];
+        let headers = requests[0].headers.keys();
+        assert_eq!(
+            headers.filter(|k| !defaults.contains(&k.as_str())).count(),
+            1
+        );
+        assert_eq!(requests[0].headers["content-type"], "application/json");
+        assert_eq!(requests[0].body.as_deref(), Some(&b"{\"user\":\"a\"}"[..]));
+    }
+}
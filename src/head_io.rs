@@ -0,0 +1,229 @@
+use std::{
+    collections::HashMap,
+    io::{self, IoSlice},
+    pin::Pin,
+    task::{Context, Poll, ready},
+};
+
+use bytes::Bytes;
+use pin_project_lite::pin_project;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+pin_project! {
+    /// An IO rewriting the request head written by hyper: restoring the case
+    /// of header names, which hyper only writes lowercase or in title case, or
+    /// replacing the whole head with raw bytes.
+    ///
+    /// The head is rewritten as it goes through, the rest of the connection
+    /// is passed as is.
+    pub(crate) struct HeadIo<T> {
+        #[pin]
+        inner: T,
+        // The names to restore, by their lowercase form.
+        names: HashMap<Vec<u8>, String>,
+        // Written instead of the head, once it is over.
+        raw: Option<Bytes>,
+        state: State,
+        // The name of the current header line, until its colon.
+        name: Vec<u8>,
+        // Rewritten bytes not yet written to `inner`.
+        out: Vec<u8>,
+        written: usize,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    RequestLine,
+    LineStart,
+    Name,
+    Value,
+    HeadEnd,
+    Done,
+}
+
+impl<T> HeadIo<T> {
+    pub(crate) fn header_case<'a, I>(inner: T, names: I) -> Self
+    where
+        I: IntoIterator<Item = &'a String>,
+    {
+        let names = names
+            .into_iter()
+            .map(|name| (name.to_ascii_lowercase().into_bytes(), name.clone()))
+            .collect();
+        Self::new(inner, names, None)
+    }
+
+    pub(crate) fn raw(inner: T, head: Bytes) -> Self {
+        Self::new(inner, HashMap::new(), Some(head))
+    }
+
+    fn new(inner: T, names: HashMap<Vec<u8>, String>, raw: Option<Bytes>) -> Self {
+        HeadIo {
+            inner,
+            names,
+            raw,
+            state: State::RequestLine,
+            name: Vec::new(),
+            out: Vec::new(),
+            written: 0,
+        }
+    }
+}
+
+impl<T: AsyncWrite> HeadIo<T> {
+    fn rewrite(self: Pin<&mut Self>, buf: &[u8]) {
+        let this = self.project();
+        for &b in buf {
+            // The bytes of a replaced head are dropped.
+            let keep = this.raw.is_none() || *this.state == State::Done;
+            match *this.state {
+                State::RequestLine | State::Value => {
+                    if keep {
+                        this.out.push(b);
+                    }
+                    if b == b'\n' {
+                        *this.state = State::LineStart;
+                    }
+                }
+                State::LineStart if b == b'\r' => {
+                    if keep {
+                        this.out.push(b);
+                    }
+                    *this.state = State::HeadEnd;
+                }
+                State::LineStart | State::Name if b == b':' => {
+                    if keep {
+                        match this.names.get(&this.name.to_ascii_lowercase()) {
+                            Some(name) => this.out.extend_from_slice(name.as_bytes()),
+                            None => this.out.extend_from_slice(this.name),
+                        }
+                        this.out.push(b);
+                    }
+                    this.name.clear();
+                    *this.state = State::Value;
+                }
+                State::LineStart | State::Name => {
+                    this.name.push(b);
+                    *this.state = State::Name;
+                }
+                State::HeadEnd => {
+                    match this.raw.take() {
+                        Some(raw) => this.out.extend_from_slice(&raw),
+                        None => this.out.push(b),
+                    }
+                    *this.state = State::Done;
+                }
+                State::Done => this.out.push(b),
+            }
+        }
+    }
+
+    /// Write the rewritten bytes.
+    fn poll_drain(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut this = self.project();
+        while *this.written < this.out.len() {
+            let n = ready!(
+                this.inner
+                    .as_mut()
+                    .poll_write(cx, &this.out[*this.written..])
+            )?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            *this.written += n;
+        }
+        this.out.clear();
+        *this.written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for HeadIo<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.project().inner.poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite> AsyncWrite for HeadIo<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        ready!(self.as_mut().poll_drain(cx))?;
+        if self.state == State::Done {
+            return self.project().inner.poll_write(cx, buf);
+        }
+        // The bytes are taken at once, what can't be written now is by the
+        // next write or flush.
+        self.as_mut().rewrite(buf);
+        if let Poll::Ready(Err(e)) = self.poll_drain(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        ready!(self.as_mut().poll_drain(cx))?;
+        if self.state == State::Done {
+            return self.project().inner.poll_write_vectored(cx, bufs);
+        }
+        let buf = bufs
+            .iter()
+            .find(|buf| !buf.is_empty())
+            .map_or(&[][..], |buf| &**buf);
+        self.poll_write(cx, buf)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_drain(cx))?;
+        self.project().inner.poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_drain(cx))?;
+        self.project().inner.poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+
+    use super::HeadIo;
+
+    #[tokio::test]
+    async fn test_header_case() {
+        let names = ["X-API-Key".to_string(), "HOST".to_string()];
+        let mut io = HeadIo::header_case(Vec::new(), names.iter());
+        let head = b"GET /x-api-key: HTTP/1.1\r\nhost: a\r\nx-api-key: k\r\naccept: x-api-key\r\n\r\nhost: body";
+        // Split inside a name.
+        io.write_all(&head[..30]).await.unwrap();
+        io.write_all(&head[30..]).await.unwrap();
+        io.flush().await.unwrap();
+        assert_eq!(
+            String::from_utf8(io.inner).unwrap(),
+            "GET /x-api-key: HTTP/1.1\r\nHOST: a\r\nX-API-Key: k\r\naccept: x-api-key\r\n\r\nhost: body"
+        );
+
+        let mut io = HeadIo::raw(Vec::new(), "GET /%% HTTP/1.0\n\n".into());
+        io.write_all(b"POST / HTTP/1.1\r\nhost: a\r\n\r\nbody")
+            .await
+            .unwrap();
+        io.flush().await.unwrap();
+        assert_eq!(io.inner, b"GET /%% HTTP/1.0\n\nbody");
+    }
+}
@@ -0,0 +1,82 @@
+//! TLS secrets in the NSS key log format, see
+//! [`ClientBuilder::tls_key_log_file`](crate::client::ClientBuilder::tls_key_log_file).
+//!
+//! Wireshark reads the file to decrypt captured `https` traffic.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+    sync::Mutex,
+};
+
+use rustls::KeyLog;
+
+/// A key log file shared by the connections of a client, appended to like
+/// the one of `SSLKEYLOGFILE`.
+#[derive(Debug)]
+pub(crate) struct KeyLogWriter {
+    out: Mutex<File>,
+}
+
+impl KeyLogWriter {
+    pub(crate) fn open<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let out = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(KeyLogWriter {
+            out: Mutex::new(out),
+        })
+    }
+}
+
+impl KeyLog for KeyLogWriter {
+    fn log(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+        let line = format!("{label} {} {}\n", hex(client_random), hex(secret));
+        // A whole line per write, so connections don't interleave.
+        _ = self.out.lock().unwrap().write_all(line.as_bytes());
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|v| format!("{v:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rustls::KeyLog;
+
+    use super::KeyLogWriter;
+    use crate::{client::Client, test_util::TestServer};
+
+    #[test]
+    fn test_key_log_writer() {
+        let path = std::env::temp_dir().join(format!("httptrace-{}.keylog", std::process::id()));
+        _ = std::fs::remove_file(&path);
+        let writer = KeyLogWriter::open(&path).unwrap();
+        writer.log("CLIENT_RANDOM", &[0x01, 0xab], &[0xff]);
+        writer.log("EXPORTER_SECRET", &[0x02], &[0x00, 0x10]);
+        let content = std::fs::read_to_string(&path).unwrap();
+        _ = std::fs::remove_file(&path);
+        assert_eq!(content, "CLIENT_RANDOM 01ab ff\nEXPORTER_SECRET 02 0010\n");
+    }
+
+    #[tokio::test]
+    async fn test_tls_key_log_file() {
+        let server = TestServer::builder().tls().start().await.unwrap();
+        let path =
+            std::env::temp_dir().join(format!("httptrace-{}.tls.keylog", std::process::id()));
+        _ = std::fs::remove_file(&path);
+        let client = Client::builder()
+            .skip_tls_verify()
+            .tls_key_log_file(&path)
+            .build()
+            .unwrap();
+        let url = format!("https://localhost:{}/", server.addr().port());
+        let response = client.get(url).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        _ = std::fs::remove_file(&path);
+        assert!(content.contains("CLIENT_HANDSHAKE_TRAFFIC_SECRET "));
+        assert!(content.contains("CLIENT_TRAFFIC_SECRET_0 "));
+    }
+}
@@ -1,12 +1,60 @@
+pub mod aggregate;
+pub mod alt_svc;
+pub mod availability;
 pub mod body;
+pub mod cache;
+pub mod capture;
+#[cfg(any(test, feature = "test-util"))]
+pub mod cassette;
+pub mod check;
 pub mod client;
+pub mod crawl;
+pub mod diagnosis;
+pub mod dns;
 pub mod error;
+pub mod events;
+pub mod export;
+pub mod haproxy;
+#[cfg(any(test, feature = "json"))]
+pub mod har;
 pub mod into_uri;
+pub mod mirror;
+pub mod monitor;
+pub mod probe;
+pub mod proxy;
+pub mod redirect;
 pub mod request;
 pub mod response;
+pub mod revocation;
+pub mod slo;
+pub mod sniff;
+pub mod sse;
 pub mod stats;
+pub mod template;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+pub mod transaction;
+pub mod tunnel;
+pub mod verbose;
+pub mod websocket;
 pub use body::Body;
 pub use error::{Error, Result};
 
+#[cfg(any(
+    feature = "gzip",
+    feature = "deflate",
+    feature = "brotli",
+    feature = "zstd"
+))]
+mod decompress;
+mod early_data;
+mod expect_continue;
+mod h2c;
+mod head_io;
+mod key_log;
+#[cfg(feature = "pcap")]
+mod pcap;
+mod pool;
 mod skip_verify;
 mod util;
+mod verifier;
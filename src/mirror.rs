@@ -0,0 +1,316 @@
+use std::{sync::Arc, time::Duration};
+
+use futures_util::{StreamExt, stream::FuturesUnordered};
+use http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri, Version};
+
+use crate::{
+    Body,
+    client::Client,
+    into_uri::IntoUri,
+    request::{Request, RequestBuilder},
+    response::Response,
+    stats::{Stats, StatsRecorder},
+};
+
+/// How the mirrors of a [`MirrorBuilder`] are tried.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MirrorStrategy {
+    /// Try the mirrors one after another, in the given order.
+    #[default]
+    Sequential,
+    /// Start all mirrors at once and keep the first one that succeeds.
+    Race,
+}
+
+/// The trace of a single mirror attempt.
+#[derive(Debug, Clone)]
+pub struct MirrorAttempt {
+    pub uri: Uri,
+    pub status: Option<StatusCode>,
+    pub error: Option<String>,
+    pub stats: Stats,
+}
+
+/// The response of the winning mirror, along with the trace of every attempt.
+#[derive(Debug)]
+pub struct MirrorResponse {
+    pub response: Response,
+    /// Index of the winning mirror in the list given to [`Client::mirrors`].
+    pub winner: usize,
+    /// One entry per mirror that was tried, in mirror order.
+    pub attempts: Vec<MirrorAttempt>,
+}
+
+/// A builder for a request that fails over across an ordered list of mirrors.
+pub struct MirrorBuilder {
+    template: RequestBuilder,
+    mirrors: crate::Result<Vec<Uri>>,
+    strategy: MirrorStrategy,
+    accept: fn(&Response) -> bool,
+}
+
+impl MirrorBuilder {
+    pub(crate) fn new<I, U>(client: Client, method: Method, uris: I) -> MirrorBuilder
+    where
+        I: IntoIterator<Item = U>,
+        U: IntoUri,
+    {
+        let mirrors = uris
+            .into_iter()
+            .map(|u| u.into_uri())
+            .collect::<crate::Result<Vec<_>>>()
+            .and_then(|mirrors| match mirrors.is_empty() {
+                true => Err(crate::Error::NoMirrors),
+                false => Ok(mirrors),
+            });
+        let template = match mirrors.as_ref() {
+            Ok(mirrors) => client.request(method, mirrors[0].clone()),
+            Err(_) => RequestBuilder::new(client, Err(crate::Error::NoMirrors)),
+        };
+        MirrorBuilder {
+            template,
+            mirrors,
+            strategy: MirrorStrategy::default(),
+            accept: default_accept,
+        }
+    }
+
+    /// Add a `Header` to the request sent to every mirror.
+    pub fn header<K, V>(mut self, key: K, value: V) -> MirrorBuilder
+    where
+        HeaderName: TryFrom<K>,
+        <HeaderName as TryFrom<K>>::Error: Into<http::Error>,
+        HeaderValue: TryFrom<V>,
+        <HeaderValue as TryFrom<V>>::Error: Into<http::Error>,
+    {
+        self.template = self.template.header(key, value);
+        self
+    }
+
+    /// Set the headers of the request sent to every mirror.
+    pub fn headers(mut self, headers: HeaderMap) -> MirrorBuilder {
+        self.template = self.template.headers(headers);
+        self
+    }
+
+    /// Set the request body.
+    ///
+    /// The body must be reusable, since it may be sent to several mirrors.
+    pub fn body<T: Into<Body>>(mut self, body: T) -> MirrorBuilder {
+        self.template = self.template.body(body);
+        self
+    }
+
+    /// Set the timeout of each mirror attempt.
+    pub fn timeout(mut self, timeout: Duration) -> MirrorBuilder {
+        self.template = self.template.timeout(timeout);
+        self
+    }
+
+    /// Set HTTP version
+    pub fn version(mut self, version: Version) -> MirrorBuilder {
+        self.template = self.template.version(version);
+        self
+    }
+
+    /// Set the strategy used to try the mirrors.
+    pub fn strategy(mut self, strategy: MirrorStrategy) -> MirrorBuilder {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Start all mirrors at once, see [`MirrorStrategy::Race`].
+    pub fn race(self) -> MirrorBuilder {
+        self.strategy(MirrorStrategy::Race)
+    }
+
+    /// Decide whether a mirror's response counts as a success.
+    ///
+    /// By default any response that is not a 4xx or 5xx is accepted.
+    pub fn accept(mut self, accept: fn(&Response) -> bool) -> MirrorBuilder {
+        self.accept = accept;
+        self
+    }
+
+    pub async fn send(self) -> crate::Result<MirrorResponse> {
+        let mirrors = self.mirrors?;
+        let (client, template) = self.template.build_split();
+        let template = template?;
+
+        let mut requests = Vec::with_capacity(mirrors.len());
+        for uri in mirrors.iter() {
+            let mut request = template.try_clone().ok_or(crate::Error::BodyNotReusable)?;
+            *request.uri_mut() = uri.clone();
+            requests.push(request);
+        }
+
+        match self.strategy {
+            MirrorStrategy::Sequential => sequential(&client, requests, self.accept).await,
+            MirrorStrategy::Race => race(&client, requests, self.accept).await,
+        }
+    }
+}
+
+async fn sequential(
+    client: &Client,
+    requests: Vec<Request>,
+    accept: fn(&Response) -> bool,
+) -> crate::Result<MirrorResponse> {
+    let mut attempts = Vec::with_capacity(requests.len());
+    for (index, request) in requests.into_iter().enumerate() {
+        let (attempt, response) = try_mirror(client, request, StatsRecorder::new(), accept).await;
+        attempts.push(attempt);
+        if let Some(response) = response {
+            return Ok(MirrorResponse {
+                response,
+                winner: index,
+                attempts,
+            });
+        }
+    }
+    Err(crate::Error::AllMirrorsFailed(attempts))
+}
+
+async fn race(
+    client: &Client,
+    requests: Vec<Request>,
+    accept: fn(&Response) -> bool,
+) -> crate::Result<MirrorResponse> {
+    let mut pending = Vec::with_capacity(requests.len());
+    let mut attempts: Vec<Option<MirrorAttempt>> = Vec::with_capacity(requests.len());
+    let mut running = FuturesUnordered::new();
+
+    for (index, request) in requests.into_iter().enumerate() {
+        let recorder = StatsRecorder::new();
+        pending.push((request.uri().clone(), recorder.clone()));
+        attempts.push(None);
+        running.push(async move {
+            let (attempt, response) = try_mirror(client, request, recorder, accept).await;
+            (index, attempt, response)
+        });
+    }
+
+    let mut winner = None;
+    while let Some((index, attempt, response)) = running.next().await {
+        attempts[index] = Some(attempt);
+        if let Some(response) = response {
+            winner = Some((index, response));
+            break;
+        }
+    }
+    // Cancel the losers, their traces end where they were interrupted.
+    drop(running);
+
+    let attempts = attempts
+        .into_iter()
+        .zip(pending)
+        .map(|(attempt, (uri, recorder))| {
+            attempt.unwrap_or_else(|| MirrorAttempt {
+                uri,
+                status: None,
+                error: Some("cancelled".to_string()),
+                stats: recorder.finish(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    match winner {
+        Some((winner, response)) => Ok(MirrorResponse {
+            response,
+            winner,
+            attempts,
+        }),
+        None => Err(crate::Error::AllMirrorsFailed(attempts)),
+    }
+}
+
+async fn try_mirror(
+    client: &Client,
+    mut request: Request,
+    recorder: StatsRecorder,
+    accept: fn(&Response) -> bool,
+) -> (MirrorAttempt, Option<Response>) {
+    let uri = request.uri().clone();
+    *request.recorder_mut() = Some(Arc::new(recorder.clone()));
+
+    let result = client.execute(request).await;
+    let stats = recorder.finish();
+    match result {
+        Ok(response) => {
+            let status = Some(response.status());
+            match accept(&response) {
+                true => (
+                    MirrorAttempt {
+                        uri,
+                        status,
+                        error: None,
+                        stats,
+                    },
+                    Some(response),
+                ),
+                false => (
+                    MirrorAttempt {
+                        uri,
+                        status,
+                        error: Some(format!("unaccepted status {}", response.status())),
+                        stats,
+                    },
+                    None,
+                ),
+            }
+        }
+        Err(e) => (
+            MirrorAttempt {
+                uri,
+                status: None,
+                error: Some(e.to_string()),
+                stats,
+            },
+            None,
+        ),
+    }
+}
+
+fn default_accept(response: &Response) -> bool {
+    let status = response.status();
+    !status.is_client_error() && !status.is_server_error()
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Method;
+
+    use super::MirrorStrategy;
+    use crate::client::Client;
+
+    #[tokio::test]
+    async fn test_no_mirrors() {
+        let client = Client::builder().build().unwrap();
+        let ret = client.mirrors(Method::GET, Vec::<&str>::new()).send().await;
+        assert!(matches!(ret, Err(crate::Error::NoMirrors)));
+    }
+
+    #[tokio::test]
+    async fn test_all_mirrors_failed() {
+        let client = Client::builder().build().unwrap();
+        for strategy in [MirrorStrategy::Sequential, MirrorStrategy::Race] {
+            let ret = client
+                .mirrors(
+                    Method::GET,
+                    ["http://127.0.0.1:1/a", "http://127.0.0.1:1/b"],
+                )
+                .strategy(strategy)
+                .send()
+                .await;
+            match ret {
+                Err(crate::Error::AllMirrorsFailed(attempts)) => {
+                    assert_eq!(attempts.len(), 2);
+                    assert_eq!(attempts[0].uri.path(), "/a");
+                    assert_eq!(attempts[1].uri.path(), "/b");
+                    assert!(attempts.iter().all(|v| v.error.is_some()));
+                }
+                _ => panic!("unexpected result"),
+            }
+        }
+    }
+}
@@ -0,0 +1,325 @@
+//! Probe a target periodically and raise alerts when thresholds are breached.
+//!
+//! ```no_run
+//! # async fn run() -> httptrace::Result<()> {
+//! use std::time::Duration;
+//!
+//! use httptrace::{
+//!     aggregate::Phase,
+//!     check::Check,
+//!     client::Client,
+//!     monitor::{Monitor, Rule},
+//! };
+//!
+//! let client = Client::builder().build()?;
+//! let request = client.get("https://example.com").build()?;
+//! Monitor::new(client, request, Duration::from_secs(60))
+//!     .check(Check::new().status(200))
+//!     .rule(Rule::Latency(Phase::Total, Duration::from_secs(1)))
+//!     .rule(Rule::ErrorStreak(3))
+//!     .hysteresis(2, 2)
+//!     .on_alert(|event| async move { eprintln!("{event}") })
+//!     .run()
+//!     .await
+//! # }
+//! ```
+
+use std::{
+    fmt,
+    future::Future,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use futures_util::{FutureExt, future::BoxFuture};
+use tokio::sync::mpsc;
+
+use crate::{
+    aggregate::Phase,
+    availability::{AvailabilitySnapshot, AvailabilityTracker, Probe},
+    check::{Check, Report},
+    client::Client,
+    request::Request,
+};
+
+const DEFAULT_WINDOW: Duration = Duration::from_secs(86400);
+
+/// A threshold watched by a [`Monitor`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rule {
+    /// The phase takes longer than the limit. Probes that didn't go through
+    /// the phase leave the rule as it is.
+    Latency(Phase, Duration),
+    /// At least this many probes failed in a row.
+    ErrorStreak(u64),
+    /// The server certificate expires within this duration.
+    CertExpiry(Duration),
+}
+
+/// A change of state of a [`Rule`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum AlertEvent {
+    Breached {
+        rule: Rule,
+        /// The measurement that breached the rule.
+        value: String,
+        at: SystemTime,
+    },
+    Recovered {
+        rule: Rule,
+        at: SystemTime,
+    },
+}
+
+type AlertCallback = Arc<dyn Fn(AlertEvent) -> BoxFuture<'static, ()> + Send + Sync>;
+
+#[derive(Debug, Clone, Default)]
+struct RuleState {
+    breached: bool,
+    /// Consecutive probes disagreeing with `breached`.
+    streak: u32,
+}
+
+/// Runs a [`Check`] at a fixed interval, tracks the availability of the target
+/// and alerts on [`Rule`] breaches and recoveries.
+pub struct Monitor {
+    client: Client,
+    request: Request,
+    interval: Duration,
+    check: Check,
+    tracker: AvailabilityTracker,
+    rules: Vec<(Rule, RuleState)>,
+    breach_after: u32,
+    recover_after: u32,
+    callbacks: Vec<AlertCallback>,
+}
+
+impl Monitor {
+    /// The request is cloned for every probe, so its body must be reusable.
+    pub fn new(client: Client, request: Request, interval: Duration) -> Self {
+        Self {
+            client,
+            request,
+            interval,
+            check: Check::new(),
+            tracker: AvailabilityTracker::new(DEFAULT_WINDOW),
+            rules: Vec::new(),
+            breach_after: 1,
+            recover_after: 1,
+            callbacks: Vec::new(),
+        }
+    }
+
+    /// The expectations a probe must meet to count as up. By default any
+    /// response does.
+    pub fn check(mut self, check: Check) -> Self {
+        self.check = check;
+        self
+    }
+
+    /// The window of the rolling uptime, a day by default.
+    pub fn window(mut self, window: Duration) -> Self {
+        self.tracker = AvailabilityTracker::new(window);
+        self
+    }
+
+    pub fn rule(mut self, rule: Rule) -> Self {
+        self.rules.push((rule, RuleState::default()));
+        self
+    }
+
+    /// Only alert once a rule is breached for `breach_after` probes in a row,
+    /// and only report the recovery after `recover_after` good probes in a
+    /// row, so a flapping target doesn't flood the alerts. Both default to 1.
+    pub fn hysteresis(mut self, breach_after: u32, recover_after: u32) -> Self {
+        self.breach_after = breach_after.max(1);
+        self.recover_after = recover_after.max(1);
+        self
+    }
+
+    /// Call `callback` on every alert event. Probing waits for it to finish.
+    pub fn on_alert<F, Fut>(mut self, callback: F) -> Self
+    where
+        F: Fn(AlertEvent) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.callbacks
+            .push(Arc::new(move |event| callback(event).boxed()));
+        self
+    }
+
+    /// Send every alert event on a channel.
+    pub fn alert_channel(self, sender: mpsc::UnboundedSender<AlertEvent>) -> Self {
+        self.on_alert(move |event| {
+            let _ = sender.send(event);
+            async {}
+        })
+    }
+
+    /// Probe forever, one probe per interval.
+    ///
+    /// Only fails if the request can't be cloned.
+    pub async fn run(mut self) -> crate::Result<()> {
+        let mut interval = tokio::time::interval(self.interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            self.probe().await?;
+        }
+    }
+
+    /// Probe once, update the availability and fire the alerts of the rules
+    /// that changed state.
+    pub async fn probe(&mut self) -> crate::Result<Report> {
+        let request = self
+            .request
+            .try_clone()
+            .ok_or(crate::Error::BodyNotReusable)?;
+        let report = self.check.run(&self.client, request).await;
+        self.tracker.record(Probe::from(&report));
+        let consecutive_failures = self.tracker.snapshot().consecutive_failures;
+
+        let at = SystemTime::now();
+        let mut events = Vec::new();
+        for (rule, state) in self.rules.iter_mut() {
+            let Some((breached, value)) = evaluate(rule, &report, consecutive_failures) else {
+                continue;
+            };
+            if breached == state.breached {
+                state.streak = 0;
+                continue;
+            }
+            state.streak += 1;
+            let needed = match breached {
+                true => self.breach_after,
+                false => self.recover_after,
+            };
+            if state.streak < needed {
+                continue;
+            }
+            state.breached = breached;
+            state.streak = 0;
+            events.push(match breached {
+                true => AlertEvent::Breached {
+                    rule: rule.clone(),
+                    value,
+                    at,
+                },
+                false => AlertEvent::Recovered {
+                    rule: rule.clone(),
+                    at,
+                },
+            });
+        }
+
+        for event in events {
+            for callback in &self.callbacks {
+                callback(event.clone()).await;
+            }
+        }
+        Ok(report)
+    }
+
+    pub fn availability(&self) -> AvailabilitySnapshot {
+        self.tracker.snapshot()
+    }
+}
+
+/// Whether the probe breaches the rule and the measurement, `None` if the
+/// probe says nothing about it.
+fn evaluate(rule: &Rule, report: &Report, consecutive_failures: u64) -> Option<(bool, String)> {
+    match rule {
+        Rule::Latency(phase, limit) => {
+            let duration = phase.duration(&report.stats)?;
+            Some((duration > *limit, format!("{:?}", duration)))
+        }
+        Rule::ErrorStreak(streak) => Some((
+            consecutive_failures >= *streak,
+            format!("{} failures", consecutive_failures),
+        )),
+        Rule::CertExpiry(limit) => {
+            let not_after = report.stats.tls_info.as_ref()?.cert_not_after?;
+            let left = not_after
+                .duration_since(SystemTime::now())
+                .unwrap_or_default();
+            Some((left < *limit, format!("expires in {:?}", left)))
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rule::Latency(phase, limit) => write!(f, "{} > {:?}", phase, limit),
+            Rule::ErrorStreak(streak) => write!(f, "{} failures in a row", streak),
+            Rule::CertExpiry(limit) => write!(f, "cert expires within {:?}", limit),
+        }
+    }
+}
+
+impl fmt::Display for AlertEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AlertEvent::Breached { rule, value, .. } => {
+                write!(f, "BREACHED {} ({})", rule, value)
+            }
+            AlertEvent::Recovered { rule, .. } => write!(f, "RECOVERED {}", rule),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::sync::mpsc;
+
+    use super::{Monitor, Rule};
+    use crate::{
+        aggregate::Phase,
+        check::Check,
+        client::Client,
+        test_util::{MockFailure, MockResponse, MockTransport},
+    };
+
+    #[tokio::test(start_paused = true)]
+    async fn test_monitor_alerts() {
+        let mock = MockTransport::new();
+        for delay in [500, 10, 500, 500, 10, 10] {
+            mock.push(MockResponse::new(200).response_delay(Duration::from_millis(delay)));
+        }
+        for _ in 0..3 {
+            mock.push(MockResponse::failure(MockFailure::Tcp));
+        }
+        let client = Client::builder().mock_transport(mock).build().unwrap();
+        let request = client.get("http://example.com").build().unwrap();
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut monitor = Monitor::new(client, request, Duration::from_secs(60))
+            .check(Check::new().status(200))
+            .rule(Rule::Latency(Phase::Request, Duration::from_millis(100)))
+            .rule(Rule::ErrorStreak(2))
+            .hysteresis(2, 2)
+            .alert_channel(tx);
+
+        let mut events = Vec::new();
+        for _ in 0..9 {
+            monitor.probe().await.unwrap();
+            while let Ok(event) = rx.try_recv() {
+                events.push(event.to_string());
+            }
+        }
+        // The first slow probe is absorbed by the hysteresis, as is the first
+        // probe reaching the error streak. Failed probes say nothing about the
+        // request latency.
+        assert_eq!(
+            events,
+            [
+                "BREACHED request > 100ms (500ms)",
+                "RECOVERED request > 100ms",
+                "BREACHED 2 failures in a row (3 failures)",
+            ]
+        );
+        assert_eq!(monitor.availability().consecutive_failures, 3);
+    }
+}
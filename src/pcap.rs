@@ -0,0 +1,241 @@
+//! Write the TCP byte streams of traced connections to a pcap file.
+//!
+//! Requires the `pcap` feature.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use crate::capture::Tap;
+
+// LINKTYPE_RAW, packets start with the IP header.
+const LINKTYPE_RAW: u32 = 101;
+
+const SNAPLEN: u32 = 65535;
+
+// Keep the IP total length within 16 bits.
+const MAX_SEGMENT: usize = 65000;
+
+const SYN: u8 = 0x02;
+const FIN: u8 = 0x01;
+const PSH: u8 = 0x08;
+const ACK: u8 = 0x10;
+
+/// A pcap file shared by the connections of a client.
+#[derive(Debug)]
+pub(crate) struct PcapWriter {
+    out: Mutex<BufWriter<File>>,
+}
+
+impl PcapWriter {
+    pub(crate) fn create<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(&0xa1b2c3d4u32.to_le_bytes())?;
+        out.write_all(&2u16.to_le_bytes())?;
+        out.write_all(&4u16.to_le_bytes())?;
+        out.write_all(&0i32.to_le_bytes())?;
+        out.write_all(&0u32.to_le_bytes())?;
+        out.write_all(&SNAPLEN.to_le_bytes())?;
+        out.write_all(&LINKTYPE_RAW.to_le_bytes())?;
+        out.flush()?;
+        Ok(PcapWriter {
+            out: Mutex::new(out),
+        })
+    }
+
+    fn write_packet(&self, packet: &[u8]) {
+        let ts = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut out = self.out.lock().unwrap();
+        _ = out.write_all(&(ts.as_secs() as u32).to_le_bytes());
+        _ = out.write_all(&ts.subsec_micros().to_le_bytes());
+        _ = out.write_all(&(packet.len() as u32).to_le_bytes());
+        _ = out.write_all(&(packet.len() as u32).to_le_bytes());
+        _ = out.write_all(packet);
+    }
+
+    fn flush(&self) {
+        _ = self.out.lock().unwrap().flush();
+    }
+}
+
+/// A connection written to a [`PcapWriter`].
+///
+/// The TCP handshake is synthesized on creation and the teardown on drop,
+/// sequence numbers follow the bytes actually exchanged.
+pub(crate) struct PcapConn {
+    writer: Arc<PcapWriter>,
+    local: SocketAddr,
+    peer: SocketAddr,
+    // (local, peer) next sequence numbers
+    seq: Mutex<(u32, u32)>,
+}
+
+impl PcapConn {
+    pub(crate) fn new(writer: Arc<PcapWriter>, local: SocketAddr, peer: SocketAddr) -> Self {
+        let conn = PcapConn {
+            writer,
+            local,
+            peer,
+            seq: Mutex::new((1, 1)),
+        };
+        conn.write(true, SYN, 0, 0, &[]);
+        conn.write(false, SYN | ACK, 0, 1, &[]);
+        conn.write(true, ACK, 1, 1, &[]);
+        conn
+    }
+
+    fn segment(&self, outgoing: bool, flags: u8, data: &[u8]) {
+        let mut seq = self.seq.lock().unwrap();
+        let (local, peer) = &mut *seq;
+        let (own, other) = match outgoing {
+            true => (local, *peer),
+            false => (peer, *local),
+        };
+        if data.is_empty() {
+            // FIN consumes one sequence number
+            self.write(outgoing, flags, *own, other, &[]);
+            *own = own.wrapping_add(1);
+            return;
+        }
+        for chunk in data.chunks(MAX_SEGMENT) {
+            self.write(outgoing, flags, *own, other, chunk);
+            *own = own.wrapping_add(chunk.len() as u32);
+        }
+    }
+
+    fn write(&self, outgoing: bool, flags: u8, seq: u32, ack: u32, data: &[u8]) {
+        let (src, dst) = match outgoing {
+            true => (self.local, self.peer),
+            false => (self.peer, self.local),
+        };
+        self.writer
+            .write_packet(&packet(src, dst, flags, seq, ack, data));
+    }
+}
+
+impl Tap for PcapConn {
+    fn on_sent(&self, data: &[u8]) {
+        if !data.is_empty() {
+            self.segment(true, PSH | ACK, data);
+        }
+    }
+
+    fn on_received(&self, data: &[u8]) {
+        if !data.is_empty() {
+            self.segment(false, PSH | ACK, data);
+        }
+    }
+}
+
+impl Drop for PcapConn {
+    fn drop(&mut self) {
+        self.segment(true, FIN | ACK, &[]);
+        self.segment(false, FIN | ACK, &[]);
+        self.writer.flush();
+    }
+}
+
+fn packet(src: SocketAddr, dst: SocketAddr, flags: u8, seq: u32, ack: u32, data: &[u8]) -> Vec<u8> {
+    let mut tcp = Vec::with_capacity(20 + data.len());
+    tcp.extend_from_slice(&src.port().to_be_bytes());
+    tcp.extend_from_slice(&dst.port().to_be_bytes());
+    tcp.extend_from_slice(&seq.to_be_bytes());
+    tcp.extend_from_slice(&(if flags & ACK != 0 { ack } else { 0 }).to_be_bytes());
+    tcp.push(5 << 4);
+    tcp.push(flags);
+    tcp.extend_from_slice(&65535u16.to_be_bytes());
+    // checksum and urgent pointer, left to zero
+    tcp.extend_from_slice(&[0, 0, 0, 0]);
+    tcp.extend_from_slice(data);
+
+    let mut packet = Vec::with_capacity(40 + tcp.len());
+    match (src.ip(), dst.ip()) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            packet.extend_from_slice(&[0x45, 0]);
+            packet.extend_from_slice(&((20 + tcp.len()) as u16).to_be_bytes());
+            packet.extend_from_slice(&[0, 0, 0x40, 0, 64, 6, 0, 0]);
+            packet.extend_from_slice(&src.octets());
+            packet.extend_from_slice(&dst.octets());
+            let checksum = ipv4_checksum(&packet);
+            packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+        }
+        (src, dst) => {
+            packet.extend_from_slice(&[0x60, 0, 0, 0]);
+            packet.extend_from_slice(&(tcp.len() as u16).to_be_bytes());
+            packet.extend_from_slice(&[6, 64]);
+            packet.extend_from_slice(&to_ipv6(src).octets());
+            packet.extend_from_slice(&to_ipv6(dst).octets());
+        }
+    }
+    packet.extend_from_slice(&tcp);
+    packet
+}
+
+fn to_ipv6(ip: IpAddr) -> std::net::Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+fn ipv4_checksum(header: &[u8]) -> u16 {
+    let mut sum = header
+        .chunks(2)
+        .map(|v| u32::from(u16::from_be_bytes([v[0], v[1]])))
+        .sum::<u32>();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{PcapConn, PcapWriter, ipv4_checksum};
+    use crate::capture::Tap;
+
+    #[test]
+    fn test_ipv4_checksum() {
+        let header = [
+            0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00, 0xc0, 0xa8,
+            0x00, 0x01, 0xc0, 0xa8, 0x00, 0xc7,
+        ];
+        assert_eq!(ipv4_checksum(&header), 0xb861);
+    }
+
+    #[test]
+    fn test_pcap_conn() {
+        let path = std::env::temp_dir().join(format!("httptrace-{}.pcap", std::process::id()));
+        let writer = Arc::new(PcapWriter::create(&path).unwrap());
+        let conn = PcapConn::new(
+            writer,
+            "127.0.0.1:50000".parse().unwrap(),
+            "127.0.0.1:80".parse().unwrap(),
+        );
+        conn.on_sent(b"GET / HTTP/1.1\r\n\r\n");
+        conn.on_received(b"HTTP/1.1 204 No Content\r\n\r\n");
+        drop(conn);
+
+        let data = std::fs::read(&path).unwrap();
+        _ = std::fs::remove_file(&path);
+        // global header, then 3 handshake + 2 data + 2 fin packets
+        let mut offset = 24;
+        let mut packets = 0;
+        while offset < data.len() {
+            let len = u32::from_le_bytes(data[offset + 8..offset + 12].try_into().unwrap());
+            offset += 16 + len as usize;
+            packets += 1;
+        }
+        assert_eq!(offset, data.len());
+        assert_eq!(packets, 7);
+    }
+}
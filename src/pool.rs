@@ -0,0 +1,436 @@
+//! Idle connections kept for the next requests to the same origin, see
+//! [`ClientBuilder::pool_idle_timeout`](crate::client::ClientBuilder::pool_idle_timeout).
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures_util::future::Either;
+use http::Uri;
+use hyper::{
+    body::Incoming,
+    client::conn::{self, TrySendError},
+};
+use tokio::time::Instant;
+
+use crate::{Body, request::Request, stats::ConnectionInfo};
+
+/// The error of a send, with the request if it wasn't written.
+pub(crate) type NotSent = TrySendError<http::Request<Body>>;
+
+/// The connections of a client, by origin.
+#[derive(Debug, Clone)]
+pub(crate) struct Pool {
+    idle: Arc<Mutex<HashMap<PoolKey, Vec<Idle>>>>,
+    idle_timeout: Option<Duration>,
+    max_idle_per_host: usize,
+}
+
+/// What a connection is bound to, requests only share a connection when
+/// they agree on all of it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct PoolKey {
+    scheme: String,
+    host: String,
+    port: u16,
+    connect_ip: Option<IpAddr>,
+    sni: Option<String>,
+    // The protocol, host and port of the alternative service.
+    alt_svc: Option<(String, Option<String>, u16)>,
+}
+
+#[derive(Debug)]
+pub(crate) enum Sender {
+    Http1(conn::http1::SendRequest<Body>),
+    Http2(Http2Sender),
+}
+
+/// A handle on an HTTP/2 connection, cloned for each request it carries.
+#[derive(Debug, Clone)]
+pub(crate) struct Http2Sender {
+    tx: conn::http2::SendRequest<Body>,
+    // An estimate of the id of the next stream, hyper doesn't tell: shared
+    // by the clones and locked while sending so streams are numbered in the
+    // order requests are sent. It drifts once a request fails before its
+    // stream opens.
+    next_stream_id: Arc<Mutex<u32>>,
+}
+
+impl Http2Sender {
+    pub(crate) fn new(tx: conn::http2::SendRequest<Body>) -> Self {
+        Http2Sender {
+            tx,
+            next_stream_id: Arc::new(Mutex::new(1)),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Idle {
+    sender: Sender,
+    conn: ConnectionInfo,
+    since: Instant,
+}
+
+impl PoolKey {
+    pub(crate) fn new(request: &Request) -> Self {
+        let uri: &Uri = request.uri();
+        PoolKey {
+            scheme: uri.scheme_str().unwrap_or_default().to_string(),
+            host: uri.host().unwrap_or_default().to_ascii_lowercase(),
+            port: request.port(),
+            connect_ip: request.connect_ip(),
+            sni: request.sni().map(str::to_string),
+            alt_svc: request
+                .alt_svc()
+                .map(|alt| (alt.protocol.clone(), alt.host.clone(), alt.port)),
+        }
+    }
+}
+
+impl Sender {
+    pub(crate) fn is_closed(&self) -> bool {
+        match self {
+            Sender::Http1(tx) => tx.is_closed(),
+            Sender::Http2(h2) => h2.tx.is_closed(),
+        }
+    }
+
+    /// Whether a request can be sent now, an HTTP/1 connection only takes
+    /// the next one once the previous response is read.
+    fn is_ready(&self) -> bool {
+        match self {
+            Sender::Http1(tx) => tx.is_ready(),
+            Sender::Http2(h2) => h2.tx.is_ready(),
+        }
+    }
+}
+
+impl Pool {
+    pub(crate) fn new(idle_timeout: Option<Duration>, max_idle_per_host: usize) -> Self {
+        Self {
+            idle: Arc::new(Mutex::new(HashMap::new())),
+            idle_timeout,
+            max_idle_per_host,
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.max_idle_per_host > 0
+    }
+
+    /// Take a connection ready for a request to `key`, dropping the closed
+    /// and expired ones on the way, with the info of the closed ones. An
+    /// HTTP/2 connection stays in the pool for concurrent requests to share.
+    pub(crate) fn checkout(
+        &self,
+        key: &PoolKey,
+    ) -> (Option<(Sender, ConnectionInfo)>, Vec<ConnectionInfo>) {
+        let mut idle = self.idle.lock().unwrap();
+        let now = Instant::now();
+        let mut dead = Vec::new();
+        if let Some(list) = idle.get_mut(key) {
+            list.retain(|entry| {
+                if !self.is_expired(entry, now) && entry.sender.is_closed() {
+                    dead.push(entry.conn.clone());
+                }
+                self.is_usable(entry, now)
+            });
+        }
+        self.prune(&mut idle, now);
+        let Some(list) = idle.get_mut(key) else {
+            return (None, dead);
+        };
+        let index = list.iter().position(|entry| entry.sender.is_ready());
+        let entry = index.map(|index| match &list[index].sender {
+            Sender::Http2(h2) => {
+                let sender = Sender::Http2(h2.clone());
+                list[index].since = now;
+                (sender, list[index].conn.clone())
+            }
+            Sender::Http1(_) => {
+                let entry = list.remove(index);
+                (entry.sender, entry.conn)
+            }
+        });
+        if list.is_empty() {
+            idle.remove(key);
+        }
+        let entry = entry.map(|(sender, conn)| {
+            let conn = ConnectionInfo {
+                reused: true,
+                ..conn
+            };
+            (sender, conn)
+        });
+        (entry, dead)
+    }
+
+    /// Whether a connection to the origin of `key` is idle in the pool, open
+    /// and not expired, whatever the address or server name it's bound to.
+    pub(crate) fn is_alive(&self, key: &PoolKey) -> bool {
+        let idle = self.idle.lock().unwrap();
+        let now = Instant::now();
+        idle.iter()
+            .filter(|(v, _)| (&v.scheme, &v.host, v.port) == (&key.scheme, &key.host, key.port))
+            .flat_map(|(_, list)| list)
+            .any(|entry| self.is_usable(entry, now))
+    }
+
+    /// Send `request` on `sender`, with the id of its stream on HTTP/2. An
+    /// HTTP/1 connection goes back to the pool right away to wait for the
+    /// end of the response, an HTTP/2 one is already there.
+    ///
+    /// The request is given back in the error if the connection was closed
+    /// before it was written.
+    pub(crate) fn send(
+        &self,
+        key: PoolKey,
+        conn: ConnectionInfo,
+        sender: Sender,
+        request: http::Request<Body>,
+    ) -> (
+        Option<u32>,
+        impl Future<Output = Result<http::Response<Incoming>, NotSent>> + use<>,
+    ) {
+        match sender {
+            Sender::Http1(mut tx) => {
+                let send = tx.try_send_request(request);
+                self.insert(key, Sender::Http1(tx), conn);
+                (None, Either::Left(send))
+            }
+            Sender::Http2(mut h2) => {
+                let mut next_stream_id = h2.next_stream_id.lock().unwrap();
+                let stream_id = *next_stream_id;
+                *next_stream_id += 2;
+                // Queued on the connection right away.
+                let send = h2.tx.try_send_request(request);
+                (Some(stream_id), Either::Right(send))
+            }
+        }
+    }
+
+    pub(crate) fn insert(&self, key: PoolKey, sender: Sender, conn: ConnectionInfo) {
+        let mut idle = self.idle.lock().unwrap();
+        self.prune(&mut idle, Instant::now());
+        let list = idle.entry(key).or_default();
+        if list.len() < self.max_idle_per_host {
+            list.push(Idle {
+                sender,
+                conn,
+                since: Instant::now(),
+            });
+        }
+    }
+
+    /// Drop the closed and expired connections of every origin, so that
+    /// those of origins not requested anymore don't stay open.
+    fn prune(&self, idle: &mut HashMap<PoolKey, Vec<Idle>>, now: Instant) {
+        idle.retain(|_, list| {
+            list.retain(|entry| self.is_usable(entry, now));
+            !list.is_empty()
+        });
+    }
+
+    fn is_expired(&self, entry: &Idle, now: Instant) -> bool {
+        self.idle_timeout
+            .is_some_and(|timeout| now.duration_since(entry.since) >= timeout)
+    }
+
+    fn is_usable(&self, entry: &Idle, now: Instant) -> bool {
+        !self.is_expired(entry, now) && !entry.sender.is_closed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr, SocketAddr},
+        sync::Arc,
+        time::Duration,
+    };
+
+    use http::{Method, Version};
+    use hyper::client::conn;
+    use hyper_util::rt::TokioIo;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::{Pool, PoolKey, Sender};
+    use crate::{
+        Body,
+        client::Client,
+        request::Request,
+        stats::{ConnectionInfo, StatsRecorder, TcpInfo},
+        test_util::TestServer,
+    };
+
+    async fn idle_connection(id: u64) -> (Sender, ConnectionInfo, tokio::io::DuplexStream) {
+        let (io, server) = tokio::io::duplex(1024);
+        let (tx, conn) = conn::http1::handshake::<_, Body>(TokioIo::new(io))
+            .await
+            .unwrap();
+        tokio::spawn(conn);
+        let conn = ConnectionInfo {
+            id,
+            version: Version::HTTP_11,
+            tcp: TcpInfo {
+                local_addr: None,
+                peer_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 80),
+                mptcp: false,
+            },
+            tls: None,
+            reused: false,
+        };
+        (Sender::Http1(tx), conn, server)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_prune() {
+        let pool = Pool::new(Some(Duration::from_secs(10)), 4);
+        let key = |uri: &str| PoolKey::new(&Request::new(Method::GET, uri.parse().unwrap()));
+
+        let (sender, conn, _a) = idle_connection(1).await;
+        pool.insert(key("http://a.test/"), sender, conn);
+        tokio::time::advance(Duration::from_secs(11)).await;
+
+        // Expired connections of other origins go too.
+        let (sender, conn, _b) = idle_connection(2).await;
+        pool.insert(key("http://b.test/"), sender, conn);
+        let idle = pool.idle.lock().unwrap();
+        assert_eq!(idle.keys().collect::<Vec<_>>(), [&key("http://b.test/")]);
+    }
+
+    #[tokio::test]
+    async fn test_pool() {
+        let server = TestServer::builder().body("ok").start().await.unwrap();
+        let client = Client::builder().pool_max_idle_per_host(4).build().unwrap();
+
+        let send = || async {
+            let recorder = StatsRecorder::new();
+            let mut request = client.get(server.url("/")).build().unwrap();
+            *request.recorder_mut() = Some(Arc::new(recorder.clone()));
+            let response = client.execute(request).await.unwrap();
+            let conn = response.connection_info().unwrap().clone();
+            assert_eq!(response.text().await.unwrap(), "ok");
+            (conn, recorder.finish())
+        };
+        let (first, stats) = send().await;
+        assert!(!first.reused && !stats.reused);
+        assert!(stats.tcp_stats.is_some());
+
+        let (second, stats) = send().await;
+        assert!(second.reused && stats.reused);
+        assert_eq!(second.id, first.id);
+        assert!(stats.tcp_stats.is_none());
+        assert_eq!(stats.tcp_info, Some(first.tcp));
+
+        // Without pooling, the default, every request opens its connection.
+        let client = Client::builder().build().unwrap();
+        for _ in 0..2 {
+            let response = client.get(server.url("/")).send().await.unwrap();
+            assert!(!response.connection_info().unwrap().reused);
+            response.text().await.unwrap();
+        }
+        assert_eq!(server.hits(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_pool_retry() {
+        // Answers the first request of each connection, then reads the next
+        // one and closes without a response.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    for i in 0..2 {
+                        let mut buf = Vec::new();
+                        while !buf.ends_with(b"\r\n\r\n") {
+                            let mut byte = [0; 1];
+                            if stream.read_exact(&mut byte).await.is_err() {
+                                return;
+                            }
+                            buf.push(byte[0]);
+                        }
+                        if i == 0 {
+                            let response = b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok";
+                            stream.write_all(response).await.unwrap();
+                        }
+                    }
+                });
+            }
+        });
+        let url = format!("http://{addr}/");
+        let client = Client::builder().pool_max_idle_per_host(4).build().unwrap();
+        let response = client.get(&url).send().await.unwrap();
+        let first = response.connection_info().unwrap().clone();
+        assert_eq!(response.text().await.unwrap(), "ok");
+
+        // Sent again once on a new connection.
+        let recorder = StatsRecorder::new();
+        let response = client
+            .get(&url)
+            .recorder(Box::new(recorder.clone()))
+            .send()
+            .await
+            .unwrap();
+        let second = response.connection_info().unwrap().clone();
+        assert_eq!(response.text().await.unwrap(), "ok");
+        assert_eq!(recorder.finish().dead_connections, [first.id]);
+        assert!(!second.reused);
+        assert_ne!(second.id, first.id);
+
+        // A request with a body isn't replayed.
+        let error = client.post(&url).body("x").send().await.unwrap_err();
+        assert!(matches!(error, crate::Error::Hyper(_)), "{error:?}");
+    }
+
+    #[tokio::test]
+    async fn test_http2_multiplexing() {
+        let server = TestServer::builder()
+            .tls()
+            .header_delay(Duration::from_millis(50))
+            .body("ok")
+            .start()
+            .await
+            .unwrap();
+        let client = Client::builder()
+            .skip_tls_verify()
+            .pool_max_idle_per_host(4)
+            .build()
+            .unwrap();
+
+        let send = || async {
+            let recorder = StatsRecorder::new();
+            let response = client
+                .get(server.url("/"))
+                .recorder(Box::new(recorder.clone()))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.version(), Version::HTTP_2);
+            let conn = response.connection_info().unwrap().clone();
+            assert_eq!(response.text().await.unwrap(), "ok");
+            (conn, recorder.finish())
+        };
+        let (first, stats) = send().await;
+        assert_eq!(stats.http2_stream_id, Some(1));
+
+        // In flight together on the same connection.
+        let (a, b, c) = tokio::join!(send(), send(), send());
+        let mut stream_ids = Vec::new();
+        for (conn, stats) in [a, b, c] {
+            assert!(conn.reused);
+            assert_eq!(conn.id, first.id);
+            stream_ids.push(stats.http2_stream_id.unwrap());
+        }
+        stream_ids.sort();
+        assert_eq!(stream_ids, [3, 5, 7]);
+    }
+}
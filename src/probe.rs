@@ -0,0 +1,112 @@
+//! Certificate probes, see [`Client::probe_certificate`](crate::client::Client::probe_certificate).
+//!
+//! A probe goes through the DNS, TCP and TLS phases of a request and stops
+//! there, which is all it takes to watch certificate expiry.
+
+use std::time::{Duration, SystemTime};
+
+use rustls::pki_types::CertificateDer;
+
+use crate::stats::{Stats, TlsInfo};
+
+/// What a certificate of the chain presented by a server says about itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertificateInfo {
+    /// The distinguished name, e.g. `CN=example.com, O=Example`, empty if it
+    /// can't be read.
+    pub subject: String,
+    pub issuer: String,
+    /// `None` if the certificate can't be read.
+    pub not_before: Option<SystemTime>,
+    pub not_after: Option<SystemTime>,
+    /// The DNS names and IP addresses it's valid for.
+    pub subject_alt_names: Vec<String>,
+    pub der: CertificateDer<'static>,
+}
+
+impl CertificateInfo {
+    pub fn new(der: CertificateDer<'static>) -> Self {
+        let fields = crate::util::cert_fields(&der);
+        let fields = fields.as_ref();
+        CertificateInfo {
+            subject: fields.map(|v| v.subject.clone()).unwrap_or_default(),
+            issuer: fields.map(|v| v.issuer.clone()).unwrap_or_default(),
+            not_before: fields.map(|v| v.not_before),
+            not_after: fields.map(|v| v.not_after),
+            subject_alt_names: fields
+                .map(|v| v.subject_alt_names.clone())
+                .unwrap_or_default(),
+            der,
+        }
+    }
+}
+
+/// The outcome of a certificate probe.
+#[derive(Debug, Clone)]
+pub struct CertificateProbe {
+    /// The chain presented by the server, the end entity first.
+    pub certificates: Vec<CertificateInfo>,
+    pub tls: TlsInfo,
+    /// The DNS, TCP and TLS phases.
+    pub stats: Stats,
+}
+
+impl CertificateProbe {
+    pub(crate) fn new(tls: TlsInfo, stats: Stats) -> Self {
+        let certificates = tls
+            .peer_certificates
+            .iter()
+            .cloned()
+            .map(CertificateInfo::new)
+            .collect();
+        CertificateProbe {
+            certificates,
+            tls,
+            stats,
+        }
+    }
+
+    /// The time left before the end entity certificate expires, zero once it
+    /// has.
+    pub fn expires_in(&self) -> Option<Duration> {
+        let not_after = self.certificates.first()?.not_after?;
+        Some(
+            not_after
+                .duration_since(SystemTime::now())
+                .unwrap_or_default(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{client::Client, test_util::TestServer};
+
+    #[tokio::test]
+    async fn test_probe_certificate() {
+        let server = TestServer::builder().tls().start().await.unwrap();
+        let client = Client::builder()
+            .add_root_certificate(server.certificate().unwrap().clone())
+            .tls_built_in_root_certs(false)
+            .build()
+            .unwrap();
+        let url = format!("https://localhost:{}/", server.addr().port());
+        let probe = client.probe_certificate(url).await.unwrap();
+
+        let [cert] = probe.certificates.as_slice() else {
+            panic!("{:?}", probe.certificates);
+        };
+        assert_eq!(&cert.der, server.certificate().unwrap());
+        assert_eq!(cert.subject_alt_names, ["localhost", "127.0.0.1"]);
+        assert_eq!(cert.subject, cert.issuer);
+        assert!(cert.not_before.unwrap() < cert.not_after.unwrap());
+        assert!(probe.expires_in().unwrap() > Duration::ZERO);
+        assert_eq!(probe.tls.sni.as_deref(), Some("localhost"));
+        assert!(probe.stats.tcp_stats.is_some());
+        assert!(probe.stats.tls_stats.is_some());
+        assert!(probe.stats.request_stats.is_none());
+        assert_eq!(server.hits(), 0);
+    }
+}
@@ -0,0 +1,372 @@
+//! Follow redirects, see
+//! [`ClientBuilder::redirect`](crate::client::ClientBuilder::redirect).
+//!
+//! Every hop reports to the recorder of the request under the same
+//! [`Request::id`], so a [`StatsRecorder`](crate::stats::StatsRecorder) lists
+//! the DNS/TCP/TLS timings of each one in [`Stats::hops`](crate::stats::Stats::hops).
+
+use std::{fmt, sync::Arc};
+
+use http::{HeaderMap, Method, StatusCode, Uri, header};
+
+use crate::request::Request;
+
+/// Which redirects to follow, none by default so that the response of the
+/// requested URL is the one traced.
+#[derive(Clone)]
+pub struct Policy {
+    kind: PolicyKind,
+}
+
+#[derive(Clone)]
+enum PolicyKind {
+    None,
+    Limited(usize),
+    Custom(Arc<dyn Fn(Attempt<'_>) -> Action + Send + Sync>),
+}
+
+impl Policy {
+    /// Follow up to `max` redirects, failing with
+    /// [`Error::TooManyRedirects`](crate::Error::TooManyRedirects) after.
+    pub fn limited(max: usize) -> Self {
+        Self {
+            kind: PolicyKind::Limited(max),
+        }
+    }
+
+    /// Return the redirect responses as they are.
+    pub fn none() -> Self {
+        Self {
+            kind: PolicyKind::None,
+        }
+    }
+
+    /// Decide on every redirect with `policy`.
+    pub fn custom<F>(policy: F) -> Self
+    where
+        F: Fn(Attempt<'_>) -> Action + Send + Sync + 'static,
+    {
+        Self {
+            kind: PolicyKind::Custom(Arc::new(policy)),
+        }
+    }
+
+    pub(crate) fn is_none(&self) -> bool {
+        matches!(self.kind, PolicyKind::None)
+    }
+
+    fn check(&self, attempt: Attempt<'_>) -> Action {
+        match &self.kind {
+            PolicyKind::None => attempt.stop(),
+            PolicyKind::Limited(max) if attempt.previous.len() > *max => {
+                Action(ActionKind::TooMany)
+            }
+            PolicyKind::Limited(_) => attempt.follow(),
+            PolicyKind::Custom(policy) => policy(attempt),
+        }
+    }
+}
+
+impl fmt::Debug for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            PolicyKind::None => f.write_str("Policy::none"),
+            PolicyKind::Limited(max) => write!(f, "Policy::limited({max})"),
+            PolicyKind::Custom(_) => f.write_str("Policy::custom"),
+        }
+    }
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
+/// A redirect about to be followed, given to a [`Policy::custom`].
+#[derive(Debug)]
+pub struct Attempt<'a> {
+    status: StatusCode,
+    next: &'a Uri,
+    previous: &'a [Uri],
+}
+
+impl Attempt<'_> {
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// The `Location` of the redirect, resolved against the current URL.
+    pub fn url(&self) -> &Uri {
+        self.next
+    }
+
+    /// The URLs requested so far, the first one is the original request.
+    pub fn previous(&self) -> &[Uri] {
+        self.previous
+    }
+
+    pub fn follow(self) -> Action {
+        Action(ActionKind::Follow)
+    }
+
+    /// Return the redirect response as the response of the request.
+    pub fn stop(self) -> Action {
+        Action(ActionKind::Stop)
+    }
+
+    /// Fail the request with [`Error::Redirect`](crate::Error::Redirect).
+    pub fn error<E: fmt::Display>(self, error: E) -> Action {
+        Action(ActionKind::Error(error.to_string()))
+    }
+}
+
+/// What a [`Policy`] decided for an [`Attempt`].
+#[derive(Debug)]
+pub struct Action(ActionKind);
+
+#[derive(Debug)]
+enum ActionKind {
+    Follow,
+    Stop,
+    TooMany,
+    Error(String),
+}
+
+/// The request to send to the `Location` of a redirect response to
+/// `request`, `None` to return the response as it is. `body_lost` tells that
+/// the body of `request` couldn't be kept.
+pub(crate) fn next_request(
+    policy: &Policy,
+    request: &Request,
+    body_lost: bool,
+    status: StatusCode,
+    headers: &HeaderMap,
+    previous: &[Uri],
+) -> crate::Result<Option<Request>> {
+    if !status.is_redirection() || status == StatusCode::NOT_MODIFIED || policy.is_none() {
+        return Ok(None);
+    }
+    let Some(location) = headers
+        .get(header::LOCATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| crate::crawl::resolve(request.uri(), v))
+    else {
+        return Ok(None);
+    };
+
+    let attempt = Attempt {
+        status,
+        next: &location,
+        previous,
+    };
+    match policy.check(attempt).0 {
+        ActionKind::Follow => {}
+        ActionKind::Stop => return Ok(None),
+        ActionKind::TooMany => return Err(crate::Error::TooManyRedirects(previous.len() - 1)),
+        ActionKind::Error(e) => return Err(crate::Error::Redirect(e)),
+    }
+
+    // 307 and 308 resend the request as it is, the others switch to a GET
+    // without body.
+    let keep_method = matches!(
+        status,
+        StatusCode::TEMPORARY_REDIRECT | StatusCode::PERMANENT_REDIRECT
+    );
+    if keep_method && body_lost {
+        // The body was a stream, it can't be sent again.
+        return Ok(None);
+    }
+    let method = match request.method() {
+        method if keep_method || *method == Method::HEAD => method.clone(),
+        _ => Method::GET,
+    };
+    let Some(mut next) = request.redirected(location, keep_method) else {
+        return Ok(None);
+    };
+    *next.method_mut() = method;
+
+    let has_body = next.body().is_some();
+    let same_origin = request.uri().scheme() == next.uri().scheme()
+        && request.uri().authority() == next.uri().authority();
+    let headers = next.headers_mut();
+    headers.remove(header::HOST);
+    if !has_body {
+        headers.remove(header::CONTENT_TYPE);
+        headers.remove(header::CONTENT_LENGTH);
+        headers.remove(header::TRANSFER_ENCODING);
+    }
+    if !same_origin {
+        headers.remove(header::AUTHORIZATION);
+        headers.remove(header::COOKIE);
+        headers.remove(header::PROXY_AUTHORIZATION);
+    }
+    Ok(Some(next))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use crate::{
+        client::Client,
+        stats::{Recorder, StatsRecorder},
+        test_util::{MockResponse, MockTransport},
+    };
+
+    use super::Policy;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_follow() {
+        let mock = MockTransport::new();
+        mock.push(
+            MockResponse::new(301)
+                .header("location", "/b")
+                .response_delay(Duration::from_millis(10)),
+        );
+        mock.push(MockResponse::new(302).header("location", "https://other.test/c"));
+        mock.push(MockResponse::new(200).body("done"));
+        let client = Client::builder()
+            .mock_transport(mock.clone())
+            .redirect(Policy::limited(5))
+            .build()
+            .unwrap();
+
+        let recorder = StatsRecorder::new();
+        let response = client
+            .post("https://example.com/a")
+            .header("authorization", "secret")
+            .body("payload")
+            .recorder(Box::new(recorder.clone()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.text().await.unwrap(), "done");
+
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[1].uri, "https://example.com/b");
+        assert_eq!(requests[1].method, http::Method::GET);
+        assert_eq!(requests[1].body.as_deref(), Some(&b""[..]));
+        assert_eq!(requests[1].headers["authorization"], "secret");
+        assert!(requests[2].headers.get("authorization").is_none());
+
+        let stats = recorder.finish();
+        let hops = stats
+            .hops
+            .iter()
+            .map(|hop| (hop.url.to_string(), hop.status.map(|v| v.as_u16())))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            hops,
+            [
+                ("https://example.com/a".to_string(), Some(301)),
+                ("https://example.com/b".to_string(), Some(302)),
+                ("https://other.test/c".to_string(), Some(200)),
+            ]
+        );
+        assert!(stats.hops[0].stats.total_duration >= Duration::from_millis(10));
+    }
+
+    #[tokio::test]
+    async fn test_policies() {
+        let redirects = || {
+            let mock = MockTransport::new();
+            for i in 0..3 {
+                mock.push(MockResponse::new(307).header("location", format!("/{i}")));
+            }
+            mock.push(MockResponse::new(200));
+            mock
+        };
+
+        let client = Client::builder()
+            .mock_transport(redirects())
+            .build()
+            .unwrap();
+        let response = client.get("https://example.com/").send().await.unwrap();
+        assert_eq!(response.status(), 307);
+
+        let client = Client::builder()
+            .mock_transport(redirects())
+            .redirect(Policy::limited(2))
+            .build()
+            .unwrap();
+        let err = client.get("https://example.com/").send().await.unwrap_err();
+        assert!(matches!(err, crate::Error::TooManyRedirects(2)));
+
+        let client = Client::builder()
+            .mock_transport(redirects())
+            .redirect(Policy::custom(|attempt| {
+                if attempt.url().path() == "/1" {
+                    attempt.stop()
+                } else {
+                    attempt.follow()
+                }
+            }))
+            .build()
+            .unwrap();
+        let response = client.get("https://example.com/").send().await.unwrap();
+        assert_eq!(response.status(), 307);
+        assert_eq!(response.headers()["location"], "/1");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_chain_timeout() {
+        let mock = MockTransport::new();
+        for i in 0..3 {
+            mock.push(
+                MockResponse::new(307)
+                    .header("location", format!("/{i}"))
+                    .response_delay(Duration::from_millis(40)),
+            );
+        }
+        mock.push(MockResponse::new(200));
+        let client = Client::builder()
+            .mock_transport(mock.clone())
+            .redirect(Policy::limited(5))
+            .build()
+            .unwrap();
+
+        // Every hop is within the timeout, the chain isn't.
+        let err = client
+            .get("https://example.com/")
+            .timeout(Duration::from_millis(100))
+            .send()
+            .await
+            .unwrap_err();
+        assert!(matches!(err, crate::Error::Timeout(_)));
+        assert_eq!(mock.requests().len(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_redirect_hops() {
+        let mock = MockTransport::new();
+        mock.push(MockResponse::new(302).response_delay(Duration::from_millis(10)));
+        mock.push(MockResponse::new(200).response_delay(Duration::from_millis(20)));
+        let client = Client::builder().mock_transport(mock).build().unwrap();
+
+        let recorder = StatsRecorder::new();
+        let location: http::Uri = "https://example.com/b".parse().unwrap();
+        let mut request = client.get("https://example.com/a").build().unwrap();
+        *request.recorder_mut() = Some(Arc::new(recorder.clone()));
+        // The next hop is the same request, sent to the new location.
+        let mut next = request.head();
+        *next.uri_mut() = location.clone();
+
+        let response = client.execute(request).await.unwrap();
+        recorder.on_redirect(&next, response.status(), &location);
+        client.execute(next).await.unwrap();
+
+        let stats = recorder.finish();
+        assert_eq!(stats.hops.len(), 2);
+        assert_eq!(stats.hops[0].url, "https://example.com/a");
+        assert_eq!(stats.hops[0].status, Some(http::StatusCode::FOUND));
+        assert_eq!(stats.hops[1].url, location);
+        assert_eq!(stats.hops[1].status, Some(http::StatusCode::OK));
+        assert_eq!(
+            stats.total_duration,
+            stats.hops[0].stats.total_duration + stats.hops[1].stats.total_duration
+        );
+    }
+}
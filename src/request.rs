@@ -1,19 +1,106 @@
-use std::{fmt, time::Duration};
-
+use std::{
+    fmt,
+    net::IpAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use bytes::Bytes;
 use http::{HeaderMap, HeaderName, HeaderValue, Method, Request as HttpRequest, Uri, Version};
+use tokio::time::Instant;
+
+use crate::{
+    Body,
+    alt_svc::AltSvc,
+    client::{Alpn, Client, VersionPolicy},
+    response::Response,
+    stats::Recorder,
+    websocket::WebSocket,
+};
 
-use crate::{Body, client::Client, response::Response, stats::Recorder};
+/// Identifies a request within the process, passed to the recorder along with
+/// the request so concurrent traces can be told apart.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RequestId(u64);
+
+impl RequestId {
+    fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        RequestId(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
 
 #[derive(Default)]
 pub struct Request {
+    id: RequestId,
     method: Method,
     uri: Uri,
     headers: HeaderMap,
     body: Option<Body>,
     timeout: Option<Duration>,
     version: Version,
+    // The names sent with their exact case on HTTP/1.
+    header_case: Vec<String>,
+    // Sent instead of the head built from the request.
+    raw_head: Option<Bytes>,
+    // Connect to this address instead of resolving the host.
+    connect_ip: Option<IpAddr>,
+    // The TLS server name, instead of the host.
+    sni: Option<String>,
+    // Fail unless the connection negotiates this protocol.
+    required_protocol: Option<Alpn>,
+    // Offered instead of the client's protocols.
+    version_policy: Option<VersionPolicy>,
+    // Set by `RequestBuilder::version`, the connection must speak it.
+    pinned_version: Option<Version>,
+    // Connected to instead of the origin, set by the client.
+    alt_svc: Option<AltSvc>,
+    // Keep the body as received, whatever its `Content-Encoding`.
+    no_decompress: bool,
+    // Hold the body until the server answers `100 Continue`.
+    expect_continue: bool,
+    // Set once handed to the client.
+    started_at: Option<Instant>,
+
+    recorder: Option<Arc<dyn Recorder>>,
+}
+
+/// The head of the request a response answers, kept in its extensions.
+#[derive(Debug, Clone)]
+pub(crate) struct RequestHead {
+    pub(crate) method: Method,
+    pub(crate) uri: Uri,
+    pub(crate) version: Version,
+    pub(crate) headers: HeaderMap,
+}
 
-    recorder: Option<Box<dyn Recorder>>,
+/// The request a response answers with its recorder, kept in its extensions
+/// to report what happens once the body is read.
+#[derive(Clone)]
+pub(crate) struct TracedRequest(pub(crate) Arc<Request>);
+
+impl RequestHead {
+    pub(crate) fn new(request: &Request) -> Self {
+        RequestHead {
+            method: request.method.clone(),
+            uri: request.uri.clone(),
+            version: request.version,
+            headers: request.headers.clone(),
+        }
+    }
 }
 
 pub struct RequestBuilder {
@@ -24,12 +111,38 @@ pub struct RequestBuilder {
 impl Request {
     pub fn new(method: Method, uri: Uri) -> Self {
         Self {
+            id: RequestId::next(),
             method,
             uri,
             ..Default::default()
         }
     }
 
+    /// The id of the request, a clone gets a new one.
+    #[inline]
+    pub fn id(&self) -> RequestId {
+        self.id
+    }
+
+    /// When the request was handed to the client, `None` before.
+    ///
+    /// Taken from tokio's clock like the [`Stats`](crate::stats::Stats), so
+    /// recorders can place the hooks on a common timeline.
+    #[inline]
+    pub fn started_at(&self) -> Option<Instant> {
+        self.started_at
+    }
+
+    /// The time since [`started_at`](Self::started_at), zero before.
+    pub fn elapsed(&self) -> Duration {
+        self.started_at.map(|v| v.elapsed()).unwrap_or_default()
+    }
+
+    /// Start the clock of the request, kept if already started.
+    pub(crate) fn start(&mut self) {
+        self.started_at.get_or_insert_with(Instant::now);
+    }
+
     #[inline]
     pub fn method(&self) -> &Method {
         &self.method
@@ -113,14 +226,173 @@ impl Request {
         *req.timeout_mut() = self.timeout().copied();
         *req.headers_mut() = self.headers().clone();
         *req.version_mut() = self.version();
+        req.header_case = self.header_case.clone();
+        req.raw_head = self.raw_head.clone();
+        req.connect_ip = self.connect_ip;
+        req.sni = self.sni.clone();
+        req.required_protocol = self.required_protocol.clone();
+        req.version_policy = self.version_policy;
+        req.pinned_version = self.pinned_version;
+        req.no_decompress = self.no_decompress;
+        req.expect_continue = self.expect_continue;
         req.body = body;
         Some(req)
     }
 
+    /// A copy of the request taking its body, keeping this one to send
+    /// again if the copy can't be.
+    pub(crate) fn take_attempt(&mut self) -> Request {
+        Request {
+            id: self.id,
+            method: self.method.clone(),
+            uri: self.uri.clone(),
+            headers: self.headers.clone(),
+            body: self.body.take(),
+            timeout: self.timeout,
+            version: self.version,
+            header_case: self.header_case.clone(),
+            raw_head: self.raw_head.clone(),
+            connect_ip: self.connect_ip,
+            sni: self.sni.clone(),
+            required_protocol: self.required_protocol.clone(),
+            version_policy: self.version_policy,
+            pinned_version: self.pinned_version,
+            alt_svc: self.alt_svc.clone(),
+            no_decompress: self.no_decompress,
+            expect_continue: self.expect_continue,
+            started_at: self.started_at,
+            recorder: self.recorder.clone(),
+        }
+    }
+
     pub fn recorder(&self) -> Option<&dyn Recorder> {
         self.recorder.as_deref()
     }
 
+    /// Get a mutable reference to the recorder.
+    #[inline]
+    pub fn recorder_mut(&mut self) -> &mut Option<Arc<dyn Recorder>> {
+        &mut self.recorder
+    }
+
+    /// A copy of this request without its body, sharing the same recorder.
+    ///
+    /// Used to keep reporting to the recorder once the request itself has been
+    /// handed over to the connection.
+    pub(crate) fn head(&self) -> Request {
+        let mut req = Request::new(self.method().clone(), self.uri().clone());
+        req.id = self.id;
+        *req.timeout_mut() = self.timeout().copied();
+        *req.headers_mut() = self.headers().clone();
+        *req.version_mut() = self.version();
+        req.header_case = self.header_case.clone();
+        req.raw_head = self.raw_head.clone();
+        req.connect_ip = self.connect_ip;
+        req.sni = self.sni.clone();
+        req.required_protocol = self.required_protocol.clone();
+        req.version_policy = self.version_policy;
+        req.pinned_version = self.pinned_version;
+        req.alt_svc = self.alt_svc.clone();
+        req.no_decompress = self.no_decompress;
+        req.expect_continue = self.expect_continue;
+        req.started_at = self.started_at;
+        req.recorder = self.recorder.clone();
+        req
+    }
+
+    /// The next hop of a redirect to `uri`, under the same id and recorder,
+    /// with the body only if `keep_body`. `None` if the body can't be cloned.
+    pub(crate) fn redirected(&self, uri: Uri, keep_body: bool) -> Option<Request> {
+        let body = match self.body.as_ref().filter(|_| keep_body) {
+            Some(body) => Some(body.try_clone()?),
+            None => None,
+        };
+        let mut req = self.head();
+        req.uri = uri;
+        // Pinned to the original host.
+        req.raw_head = None;
+        req.connect_ip = None;
+        req.sni = None;
+        req.alt_svc = None;
+        req.body = body;
+        Some(req)
+    }
+
+    /// A copy to send again over HTTP/1.1 after HTTP/2 failed, under the same
+    /// id and recorder. `None` if the body can't be cloned.
+    pub(crate) fn downgraded(&self) -> Option<Request> {
+        let body = match self.body.as_ref() {
+            Some(body) => Some(body.try_clone()?),
+            None => None,
+        };
+        let mut req = self.head();
+        req.version = Version::HTTP_11;
+        req.pinned_version = Some(Version::HTTP_11);
+        req.body = body;
+        Some(req)
+    }
+
+    /// The header names to send with their exact case on HTTP/1.
+    pub(crate) fn header_case(&self) -> &[String] {
+        &self.header_case
+    }
+
+    /// The bytes sent instead of the request head, if any.
+    pub(crate) fn raw_head(&self) -> Option<&Bytes> {
+        self.raw_head.as_ref()
+    }
+
+    /// The address to connect to instead of resolving the host, if any.
+    pub(crate) fn connect_ip(&self) -> Option<IpAddr> {
+        self.connect_ip
+    }
+
+    /// The protocol the connection must negotiate, if any.
+    pub(crate) fn required_protocol(&self) -> Option<&Alpn> {
+        self.required_protocol.as_ref()
+    }
+
+    /// The protocols to offer instead of the client's, if any.
+    pub(crate) fn version_policy(&self) -> Option<VersionPolicy> {
+        self.version_policy
+    }
+
+    /// The alternative service to connect to instead of the origin, if any.
+    pub(crate) fn alt_svc(&self) -> Option<&AltSvc> {
+        self.alt_svc.as_ref()
+    }
+
+    pub(crate) fn set_alt_svc(&mut self, alt_svc: Option<AltSvc>) {
+        self.alt_svc = alt_svc;
+    }
+
+    /// The version the connection must speak, if set by
+    /// [`RequestBuilder::version`].
+    pub(crate) fn pinned_version(&self) -> Option<Version> {
+        self.pinned_version
+    }
+
+    /// Whether the body is handed out as received.
+    #[cfg(any(
+        feature = "gzip",
+        feature = "deflate",
+        feature = "brotli",
+        feature = "zstd"
+    ))]
+    pub(crate) fn no_decompress(&self) -> bool {
+        self.no_decompress
+    }
+
+    /// Whether to wait for `100 Continue` before sending the body.
+    pub(crate) fn expect_continue(&self) -> bool {
+        self.expect_continue
+    }
+
+    /// The TLS server name to send instead of the host, if any.
+    pub(crate) fn sni(&self) -> Option<&str> {
+        self.sni.as_deref()
+    }
+
     pub(crate) fn port(&self) -> u16 {
         self.uri.port_u16().unwrap_or_else(|| {
             if self.uri.scheme() == Some(&http::uri::Scheme::HTTPS) {
@@ -160,6 +432,122 @@ impl RequestBuilder {
         self.header_sensitive(key, value, false)
     }
 
+    /// Add a `Header` whose name is sent with exactly this case on HTTP/1,
+    /// e.g. `X-API-Key`.
+    ///
+    /// Other names are sent lowercase, or in title case with
+    /// [`ClientBuilder::title_case_headers`](crate::client::ClientBuilder::title_case_headers).
+    /// Headers go out in the order they were added, the values of a repeated
+    /// name together. Only HTTP/1.1 is offered by ALPN, unless HTTP/2 is
+    /// pinned with [`version`](Self::version), which sends names lowercase.
+    pub fn header_with_case<V>(mut self, name: &str, value: V) -> RequestBuilder
+    where
+        HeaderValue: TryFrom<V>,
+        <HeaderValue as TryFrom<V>>::Error: Into<http::Error>,
+    {
+        if let Ok(ref mut req) = self.request {
+            req.header_case.push(name.to_string());
+        }
+        self.header(name, value)
+    }
+
+    /// Test a virtual host: connect to `connect_ip`, send `sni` as the TLS
+    /// server name, also verifying the certificate against it, and `host` as
+    /// the `Host` header, whatever the host of the URI.
+    ///
+    /// The port and the scheme still come from the URI.
+    pub fn vhost(mut self, connect_ip: IpAddr, sni: &str, host: &str) -> RequestBuilder {
+        if let Ok(ref mut req) = self.request {
+            match HeaderValue::try_from(host) {
+                Ok(value) => {
+                    req.headers_mut().insert(http::header::HOST, value);
+                    req.connect_ip = Some(connect_ip);
+                    req.sni = Some(sni.to_string());
+                }
+                Err(e) => self.request = Err(e.into()),
+            }
+        }
+        self
+    }
+
+    /// Send `sni` as the TLS server name instead of the host of the URI,
+    /// e.g. to test which certificate a CDN serves for a name or domain
+    /// fronting. The certificate is verified against `sni`.
+    pub fn sni(mut self, sni: &str) -> RequestBuilder {
+        if let Ok(ref mut req) = self.request {
+            req.sni = Some(sni.to_string());
+        }
+        self
+    }
+
+    /// Send these bytes instead of the request head, e.g. to probe a server
+    /// with a malformed request line or duplicated headers.
+    ///
+    /// The URI still decides where to connect, the method how the response
+    /// is read, and the body follows the raw head as is. The head must end
+    /// with an empty line. The recorder and [`Response::save_bundle`] see the
+    /// head built from the request, not the raw one.
+    ///
+    /// Only HTTP/1.1 is offered by ALPN. The request fails with
+    /// [`Error::RawHeadNotHttp1`](crate::Error::RawHeadNotHttp1) if HTTP/2 is
+    /// pinned with [`version`](Self::version).
+    pub fn raw_head<T: Into<Bytes>>(mut self, head: T) -> RequestBuilder {
+        if let Ok(ref mut req) = self.request {
+            req.raw_head = Some(head.into());
+        }
+        self
+    }
+
+    /// Fail with [`Error::ProtocolNotNegotiated`](crate::Error::ProtocolNotNegotiated)
+    /// unless the connection negotiates `protocol`, instead of falling back
+    /// to HTTP/1.1.
+    ///
+    /// The protocol must be among the client's
+    /// [`alpn_protocols`](crate::client::ClientBuilder::alpn_protocols). A
+    /// plain HTTP connection only speaks HTTP/1.1.
+    pub fn require_protocol(mut self, protocol: Alpn) -> RequestBuilder {
+        if let Ok(ref mut req) = self.request {
+            req.required_protocol = Some(protocol);
+        }
+        self
+    }
+
+    /// Offer the protocols of `policy` instead of the client's, the
+    /// outcome is reported by [`Recorder::on_negotiated`].
+    pub fn version_policy(mut self, policy: VersionPolicy) -> RequestBuilder {
+        if let Ok(ref mut req) = self.request {
+            req.version_policy = Some(policy);
+        }
+        self
+    }
+
+    /// Hand out the response body as received, still compressed, e.g. to
+    /// measure the transfer size. The `Accept-Encoding` is sent all the same.
+    pub fn no_decompress(mut self) -> RequestBuilder {
+        if let Ok(ref mut req) = self.request {
+            req.no_decompress = true;
+        }
+        self
+    }
+
+    /// Send `Expect: 100-continue` and hold the body until the server
+    /// answers `100 Continue`, or for one second if it doesn't. A final
+    /// response first means the body isn't sent at all. The wait is
+    /// reported to [`Recorder::on_expect_continue_start`].
+    ///
+    /// Only HTTP/1.1 is offered by ALPN, the request is sent on a connection
+    /// of its own.
+    pub fn expect_continue(mut self) -> RequestBuilder {
+        if let Ok(ref mut req) = self.request {
+            req.headers.insert(
+                http::header::EXPECT,
+                HeaderValue::from_static("100-continue"),
+            );
+            req.expect_continue = true;
+        }
+        self
+    }
+
     /// Add a `Header` to this Request with ability to define if `header_value` is sensitive.
     fn header_sensitive<K, V>(mut self, key: K, value: V, sensitive: bool) -> RequestBuilder
     where
@@ -232,8 +620,9 @@ impl RequestBuilder {
     /// Enables a request timeout.
     ///
     /// The timeout is applied from when the request starts connecting until the
-    /// response body has finished. It affects only this request and overrides
-    /// the timeout configured using `ClientBuilder::timeout()`.
+    /// response body has finished, across every redirect followed. It affects
+    /// only this request and overrides the timeout configured using
+    /// `ClientBuilder::timeout()`.
     pub fn timeout(mut self, timeout: Duration) -> RequestBuilder {
         if let Ok(ref mut req) = self.request {
             *req.timeout_mut() = Some(timeout);
@@ -241,21 +630,37 @@ impl RequestBuilder {
         self
     }
 
-    /// Set HTTP version
+    /// Send the request over `version`, offering only its protocol by ALPN.
+    ///
+    /// HTTP/2 on plain HTTP is spoken with prior knowledge. Fails with
+    /// [`Error::ProtocolNotNegotiated`](crate::Error::ProtocolNotNegotiated)
+    /// if the server doesn't agree, and with
+    /// [`Error::UnsupportedVersion`](crate::Error::UnsupportedVersion) for
+    /// HTTP/3.
     pub fn version(mut self, version: Version) -> RequestBuilder {
         if let Ok(ref mut req) = self.request {
             req.version = version;
+            req.pinned_version = Some(version);
         }
         self
     }
 
     pub fn recorder(mut self, recorder: Box<dyn Recorder>) -> RequestBuilder {
         if let Ok(ref mut req) = self.request {
-            req.recorder = Some(recorder);
+            req.recorder = Some(Arc::from(recorder));
         }
         self
     }
 
+    /// Open a WebSocket with this request as the handshake, over HTTP/1.1.
+    ///
+    /// The URL may use the `ws` and `wss` schemes, see
+    /// [`websocket`](crate::websocket).
+    pub async fn upgrade_websocket(self) -> crate::Result<WebSocket> {
+        let (client, request) = self.version(Version::HTTP_11).build_split();
+        crate::websocket::connect(client, request?).await
+    }
+
     /// Build a `Request`, which can be inspected, modified and executed with
     /// `Client::execute()`.
     pub fn build(self) -> crate::Result<Request> {
@@ -287,6 +692,7 @@ impl TryFrom<Request> for HttpRequest<Body> {
     type Error = crate::Error;
 
     fn try_from(value: Request) -> Result<Self, Self::Error> {
+        let head = value.recorder().map(|_| value.head());
         let Request {
             method,
             uri,
@@ -302,6 +708,24 @@ impl TryFrom<Request> for HttpRequest<Body> {
             .version(version)
             .body(body.unwrap_or_else(Body::empty))?;
         *req.headers_mut() = headers;
+        // HTTP/1.1 only sends the trailers it's told about.
+        if let Some(trailers) = req.body().trailers()
+            && !req.headers().contains_key(http::header::TRAILER)
+        {
+            let names: Vec<&str> = trailers.keys().map(HeaderName::as_str).collect();
+            if !names.is_empty() {
+                let value = HeaderValue::from_str(&names.join(", "))?;
+                req.headers_mut().insert(http::header::TRAILER, value);
+            }
+        }
+        // Only called on HTTP/1, hyper drops them on HTTP/2.
+        if let Some(head) = head {
+            hyper::ext::on_informational(&mut req, move |res| {
+                if let Some(recorder) = head.recorder() {
+                    recorder.on_informational(&head, res.status(), res.headers());
+                }
+            });
+        }
 
         Ok(req)
     }
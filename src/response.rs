@@ -1,9 +1,37 @@
+use std::{
+    fmt,
+    path::Path,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
 use bytes::Bytes;
+use chardetng::EncodingDetector;
 use encoding_rs::{Encoding, UTF_8};
-use http::{HeaderMap, Response as HttpResponse, StatusCode, Version};
+use http::{HeaderMap, HeaderName, Response as HttpResponse, StatusCode, Version, header};
+use hyper::upgrade::OnUpgrade;
+use hyper_util::rt::TokioIo;
 use mime::Mime;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::{
+    Body,
+    body::ResponseBody,
+    request::{RequestHead, TracedRequest},
+    sse::{SseEvent, SseParser},
+    stats::{ConnectionInfo, Stats},
+};
+// The most `bytes` allocates upfront, whatever the `Content-Length` says.
+const MAX_PREALLOCATED_BODY: u64 = 64 * 1024 * 1024;
+
+// The headers whose values a bundle leaves out, along with the sensitive ones.
+const REDACTED_HEADERS: &[HeaderName] = &[
+    header::AUTHORIZATION,
+    header::COOKIE,
+    header::PROXY_AUTHORIZATION,
+    header::SET_COOKIE,
+];
 
-use crate::body::ResponseBody;
 pub struct Response {
     pub(super) res: HttpResponse<ResponseBody>,
 }
@@ -55,6 +83,16 @@ impl Response {
         Body::size_hint(self.res.body()).exact()
     }
 
+    /// The parsed `Content-Type` header, `None` if missing or invalid.
+    ///
+    /// See [`sniff`](crate::sniff) to check it against the body.
+    pub fn content_type(&self) -> Option<Mime> {
+        self.headers()
+            .get(http::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<Mime>().ok())
+    }
+
     pub async fn text(self) -> crate::Result<String> {
         self.text_with_charset("utf-8").await
     }
@@ -68,12 +106,14 @@ impl Response {
         self.res.extensions_mut()
     }
 
+    /// The connection the response was received on, `None` for a response
+    /// served from the cache.
+    pub fn connection_info(&self) -> Option<&ConnectionInfo> {
+        self.extensions().get()
+    }
+
     pub async fn text_with_charset(self, default_encoding: &str) -> crate::Result<String> {
-        let content_type = self
-            .headers()
-            .get(http::header::CONTENT_TYPE)
-            .and_then(|value| value.to_str().ok())
-            .and_then(|value| value.parse::<Mime>().ok());
+        let content_type = self.content_type();
         let encoding_name = content_type
             .as_ref()
             .and_then(|mime| mime.get_param("charset").map(|charset| charset.as_str()))
@@ -86,13 +126,262 @@ impl Response {
         Ok(text.into_owned())
     }
 
+    /// The first `n` bytes of the body, fewer if the body is shorter.
+    ///
+    /// The bytes are buffered, the body still reads from its start afterwards,
+    /// also when reading ahead fails.
+    pub async fn peek(&mut self, n: usize) -> crate::Result<Bytes> {
+        use http_body_util::BodyExt;
+
+        let mut frames = Vec::new();
+        let mut peeked = bytes::BytesMut::new();
+        let mut result = Ok(());
+        while peeked.len() < n {
+            match self.res.body_mut().frame().await {
+                Some(Ok(frame)) => {
+                    if let Some(data) = frame.data_ref() {
+                        peeked.extend_from_slice(data);
+                    }
+                    frames.push(frame);
+                }
+                Some(Err(e)) => {
+                    result = Err(e);
+                    break;
+                }
+                None => break,
+            }
+        }
+
+        let rest = std::mem::replace(self.res.body_mut(), crate::body::boxed(Body::empty()));
+        *self.res.body_mut() = crate::body::boxed(crate::body::peeked(frames, rest));
+        result?;
+
+        peeked.truncate(n);
+        Ok(peeked.freeze())
+    }
+
+    /// Copy the body to `sink` while it is read.
+    ///
+    /// Each chunk is written before being handed out, and the sink is flushed
+    /// once the body ends. A failing sink fails the body.
+    pub fn tee<W>(self, sink: W) -> Response
+    where
+        W: AsyncWrite + Send + Sync + 'static,
+    {
+        Response::new(
+            self.res
+                .map(|body| crate::body::boxed(crate::body::tee(body, sink))),
+        )
+    }
+
+    /// Decode the body, guessing the charset when the `Content-Type` doesn't
+    /// tell it or tells a wrong one.
+    ///
+    /// A BOM wins over the header, then the header charset is used if the body
+    /// decodes without errors. Otherwise the charset is sniffed from the bytes,
+    /// invalid sequences are replaced.
+    pub async fn text_lossy(self) -> crate::Result<String> {
+        let charset = self
+            .content_type()
+            .and_then(|mime| mime.get_param("charset").map(|v| v.as_str().to_string()))
+            .and_then(|charset| Encoding::for_label(charset.as_bytes()));
+
+        let full = self.bytes().await?;
+        Ok(decode_lossy(&full, charset))
+    }
+
+    /// Decode the body according to its `Content-Type`.
+    ///
+    /// JSON, MessagePack and CBOR are supported, each one with the feature of
+    /// the same name (`json`, `msgpack`, `cbor`). Structured syntax suffixes
+    /// are honored, e.g. `application/problem+json`.
+    #[cfg(any(test, feature = "json", feature = "msgpack", feature = "cbor"))]
+    pub async fn decode<T: serde::de::DeserializeOwned>(self) -> crate::Result<T> {
+        let content_type = self
+            .content_type()
+            .ok_or(crate::Error::UnsupportedContentType(None))?;
+        let format = content_type
+            .suffix()
+            .unwrap_or_else(|| content_type.subtype())
+            .as_str()
+            .to_ascii_lowercase();
+        let unsupported = || crate::Error::UnsupportedContentType(Some(content_type.to_string()));
+
+        match format.as_str() {
+            #[cfg(any(test, feature = "json"))]
+            "json" => Ok(serde_json::from_slice(&self.bytes().await?)?),
+            #[cfg(feature = "msgpack")]
+            "msgpack" | "x-msgpack" | "vnd.msgpack" => {
+                Ok(rmp_serde::from_slice(&self.bytes().await?)?)
+            }
+            #[cfg(feature = "cbor")]
+            "cbor" => Ok(ciborium::from_reader(&self.bytes().await?[..])?),
+            _ => Err(unsupported()),
+        }
+    }
+
+    /// Decode the body as newline-delimited JSON, one item per line, as the
+    /// lines arrive.
+    ///
+    /// Blank lines are skipped, a line that doesn't decode yields an error
+    /// without ending the stream. Requires the `json` feature.
+    #[cfg(any(test, feature = "json"))]
+    pub fn json_lines<T: serde::de::DeserializeOwned>(
+        self,
+    ) -> impl futures_util::Stream<Item = crate::Result<T>> {
+        let state = (self, bytes::BytesMut::new(), false);
+        futures_util::stream::unfold(state, |(mut response, mut buf, mut eof)| async move {
+            loop {
+                let line = match buf.iter().position(|v| *v == b'\n') {
+                    Some(pos) => buf.split_to(pos + 1),
+                    None if eof => {
+                        if buf.is_empty() {
+                            return None;
+                        }
+                        buf.split()
+                    }
+                    None => {
+                        match response.chunk().await {
+                            Ok(Some(chunk)) => buf.extend_from_slice(&chunk),
+                            Ok(None) => eof = true,
+                            Err(e) => {
+                                // The body can't be read any further.
+                                buf.clear();
+                                return Some((Err(e), (response, buf, true)));
+                            }
+                        }
+                        continue;
+                    }
+                };
+                if line.iter().all(|v| v.is_ascii_whitespace()) {
+                    continue;
+                }
+                let item = serde_json::from_slice(&line).map_err(crate::Error::from);
+                return Some((item, (response, buf, eof)));
+            }
+        })
+    }
+
+    /// Parse a `text/event-stream` body into events as they arrive.
+    ///
+    /// Each event is reported to
+    /// [`Recorder::on_sse_event`](crate::stats::Recorder::on_sse_event) of the
+    /// request, to trace when it was received.
+    pub fn event_stream(self) -> impl futures_util::Stream<Item = crate::Result<SseEvent>> {
+        let traced = self.extensions().get::<TracedRequest>().cloned();
+        let state = (self, SseParser::default(), traced);
+        futures_util::stream::unfold(state, |(mut response, mut parser, traced)| async move {
+            loop {
+                if let Some(event) = parser.next_event() {
+                    if let Some(TracedRequest(request)) = traced.as_ref()
+                        && let Some(recorder) = request.recorder()
+                    {
+                        recorder.on_sse_event(request, &event);
+                    }
+                    return Some((Ok(event), (response, parser, traced)));
+                }
+                match response.chunk().await {
+                    Ok(Some(chunk)) => parser.push(&chunk),
+                    Ok(None) => return None,
+                    Err(e) => return Some((Err(e), (response, SseParser::default(), None))),
+                }
+            }
+        })
+    }
+
+    /// Read the body and write the exchange to `dir`, e.g. to attach it to an
+    /// incident ticket. Returns the body.
+    ///
+    /// The folder holds `request.txt` and `response.txt` with the heads, `body`
+    /// and, given the stats of the request, `stats.txt` along with `stats.json`
+    /// with the `json` feature. The values of the credential headers,
+    /// `Authorization`, `Cookie`, `Set-Cookie`, `Proxy-Authorization` and the
+    /// ones marked sensitive, are written as `<redacted>`.
+    pub async fn save_bundle<P: AsRef<Path>>(
+        self,
+        dir: P,
+        stats: Option<&Stats>,
+    ) -> crate::Result<Bytes> {
+        self.write_bundle(dir.as_ref(), stats, true).await
+    }
+
+    /// Like [`save_bundle`](Self::save_bundle), with the credential headers
+    /// written as they are.
+    pub async fn save_bundle_unredacted<P: AsRef<Path>>(
+        self,
+        dir: P,
+        stats: Option<&Stats>,
+    ) -> crate::Result<Bytes> {
+        self.write_bundle(dir.as_ref(), stats, false).await
+    }
+
+    async fn write_bundle(
+        self,
+        dir: &Path,
+        stats: Option<&Stats>,
+        redact: bool,
+    ) -> crate::Result<Bytes> {
+        tokio::fs::create_dir_all(dir).await?;
+
+        if let Some(head) = self.extensions().get::<RequestHead>() {
+            let target = head.uri.path_and_query().map_or("/", |v| v.as_str());
+            let mut text = format!("{} {} {:?}\r\n", head.method, target, head.version);
+            write_headers(&mut text, &head.headers, redact);
+            tokio::fs::write(dir.join("request.txt"), text).await?;
+        }
+        let mut text = format!("{:?} {}\r\n", self.version(), self.status());
+        write_headers(&mut text, self.headers(), redact);
+        tokio::fs::write(dir.join("response.txt"), text).await?;
+
+        let body = self.bytes().await?;
+        tokio::fs::write(dir.join("body"), &body).await?;
+
+        if let Some(stats) = stats {
+            tokio::fs::write(dir.join("stats.txt"), stats.to_string()).await?;
+            #[cfg(any(test, feature = "json"))]
+            tokio::fs::write(
+                dir.join("stats.json"),
+                serde_json::to_vec_pretty(&stats.to_json())?,
+            )
+            .await?;
+        }
+        Ok(body)
+    }
+
+    /// Read the whole body.
+    ///
+    /// A body received in one chunk is returned as is. Otherwise the chunks
+    /// are copied into a single buffer, sized upfront from the
+    /// `Content-Length` so that a large body doesn't grow it step by step.
     pub async fn bytes(self) -> crate::Result<Bytes> {
         use http_body_util::BodyExt;
 
-        let d = BodyExt::collect(self.res.into_body())
-            .await
-            .map(|buf| buf.to_bytes())?;
-        Ok(d)
+        let capacity = self
+            .content_length()
+            .map_or(0, |len| len.min(MAX_PREALLOCATED_BODY) as usize);
+        let mut body = self.res.into_body();
+        let mut first: Option<Bytes> = None;
+        let mut buf: Option<bytes::BytesMut> = None;
+        while let Some(frame) = body.frame().await {
+            let Ok(data) = frame?.into_data() else {
+                continue;
+            };
+            match (buf.as_mut(), first.take()) {
+                (Some(buf), _) => buf.extend_from_slice(&data),
+                (None, None) => first = Some(data),
+                (None, Some(first)) => {
+                    let mut full =
+                        bytes::BytesMut::with_capacity(capacity.max(first.len() + data.len()));
+                    full.extend_from_slice(&first);
+                    full.extend_from_slice(&data);
+                    buf = Some(full);
+                }
+            }
+        }
+        Ok(match buf {
+            Some(buf) => buf.freeze(),
+            None => first.unwrap_or_default(),
+        })
     }
 
     pub async fn chunk(&mut self) -> crate::Result<Option<Bytes>> {
@@ -111,4 +400,487 @@ impl Response {
             }
         }
     }
+
+    /// The connection after a `101 Switching Protocols`, to speak the
+    /// protocol the request asked for with `Upgrade`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async fn run() -> httptrace::Result<()> {
+    /// use tokio::io::AsyncWriteExt;
+    ///
+    /// let client = httptrace::client::Client::builder().build()?;
+    /// let response = client
+    ///     .get("http://example.com/chat")
+    ///     .header("connection", "upgrade")
+    ///     .header("upgrade", "chat")
+    ///     .send()
+    ///     .await?;
+    /// let mut io = response.upgrade().await?;
+    /// io.write_all(b"hello").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn upgrade(mut self) -> crate::Result<Upgraded> {
+        if self.status() != StatusCode::SWITCHING_PROTOCOLS {
+            return Err(crate::Error::Upgrade(format!(
+                "unexpected status {}",
+                self.status()
+            )));
+        }
+        let upgrade = self
+            .extensions_mut()
+            .remove::<OnUpgrade>()
+            .ok_or_else(|| crate::Error::Upgrade("connection not upgradable".into()))?;
+        Ok(Upgraded {
+            io: TokioIo::new(upgrade.await?),
+        })
+    }
+}
+
+fn write_headers(text: &mut String, headers: &HeaderMap, redact: bool) {
+    for (name, value) in headers {
+        let value = match redact && (value.is_sensitive() || REDACTED_HEADERS.contains(name)) {
+            true => "<redacted>".into(),
+            false => String::from_utf8_lossy(value.as_bytes()),
+        };
+        text.push_str(&format!("{name}: {value}\r\n"));
+    }
+}
+
+fn decode_lossy(bytes: &[u8], charset: Option<&'static Encoding>) -> String {
+    if let Some((encoding, bom)) = Encoding::for_bom(bytes) {
+        return encoding
+            .decode_without_bom_handling(&bytes[bom..])
+            .0
+            .into_owned();
+    }
+    if let Some(text) =
+        charset.and_then(|v| v.decode_without_bom_handling_and_without_replacement(bytes))
+    {
+        return text.into_owned();
+    }
+
+    let mut detector = EncodingDetector::new();
+    detector.feed(bytes, true);
+    let encoding = detector.guess(None, true);
+    encoding.decode_without_bom_handling(bytes).0.into_owned()
+}
+
+impl Response {
+    /// Split the response into its head and body.
+    pub fn into_parts(self) -> (http::response::Parts, Body) {
+        let (parts, body) = self.res.into_parts();
+        (parts, Body::from(body))
+    }
+
+    /// Build a response from a head and a body, e.g. to feed a test double.
+    pub fn from_parts(parts: http::response::Parts, body: Body) -> Self {
+        Response::new(HttpResponse::from_parts(parts, crate::body::boxed(body)))
+    }
+}
+
+impl From<Response> for HttpResponse<Body> {
+    fn from(response: Response) -> Self {
+        let (parts, body) = response.into_parts();
+        HttpResponse::from_parts(parts, body)
+    }
+}
+
+impl<B: Into<Body>> From<HttpResponse<B>> for Response {
+    fn from(response: HttpResponse<B>) -> Self {
+        let (parts, body) = response.into_parts();
+        Response::from_parts(parts, body.into())
+    }
+}
+
+impl fmt::Debug for Response {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Response")
+            .field("status", &self.status())
+            .field("version", &self.version())
+            .field("headers", self.headers())
+            .finish()
+    }
+}
+
+/// An upgraded connection, see [`Response::upgrade`].
+#[derive(Debug)]
+pub struct Upgraded {
+    io: TokioIo<hyper::upgrade::Upgraded>,
+}
+
+impl AsyncRead for Upgraded {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.io).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Upgraded {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[std::io::IoSlice<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.io.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use encoding_rs::{UTF_8, WINDOWS_1252};
+    use futures_util::StreamExt;
+    use http_body_util::StreamBody;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::{Response, decode_lossy};
+    use crate::{
+        client::Client,
+        events::{ChannelRecorder, EventKind, Overflow},
+        sse::SseEvent,
+        stats::StatsRecorder,
+        test_util::{MockResponse, MockTransport},
+    };
+
+    #[test]
+    fn test_decode_lossy() {
+        // BOM over the header
+        assert_eq!(
+            decode_lossy(b"\xef\xbb\xbfcaf\xc3\xa9", Some(WINDOWS_1252)),
+            "café"
+        );
+        assert_eq!(
+            decode_lossy(b"caf\xc3\xa9 cr\xc3\xa8me", Some(UTF_8)),
+            "café crème"
+        );
+        // wrong header charset
+        assert_eq!(
+            decode_lossy(b"caf\xe9 cr\xe8me br\xfbl\xe9e", Some(UTF_8)),
+            "café crème brûlée"
+        );
+        // no header
+        assert_eq!(
+            decode_lossy("\u{4f60}\u{597d}".as_bytes(), None),
+            "\u{4f60}\u{597d}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_decode() {
+        let response = |content_type: &str, body: &'static str| {
+            let response = http::Response::builder()
+                .header("content-type", content_type)
+                .body(crate::body::boxed(http_body_util::Full::new(
+                    bytes::Bytes::from(body),
+                )))
+                .unwrap();
+            Response::new(response)
+        };
+
+        let value = response("application/problem+json", "{\"n\": 1}")
+            .decode::<serde_json::Value>()
+            .await
+            .unwrap();
+        assert_eq!(value["n"], 1);
+
+        let ret = response("text/plain", "1").decode::<u32>().await;
+        assert!(matches!(
+            ret,
+            Err(crate::Error::UnsupportedContentType(Some(v))) if v == "text/plain"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_http_conversion() {
+        let response = Response::from(
+            http::Response::builder()
+                .status(201)
+                .header("x-test", "1")
+                .body("created")
+                .unwrap(),
+        );
+        assert_eq!(response.status(), 201);
+
+        let response = http::Response::<crate::Body>::from(response);
+        assert_eq!(response.headers()["x-test"], "1");
+        let response = Response::from(response);
+        assert_eq!(response.text().await.unwrap(), "created");
+    }
+
+    #[tokio::test]
+    async fn test_bytes() {
+        let data = bytes::Bytes::from(vec![7u8; 1000]);
+        let body = http_body_util::Full::new(data.clone());
+        let response = Response::new(http::Response::new(crate::body::boxed(body)));
+        assert_eq!(response.content_length(), Some(1000));
+        let body = response.bytes().await.unwrap();
+        assert_eq!(body.as_ptr(), data.as_ptr());
+
+        let chunks = ["a", "", "bc", "def"];
+        let body =
+            StreamBody::new(futures_util::stream::iter(chunks.map(|v| {
+                Ok::<_, std::io::Error>(http_body::Frame::data(bytes::Bytes::from(v)))
+            })));
+        let response = Response::new(http::Response::new(crate::body::boxed(body)));
+        assert_eq!(response.bytes().await.unwrap(), "abcdef");
+    }
+
+    #[tokio::test]
+    async fn test_peek() {
+        let chunks = ["<html>", "<body>", "</body></html>"];
+        let body =
+            StreamBody::new(futures_util::stream::iter(chunks.map(|v| {
+                Ok::<_, std::io::Error>(http_body::Frame::data(bytes::Bytes::from(v)))
+            })));
+        let mut response = Response::new(http::Response::new(crate::body::boxed(body)));
+
+        assert_eq!(response.peek(8).await.unwrap(), "<html><b");
+        assert_eq!(response.peek(4).await.unwrap(), "<htm");
+        assert_eq!(response.peek(100).await.unwrap(), chunks.concat());
+        assert_eq!(response.text().await.unwrap(), chunks.concat());
+
+        let body = http_body_util::Full::new(bytes::Bytes::from("hello world"));
+        let mut response = Response::new(http::Response::new(crate::body::boxed(body)));
+        assert_eq!(response.peek(5).await.unwrap(), "hello");
+        let hint = http_body::Body::size_hint(response.res.body());
+        assert_eq!(hint.exact(), Some(11));
+
+        // What was read before the error is kept.
+        let reset = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+        let frames = [Ok("hello"), Err(reset)];
+        let body = StreamBody::new(futures_util::stream::iter(
+            frames.map(|v| v.map(|v| http_body::Frame::data(bytes::Bytes::from(v)))),
+        ));
+        let mut response = Response::new(http::Response::new(crate::body::boxed(body)));
+        assert!(response.peek(8).await.is_err());
+        assert_eq!(response.peek(8).await.unwrap(), "hello");
+    }
+
+    #[tokio::test]
+    async fn test_tee() {
+        let chunks = ["hello ", "world"];
+        let body =
+            StreamBody::new(futures_util::stream::iter(chunks.map(|v| {
+                Ok::<_, std::io::Error>(http_body::Frame::data(bytes::Bytes::from(v)))
+            })));
+        let response = Response::new(http::Response::new(crate::body::boxed(body)));
+
+        let (writer, mut reader) = tokio::io::duplex(4);
+        let copy = tokio::spawn(async move {
+            let mut copy = Vec::new();
+            tokio::io::AsyncReadExt::read_to_end(&mut reader, &mut copy)
+                .await
+                .unwrap();
+            copy
+        });
+        let body = response.tee(writer).bytes().await.unwrap();
+        assert_eq!(body, "hello world");
+        assert_eq!(copy.await.unwrap(), b"hello world");
+    }
+
+    #[tokio::test]
+    async fn test_json_lines() {
+        // lines split across chunks
+        let chunks = ["{\"n\": 1}\n{\"n\"", ": 2}\n\nnot json\n{\"n\": 3}"];
+        let body =
+            StreamBody::new(futures_util::stream::iter(chunks.map(|v| {
+                Ok::<_, std::io::Error>(http_body::Frame::data(bytes::Bytes::from(v)))
+            })));
+        let response = Response::new(http::Response::new(crate::body::boxed(body)));
+
+        #[derive(serde::Deserialize)]
+        struct Item {
+            n: u32,
+        }
+        let items = response.json_lines::<Item>().collect::<Vec<_>>().await;
+        assert_eq!(items.len(), 4);
+        assert_eq!(items[0].as_ref().unwrap().n, 1);
+        assert_eq!(items[1].as_ref().unwrap().n, 2);
+        assert!(matches!(items[2], Err(crate::Error::Json(_))));
+        assert_eq!(items[3].as_ref().unwrap().n, 3);
+    }
+
+    #[tokio::test]
+    async fn test_event_stream() {
+        // events split across chunks
+        let chunks = [
+            "\u{feff}: comment\nid: 1\ndata: a\ndata",
+            ": b\r\n\r\nevent: ping\nretry: 500\ndata\n\n",
+            "data: cut",
+        ];
+        let body =
+            StreamBody::new(futures_util::stream::iter(chunks.map(|v| {
+                Ok::<_, std::io::Error>(http_body::Frame::data(bytes::Bytes::from(v)))
+            })));
+        let response = Response::new(http::Response::new(crate::body::boxed(body)));
+
+        let events = response.event_stream().collect::<Vec<_>>().await;
+        let events = events.into_iter().map(Result::unwrap).collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            [
+                SseEvent {
+                    event: "message".into(),
+                    data: "a\nb".into(),
+                    id: Some("1".into()),
+                    retry: None,
+                },
+                SseEvent {
+                    event: "ping".into(),
+                    data: "".into(),
+                    id: Some("1".into()),
+                    retry: Some(Duration::from_millis(500)),
+                },
+            ]
+        );
+
+        let mock = MockTransport::new();
+        mock.push(MockResponse::new(200).body("data: 1\n\ndata: 2\n\n"));
+        let client = Client::builder().mock_transport(mock).build().unwrap();
+        let (recorder, mut received) = ChannelRecorder::new(64, Overflow::Block);
+        let response = client
+            .get("http://example.com/events")
+            .recorder(Box::new(recorder))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.event_stream().count().await, 2);
+        let mut traced = 0;
+        while let Some(event) = received.recv().await {
+            traced += matches!(event.kind, EventKind::SseEvent(_)) as usize;
+        }
+        assert_eq!(traced, 2);
+    }
+
+    #[tokio::test]
+    async fn test_upgrade() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut head = Vec::new();
+            while !head.ends_with(b"\r\n\r\n") {
+                head.push(stream.read_u8().await.unwrap());
+            }
+            stream
+                .write_all(
+                    b"HTTP/1.1 101 Switching Protocols\r\n\
+                      connection: upgrade\r\nupgrade: echo\r\n\r\n",
+                )
+                .await
+                .unwrap();
+            let mut buf = [0; 5];
+            stream.read_exact(&mut buf).await.unwrap();
+            stream.write_all(&buf).await.unwrap();
+        });
+
+        let client = Client::builder().build().unwrap();
+        let response = client
+            .get(format!("http://{addr}/"))
+            .header("connection", "upgrade")
+            .header("upgrade", "echo")
+            .send()
+            .await
+            .unwrap();
+        let mut io = response.upgrade().await.unwrap();
+        io.write_all(b"hello").await.unwrap();
+        let mut buf = [0; 5];
+        io.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+
+        let mock = MockTransport::new();
+        mock.push(MockResponse::new(200));
+        let client = Client::builder().mock_transport(mock).build().unwrap();
+        let response = client.get("http://example.com/").send().await.unwrap();
+        assert!(matches!(
+            response.upgrade().await,
+            Err(crate::Error::Upgrade(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_save_bundle() {
+        let mock = MockTransport::new();
+        mock.push(MockResponse::new(404).header("x-mock", "1").body("missing"));
+        mock.push(MockResponse::new(200).header("set-cookie", "id=1"));
+        let client = Client::builder()
+            .mock_transport(mock.clone())
+            .build()
+            .unwrap();
+
+        let recorder = StatsRecorder::new();
+        let mut request = client
+            .get("https://example.com/a?b=c")
+            .header("x-api-key", "key")
+            .build()
+            .unwrap();
+        *request.recorder_mut() = Some(Arc::new(recorder.clone()));
+        let response = client.execute(request).await.unwrap();
+
+        let dir = std::env::temp_dir().join(format!("httptrace-bundle-{}", std::process::id()));
+        let body = response
+            .save_bundle(&dir, Some(&recorder.finish()))
+            .await
+            .unwrap();
+        assert_eq!(body, "missing");
+
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+        assert!(read("request.txt").starts_with("GET /a?b=c HTTP/1.1\r\nx-api-key: key\r\n"));
+        assert_eq!(
+            read("response.txt"),
+            "HTTP/1.1 404 Not Found\r\nx-mock: 1\r\n"
+        );
+        assert_eq!(read("body"), "missing");
+        let stats: serde_json::Value = serde_json::from_str(&read("stats.json")).unwrap();
+        assert_eq!(stats["tcp"][0]["peer_addr"], "127.0.0.1:443");
+
+        // Credentials are left out unless asked for.
+        let send = || {
+            client
+                .get("https://example.com/")
+                .bearer_auth("token")
+                .header("cookie", "id=1")
+                .send()
+        };
+        send().await.unwrap().save_bundle(&dir, None).await.unwrap();
+        let request = read("request.txt");
+        assert!(request.contains("\r\nauthorization: <redacted>\r\ncookie: <redacted>\r\n"));
+        assert!(read("response.txt").contains("\r\nset-cookie: <redacted>\r\n"));
+        mock.push(MockResponse::new(200).header("set-cookie", "id=1"));
+        let response = send().await.unwrap();
+        response.save_bundle_unredacted(&dir, None).await.unwrap();
+        assert!(read("request.txt").contains("\r\nauthorization: Bearer token\r\n"));
+        assert!(read("response.txt").contains("\r\nset-cookie: id=1\r\n"));
+        _ = std::fs::remove_dir_all(&dir);
+    }
 }
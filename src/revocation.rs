@@ -0,0 +1,228 @@
+//! Check the server certificate against certificate revocation lists, see
+//! [`ClientBuilder::crls`](crate::client::ClientBuilder::crls).
+
+use std::{
+    fmt,
+    sync::{Arc, OnceLock},
+};
+
+use rustls::{
+    CertificateError, RootCertStore,
+    client::{WebPkiServerVerifier, danger::ServerCertVerifier},
+    pki_types::{CertificateDer, CertificateRevocationListDer, ServerName, UnixTime},
+};
+
+/// The revocation status of the server certificate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RevocationStatus {
+    /// A list of its issuer is given and doesn't name it.
+    Good,
+    Revoked,
+    /// No list of its issuer is given.
+    Unknown,
+}
+
+/// Checks the server certificates after the handshake, with the verifier
+/// built on first use.
+#[derive(Debug)]
+pub(crate) struct RevocationChecker {
+    crls: Vec<CertificateRevocationListDer<'static>>,
+    verifier: OnceLock<Arc<WebPkiServerVerifier>>,
+}
+
+impl RevocationChecker {
+    pub(crate) fn new(crls: Vec<CertificateRevocationListDer<'static>>) -> Self {
+        Self {
+            crls,
+            verifier: OnceLock::new(),
+        }
+    }
+
+    pub(crate) fn crls(&self) -> &[CertificateRevocationListDer<'static>] {
+        &self.crls
+    }
+
+    /// Check the chain presented by the server, the end entity first.
+    ///
+    /// Only the revocation of the end entity is checked, the chain must be
+    /// valid for `server_name`.
+    pub(crate) fn check(
+        &self,
+        roots: impl FnOnce() -> crate::Result<Arc<RootCertStore>>,
+        chain: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+    ) -> crate::Result<RevocationStatus> {
+        let verifier = match self.verifier.get() {
+            Some(verifier) => verifier.clone(),
+            None => {
+                let verifier = WebPkiServerVerifier::builder(roots()?)
+                    .with_crls(self.crls.iter().cloned())
+                    .only_check_end_entity_revocation()
+                    .build()
+                    .map_err(|e| crate::Error::Revocation(e.to_string()))?;
+                self.verifier.get_or_init(|| verifier).clone()
+            }
+        };
+
+        let (end_entity, intermediates) = chain
+            .split_first()
+            .ok_or(rustls::Error::NoCertificatesPresented)?;
+        let ret = verifier.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            &[],
+            UnixTime::now(),
+        );
+        match ret {
+            Ok(_) => Ok(RevocationStatus::Good),
+            Err(rustls::Error::InvalidCertificate(CertificateError::Revoked)) => {
+                Ok(RevocationStatus::Revoked)
+            }
+            Err(rustls::Error::InvalidCertificate(CertificateError::UnknownRevocationStatus)) => {
+                Ok(RevocationStatus::Unknown)
+            }
+            // The name is checked after the revocation, and by the handshake
+            // unless skipped on purpose.
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::NotValidForName | CertificateError::NotValidForNameContext { .. },
+            )) => Ok(RevocationStatus::Good),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl fmt::Display for RevocationStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RevocationStatus::Good => "good",
+            RevocationStatus::Revoked => "revoked",
+            RevocationStatus::Unknown => "unknown",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rcgen::{
+        BasicConstraints, CertificateParams, CertificateRevocationListParams, DnType, IsCa,
+        KeyIdMethod, KeyPair, KeyUsagePurpose, RevokedCertParams, SerialNumber, date_time_ymd,
+    };
+    use rustls::{
+        RootCertStore, ServerConfig,
+        pki_types::{PrivateKeyDer, ServerName},
+    };
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    use super::{RevocationChecker, RevocationStatus};
+    use crate::client::Client;
+
+    fn ca_params(name: &str) -> CertificateParams {
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params.distinguished_name.push(DnType::CommonName, name);
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+        params
+    }
+
+    /// A list revoking serial number 2.
+    fn crl_params() -> CertificateRevocationListParams {
+        CertificateRevocationListParams {
+            this_update: date_time_ymd(2024, 1, 1),
+            next_update: date_time_ymd(2124, 1, 1),
+            crl_number: SerialNumber::from(1),
+            issuing_distribution_point: None,
+            revoked_certs: vec![RevokedCertParams {
+                serial_number: SerialNumber::from(2),
+                revocation_time: date_time_ymd(2024, 1, 1),
+                reason_code: None,
+                invalidity_date: None,
+            }],
+            key_identifier_method: KeyIdMethod::Sha256,
+        }
+    }
+
+    #[test]
+    fn test_revocation() {
+        crate::client::ensure_crypto_provider();
+
+        let ca_key = KeyPair::generate().unwrap();
+        let ca = ca_params("ca").self_signed(&ca_key).unwrap();
+
+        let leaf = |serial: u64| {
+            let mut params = CertificateParams::new(vec!["a.test".to_string()]).unwrap();
+            params.serial_number = Some(SerialNumber::from(serial));
+            let key = KeyPair::generate().unwrap();
+            params.signed_by(&key, &ca, &ca_key).unwrap().der().clone()
+        };
+        let crl = crl_params().signed_by(&ca, &ca_key).unwrap();
+
+        let mut roots = RootCertStore::empty();
+        roots.add(ca.der().clone()).unwrap();
+        let roots = Arc::new(roots);
+        let name = ServerName::try_from("a.test").unwrap();
+
+        let checker = RevocationChecker::new(vec![crl.der().clone()]);
+        let check = |serial| checker.check(|| Ok(roots.clone()), &[leaf(serial)], &name);
+        assert_eq!(check(1).unwrap(), RevocationStatus::Good);
+        assert_eq!(check(2).unwrap(), RevocationStatus::Revoked);
+
+        // Only a list of another issuer.
+        let other_key = KeyPair::generate().unwrap();
+        let other = ca_params("other").self_signed(&other_key).unwrap();
+        let crl = CertificateRevocationListParams {
+            revoked_certs: Vec::new(),
+            ..crl_params()
+        }
+        .signed_by(&other, &other_key)
+        .unwrap();
+        let checker = RevocationChecker::new(vec![crl.der().clone()]);
+        let status = checker.check(|| Ok(roots.clone()), &[leaf(2)], &name);
+        assert_eq!(status.unwrap(), RevocationStatus::Unknown);
+    }
+
+    #[tokio::test]
+    async fn test_tls_enforce_crls() {
+        crate::client::ensure_crypto_provider();
+
+        let ca_key = KeyPair::generate().unwrap();
+        let ca = ca_params("ca").self_signed(&ca_key).unwrap();
+        let crl = crl_params().signed_by(&ca, &ca_key).unwrap();
+        let client = Client::builder()
+            .add_root_certificate(ca.der().clone())
+            .tls_built_in_root_certs(false)
+            .crls([crl.der().clone()])
+            .tls_enforce_crls(true)
+            .build()
+            .unwrap();
+
+        for serial in [1, 2] {
+            let mut params = CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+            params.serial_number = Some(SerialNumber::from(serial));
+            let key = KeyPair::generate().unwrap();
+            let cert = params.signed_by(&key, &ca, &ca_key).unwrap();
+            let key = PrivateKeyDer::Pkcs8(key.serialize_der().into());
+            let config = ServerConfig::builder()
+                .with_no_client_auth()
+                .with_single_cert(vec![cert.der().clone()], key)
+                .unwrap();
+            let acceptor = TlsAcceptor::from(Arc::new(config));
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(async move {
+                let (stream, _) = listener.accept().await.unwrap();
+                _ = acceptor.accept(stream).await;
+            });
+
+            let url = format!("https://localhost:{}/", addr.port());
+            let ret = client.probe_certificate(url).await;
+            match serial {
+                1 => assert_eq!(ret.unwrap().tls.revocation, Some(RevocationStatus::Good)),
+                _ => assert!(ret.unwrap_err().to_string().contains("Revoked")),
+            }
+        }
+    }
+}
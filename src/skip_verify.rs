@@ -26,6 +26,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::sync::Arc;
+
+use rustls::CertificateError;
+use rustls::client::WebPkiServerVerifier;
 use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
 use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
 
@@ -78,3 +82,97 @@ impl ServerCertVerifier for SkipVerifier {
         ]
     }
 }
+
+/// Verifies the chain like the default verifier, but accepts a certificate
+/// presented under another name.
+#[derive(Debug)]
+pub(crate) struct SkipHostnameVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+}
+
+impl SkipHostnameVerifier {
+    pub(crate) fn new(inner: Arc<WebPkiServerVerifier>) -> Self {
+        Self { inner }
+    }
+}
+
+impl ServerCertVerifier for SkipHostnameVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        // The name is checked once the chain is verified.
+        match self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        ) {
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::NotValidForName | CertificateError::NotValidForNameContext { .. },
+            )) => Ok(ServerCertVerified::assertion()),
+            ret => ret,
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rustls::{
+        RootCertStore,
+        client::{WebPkiServerVerifier, danger::ServerCertVerifier},
+        pki_types::{ServerName, UnixTime},
+    };
+
+    use super::SkipHostnameVerifier;
+
+    #[test]
+    fn test_skip_hostname() {
+        crate::client::ensure_crypto_provider();
+
+        let trusted = rcgen::generate_simple_self_signed(["a.test".to_string()]).unwrap();
+        let untrusted = rcgen::generate_simple_self_signed(["a.test".to_string()]).unwrap();
+        let mut roots = RootCertStore::empty();
+        roots.add(trusted.cert.der().clone()).unwrap();
+        let inner = WebPkiServerVerifier::builder(Arc::new(roots))
+            .build()
+            .unwrap();
+        let verifier = SkipHostnameVerifier::new(inner);
+
+        let name = ServerName::try_from("b.test").unwrap();
+        let verify = |cert: &rcgen::CertifiedKey| {
+            verifier.verify_server_cert(cert.cert.der(), &[], &name, &[], UnixTime::now())
+        };
+        assert!(verify(&trusted).is_ok());
+        assert!(verify(&untrusted).is_err());
+    }
+}
@@ -0,0 +1,199 @@
+//! Service level objectives, evaluated against a [`StatsAggregate`].
+//!
+//! Each objective allows a fraction of bad requests, its error budget. The
+//! burn rate is the observed bad fraction over the allowed one: below 1 the
+//! budget lasts the whole window, at 2 it is spent in half of it.
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! use httptrace::{aggregate::{Phase, StatsAggregate}, slo::Slo};
+//!
+//! let slo = Slo::new()
+//!     .latency(Phase::Total, Duration::from_millis(500), 99.0)
+//!     .error_rate(0.1);
+//! let results = slo.evaluate(&StatsAggregate::new());
+//! assert!(results.iter().all(|v| v.met()));
+//! ```
+
+use std::{fmt, time::Duration};
+
+use crate::aggregate::{Phase, StatsAggregate};
+
+/// A single objective of an [`Slo`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Objective {
+    /// `target` percent of the requests that went through the phase take at
+    /// most `threshold`.
+    Latency {
+        phase: Phase,
+        threshold: Duration,
+        target: f64,
+    },
+    /// At most `max` percent of the requests fail.
+    ErrorRate { max: f64 },
+}
+
+/// How an [`Objective`] fared against the aggregated requests.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SloResult {
+    pub objective: Objective,
+    /// The number of requests the objective applies to.
+    pub total: u64,
+    /// The requests that were too slow, or failed.
+    pub bad: u64,
+    /// The bad fraction over the allowed one, 0 without any request.
+    pub burn_rate: f64,
+}
+
+/// A list of objectives.
+#[derive(Debug, Clone, Default)]
+pub struct Slo {
+    objectives: Vec<Objective>,
+}
+
+impl Slo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn objective(mut self, objective: Objective) -> Self {
+        self.objectives.push(objective);
+        self
+    }
+
+    /// `target` percent of the requests take at most `threshold` in `phase`,
+    /// e.g. `99.0`.
+    pub fn latency(self, phase: Phase, threshold: Duration, target: f64) -> Self {
+        self.objective(Objective::Latency {
+            phase,
+            threshold,
+            target,
+        })
+    }
+
+    /// At most `max` percent of the requests fail, e.g. `0.1`.
+    pub fn error_rate(self, max: f64) -> Self {
+        self.objective(Objective::ErrorRate { max })
+    }
+
+    /// One result per objective, in declaration order.
+    pub fn evaluate(&self, aggregate: &StatsAggregate) -> Vec<SloResult> {
+        self.objectives
+            .iter()
+            .map(|objective| {
+                let (total, bad, allowed) = match objective {
+                    Objective::Latency {
+                        phase,
+                        threshold,
+                        target,
+                    } => {
+                        let histogram = aggregate.histogram(*phase);
+                        let total = histogram.count();
+                        let bad = total - histogram.count_within(*threshold);
+                        (total, bad, 100.0 - target)
+                    }
+                    Objective::ErrorRate { max } => {
+                        (aggregate.total.count(), aggregate.errors, *max)
+                    }
+                };
+                let burn_rate = match (total, bad) {
+                    (0, _) | (_, 0) => 0.0,
+                    _ if allowed <= 0.0 => f64::INFINITY,
+                    _ => bad as f64 / total as f64 * 100.0 / allowed,
+                };
+                SloResult {
+                    objective: objective.clone(),
+                    total,
+                    bad,
+                    burn_rate,
+                }
+            })
+            .collect()
+    }
+}
+
+impl SloResult {
+    /// Whether the error budget isn't overspent.
+    pub fn met(&self) -> bool {
+        self.burn_rate <= 1.0
+    }
+
+    /// The fraction of the error budget left, negative once overspent.
+    pub fn budget_remaining(&self) -> f64 {
+        1.0 - self.burn_rate
+    }
+}
+
+impl fmt::Display for Objective {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Objective::Latency {
+                phase,
+                threshold,
+                target,
+            } => write!(f, "{}% of {} <= {:?}", target, phase, threshold),
+            Objective::ErrorRate { max } => write!(f, "error rate <= {}%", max),
+        }
+    }
+}
+
+impl fmt::Display for SloResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}: {}/{} bad, burn rate {:.2}",
+            if self.met() { "MET" } else { "MISSED" },
+            self.objective,
+            self.bad,
+            self.total,
+            self.burn_rate
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Slo;
+    use crate::{
+        aggregate::{Phase, StatsAggregate},
+        stats::{Stat, Stats},
+    };
+
+    #[test]
+    fn test_slo() {
+        let mut aggregate = StatsAggregate::new();
+        for ms in 1..=1000 {
+            let mut stats = Stats {
+                total_duration: Duration::from_millis(ms),
+                ..Default::default()
+            };
+            if ms % 500 == 0 {
+                stats.request_stats = Some(Stat {
+                    error: Some("reset".to_string()),
+                    ..Default::default()
+                });
+            }
+            aggregate.add(&stats);
+        }
+
+        let results = Slo::new()
+            .latency(Phase::Total, Duration::from_millis(980), 99.0)
+            .latency(Phase::Total, Duration::from_millis(995), 99.0)
+            .error_rate(0.1)
+            .evaluate(&aggregate);
+        assert_eq!((results[0].total, results[0].bad), (1000, 20));
+        assert!((results[0].burn_rate - 2.0).abs() < 1e-9);
+        assert!(!results[0].met());
+        assert!((results[1].budget_remaining() - 0.5).abs() < 1e-9);
+        assert!(results[1].met());
+        assert_eq!(results[2].bad, 2);
+        assert!(!results[2].met());
+        assert_eq!(
+            results[2].to_string(),
+            "MISSED error rate <= 0.1%: 2/1000 bad, burn rate 2.00"
+        );
+    }
+}
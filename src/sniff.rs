@@ -0,0 +1,130 @@
+//! Guess the type of a body from its first bytes, whatever the `Content-Type`
+//! claims.
+//!
+//! ```
+//! # use httptrace::sniff;
+//! assert!(sniff::is_html(b"\n<!DOCTYPE html><html></html>"));
+//! assert_eq!(sniff::detected_mime(b"%PDF-1.7"), Some(mime::APPLICATION_PDF));
+//! ```
+
+use mime::Mime;
+
+// Matched case-insensitively after leading whitespace and followed by a
+// tag-terminating byte, as in the WHATWG sniffing algorithm.
+const HTML_TAGS: &[&[u8]] = &[
+    b"<!DOCTYPE HTML",
+    b"<HTML",
+    b"<HEAD",
+    b"<SCRIPT",
+    b"<IFRAME",
+    b"<H1",
+    b"<DIV",
+    b"<FONT",
+    b"<TABLE",
+    b"<A",
+    b"<STYLE",
+    b"<TITLE",
+    b"<B",
+    b"<BODY",
+    b"<BR",
+    b"<P",
+    b"<!--",
+];
+
+const SIGNATURES: &[(&[u8], &str)] = &[
+    (b"%PDF-", "application/pdf"),
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1f\x8b\x08", "application/gzip"),
+    (b"\x00asm", "application/wasm"),
+];
+
+/// The type of the body, `None` if it isn't recognized.
+///
+/// Only the first bytes matter, so a prefix of the body is enough, except for
+/// JSON: [`is_json`] also checks the last byte, so it needs the whole body.
+pub fn detected_mime(bytes: &[u8]) -> Option<Mime> {
+    for (signature, mime) in SIGNATURES {
+        if bytes.starts_with(signature) {
+            return mime.parse().ok();
+        }
+    }
+    if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        return Some("image/webp".parse().unwrap());
+    }
+
+    if is_html(bytes) {
+        return Some(mime::TEXT_HTML);
+    }
+    if trim_start(bytes).starts_with(b"<?xml") {
+        return Some(mime::TEXT_XML);
+    }
+    if is_json(bytes) {
+        return Some(mime::APPLICATION_JSON);
+    }
+    None
+}
+
+/// Whether the body starts like an HTML document.
+pub fn is_html(bytes: &[u8]) -> bool {
+    let bytes = trim_start(bytes);
+    HTML_TAGS.iter().any(|tag| {
+        bytes.len() > tag.len()
+            && bytes[..tag.len()].eq_ignore_ascii_case(tag)
+            && (*tag == b"<!--" || matches!(bytes[tag.len()], b' ' | b'>'))
+    })
+}
+
+/// Whether the body looks like a JSON object or array.
+///
+/// Only the first and last bytes are checked, the document is not parsed.
+pub fn is_json(bytes: &[u8]) -> bool {
+    let bytes = trim_start(bytes);
+    let bytes = match bytes.iter().rposition(|v| !v.is_ascii_whitespace()) {
+        Some(end) => &bytes[..=end],
+        None => return false,
+    };
+    matches!(
+        (bytes.first(), bytes.last()),
+        (Some(b'{'), Some(b'}')) | (Some(b'['), Some(b']'))
+    )
+}
+
+fn trim_start(bytes: &[u8]) -> &[u8] {
+    let start = bytes
+        .iter()
+        .position(|v| !v.is_ascii_whitespace())
+        .unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{detected_mime, is_html, is_json};
+
+    #[test]
+    fn test_sniff() {
+        assert!(is_html(b"  <html lang=\"en\">"));
+        assert!(is_html(b"<!-- comment -->"));
+        assert!(!is_html(b"<htmlx>"));
+        assert!(is_json(b" {\"a\": 1}\n"));
+        assert!(is_json(b"[1, 2]"));
+        assert!(!is_json(b"{\"a\": 1"));
+        assert_eq!(detected_mime(b"{\"a\": 1"), None);
+
+        assert_eq!(detected_mime(b"\x89PNG\r\n\x1a\n...").unwrap(), "image/png");
+        assert_eq!(
+            detected_mime(b"RIFF\0\0\0\0WEBPVP8 ").unwrap(),
+            "image/webp"
+        );
+        assert_eq!(
+            detected_mime(b"<?xml version=\"1.0\"?>").unwrap(),
+            mime::TEXT_XML
+        );
+        assert_eq!(detected_mime(b"{}").unwrap(), mime::APPLICATION_JSON);
+        assert_eq!(detected_mime(b"plain text"), None);
+    }
+}
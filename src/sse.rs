@@ -0,0 +1,100 @@
+//! Server-Sent Events, see [`Response::event_stream`](crate::response::Response::event_stream).
+
+use std::time::Duration;
+
+use bytes::{Buf, BytesMut};
+
+/// An event of a `text/event-stream` body.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SseEvent {
+    /// The `event` field, `message` when not set.
+    pub event: String,
+    /// The `data` lines, joined by newlines.
+    pub data: String,
+    /// The last `id` received, carried over to the following events.
+    pub id: Option<String>,
+    /// The reconnection time asked by the server with this event, if any.
+    pub retry: Option<Duration>,
+}
+
+/// Splits a `text/event-stream` body into events as its chunks arrive.
+#[derive(Debug, Default)]
+pub(crate) struct SseParser {
+    buf: BytesMut,
+    started: bool,
+    event: Option<String>,
+    data: Option<String>,
+    last_id: Option<String>,
+    retry: Option<Duration>,
+}
+
+impl SseParser {
+    pub(crate) fn push(&mut self, chunk: &[u8]) {
+        self.buf.extend_from_slice(chunk);
+        if !self.started && self.buf.len() >= 3 {
+            self.started = true;
+            if self.buf.starts_with(b"\xef\xbb\xbf") {
+                self.buf.advance(3);
+            }
+        }
+    }
+
+    /// The next complete event in the received chunks, `None` until more
+    /// arrive. An event cut by the end of the body is dropped.
+    pub(crate) fn next_event(&mut self) -> Option<SseEvent> {
+        while let Some(pos) = self.buf.iter().position(|v| *v == b'\n') {
+            let line = self.buf.split_to(pos + 1);
+            let line = line.strip_suffix(b"\n").unwrap_or(&line);
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            let line = String::from_utf8_lossy(line);
+            if line.is_empty() {
+                if let Some(event) = self.dispatch() {
+                    return Some(event);
+                }
+                continue;
+            }
+            self.field(&line);
+        }
+        None
+    }
+
+    fn field(&mut self, line: &str) {
+        let (name, value) = match line.split_once(':') {
+            // A comment.
+            Some(("", _)) => return,
+            Some((name, value)) => (name, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match name {
+            "event" => self.event = Some(value.to_string()),
+            "data" => match self.data.as_mut() {
+                Some(data) => {
+                    data.push('\n');
+                    data.push_str(value);
+                }
+                None => self.data = Some(value.to_string()),
+            },
+            "id" if !value.contains('\0') => self.last_id = Some(value.to_string()),
+            "retry" => {
+                if let Ok(ms) = value.parse::<u64>() {
+                    self.retry = Some(Duration::from_millis(ms));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        let event = self.event.take();
+        let retry = self.retry.take();
+        let data = self.data.take()?;
+        Some(SseEvent {
+            event: event
+                .filter(|v| !v.is_empty())
+                .unwrap_or_else(|| "message".into()),
+            data,
+            id: self.last_id.clone(),
+            retry,
+        })
+    }
+}
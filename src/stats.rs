@@ -1,33 +1,379 @@
 use std::{
     collections::HashMap,
-    net::SocketAddr,
-    sync::{Arc, Mutex},
-    time::{Duration, Instant},
+    error::Error,
+    fmt,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime},
 };
 
 use hickory_resolver::config::NameServerConfig;
-use tokio::net::TcpStream;
+use http::{HeaderMap, StatusCode, Uri, Version};
+use rustls::{
+    CipherSuite, HandshakeKind, NamedGroup, ProtocolVersion, client::EchStatus,
+    pki_types::CertificateDer,
+};
+use tokio::{net::TcpStream, time::Instant};
 use tokio_rustls::client::TlsStream;
 
-use crate::request::Request;
+use crate::{
+    alt_svc::AltSvcDecision,
+    dns::DnsInfo,
+    request::{Request, RequestId},
+    response::Response,
+    revocation::RevocationStatus,
+    sse::SseEvent,
+};
 
 #[derive(Debug, Clone, Default)]
 pub struct Stats {
+    /// The time spent waiting for a free slot before starting, `None` if the
+    /// request didn't wait.
+    pub blocked_stats: Option<Stat>,
     pub dns_stats: Stat,
-    pub tcp_stats: Option<Vec<Stat>>,
+    pub dns_info: Option<DnsInfo>,
+    /// One entry per connect attempt, in start order.
+    pub tcp_stats: Option<Vec<TcpStat>>,
+    /// The delay between connect attempts, adapted to the host.
+    pub connect_delay: Option<Duration>,
+    /// The connection the request was sent on, to the proxy if any.
+    pub tcp_info: Option<TcpInfo>,
+    /// Whether the request was sent on a connection of the pool, without
+    /// DNS, TCP and TLS phases.
+    pub reused: bool,
+    /// The connections of the pool found closed before the request, which
+    /// then went on another one.
+    pub dead_connections: Vec<u64>,
+    /// The tunnel setup, only with
+    /// [`ClientBuilder::proxy`](crate::client::ClientBuilder::proxy).
+    pub proxy_stats: Option<Stat>,
+    /// The `CONNECT` sent again with credentials after a `407`, within
+    /// [`proxy_stats`](Self::proxy_stats).
+    pub proxy_auth_stats: Option<Stat>,
+    /// The SOCKS handshake, only with a SOCKS
+    /// [`ClientBuilder::proxy`](crate::client::ClientBuilder::proxy).
+    pub socks_stats: Option<Stat>,
+    /// The HTTP/1.1 round trip asking to switch to h2c, until the response
+    /// head, only with
+    /// [`ClientBuilder::http2_upgrade`](crate::client::ClientBuilder::http2_upgrade).
+    pub h2c_upgrade_stats: Option<Stat>,
+    /// The TLS handshake, what it negotiated is in
+    /// [`tls_info`](Self::tls_info).
     pub tls_stats: Option<Stat>,
+    /// The protocol version, cipher suite, ALPN protocol and key exchange
+    /// group of the handshake, `None` if it failed.
+    pub tls_info: Option<TlsInfo>,
+    /// The chain of a server certificate failing verification, the end
+    /// entity first, only with
+    /// [`ClientBuilder::tls_capture_rejected_chain`](crate::client::ClientBuilder::tls_capture_rejected_chain).
+    pub rejected_certificates: Vec<CertificateDer<'static>>,
+    /// Whether the server accepted the request sent as early data, `None`
+    /// if it wasn't, see
+    /// [`ClientBuilder::tls_early_data`](crate::client::ClientBuilder::tls_early_data).
+    pub tls_early_data: Option<bool>,
+    /// Only with [`ClientBuilder::crls`](crate::client::ClientBuilder::crls).
+    pub revocation_stats: Option<Stat>,
+    pub revocation: Option<RevocationStatus>,
+    /// The HTTP/2 stream the request was sent on, an estimate, see
+    /// [`Recorder::on_http2_stream`].
+    pub http2_stream_id: Option<u32>,
+    /// The HTTP/2 error the request was sent again over HTTP/1.1 after.
+    pub h2_fallback: Option<String>,
+    /// The protocol the request was sent with, and why.
+    pub negotiation: Option<Negotiation>,
+    /// What the client made of the alternative services of the origin, only
+    /// with [`ClientBuilder::alt_svc`](crate::client::ClientBuilder::alt_svc)
+    /// and if the origin advertised some.
+    pub alt_svc: Option<AltSvcDecision>,
     pub request_stats: Option<Stat>,
+    /// The wait for `100 Continue` before sending the body, only with
+    /// [`RequestBuilder::expect_continue`](crate::request::RequestBuilder::expect_continue).
+    pub expect_continue_stats: Option<Stat>,
+    /// The interim responses received before the final one, in order.
+    pub informational: Vec<Informational>,
+    /// `None` until the body is read to the end.
+    pub body_info: Option<BodyInfo>,
+    pub cache_read_stats: Option<Stat>,
+    pub cache_write_stats: Option<Stat>,
+    pub cache_revalidate_stats: Option<Stat>,
+    /// One entry per request of a redirect chain, empty if no redirect was
+    /// followed. `total_duration` then spans the whole chain.
+    pub hops: Vec<HopStats>,
+    /// The wall-clock time the first phase started.
+    pub started_at: Option<SystemTime>,
+    /// The wall-clock time the stats were taken, the end of
+    /// `total_duration`.
+    pub finished_at: Option<SystemTime>,
     pub total_duration: Duration,
 }
 
+/// An interim `1xx` response, e.g. `103 Early Hints` with the `Link`
+/// headers of resources to preload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Informational {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+}
+
+/// A request of a redirect chain.
+#[derive(Debug, Clone)]
+pub struct HopStats {
+    pub url: Uri,
+    /// `None` if no response was received.
+    pub status: Option<StatusCode>,
+    pub stats: Stats,
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct Stat {
     pub duration: Duration,
     pub extend: Option<String>,
     pub error: Option<String>,
+    /// The wall-clock time the phase started, to line up with external logs.
+    pub started_at: Option<SystemTime>,
+    /// The wall-clock time the phase ended, `None` if it didn't.
+    pub finished_at: Option<SystemTime>,
+}
+
+/// A TCP connect attempt.
+#[derive(Debug, Clone)]
+pub struct TcpStat {
+    pub peer_addr: SocketAddr,
+    /// The local address of the socket, `None` if the attempt failed.
+    pub local_addr: Option<SocketAddr>,
+    pub duration: Duration,
+    pub error: Option<String>,
+    pub started_at: Option<SystemTime>,
+    pub finished_at: Option<SystemTime>,
+}
+
+/// Facts about an established TCP connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TcpInfo {
+    pub local_addr: Option<SocketAddr>,
+    pub peer_addr: SocketAddr,
+    /// Whether the socket is Multipath TCP, see
+    /// [`ClientBuilder::mptcp`](crate::client::ClientBuilder::mptcp).
+    pub mptcp: bool,
 }
 
-pub trait Recorder {
+#[cfg(target_os = "linux")]
+fn is_mptcp(stream: &TcpStream) -> bool {
+    let protocol = socket2::SockRef::from(stream).protocol();
+    protocol.is_ok_and(|v| v == Some(socket2::Protocol::MPTCP))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_mptcp(_stream: &TcpStream) -> bool {
+    false
+}
+
+/// Facts about a response body read to the end.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BodyInfo {
+    /// The number of bytes read.
+    pub len: u64,
+    /// Only with [`ClientBuilder::body_digest`](crate::client::ClientBuilder::body_digest).
+    pub sha256: Option<[u8; 32]>,
+}
+
+/// Facts about a completed TLS handshake.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TlsInfo {
+    pub protocol_version: Option<ProtocolVersion>,
+    pub cipher_suite: Option<CipherSuite>,
+    pub kx_group: Option<NamedGroup>,
+    pub alpn_protocol: Option<Vec<u8>>,
+    /// The server name sent, `None` when connecting to an IP address.
+    pub sni: Option<String>,
+    pub resumed: bool,
+    /// When the server's leaf certificate expires.
+    pub cert_not_after: Option<SystemTime>,
+    /// The DER encoded certificates presented by the server, leaf first.
+    pub peer_certificates: Vec<CertificateDer<'static>>,
+    /// `Good` when the chain was checked against revocation lists in the
+    /// handshake, see
+    /// [`ClientBuilder::tls_enforce_crls`](crate::client::ClientBuilder::tls_enforce_crls).
+    pub revocation: Option<RevocationStatus>,
+    /// The OCSP response stapled by the server, `None` if it didn't staple
+    /// one or the session was resumed.
+    pub ocsp_response: Option<Vec<u8>>,
+    /// `Accepted` when the server decrypted the ClientHello, `NotOffered`
+    /// when it was sent in the clear for lack of a config, `None` without
+    /// [`ClientBuilder::tls_ech`](crate::client::ClientBuilder::tls_ech).
+    pub ech: Option<EchStatus>,
+}
+
+/// The connection a response was received on, found in
+/// [`Response::extensions`] even without a recorder.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    /// Unique within the process, shared by the requests sent on the same
+    /// connection.
+    pub id: u64,
+    /// The negotiated protocol.
+    pub version: Version,
+    pub tcp: TcpInfo,
+    /// `None` for plain HTTP.
+    pub tls: Option<TlsInfo>,
+    /// Whether the connection was already used by an earlier request.
+    pub reused: bool,
+}
+
+/// The protocol a request was sent with, and why.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Negotiation {
+    pub version: Version,
+    pub reason: NegotiationReason,
+}
+
+/// Why a protocol was used, see
+/// [`VersionPolicy`](crate::client::VersionPolicy).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NegotiationReason {
+    /// The server picked it among the offered protocols.
+    Alpn(String),
+    /// Protocols were offered but the server picked none, falling back to
+    /// HTTP/1.1.
+    NoAlpn,
+    /// No protocol was offered.
+    NotOffered,
+    /// Plain HTTP, which only speaks HTTP/1.1.
+    Cleartext,
+    /// Plain HTTP/2, see
+    /// [`ClientBuilder::http2_prior_knowledge`](crate::client::ClientBuilder::http2_prior_knowledge).
+    PriorKnowledge,
+    /// Plain HTTP/2 after the server agreed to `Upgrade: h2c`, see
+    /// [`ClientBuilder::http2_upgrade`](crate::client::ClientBuilder::http2_upgrade).
+    Upgrade,
+    /// The server ignored `Upgrade: h2c`, staying on HTTP/1.1.
+    UpgradeRefused,
+}
+
+impl Negotiation {
+    pub(crate) fn tls(alpn: Option<&[u8]>, offered: bool) -> Self {
+        let reason = match alpn {
+            Some(alpn) => NegotiationReason::Alpn(String::from_utf8_lossy(alpn).into_owned()),
+            None if offered => NegotiationReason::NoAlpn,
+            None => NegotiationReason::NotOffered,
+        };
+        let version = match alpn {
+            Some(b"h2") => Version::HTTP_2,
+            _ => Version::HTTP_11,
+        };
+        Self { version, reason }
+    }
+
+    pub(crate) fn h2c_upgrade(switched: bool) -> Self {
+        match switched {
+            true => Self {
+                version: Version::HTTP_2,
+                reason: NegotiationReason::Upgrade,
+            },
+            false => Self {
+                version: Version::HTTP_11,
+                reason: NegotiationReason::UpgradeRefused,
+            },
+        }
+    }
+
+    pub(crate) fn cleartext(prior_knowledge: bool) -> Self {
+        match prior_knowledge {
+            true => Self {
+                version: Version::HTTP_2,
+                reason: NegotiationReason::PriorKnowledge,
+            },
+            false => Self {
+                version: Version::HTTP_11,
+                reason: NegotiationReason::Cleartext,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Negotiation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.version)?;
+        match &self.reason {
+            NegotiationReason::Alpn(alpn) => write!(f, " (ALPN {alpn})"),
+            NegotiationReason::NoAlpn => f.write_str(" (server picked no ALPN protocol)"),
+            NegotiationReason::NotOffered => f.write_str(" (no ALPN offered)"),
+            NegotiationReason::Cleartext => f.write_str(" (plain HTTP)"),
+            NegotiationReason::PriorKnowledge => f.write_str(" (prior knowledge)"),
+            NegotiationReason::Upgrade => f.write_str(" (h2c upgrade)"),
+            NegotiationReason::UpgradeRefused => f.write_str(" (h2c upgrade refused)"),
+        }
+    }
+}
+
+impl ConnectionInfo {
+    pub(crate) fn new(tcp: TcpInfo, tls: Option<TlsInfo>, version: Version) -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            version,
+            tcp,
+            tls,
+            reused: false,
+        }
+    }
+}
+
+impl TcpInfo {
+    pub(crate) fn new(stream: &TcpStream, peer_addr: SocketAddr) -> Self {
+        Self {
+            local_addr: stream.local_addr().ok(),
+            peer_addr,
+            mptcp: is_mptcp(stream),
+        }
+    }
+}
+
+impl TlsInfo {
+    pub(crate) fn new<S>(stream: &TlsStream<S>, sni: Option<String>) -> Self {
+        let session = stream.get_ref().1;
+        let peer_certificates = session.peer_certificates().unwrap_or_default();
+        Self {
+            protocol_version: session.protocol_version(),
+            cipher_suite: session.negotiated_cipher_suite().map(|v| v.suite()),
+            kx_group: session.negotiated_key_exchange_group().map(|v| v.name()),
+            alpn_protocol: session.alpn_protocol().map(|v| v.to_vec()),
+            sni,
+            resumed: session.handshake_kind() == Some(HandshakeKind::Resumed),
+            cert_not_after: peer_certificates
+                .first()
+                .and_then(|cert| crate::util::cert_not_after(cert)),
+            peer_certificates: peer_certificates
+                .iter()
+                .map(|cert| cert.clone().into_owned())
+                .collect(),
+            revocation: None,
+            ocsp_response: None,
+            ech: None,
+        }
+    }
+}
+
+/// Receives the events of a request as they happen.
+///
+/// Events of concurrent requests can be told apart with [`Request::id`].
+///
+/// Hooks are called synchronously, so `SystemTime::now()` in a hook is the
+/// wall-clock time of the event, and [`Request::elapsed`] its offset from
+/// the start of the request on a monotonic clock.
+///
+/// Failures are passed as borrowed errors, a hook formats them only if it
+/// keeps them.
+pub trait Recorder: Send + Sync {
+    /// Called when the request has to wait before starting, e.g. for a slot of
+    /// [`ClientBuilder::max_concurrent_requests`](crate::client::ClientBuilder::max_concurrent_requests).
+    fn on_blocked_start(&self, _request: &Request) {}
+
+    fn on_blocked_done(&self, _request: &Request) {}
+
     fn on_dns_start(&self, _request: &Request, _name_servers: &[NameServerConfig], _host: &str) {}
 
     fn on_dns_done(
@@ -35,128 +381,553 @@ pub trait Recorder {
         _request: &Request,
         _name_servers: &[NameServerConfig],
         _host: &str,
-        _result: Result<(&[SocketAddr], bool), String>,
+        _result: Result<&DnsInfo, &dyn Error>,
     ) {
     }
 
+    /// Called before the first connect attempt with the delay before each
+    /// next one, see
+    /// [`ClientBuilder::connect_attempt_delay`](crate::client::ClientBuilder::connect_attempt_delay).
+    fn on_connect_delay(&self, _request: &Request, _delay: Duration) {}
+
     fn on_tcp_start(&self, _request: &Request, _dest: &SocketAddr) {}
 
     fn on_tcp_done(
         &self,
         _request: &Request,
         _dest: &SocketAddr,
-        _stream: Result<&TcpStream, String>,
+        _tcp: Result<&TcpInfo, &dyn Error>,
+    ) {
+    }
+
+    /// Called instead of the DNS, TCP and TLS hooks when the request is sent
+    /// on a connection of the pool.
+    fn on_connection_reused(&self, _request: &Request, _conn: &ConnectionInfo) {}
+
+    /// Called when a connection of the pool is found closed by the server
+    /// before the request could be sent on it, which then goes on another
+    /// one.
+    fn on_connection_dead(&self, _request: &Request, _conn: &ConnectionInfo) {}
+
+    /// Called when opening the tunnel through the proxy, once connected to
+    /// it.
+    fn on_proxy_start(&self, _request: &Request, _proxy: &Uri) {}
+
+    fn on_proxy_done(&self, _request: &Request, _proxy: &Uri, _result: Result<(), &dyn Error>) {}
+
+    /// Called when sending the credentials after the proxy answered `407`.
+    fn on_proxy_auth_start(&self, _request: &Request, _proxy: &Uri) {}
+
+    fn on_proxy_auth_done(
+        &self,
+        _request: &Request,
+        _proxy: &Uri,
+        _result: Result<(), &dyn Error>,
+    ) {
+    }
+
+    /// Called instead of [`on_proxy_start`](Recorder::on_proxy_start) when
+    /// the proxy is a SOCKS one.
+    fn on_socks_start(&self, _request: &Request, _proxy: &Uri) {}
+
+    fn on_socks_done(&self, _request: &Request, _proxy: &Uri, _result: Result<(), &dyn Error>) {}
+
+    /// Called when sending a request with `Upgrade: h2c`.
+    fn on_h2c_upgrade_start(&self, _request: &Request) {}
+
+    /// Called once the response to `Upgrade: h2c` is received, `true` if the
+    /// server switched to HTTP/2.
+    fn on_h2c_upgrade_done(&self, _request: &Request, _switched: Result<bool, &dyn Error>) {}
+
+    fn on_tls_start(&self, _request: &Request, _tcp: &TcpInfo) {}
+
+    fn on_tls_done(&self, _request: &Request, _tls: Result<&TlsInfo, &dyn Error>) {}
+
+    /// Called after [`on_tls_done`](Recorder::on_tls_done) when the request
+    /// was sent as early data, with whether the server accepted it. If not,
+    /// it was sent again after the handshake.
+    fn on_tls_early_data(&self, _request: &Request, _accepted: bool) {}
+
+    /// Called before [`on_tls_done`](Recorder::on_tls_done) when the server
+    /// certificate fails verification, with the chain presented, the end
+    /// entity first. Only with
+    /// [`ClientBuilder::tls_capture_rejected_chain`](crate::client::ClientBuilder::tls_capture_rejected_chain).
+    fn on_tls_rejected_chain(&self, _request: &Request, _chain: &[CertificateDer<'static>]) {}
+
+    /// Called after the handshake when checking the server certificate
+    /// against the revocation lists of
+    /// [`ClientBuilder::crls`](crate::client::ClientBuilder::crls).
+    fn on_revocation_start(&self, _request: &Request) {}
+
+    fn on_revocation_done(
+        &self,
+        _request: &Request,
+        _status: Result<RevocationStatus, &dyn Error>,
     ) {
     }
 
-    fn on_tls_start(&self, _request: &Request, _stream: &TcpStream) {}
+    /// Called once the protocol to send the request with is known.
+    fn on_negotiated(&self, _request: &Request, _negotiation: &Negotiation) {}
+
+    /// Called when the request is sent on an HTTP/2 stream, concurrent
+    /// requests sharing a pooled connection each having their own.
+    ///
+    /// `stream_id` is an estimate, counted by the client in the order
+    /// requests are sent on the connection: hyper doesn't expose the id,
+    /// and a request failing before its stream opens shifts the later ones.
+    fn on_http2_stream(&self, _request: &Request, _stream_id: u32) {}
+
+    /// Called when HTTP/2 failed before the response, e.g. with
+    /// `REFUSED_STREAM` or `GOAWAY`, and the request is sent again over
+    /// HTTP/1.1 on a new connection.
+    fn on_h2_fallback(&self, _request: &Request, _error: &dyn Error) {}
 
-    fn on_tls_done(&self, _request: &Request, _stream: Result<&TlsStream<TcpStream>, String>) {}
+    /// Called before connecting when the origin has cached alternative
+    /// services, with the one connected to instead if any.
+    fn on_alt_svc(&self, _request: &Request, _decision: &AltSvcDecision) {}
 
     fn on_request_start(&self, _request: &Request) {}
+
+    /// Called once the head of a request with `Expect: 100-continue` is
+    /// written, the body being held.
+    fn on_expect_continue_start(&self, _request: &Request) {}
+
+    /// Called when the body is released, `true` if on `100 Continue` rather
+    /// than after the timeout.
+    fn on_expect_continue_done(&self, _request: &Request, _received: bool) {}
+
+    /// Called for each interim `1xx` response other than `101`, on HTTP/1
+    /// only.
+    fn on_informational(&self, _request: &Request, _status: StatusCode, _headers: &HeaderMap) {}
+
+    /// Called once the response head is received, before the body is read.
+    fn on_response(&self, _request: &Request, _response: &Response) {}
+
+    /// Called once the response body is read to the end, or fails to be.
+    fn on_body_done(&self, _request: &Request, _body: Result<&BodyInfo, &dyn Error>) {}
+
+    /// Called for each event parsed by
+    /// [`Response::event_stream`](crate::response::Response::event_stream).
+    fn on_sse_event(&self, _request: &Request, _event: &SseEvent) {}
+
+    /// Called when a redirect is followed, before the request to `location`
+    /// starts.
+    fn on_redirect(&self, _request: &Request, _status: StatusCode, _location: &Uri) {}
+
+    fn on_cache_read_start(&self, _request: &Request, _key: &str) {}
+
+    /// `hit` tells whether a stored entry was found for the key. On a hit,
+    /// called once the stored body is read, so that the read spans it.
+    fn on_cache_read_done(&self, _request: &Request, _key: &str, _hit: Result<bool, &dyn Error>) {}
+
+    fn on_cache_write_start(&self, _request: &Request, _key: &str) {}
+
+    /// Called once the body has been fully written to disk, with its length.
+    fn on_cache_write_done(
+        &self,
+        _request: &Request,
+        _key: &str,
+        _written: Result<u64, &dyn Error>,
+    ) {
+    }
+
+    /// Called when a stale entry starts being revalidated in background.
+    ///
+    /// The background request reports to the same recorder, its own
+    /// DNS/TCP/TLS events happen between this hook and
+    /// [`Recorder::on_cache_revalidate_done`].
+    fn on_cache_revalidate_start(&self, _request: &Request, _key: &str) {}
+
+    fn on_cache_revalidate_done(
+        &self,
+        _request: &Request,
+        _key: &str,
+        _status: Result<http::StatusCode, &dyn Error>,
+    ) {
+    }
 }
 
+/// A recorder collecting the duration of each phase into [`Stats`].
+///
+/// Timings are taken from tokio's clock, so they follow `tokio::time::pause()`.
+///
+/// A recorder can be shared by many requests, even concurrent ones: phases are
+/// kept apart by [`Request::id`] and read back with [`StatsRecorder::finish_for`].
 #[derive(Clone)]
 pub struct StatsRecorder {
-    inner: Arc<Mutex<StatsRecorderInner>>,
+    inner: Arc<Mutex<StatsRecords>>,
+}
+
+#[derive(Debug, Default)]
+struct StatsRecords {
+    requests: HashMap<RequestId, StatsRecorderInner>,
+    // The request seen last, the one reported by `finish`.
+    last: Option<RequestId>,
+}
+
+impl StatsRecords {
+    fn get(&mut self, request: &Request) -> &mut StatsRecorderInner {
+        if !self.requests.contains_key(&request.id()) {
+            self.last = Some(request.id());
+        }
+        self.requests.entry(request.id()).or_default()
+    }
 }
 
 impl Recorder for StatsRecorder {
-    fn on_dns_start(&self, _request: &Request, _name_servers: &[NameServerConfig], _host: &str) {
-        self.inner.lock().unwrap().dns_stat.start = Some(Instant::now());
+    fn on_blocked_start(&self, request: &Request) {
+        let mut records = self.inner.lock().unwrap();
+        let inner = records.get(request);
+
+        _ = inner.blocked_stat.insert(StatRecord::started());
+    }
+
+    fn on_blocked_done(&self, request: &Request) {
+        let mut records = self.inner.lock().unwrap();
+        let inner = records.get(request);
+
+        if let Some(record) = inner.blocked_stat.as_mut() {
+            record.end();
+        }
+    }
+
+    fn on_dns_start(&self, request: &Request, _name_servers: &[NameServerConfig], _host: &str) {
+        self.inner.lock().unwrap().get(request).dns_stat.begin();
     }
 
     fn on_dns_done(
         &self,
-        _request: &Request,
-        name_servers: &[NameServerConfig],
+        request: &Request,
+        _name_servers: &[NameServerConfig],
         _host: &str,
-        result: Result<(&[SocketAddr], bool), String>,
+        result: Result<&DnsInfo, &dyn Error>,
     ) {
-        let mut inner = self.inner.lock().unwrap();
-        inner.dns_stat.done = Some(Instant::now());
-        inner.dns_name_servers = name_servers
-            .iter()
-            .map(|v| v.to_string())
-            .collect::<Vec<_>>()
-            .join(",");
-        inner.dns_hit_cache = result.as_ref().is_ok_and(|v| v.1);
-        inner.dns_stat.result = Some(result.map(|v| {
-            v.0.iter()
-                .map(|vv| vv.to_string())
-                .collect::<Vec<_>>()
-                .join(",")
-        }));
-    }
-
-    fn on_tcp_start(&self, _request: &Request, dest: &SocketAddr) {
-        let mut inner = self.inner.lock().unwrap();
+        let mut records = self.inner.lock().unwrap();
+        let inner = records.get(request);
+        inner.dns_stat.end();
+        inner.dns_info = result.ok().cloned();
+        inner.dns_stat.result = Some(match result {
+            Ok(info) => Ok(Extend::Addrs(info.addrs.clone())),
+            Err(e) => Err(e.to_string()),
+        });
+    }
+
+    fn on_connect_delay(&self, request: &Request, delay: Duration) {
+        self.inner.lock().unwrap().get(request).connect_delay = Some(delay);
+    }
+
+    fn on_tcp_start(&self, request: &Request, dest: &SocketAddr) {
+        let mut records = self.inner.lock().unwrap();
+        let inner = records.get(request);
 
         let tcp_stats = inner.tcp_stats.get_or_insert(HashMap::new());
         tcp_stats.insert(
-            dest.to_string(),
-            StatRecord {
-                start: Some(Instant::now()),
-                done: None,
-                result: None,
+            *dest,
+            TcpRecord {
+                record: StatRecord::started(),
+                local_addr: None,
             },
         );
     }
 
-    fn on_tcp_done(
-        &self,
-        _request: &Request,
-        dest: &SocketAddr,
-        stream: Result<&TcpStream, String>,
-    ) {
-        let mut inner = self.inner.lock().unwrap();
+    fn on_tcp_done(&self, request: &Request, dest: &SocketAddr, tcp: Result<&TcpInfo, &dyn Error>) {
+        let mut records = self.inner.lock().unwrap();
+        let inner = records.get(request);
+
+        if let Ok(tcp) = tcp.as_ref()
+            && inner.tcp_info.is_none()
+        {
+            inner.tcp_info = Some((*tcp).clone());
+        }
 
         let tcp_stats = inner.tcp_stats.get_or_insert(HashMap::new());
+        if let Some(record) = tcp_stats.get_mut(dest) {
+            record.record.end();
+            record.local_addr = tcp.as_ref().ok().and_then(|tcp| tcp.local_addr);
+            record.record.result = Some(match tcp {
+                Ok(_) => Ok(Extend::Peer(*dest)),
+                Err(e) => Err(e.to_string()),
+            });
+        }
+    }
+
+    fn on_connection_reused(&self, request: &Request, conn: &ConnectionInfo) {
+        let mut records = self.inner.lock().unwrap();
+        let inner = records.get(request);
+
+        inner.reused = true;
+        inner.tcp_info = Some(conn.tcp.clone());
+        inner.tls_info = conn.tls.clone();
+    }
+
+    fn on_connection_dead(&self, request: &Request, conn: &ConnectionInfo) {
+        let mut records = self.inner.lock().unwrap();
+        let inner = records.get(request);
+
+        inner.dead_connections.push(conn.id);
+        inner.reused = false;
+        inner.tcp_info = None;
+        inner.tls_info = None;
+    }
+
+    fn on_proxy_start(&self, request: &Request, _proxy: &Uri) {
+        let mut records = self.inner.lock().unwrap();
+        let inner = records.get(request);
+
+        _ = inner.proxy_stat.insert(StatRecord::started());
+    }
+
+    fn on_proxy_done(&self, request: &Request, _proxy: &Uri, result: Result<(), &dyn Error>) {
+        let mut records = self.inner.lock().unwrap();
+        let inner = records.get(request);
 
-        let dest = dest.to_string();
-        if let Some(record) = tcp_stats.get_mut(&dest) {
-            let now = Instant::now();
-            record.done = Some(now);
-            record.result = Some(stream.map(|_| dest));
+        if let Some(record) = inner.proxy_stat.as_mut() {
+            record.end();
+            record.result = result.err().map(|e| Err(e.to_string()));
         }
-        // else {
-        //     unreachable!()
-        // }
     }
 
-    fn on_tls_start(&self, _request: &Request, _stream: &TcpStream) {
-        let mut inner = self.inner.lock().unwrap();
+    fn on_proxy_auth_start(&self, request: &Request, _proxy: &Uri) {
+        let mut records = self.inner.lock().unwrap();
+        let inner = records.get(request);
 
-        _ = inner.tls_stat.insert(StatRecord {
-            start: Some(Instant::now()),
-            done: None,
-            result: None,
-        });
+        _ = inner.proxy_auth_stat.insert(StatRecord::started());
+    }
+
+    fn on_proxy_auth_done(&self, request: &Request, _proxy: &Uri, result: Result<(), &dyn Error>) {
+        let mut records = self.inner.lock().unwrap();
+        let inner = records.get(request);
+
+        if let Some(record) = inner.proxy_auth_stat.as_mut() {
+            record.end();
+            record.result = result.err().map(|e| Err(e.to_string()));
+        }
+    }
+
+    fn on_socks_start(&self, request: &Request, _proxy: &Uri) {
+        let mut records = self.inner.lock().unwrap();
+        let inner = records.get(request);
+
+        _ = inner.socks_stat.insert(StatRecord::started());
+    }
+
+    fn on_socks_done(&self, request: &Request, _proxy: &Uri, result: Result<(), &dyn Error>) {
+        let mut records = self.inner.lock().unwrap();
+        let inner = records.get(request);
+
+        if let Some(record) = inner.socks_stat.as_mut() {
+            record.end();
+            record.result = result.err().map(|e| Err(e.to_string()));
+        }
+    }
+
+    fn on_h2c_upgrade_start(&self, request: &Request) {
+        let mut records = self.inner.lock().unwrap();
+        let inner = records.get(request);
+
+        _ = inner.h2c_upgrade_stat.insert(StatRecord::started());
+    }
+
+    fn on_h2c_upgrade_done(&self, request: &Request, switched: Result<bool, &dyn Error>) {
+        let mut records = self.inner.lock().unwrap();
+        let inner = records.get(request);
+
+        if let Some(record) = inner.h2c_upgrade_stat.as_mut() {
+            record.end();
+            record.result = switched.err().map(|e| Err(e.to_string()));
+        }
+    }
+
+    fn on_tls_start(&self, request: &Request, _tcp: &TcpInfo) {
+        let mut records = self.inner.lock().unwrap();
+        let inner = records.get(request);
+
+        _ = inner.tls_stat.insert(StatRecord::started());
     }
 
-    fn on_tls_done(&self, _request: &Request, stream: Result<&TlsStream<TcpStream>, String>) {
-        let mut inner = self.inner.lock().unwrap();
+    fn on_tls_done(&self, request: &Request, tls: Result<&TlsInfo, &dyn Error>) {
+        let mut records = self.inner.lock().unwrap();
+        let inner = records.get(request);
 
+        inner.tls_info = tls.as_ref().ok().map(|tls| (*tls).clone());
         if let Some(record) = inner.tls_stat.as_mut() {
-            let now = Instant::now();
-            record.done = Some(now);
-            record.result = Some(stream.map(|stream| {
-                stream.get_ref().1.protocol_version().map_or_else(
-                    || "unknown".to_string(),
-                    |v| v.as_str().unwrap_or_default().to_string(),
-                )
-            }));
+            record.end();
+            record.result = tls.err().map(|e| Err(e.to_string()));
         }
     }
 
-    fn on_request_start(&self, _request: &Request) {
-        let mut inner = self.inner.lock().unwrap();
+    fn on_tls_early_data(&self, request: &Request, accepted: bool) {
+        self.inner.lock().unwrap().get(request).tls_early_data = Some(accepted);
+    }
+
+    fn on_tls_rejected_chain(&self, request: &Request, chain: &[CertificateDer<'static>]) {
+        self.inner
+            .lock()
+            .unwrap()
+            .get(request)
+            .rejected_certificates = chain.to_vec();
+    }
 
-        _ = inner.request_stat.insert(StatRecord {
-            start: Some(Instant::now()),
-            done: None,
-            result: None,
-        });
+    fn on_revocation_start(&self, request: &Request) {
+        let mut records = self.inner.lock().unwrap();
+        let inner = records.get(request);
+
+        _ = inner.revocation_stat.insert(StatRecord::started());
+    }
+
+    fn on_revocation_done(&self, request: &Request, status: Result<RevocationStatus, &dyn Error>) {
+        let mut records = self.inner.lock().unwrap();
+        let inner = records.get(request);
+
+        inner.revocation = status.as_ref().ok().copied();
+        if let Some(record) = inner.revocation_stat.as_mut() {
+            record.end();
+            record.result = Some(status.map(Extend::Revocation).map_err(|e| e.to_string()));
+        }
+    }
+
+    fn on_negotiated(&self, request: &Request, negotiation: &Negotiation) {
+        self.inner.lock().unwrap().get(request).negotiation = Some(negotiation.clone());
+    }
+
+    fn on_http2_stream(&self, request: &Request, stream_id: u32) {
+        self.inner.lock().unwrap().get(request).http2_stream_id = Some(stream_id);
+    }
+
+    fn on_h2_fallback(&self, request: &Request, error: &dyn Error) {
+        self.inner.lock().unwrap().get(request).h2_fallback = Some(error.to_string());
+    }
+
+    fn on_alt_svc(&self, request: &Request, decision: &AltSvcDecision) {
+        self.inner.lock().unwrap().get(request).alt_svc = Some(decision.clone());
+    }
+
+    fn on_request_start(&self, request: &Request) {
+        let mut records = self.inner.lock().unwrap();
+        let inner = records.get(request);
+
+        inner.url = Some(request.uri().clone());
+        _ = inner.request_stat.insert(StatRecord::started());
+    }
+
+    fn on_expect_continue_start(&self, request: &Request) {
+        let mut records = self.inner.lock().unwrap();
+        let inner = records.get(request);
+
+        _ = inner.expect_continue_stat.insert(StatRecord::started());
+    }
+
+    fn on_expect_continue_done(&self, request: &Request, received: bool) {
+        let mut records = self.inner.lock().unwrap();
+        let inner = records.get(request);
+
+        if let Some(record) = inner.expect_continue_stat.as_mut() {
+            record.end();
+            record.result = received.then_some(Ok(Extend::Status(StatusCode::CONTINUE)));
+        }
+    }
+
+    fn on_informational(&self, request: &Request, status: StatusCode, headers: &HeaderMap) {
+        let informational = Informational {
+            status,
+            headers: headers.clone(),
+        };
+        self.inner
+            .lock()
+            .unwrap()
+            .get(request)
+            .informational
+            .push(informational);
+    }
+
+    fn on_response(&self, request: &Request, response: &Response) {
+        let mut records = self.inner.lock().unwrap();
+        let inner = records.get(request);
+
+        inner.status = Some(response.status());
+    }
+
+    fn on_body_done(&self, request: &Request, body: Result<&BodyInfo, &dyn Error>) {
+        let mut records = self.inner.lock().unwrap();
+        let inner = records.get(request);
+
+        inner.body_info = body.ok().cloned();
+    }
+
+    fn on_redirect(&self, request: &Request, _status: StatusCode, _location: &Uri) {
+        let mut records = self.inner.lock().unwrap();
+        let inner = records.get(request);
+
+        let now = Instant::now();
+        let hop = HopStats {
+            url: inner.url.clone().unwrap_or_default(),
+            status: inner.status,
+            stats: inner.stats(now),
+        };
+        let start = inner.start();
+        let mut hops = std::mem::take(&mut inner.hops);
+        hops.push(hop);
+        *inner = StatsRecorderInner {
+            hops,
+            hops_start: Some(inner.hops_start.map_or(start, |v| v.min(start))),
+            ..Default::default()
+        };
+    }
+
+    fn on_cache_read_start(&self, request: &Request, _key: &str) {
+        let mut records = self.inner.lock().unwrap();
+        let inner = records.get(request);
+
+        _ = inner.cache_read_stat.insert(StatRecord::started());
+    }
+
+    fn on_cache_read_done(&self, request: &Request, _key: &str, hit: Result<bool, &dyn Error>) {
+        let mut records = self.inner.lock().unwrap();
+        let inner = records.get(request);
+
+        if let Some(record) = inner.cache_read_stat.as_mut() {
+            record.end();
+            record.result = Some(hit.map(Extend::Hit).map_err(|e| e.to_string()));
+        }
+    }
+
+    fn on_cache_write_start(&self, request: &Request, _key: &str) {
+        let mut records = self.inner.lock().unwrap();
+        let inner = records.get(request);
+
+        _ = inner.cache_write_stat.insert(StatRecord::started());
+    }
+
+    fn on_cache_write_done(&self, request: &Request, _key: &str, written: Result<u64, &dyn Error>) {
+        let mut records = self.inner.lock().unwrap();
+        let inner = records.get(request);
+
+        if let Some(record) = inner.cache_write_stat.as_mut() {
+            record.end();
+            record.result = Some(written.map(Extend::Written).map_err(|e| e.to_string()));
+        }
+    }
+
+    fn on_cache_revalidate_start(&self, request: &Request, _key: &str) {
+        let mut records = self.inner.lock().unwrap();
+        let inner = records.get(request);
+
+        _ = inner.cache_revalidate_stat.insert(StatRecord::started());
+    }
+
+    fn on_cache_revalidate_done(
+        &self,
+        request: &Request,
+        _key: &str,
+        status: Result<http::StatusCode, &dyn Error>,
+    ) {
+        let mut records = self.inner.lock().unwrap();
+        let inner = records.get(request);
+
+        if let Some(record) = inner.cache_revalidate_stat.as_mut() {
+            record.end();
+            record.result = Some(status.map(Extend::Status).map_err(|e| e.to_string()));
+        }
     }
 }
 
@@ -169,123 +940,354 @@ impl Default for StatsRecorder {
 impl StatsRecorder {
     pub fn new() -> Self {
         Self {
-            inner: Arc::new(Mutex::new(StatsRecorderInner::default())),
+            inner: Arc::new(Mutex::new(StatsRecords::default())),
         }
     }
 
+    /// The stats of the latest request seen by the recorder.
+    ///
+    /// The request is forgotten as with [`finish_for`](Self::finish_for).
     pub fn finish(&self) -> Stats {
-        let inner = self.inner.lock().unwrap();
+        let mut records = self.inner.lock().unwrap();
+        let inner = records
+            .last
+            .take()
+            .and_then(|id| records.requests.remove(&id))
+            .unwrap_or_default();
+        inner.finish()
+    }
 
-        let now = Instant::now();
-        let mut stats = Stats::default();
+    /// The stats of the given request, `None` if the recorder didn't see it.
+    ///
+    /// The request is forgotten, so that a long-lived recorder doesn't grow.
+    pub fn finish_for(&self, id: RequestId) -> Option<Stats> {
+        let inner = self.inner.lock().unwrap().requests.remove(&id)?;
+        Some(inner.finish())
+    }
+}
 
-        stats.dns_stats.duration = inner
-            .dns_stat
-            .done
-            .map(|done| done.duration_since(inner.dns_stat.start()))
-            .unwrap_or_default();
-        if let Some(dns_result) = inner.dns_stat.result.as_ref() {
-            match dns_result {
-                Ok(v) => stats.dns_stats.extend = Some(v.clone()),
-                Err(e) => stats.dns_stats.error = Some(e.clone()),
-            }
+#[derive(Debug, Clone, Default)]
+struct StatsRecorderInner {
+    blocked_stat: Option<StatRecord>,
+    dns_stat: StatRecord,
+    dns_info: Option<DnsInfo>,
+
+    tcp_stats: Option<HashMap<SocketAddr, TcpRecord>>,
+    connect_delay: Option<Duration>,
+    tcp_info: Option<TcpInfo>,
+    reused: bool,
+    dead_connections: Vec<u64>,
+    proxy_stat: Option<StatRecord>,
+    proxy_auth_stat: Option<StatRecord>,
+    socks_stat: Option<StatRecord>,
+    h2c_upgrade_stat: Option<StatRecord>,
+    tls_stat: Option<StatRecord>,
+    tls_early_data: Option<bool>,
+    tls_info: Option<TlsInfo>,
+    rejected_certificates: Vec<CertificateDer<'static>>,
+    revocation_stat: Option<StatRecord>,
+    revocation: Option<RevocationStatus>,
+    negotiation: Option<Negotiation>,
+    http2_stream_id: Option<u32>,
+    h2_fallback: Option<String>,
+    alt_svc: Option<AltSvcDecision>,
+    request_stat: Option<StatRecord>,
+    expect_continue_stat: Option<StatRecord>,
+    informational: Vec<Informational>,
+    body_info: Option<BodyInfo>,
+    cache_read_stat: Option<StatRecord>,
+    cache_write_stat: Option<StatRecord>,
+    cache_revalidate_stat: Option<StatRecord>,
+
+    url: Option<Uri>,
+    status: Option<StatusCode>,
+    // Completed hops of a redirect chain, and the start of the first one.
+    hops: Vec<HopStats>,
+    hops_start: Option<Instant>,
+}
+
+impl StatsRecorderInner {
+    fn finish(&self) -> Stats {
+        let now = Instant::now();
+        let mut stats = self.stats(now);
+        if let Some(hops_start) = self.hops_start {
+            stats.hops = self.hops.clone();
+            stats.hops.push(HopStats {
+                url: self.url.clone().unwrap_or_default(),
+                status: self.status,
+                stats: stats.clone(),
+            });
+            stats.total_duration = now.duration_since(hops_start.min(self.start()));
         }
+        stats
+    }
 
-        if let Some(tcp_stats) = inner.tcp_stats.as_ref() {
+    fn stats(&self, now: Instant) -> Stats {
+        let mut stats = Stats {
+            blocked_stats: self.blocked_stat.as_ref().map(StatRecord::stat),
+            dns_stats: self.dns_stat.stat(),
+            ..Default::default()
+        };
+
+        if let Some(tcp_stats) = self.tcp_stats.as_ref() {
+            let mut tcp_stats = tcp_stats.iter().collect::<Vec<_>>();
+            tcp_stats.sort_by_key(|(_, value)| value.record.start());
             _ = stats.tcp_stats.insert(
                 tcp_stats
-                    .iter()
-                    .map(|(key, value)| {
-                        let duration = value
-                            .done
-                            .map(|done| done.duration_since(value.start()))
-                            .unwrap_or_default();
-                        let extend = Some(key.clone());
-                        let error = value
-                            .result
-                            .as_ref()
-                            .and_then(|v| v.as_ref().err().cloned());
-
-                        Stat {
-                            duration,
-                            extend,
-                            error,
+                    .into_iter()
+                    .map(|(peer_addr, value)| {
+                        let stat = value.record.stat();
+                        TcpStat {
+                            peer_addr: *peer_addr,
+                            local_addr: value.local_addr,
+                            duration: stat.duration,
+                            error: stat.error,
+                            started_at: stat.started_at,
+                            finished_at: stat.finished_at,
                         }
                     })
                     .collect(),
             );
         }
+        stats.dns_info = self.dns_info.clone();
+        stats.connect_delay = self.connect_delay;
+        stats.body_info = self.body_info.clone();
+        stats.tcp_info = self.tcp_info.clone();
+        stats.reused = self.reused;
+        stats.dead_connections = self.dead_connections.clone();
+        stats.tls_info = self.tls_info.clone();
+        stats.rejected_certificates = self.rejected_certificates.clone();
 
-        if let Some(tls_stats) = inner.tls_stat.as_ref() {
-            _ = stats.tls_stats.insert({
-                let duration = tls_stats
-                    .done
-                    .map(|done| done.duration_since(tls_stats.start()))
-                    .unwrap_or_default();
-                let extend = tls_stats
-                    .result
-                    .as_ref()
-                    .and_then(|v| v.as_ref().ok().cloned());
-                let error = tls_stats
-                    .result
-                    .as_ref()
-                    .and_then(|v| v.as_ref().err().cloned());
-
-                Stat {
-                    duration,
-                    extend,
-                    error,
-                }
-            });
-        }
+        stats.proxy_stats = self.proxy_stat.as_ref().map(StatRecord::stat);
+        stats.proxy_auth_stats = self.proxy_auth_stat.as_ref().map(StatRecord::stat);
+        stats.socks_stats = self.socks_stat.as_ref().map(StatRecord::stat);
+        stats.h2c_upgrade_stats = self.h2c_upgrade_stat.as_ref().map(StatRecord::stat);
+        stats.tls_stats = self.tls_stat.as_ref().map(StatRecord::stat);
+        stats.tls_early_data = self.tls_early_data;
+        stats.revocation_stats = self.revocation_stat.as_ref().map(StatRecord::stat);
+        stats.revocation = self.revocation;
+        stats.negotiation = self.negotiation.clone();
+        stats.http2_stream_id = self.http2_stream_id;
+        stats.h2_fallback = self.h2_fallback.clone();
+        stats.alt_svc = self.alt_svc.clone();
+        stats.finished_at = Some(SystemTime::now());
+        // The request lasts until the stats are taken.
+        stats.request_stats = self.request_stat.as_ref().map(|record| Stat {
+            duration: now.duration_since(record.start()),
+            finished_at: stats.finished_at,
+            ..record.stat()
+        });
+        stats.expect_continue_stats = self.expect_continue_stat.as_ref().map(StatRecord::stat);
+        stats.informational = self.informational.clone();
+        stats.cache_read_stats = self.cache_read_stat.as_ref().map(StatRecord::stat);
+        stats.cache_write_stats = self.cache_write_stat.as_ref().map(StatRecord::stat);
+        stats.cache_revalidate_stats = self.cache_revalidate_stat.as_ref().map(StatRecord::stat);
 
-        if let Some(request_stats) = inner.request_stat.as_ref() {
-            _ = stats.request_stats.insert({
-                let duration = now.duration_since(request_stats.start());
-                let extend = request_stats
-                    .result
-                    .as_ref()
-                    .and_then(|v| v.as_ref().ok().cloned());
-                let error = request_stats
-                    .result
-                    .as_ref()
-                    .and_then(|v| v.as_ref().err().cloned());
-
-                Stat {
-                    duration,
-                    extend,
-                    error,
-                }
-            });
-        }
-        stats.total_duration = now.duration_since(inner.dns_stat.start());
+        stats.started_at = [
+            self.blocked_stat.as_ref(),
+            Some(&self.dns_stat),
+            self.cache_read_stat.as_ref(),
+            self.request_stat.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .filter_map(|record| record.start_at)
+        .min();
+        stats.total_duration = now.duration_since(self.start());
         stats
     }
+
+    fn start(&self) -> Instant {
+        [
+            self.blocked_stat.as_ref(),
+            self.cache_read_stat.as_ref(),
+            self.request_stat.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .fold(self.dns_stat.start(), |start, record| {
+            start.min(record.start())
+        })
+    }
 }
-#[derive(Debug, Clone, Default)]
-struct StatsRecorderInner {
-    dns_stat: StatRecord,
-    dns_hit_cache: bool,
-    dns_name_servers: String,
 
-    tcp_stats: Option<HashMap<String, StatRecord>>,
-    tls_stat: Option<StatRecord>,
-    request_stat: Option<StatRecord>,
+#[derive(Debug, Clone)]
+struct TcpRecord {
+    record: StatRecord,
+    local_addr: Option<SocketAddr>,
 }
 
 #[derive(Debug, Clone, Default)]
 struct StatRecord {
     start: Option<Instant>,
     done: Option<Instant>,
-    result: Option<Result<String, String>>,
+    start_at: Option<SystemTime>,
+    done_at: Option<SystemTime>,
+    // Formatted into `Stat::extend` only when the stats are taken.
+    result: Option<Result<Extend, String>>,
+}
+
+/// The outcome of a successful phase, as reported to the recorder.
+#[derive(Debug, Clone)]
+enum Extend {
+    Addrs(Vec<IpAddr>),
+    Peer(SocketAddr),
+    Hit(bool),
+    Written(u64),
+    Status(StatusCode),
+    Revocation(RevocationStatus),
+}
+
+impl fmt::Display for Extend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Extend::Addrs(addrs) => {
+                for (index, addr) in addrs.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}", addr)?;
+                }
+                Ok(())
+            }
+            Extend::Peer(addr) => write!(f, "{}", addr),
+            Extend::Hit(hit) => f.write_str(if *hit { "hit" } else { "miss" }),
+            Extend::Written(len) => write!(f, "{} bytes", len),
+            Extend::Status(status) => write!(f, "{}", status),
+            Extend::Revocation(status) => write!(f, "{}", status),
+        }
+    }
 }
 
 impl StatRecord {
+    fn started() -> Self {
+        let mut record = StatRecord::default();
+        record.begin();
+        record
+    }
+
+    fn begin(&mut self) {
+        self.start = Some(Instant::now());
+        self.start_at = Some(SystemTime::now());
+    }
+
+    fn end(&mut self) {
+        self.done = Some(Instant::now());
+        self.done_at = Some(SystemTime::now());
+    }
+
     fn start(&self) -> Instant {
         // ok or far future
         self.start
             .unwrap_or(Instant::now() + Duration::from_secs(86400 * 365 * 30))
     }
+
+    fn stat(&self) -> Stat {
+        Stat {
+            duration: self
+                .done
+                .map(|done| done.duration_since(self.start()))
+                .unwrap_or_default(),
+            extend: self
+                .result
+                .as_ref()
+                .and_then(|v| v.as_ref().ok().map(|v| v.to_string())),
+            error: self.result.as_ref().and_then(|v| v.as_ref().err().cloned()),
+            started_at: self.start_at,
+            finished_at: self.done_at,
+        }
+    }
+}
+
+impl Stats {
+    /// The error the request failed with, `None` if it got a response.
+    pub fn error(&self) -> Option<&str> {
+        if let Some(error) = self.dns_stats.error.as_deref() {
+            return Some(error);
+        }
+        if let Some(tcp_stats) = self.tcp_stats.as_ref()
+            && tcp_stats.iter().all(|stat| stat.error.is_some())
+        {
+            return tcp_stats.last().and_then(|stat| stat.error.as_deref());
+        }
+        [self.tls_stats.as_ref(), self.request_stats.as_ref()]
+            .into_iter()
+            .flatten()
+            .find_map(|stat| stat.error.as_deref())
+    }
+}
+
+#[cfg(any(test, feature = "json"))]
+impl Stats {
+    /// The stats as JSON, durations in milliseconds and timestamps in
+    /// milliseconds since the Unix epoch. Requires the `json` feature.
+    pub fn to_json(&self) -> serde_json::Value {
+        use serde_json::{Value, json};
+
+        fn ms(duration: Duration) -> f64 {
+            duration.as_secs_f64() * 1000.0
+        }
+        fn timestamp(time: Option<SystemTime>) -> Value {
+            time.and_then(|v| v.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .map_or(Value::Null, |v| json!(v.as_millis() as u64))
+        }
+        fn stat(stat: Option<&Stat>) -> Value {
+            stat.map_or(Value::Null, |stat| {
+                json!({
+                    "duration_ms": ms(stat.duration),
+                    "extend": stat.extend,
+                    "error": stat.error,
+                    "started_at": timestamp(stat.started_at),
+                    "finished_at": timestamp(stat.finished_at),
+                })
+            })
+        }
+
+        json!({
+            "blocked": stat(self.blocked_stats.as_ref()),
+            "dns": stat(Some(&self.dns_stats)),
+            "dns_addrs": self.dns_info.as_ref().map(|v| {
+                v.addrs.iter().map(|v| v.to_string()).collect::<Vec<_>>()
+            }),
+            "tcp": self.tcp_stats.iter().flatten().map(|v| json!({
+                "peer_addr": v.peer_addr.to_string(),
+                "local_addr": v.local_addr.map(|v| v.to_string()),
+                "duration_ms": ms(v.duration),
+                "error": v.error,
+                "started_at": timestamp(v.started_at),
+                "finished_at": timestamp(v.finished_at),
+            })).collect::<Vec<_>>(),
+            "tls": stat(self.tls_stats.as_ref()),
+            "tls_info": self.tls_info.as_ref().map(|v| json!({
+                "protocol_version": v.protocol_version.map(|v| format!("{v:?}")),
+                "cipher_suite": v.cipher_suite.map(|v| format!("{v:?}")),
+                "kx_group": v.kx_group.map(|v| format!("{v:?}")),
+                "alpn_protocol": v.alpn_protocol.as_ref().map(|v| String::from_utf8_lossy(v)),
+                "sni": v.sni,
+                "resumed": v.resumed,
+                "cert_not_after": timestamp(v.cert_not_after),
+                "revocation": v.revocation.map(|v| v.to_string()),
+                "ocsp_stapled": v.ocsp_response.is_some(),
+                "ech": v.ech.map(|v| format!("{v:?}")),
+            })),
+            "request": stat(self.request_stats.as_ref()),
+            "body": self.body_info.as_ref().map(|v| json!({
+                "len": v.len,
+                "sha256": v.sha256.map(|v| v.map(|v| format!("{v:02x}")).concat()),
+            })),
+            "cache_read": stat(self.cache_read_stats.as_ref()),
+            "cache_write": stat(self.cache_write_stats.as_ref()),
+            "cache_revalidate": stat(self.cache_revalidate_stats.as_ref()),
+            "hops": self.hops.iter().map(|v| json!({
+                "url": v.url.to_string(),
+                "status": v.status.map(|v| v.as_u16()),
+                "stats": v.stats.to_json(),
+            })).collect::<Vec<_>>(),
+            "started_at": timestamp(self.started_at),
+            "finished_at": timestamp(self.finished_at),
+            "total_duration_ms": ms(self.total_duration),
+        })
+    }
 }
 
 impl std::fmt::Display for Stats {
@@ -295,6 +1297,9 @@ impl std::fmt::Display for Stats {
             "total_duration:   {:>4}ms",
             self.total_duration.as_millis()
         )?;
+        if let Some(stats) = self.blocked_stats.as_ref() {
+            writeln!(f, "blocked_duration: {:>4}ms", stats.duration.as_millis())?;
+        }
         writeln!(
             f,
             "dns_duration:     {:>4}ms >>> resolve: {}",
@@ -305,12 +1310,14 @@ impl std::fmt::Display for Stats {
         if let Some(tcp_stats) = self.tcp_stats.as_ref() {
             for stat in tcp_stats {
                 let duration = stat.duration.as_millis();
-                let extend = stat.extend.clone().unwrap_or_default();
                 write!(
                     f,
                     "tcp_duration:     {:>4}ms >>> connect: {} ",
-                    duration, extend
+                    duration, stat.peer_addr
                 )?;
+                if let Some(local_addr) = stat.local_addr {
+                    write!(f, "from {} ", local_addr)?;
+                }
                 if let Some(error) = &stat.error {
                     write!(f, "; failed: {}", error)?;
                 }
@@ -319,12 +1326,26 @@ impl std::fmt::Display for Stats {
         }
         if let Some(tls_stats) = self.tls_stats.as_ref() {
             let duration = tls_stats.duration.as_millis();
-            let extend = tls_stats.extend.clone().unwrap_or_default();
+            let version = self
+                .tls_info
+                .as_ref()
+                .and_then(|v| v.protocol_version)
+                .and_then(|v| v.as_str())
+                .unwrap_or_default();
             write!(
                 f,
                 "tls_duration:     {:>4}ms >>> version: {} ",
-                duration, extend
+                duration, version
             )?;
+            if let Some(suite) = self.tls_info.as_ref().and_then(|v| v.cipher_suite) {
+                write!(f, "cipher: {:?} ", suite)?;
+            }
+            if let Some(group) = self.tls_info.as_ref().and_then(|v| v.kx_group) {
+                write!(f, "group: {:?} ", group)?;
+            }
+            if self.tls_info.as_ref().is_some_and(|v| v.resumed) {
+                write!(f, "resumed ")?;
+            }
             if let Some(error) = &tls_stats.error {
                 write!(f, "; failed: {}", error)?;
             }
@@ -339,6 +1360,281 @@ impl std::fmt::Display for Stats {
             }
             writeln!(f)?;
         }
+
+        if let Some(body) = self.body_info.as_ref() {
+            write!(f, "body:             {:>4} bytes", body.len)?;
+            if let Some(sha256) = body.sha256 {
+                write!(f, " >>> sha256: ")?;
+                for byte in sha256 {
+                    write!(f, "{:02x}", byte)?;
+                }
+            }
+            writeln!(f)?;
+        }
+
+        for (name, stats) in [
+            ("cache_read:      ", self.cache_read_stats.as_ref()),
+            ("cache_write:     ", self.cache_write_stats.as_ref()),
+            ("cache_revalidate:", self.cache_revalidate_stats.as_ref()),
+        ] {
+            if let Some(stats) = stats {
+                let duration = stats.duration.as_millis();
+                let extend = stats.extend.clone().unwrap_or_default();
+                write!(f, "{} {:>4}ms >>> {} ", name, duration, extend)?;
+                if let Some(error) = &stats.error {
+                    write!(f, "; failed: {}", error)?;
+                }
+                writeln!(f)?;
+            }
+        }
+
+        for (i, hop) in self.hops.iter().enumerate() {
+            let status = hop.status.map(|v| v.to_string()).unwrap_or_default();
+            writeln!(
+                f,
+                "hop {:<13} {:>4}ms >>> {} {}",
+                i,
+                hop.stats.total_duration.as_millis(),
+                status,
+                hop.url
+            )?;
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        sync::Arc,
+        time::Duration,
+    };
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::StatsRecorder;
+    use crate::{
+        client::Client,
+        test_util::{MockFailure, MockResponse, MockTransport, TestServer, self_signed_acceptor},
+    };
+
+    #[tokio::test(start_paused = true)]
+    async fn test_paused_stats() {
+        let mock = MockTransport::new();
+        mock.push(
+            MockResponse::new(200)
+                .dns_delay(Duration::from_millis(20))
+                .tcp_delay(Duration::from_millis(30))
+                .tls_delay(Duration::from_millis(40))
+                .response_delay(Duration::from_millis(50)),
+        );
+        let client = Client::builder().mock_transport(mock).build().unwrap();
+
+        let recorder = StatsRecorder::new();
+        let mut request = client.get("https://example.com").build().unwrap();
+        *request.recorder_mut() = Some(Arc::new(recorder.clone()));
+        client.execute(request).await.unwrap();
+
+        let stats = recorder.finish();
+        assert_eq!(stats.dns_stats.duration, Duration::from_millis(20));
+        assert_eq!(
+            stats.tcp_stats.unwrap()[0].duration,
+            Duration::from_millis(30)
+        );
+        assert_eq!(stats.tls_stats.unwrap().duration, Duration::from_millis(40));
+        assert_eq!(stats.total_duration, Duration::from_millis(140));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shared_stats_recorder() {
+        let mock = MockTransport::new();
+        mock.push(MockResponse::new(200).dns_delay(Duration::from_millis(10)));
+        mock.push(MockResponse::new(200).dns_delay(Duration::from_millis(20)));
+        mock.push(MockResponse::new(200).dns_delay(Duration::from_millis(30)));
+        let client = Client::builder().mock_transport(mock).build().unwrap();
+
+        let recorder = StatsRecorder::new();
+        let request = || {
+            let mut request = client.get("https://example.com").build().unwrap();
+            *request.recorder_mut() = Some(Arc::new(recorder.clone()));
+            request
+        };
+        let (a, b) = (request(), request());
+        let ids = [a.id(), b.id()];
+        assert_ne!(ids[0], ids[1]);
+
+        let (a, b) = tokio::join!(client.execute(a), client.execute(b));
+        a.unwrap();
+        b.unwrap();
+
+        for (id, delay) in ids.into_iter().zip([10, 20]) {
+            let stats = recorder.finish_for(id).unwrap();
+            assert_eq!(stats.dns_stats.duration, Duration::from_millis(delay));
+            assert!(recorder.finish_for(id).is_none());
+        }
+
+        // The latest request is forgotten as well.
+        let c = request();
+        let id = c.id();
+        client.execute(c).await.unwrap();
+        let stats = recorder.finish();
+        assert_eq!(stats.dns_stats.duration, Duration::from_millis(30));
+        assert!(recorder.finish_for(id).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_peer_certificates() {
+        let server = TestServer::builder().tls().start().await.unwrap();
+        let client = Client::builder().skip_tls_verify().build().unwrap();
+        let recorder = StatsRecorder::new();
+        client
+            .get(format!("https://localhost:{}/", server.addr().port()))
+            .recorder(Box::new(recorder.clone()))
+            .send()
+            .await
+            .unwrap();
+        let tls = recorder.finish().tls_info.unwrap();
+        assert_eq!(
+            tls.peer_certificates.as_slice(),
+            [server.certificate().unwrap().clone()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tcp_local_addr() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut stream, peer) = listener.accept().await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                .await
+                .unwrap();
+            peer
+        });
+        let client = Client::builder()
+            .local_addr(IpAddr::V4(Ipv4Addr::LOCALHOST))
+            .build()
+            .unwrap();
+
+        let recorder = StatsRecorder::new();
+        let url = format!("http://{addr}/");
+        let send = || client.get(&url).recorder(Box::new(recorder.clone())).send();
+        send().await.unwrap();
+        let peer = server.await.unwrap();
+        let stats = recorder.finish();
+        let [tcp] = stats.tcp_stats.unwrap().try_into().unwrap();
+        assert_eq!(tcp.peer_addr, addr);
+        assert_eq!(tcp.local_addr, Some(peer));
+        assert_eq!(stats.tcp_info.unwrap().local_addr, Some(peer));
+
+        // Nothing listens on the port any more.
+        send().await.unwrap_err();
+        let stats = recorder.finish();
+        let [tcp] = stats.tcp_stats.unwrap().try_into().unwrap();
+        assert!(tcp.error.is_some());
+        assert_eq!(tcp.local_addr, None);
+        assert!(stats.tcp_info.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_tls_info() {
+        let (acceptor, _) = self_signed_acceptor().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // What the server negotiated, for each connection.
+        let server = tokio::spawn(async move {
+            let mut negotiated = Vec::new();
+            for _ in 0..2 {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut stream = acceptor.accept(stream).await.unwrap();
+                let conn = stream.get_ref().1;
+                negotiated.push((
+                    conn.protocol_version(),
+                    conn.negotiated_cipher_suite().map(|v| v.suite()),
+                    conn.negotiated_key_exchange_group().map(|v| v.name()),
+                    conn.alpn_protocol().map(<[u8]>::to_vec),
+                    conn.server_name().map(str::to_string),
+                ));
+                let mut head = Vec::new();
+                while !head.ends_with(b"\r\n\r\n") {
+                    head.push(stream.read_u8().await.unwrap());
+                }
+                stream
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                    .await
+                    .unwrap();
+                stream.flush().await.unwrap();
+            }
+            negotiated
+        });
+
+        let client = Client::builder().skip_tls_verify().build().unwrap();
+        let mut infos = Vec::new();
+        for _ in 0..2 {
+            let recorder = StatsRecorder::new();
+            client
+                .get(format!("https://localhost:{port}/"))
+                .version(http::Version::HTTP_11)
+                .recorder(Box::new(recorder.clone()))
+                .send()
+                .await
+                .unwrap();
+            infos.push(recorder.finish().tls_info.unwrap());
+        }
+        let negotiated = server.await.unwrap();
+        for (tls, server) in infos.iter().zip(negotiated) {
+            let (version, suite, group, alpn, sni) = server;
+            assert_eq!(tls.protocol_version, version);
+            assert_eq!(tls.cipher_suite, suite);
+            assert!(tls.cipher_suite.is_some());
+            assert_eq!(tls.kx_group, group);
+            assert!(tls.kx_group.is_some());
+            assert_eq!(tls.alpn_protocol.as_deref(), Some(&b"http/1.1"[..]));
+            assert_eq!(tls.alpn_protocol, alpn);
+            assert_eq!(tls.sni.as_deref(), Some("localhost"));
+            assert_eq!(tls.sni, sni);
+        }
+        assert!(!infos[0].resumed);
+        assert!(infos[1].resumed);
+    }
+
+    #[tokio::test]
+    async fn test_tls_display() {
+        let mock = MockTransport::new();
+        mock.push(MockResponse::new(200))
+            .push(MockResponse::failure(MockFailure::Tls));
+        let client = Client::builder().mock_transport(mock).build().unwrap();
+        let recorder = StatsRecorder::new();
+        let send = || {
+            client
+                .get("https://example.com")
+                .recorder(Box::new(recorder.clone()))
+                .send()
+        };
+
+        send().await.unwrap();
+        let stats = recorder.finish();
+        // Rendered from the TlsInfo, the stat carries nothing of its own.
+        assert!(stats.tls_stats.as_ref().unwrap().extend.is_none());
+        assert!(
+            stats
+                .to_string()
+                .contains(">>> version: TLSv1_3 cipher: TLS13_AES_128_GCM_SHA256 group: X25519 \n")
+        );
+        let json = stats.to_json();
+        assert_eq!(json["tls_info"]["protocol_version"], "TLSv1_3");
+        assert_eq!(json["tls_info"]["kx_group"], "X25519");
+        assert_eq!(json["tls_info"]["resumed"], false);
+
+        send().await.unwrap_err();
+        let stats = recorder.finish();
+        assert!(stats.tls_info.is_none());
+        assert!(stats.tls_stats.as_ref().unwrap().extend.is_none());
+        assert!(stats.to_string().contains(">>> version:  ; failed: "));
+    }
+}
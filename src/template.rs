@@ -0,0 +1,320 @@
+//! Request templates with `{var}` placeholders, expanded once per row of data
+//! into traced requests.
+//!
+//! ```no_run
+//! # async fn run() -> httptrace::Result<()> {
+//! use http::Method;
+//! use httptrace::{client::Client, template::{RequestTemplate, parse_csv}};
+//!
+//! let client = Client::builder().build()?;
+//! let rows = parse_csv("id,token\n1,abc\n2,def\n")?;
+//! let results = RequestTemplate::new(Method::GET, "https://example.com/users/{id}")
+//!     .header("authorization", "Bearer {token}")
+//!     .run(&client, rows, 4)
+//!     .await;
+//! for result in results {
+//!     println!("{} {:?} {:?}", result.vars["id"], result.status, result.stats.total_duration);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use futures_util::{StreamExt, stream};
+use http::{Method, StatusCode};
+
+use crate::{
+    client::Client,
+    request::Request,
+    stats::{Stats, StatsRecorder},
+};
+
+/// The values of the placeholders of one expansion.
+pub type Vars = HashMap<String, String>;
+
+/// A request whose URL, header values and body may contain `{var}`
+/// placeholders. `{{` and `}}` stand for literal braces.
+///
+/// Values are inserted verbatim, they are not percent-encoded.
+#[derive(Debug, Clone)]
+pub struct RequestTemplate {
+    method: Method,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Option<String>,
+    timeout: Option<Duration>,
+}
+
+/// The trace of one expanded request.
+#[derive(Debug, Clone)]
+pub struct BatchResult {
+    pub vars: Vars,
+    pub status: Option<StatusCode>,
+    /// The expansion or request error, if any.
+    pub error: Option<String>,
+    pub stats: Stats,
+}
+
+impl RequestTemplate {
+    pub fn new<S: Into<String>>(method: Method, url: S) -> Self {
+        Self {
+            method,
+            url: url.into(),
+            headers: Vec::new(),
+            body: None,
+            timeout: None,
+        }
+    }
+
+    /// Add a header, only its value is a template.
+    pub fn header<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.headers.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn body<S: Into<String>>(mut self, body: S) -> Self {
+        self.body = Some(body.into());
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Build the request for one set of values.
+    pub fn expand(&self, client: &Client, vars: &Vars) -> crate::Result<Request> {
+        let url = substitute(&self.url, vars)?;
+        let mut builder = client.request(self.method.clone(), url);
+        for (key, value) in self.headers.iter() {
+            builder = builder.header(key.as_str(), substitute(value, vars)?);
+        }
+        if let Some(body) = self.body.as_ref() {
+            builder = builder.body(substitute(body, vars)?);
+        }
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        builder.build()
+    }
+
+    /// Expand the template once per row and send the requests, at most
+    /// `concurrency` at a time. Bodies are read to the end.
+    ///
+    /// The results are in row order.
+    pub async fn run<I>(&self, client: &Client, rows: I, concurrency: usize) -> Vec<BatchResult>
+    where
+        I: IntoIterator<Item = Vars>,
+    {
+        stream::iter(rows)
+            .map(|vars| self.run_one(client, vars))
+            .buffered(concurrency.max(1))
+            .collect()
+            .await
+    }
+
+    async fn run_one(&self, client: &Client, vars: Vars) -> BatchResult {
+        let mut request = match self.expand(client, &vars) {
+            Ok(request) => request,
+            Err(e) => {
+                return BatchResult {
+                    vars,
+                    status: None,
+                    error: Some(e.to_string()),
+                    stats: Stats::default(),
+                };
+            }
+        };
+        let recorder = StatsRecorder::new();
+        *request.recorder_mut() = Some(Arc::new(recorder.clone()));
+
+        let (status, error) = match client.execute(request).await {
+            Ok(response) => {
+                let status = response.status();
+                (Some(status), response.bytes().await.err())
+            }
+            Err(e) => (None, Some(e)),
+        };
+        BatchResult {
+            vars,
+            status,
+            error: error.map(|e| e.to_string()),
+            stats: recorder.finish(),
+        }
+    }
+}
+
+fn substitute(template: &str, vars: &Vars) -> crate::Result<String> {
+    let invalid = || crate::Error::InvalidTemplate(template.to_string());
+
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(index) = rest.find(['{', '}']) {
+        expanded.push_str(&rest[..index]);
+        rest = &rest[index..];
+        if let Some(tail) = rest.strip_prefix("{{") {
+            expanded.push('{');
+            rest = tail;
+        } else if let Some(tail) = rest.strip_prefix("}}") {
+            expanded.push('}');
+            rest = tail;
+        } else if rest.starts_with('}') {
+            return Err(invalid());
+        } else {
+            let end = rest.find('}').ok_or_else(invalid)?;
+            let name = &rest[1..end];
+            if name.contains('{') {
+                return Err(invalid());
+            }
+            let value = vars
+                .get(name)
+                .ok_or_else(|| crate::Error::MissingVariable(name.to_string()))?;
+            expanded.push_str(value);
+            rest = &rest[end + 1..];
+        }
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Parse CSV data whose first record names the columns, one [`Vars`] per
+/// following record.
+///
+/// Fields may be quoted with `"`, a quote inside a quoted field is doubled.
+/// Blank lines are skipped.
+pub fn parse_csv(data: &str) -> crate::Result<Vec<Vars>> {
+    let mut records = csv_records(data)?.into_iter();
+    let Some((_, columns)) = records.next() else {
+        return Ok(Vec::new());
+    };
+    records
+        .map(|(line, record)| {
+            if record.len() != columns.len() {
+                return Err(crate::Error::InvalidCsv(line));
+            }
+            Ok(columns.iter().cloned().zip(record).collect())
+        })
+        .collect()
+}
+
+/// The records of the data with the line they start on.
+fn csv_records(data: &str) -> crate::Result<Vec<(usize, Vec<String>)>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut line = 1;
+    let mut record_line = 1;
+
+    let mut chars = data.chars().peekable();
+    while let Some(c) = chars.next() {
+        if quoted {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => quoted = false,
+                _ => {
+                    line += usize::from(c == '\n');
+                    field.push(c);
+                }
+            }
+            continue;
+        }
+        match c {
+            '"' if field.is_empty() => quoted = true,
+            '"' => return Err(crate::Error::InvalidCsv(line)),
+            ',' => record.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                record.push(std::mem::take(&mut field));
+                if record != [""] {
+                    records.push((record_line, std::mem::take(&mut record)));
+                }
+                record.clear();
+                line += 1;
+                record_line = line;
+            }
+            _ => field.push(c),
+        }
+    }
+    if quoted {
+        return Err(crate::Error::InvalidCsv(record_line));
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push((record_line, record));
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Method;
+
+    use super::{RequestTemplate, Vars, parse_csv, substitute};
+    use crate::{
+        client::Client,
+        test_util::{MockResponse, MockTransport},
+    };
+
+    #[test]
+    fn test_substitute() {
+        let vars = Vars::from([("id".to_string(), "42".to_string())]);
+        assert_eq!(
+            substitute("/users/{id}?a={{b}}", &vars).unwrap(),
+            "/users/42?a={b}"
+        );
+        assert!(matches!(
+            substitute("{name}", &vars),
+            Err(crate::Error::MissingVariable(name)) if name == "name"
+        ));
+        assert!(substitute("{id", &vars).is_err());
+        assert!(substitute("id}", &vars).is_err());
+
+        let rows = parse_csv("id,name\r\n1,\"Doe, \"\"J\"\"\"\n\n2,\"multi\nline\"\n").unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["name"], "Doe, \"J\"");
+        assert_eq!(rows[1]["name"], "multi\nline");
+        assert!(matches!(
+            parse_csv("id\n1\n2,3\n"),
+            Err(crate::Error::InvalidCsv(3))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_template_run() {
+        let mock = MockTransport::new();
+        mock.push(MockResponse::new(200));
+        mock.push(MockResponse::new(404));
+        let client = Client::builder()
+            .mock_transport(mock.clone())
+            .build()
+            .unwrap();
+
+        let mut rows = parse_csv("id,token\n1,abc\n2,def\n").unwrap();
+        rows.push(Vars::from([("id".to_string(), "3".to_string())]));
+        let results = RequestTemplate::new(Method::POST, "http://example.com/users/{id}")
+            .header("authorization", "Bearer {token}")
+            .body("{{\"id\": {id}}}")
+            .run(&client, rows, 2)
+            .await;
+
+        let statuses = results
+            .iter()
+            .map(|v| v.status.map(|v| v.as_u16()))
+            .collect::<Vec<_>>();
+        assert_eq!(statuses, [Some(200), Some(404), None]);
+        assert_eq!(
+            results[2].error.as_deref(),
+            Some("template variable token has no value")
+        );
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].uri, "http://example.com/users/2");
+        assert_eq!(requests[1].headers["authorization"], "Bearer def");
+        assert_eq!(requests[1].body.as_deref(), Some(&b"{\"id\": 2}"[..]));
+    }
+}
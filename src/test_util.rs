@@ -0,0 +1,1438 @@
+//! Utilities to test code embedding httptrace, either without touching the
+//! network or against a local server.
+//!
+//! Requires the `test-util` feature.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::Infallible,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, SystemTime},
+};
+
+use bytes::Bytes;
+use futures_util::{
+    StreamExt,
+    future::BoxFuture,
+    stream::{self, BoxStream, FuturesUnordered},
+};
+use http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri, Version};
+use http_body::Frame;
+use http_body_util::StreamBody;
+use hyper::service::service_fn;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto,
+};
+use rustls::{
+    CipherSuite, NamedGroup, ProtocolVersion, ServerConfig,
+    pki_types::{CertificateDer, PrivateKeyDer},
+};
+use tokio::{net::TcpListener, task::JoinHandle};
+use tokio_rustls::TlsAcceptor;
+
+use crate::{
+    Body,
+    dns::{DnsInfo, Resolve},
+    request::Request,
+    response::Response,
+    stats::{ConnectionInfo, TcpInfo, TlsInfo},
+};
+
+/// An in-memory transport serving scripted responses.
+///
+/// Responses are served in the order they were pushed, one per request. Each
+/// of them fakes the DNS/TCP/TLS phases with the configured delays, reporting
+/// them to the recorder just like a real connection would.
+///
+/// ```
+/// # use httptrace::{client::Client, test_util::{MockResponse, MockTransport}};
+/// # async fn run() {
+/// let mock = MockTransport::new();
+/// mock.push(MockResponse::new(200).body("hello"));
+///
+/// let client = Client::builder().mock_transport(mock.clone()).build().unwrap();
+/// let response = client.get("https://example.com").send().await.unwrap();
+/// assert_eq!(response.text().await.unwrap(), "hello");
+/// assert_eq!(mock.requests().len(), 1);
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MockTransport {
+    inner: Arc<Mutex<MockTransportInner>>,
+}
+
+#[derive(Debug, Default)]
+struct MockTransportInner {
+    responses: VecDeque<MockResponse>,
+    requests: Vec<MockRequest>,
+}
+
+/// A request received by a [`MockTransport`].
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    pub method: Method,
+    pub uri: Uri,
+    pub headers: HeaderMap,
+    /// `None` if the body is a stream.
+    pub body: Option<Bytes>,
+}
+
+/// The phase a [`MockResponse`] fails at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MockFailure {
+    Dns,
+    Tcp,
+    Tls,
+    Request,
+}
+
+/// A scripted response, along with the fake timings of its connection.
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    status: StatusCode,
+    version: Version,
+    headers: HeaderMap,
+    body: Bytes,
+    addr: IpAddr,
+    alpn: Option<Vec<u8>>,
+    cert_validity: Duration,
+    failure: Option<MockFailure>,
+
+    dns_delay: Duration,
+    tcp_delay: Duration,
+    tls_delay: Duration,
+    response_delay: Duration,
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue a response, served after the ones already queued.
+    pub fn push(&self, response: MockResponse) -> &Self {
+        self.inner.lock().unwrap().responses.push_back(response);
+        self
+    }
+
+    /// The requests received so far.
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.inner.lock().unwrap().requests.clone()
+    }
+
+    /// The number of queued responses not served yet.
+    pub fn remaining(&self) -> usize {
+        self.inner.lock().unwrap().responses.len()
+    }
+
+    pub(crate) async fn execute(&self, request: Request) -> crate::Result<Response> {
+        let scripted = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.requests.push(MockRequest {
+                method: request.method().clone(),
+                uri: request.uri().clone(),
+                headers: request.headers().clone(),
+                body: match request.body() {
+                    Some(body) => body.as_bytes().map(Bytes::copy_from_slice),
+                    None => Some(Bytes::new()),
+                },
+            });
+            inner.responses.pop_front()
+        };
+        let scripted = scripted.ok_or(crate::Error::MockExhausted)?;
+        scripted.serve(request).await
+    }
+}
+
+impl MockResponse {
+    /// A response with `status`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `status` isn't between 100 and 999.
+    pub fn new(status: u16) -> Self {
+        Self {
+            status: StatusCode::from_u16(status).expect("invalid status code"),
+            version: Version::HTTP_11,
+            headers: HeaderMap::new(),
+            body: Bytes::new(),
+            addr: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            alpn: None,
+            cert_validity: Duration::from_secs(90 * 86400),
+            failure: None,
+            dns_delay: Duration::ZERO,
+            tcp_delay: Duration::ZERO,
+            tls_delay: Duration::ZERO,
+            response_delay: Duration::ZERO,
+        }
+    }
+
+    /// A response failing at the given phase, with the error a real
+    /// connection would return.
+    pub fn failure(failure: MockFailure) -> Self {
+        let mut response = Self::new(500);
+        response.failure = Some(failure);
+        response
+    }
+
+    /// Add a header to the response.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the name or the value is invalid.
+    pub fn header<K, V>(mut self, key: K, value: V) -> Self
+    where
+        HeaderName: TryFrom<K>,
+        <HeaderName as TryFrom<K>>::Error: std::fmt::Debug,
+        HeaderValue: TryFrom<V>,
+        <HeaderValue as TryFrom<V>>::Error: std::fmt::Debug,
+    {
+        self.headers.append(
+            HeaderName::try_from(key).expect("invalid header name"),
+            HeaderValue::try_from(value).expect("invalid header value"),
+        );
+        self
+    }
+
+    pub fn body<B: Into<Bytes>>(mut self, body: B) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    pub fn version(mut self, version: Version) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// The address the host resolves to, the port is the one of the request.
+    pub fn addr(mut self, addr: IpAddr) -> Self {
+        self.addr = addr;
+        self
+    }
+
+    /// The ALPN protocol reported by the fake TLS handshake.
+    pub fn alpn(mut self, alpn: &[u8]) -> Self {
+        self.alpn = Some(alpn.to_vec());
+        self
+    }
+
+    /// How long the fake certificate stays valid, 90 days by default.
+    pub fn cert_validity(mut self, validity: Duration) -> Self {
+        self.cert_validity = validity;
+        self
+    }
+
+    pub fn dns_delay(mut self, delay: Duration) -> Self {
+        self.dns_delay = delay;
+        self
+    }
+
+    pub fn tcp_delay(mut self, delay: Duration) -> Self {
+        self.tcp_delay = delay;
+        self
+    }
+
+    /// The duration of the fake TLS handshake, only for `https` requests.
+    pub fn tls_delay(mut self, delay: Duration) -> Self {
+        self.tls_delay = delay;
+        self
+    }
+
+    /// The time between sending the request and receiving the response head.
+    pub fn response_delay(mut self, delay: Duration) -> Self {
+        self.response_delay = delay;
+        self
+    }
+
+    async fn serve(self, request: Request) -> crate::Result<Response> {
+        let recorder = request.recorder();
+        let host = request.uri().host().ok_or(crate::Error::HostRequired)?;
+        let addr = SocketAddr::new(self.addr, request.port());
+
+        if let Some(recorder) = recorder {
+            recorder.on_dns_start(&request, &[], host);
+        }
+        tokio::time::sleep(self.dns_delay).await;
+        let ret = match self.failure {
+            Some(MockFailure::Dns) => Err(crate::Error::EmptyResolveResult),
+            _ => Ok(DnsInfo::new(vec![self.addr])),
+        };
+        if let Some(recorder) = recorder {
+            recorder.on_dns_done(&request, &[], host, ret.as_ref().map_err(|e| e as _));
+        }
+        ret?;
+
+        if let Some(recorder) = recorder {
+            recorder.on_tcp_start(&request, &addr);
+        }
+        tokio::time::sleep(self.tcp_delay).await;
+        let tcp = TcpInfo {
+            local_addr: Some(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 50000)),
+            peer_addr: addr,
+            mptcp: false,
+        };
+        let ret = match self.failure {
+            Some(MockFailure::Tcp) => Err(crate::Error::AllTcpConnectFailed),
+            _ => Ok(&tcp),
+        };
+        if let Some(recorder) = recorder {
+            recorder.on_tcp_done(&request, &addr, ret.as_ref().copied().map_err(|e| e as _));
+        }
+        ret?;
+
+        let mut conn_tls = None;
+        if request.uri().scheme() == Some(&http::uri::Scheme::HTTPS) {
+            if let Some(recorder) = recorder {
+                recorder.on_tls_start(&request, &tcp);
+            }
+            tokio::time::sleep(self.tls_delay).await;
+            let tls = TlsInfo {
+                protocol_version: Some(ProtocolVersion::TLSv1_3),
+                cipher_suite: Some(CipherSuite::TLS13_AES_128_GCM_SHA256),
+                kx_group: Some(NamedGroup::X25519),
+                alpn_protocol: self.alpn.clone(),
+                sni: Some(host.to_string()),
+                resumed: false,
+                cert_not_after: Some(SystemTime::now() + self.cert_validity),
+                peer_certificates: Vec::new(),
+                revocation: None,
+                ocsp_response: None,
+                ech: None,
+            };
+            let ret = match self.failure {
+                Some(MockFailure::Tls) => Err(crate::Error::Io(std::io::Error::from(
+                    std::io::ErrorKind::ConnectionReset,
+                ))),
+                _ => Ok(&tls),
+            };
+            if let Some(recorder) = recorder {
+                recorder.on_tls_done(&request, ret.as_ref().copied().map_err(|e| e as _));
+            }
+            ret?;
+            conn_tls = Some(tls);
+        }
+
+        if let Some(recorder) = recorder {
+            recorder.on_request_start(&request);
+        }
+        tokio::time::sleep(self.response_delay).await;
+        if self.failure == Some(MockFailure::Request) {
+            return Err(crate::Error::Io(std::io::Error::from(
+                std::io::ErrorKind::BrokenPipe,
+            )));
+        }
+
+        let mut res = http::Response::new(crate::body::boxed(Body::from(self.body)));
+        *res.status_mut() = self.status;
+        *res.version_mut() = self.version;
+        *res.headers_mut() = self.headers;
+        res.extensions_mut()
+            .insert(ConnectionInfo::new(tcp, conn_tls, self.version));
+        let res = Response::new(res);
+        if let Some(recorder) = recorder {
+            recorder.on_response(&request, &res);
+        }
+        Ok(res)
+    }
+}
+
+/// A resolver serving scripted lookups, to test the connection logic against
+/// real sockets.
+///
+/// Lookups are queued per host and served in order. Looking up a host with no
+/// lookup left fails with [`Error::MockExhausted`](crate::Error::MockExhausted).
+#[derive(Debug, Clone, Default)]
+pub struct MockResolver {
+    inner: Arc<Mutex<MockResolverInner>>,
+}
+
+#[derive(Debug, Default)]
+struct MockResolverInner {
+    // Each entry answers one resolution, in one or more parts.
+    lookups: HashMap<String, VecDeque<Vec<MockLookup>>>,
+    hosts: Vec<String>,
+    ech_config_lists: HashMap<String, Vec<u8>>,
+}
+
+/// A scripted lookup of a [`MockResolver`].
+#[derive(Debug, Clone)]
+pub struct MockLookup {
+    info: DnsInfo,
+    failed: bool,
+    delay: Duration,
+}
+
+impl MockResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue a lookup for the host, served after the ones already queued.
+    pub fn push(&self, host: &str, lookup: MockLookup) -> &Self {
+        self.push_partial(host, [lookup])
+    }
+
+    /// Queue a lookup answered in parts, like `A` and `AAAA` queried
+    /// separately, see [`Resolve::resolve_partial`]. The delay of each part
+    /// counts from the start of the lookup.
+    pub fn push_partial<I>(&self, host: &str, parts: I) -> &Self
+    where
+        I: IntoIterator<Item = MockLookup>,
+    {
+        self.inner
+            .lock()
+            .unwrap()
+            .lookups
+            .entry(host.to_string())
+            .or_default()
+            .push_back(parts.into_iter().collect());
+        self
+    }
+
+    fn next(&self, host: &str) -> Option<Vec<MockLookup>> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.hosts.push(host.to_string());
+        inner.lookups.get_mut(host).and_then(|v| v.pop_front())
+    }
+
+    /// The hosts looked up so far.
+    pub fn hosts(&self) -> Vec<String> {
+        self.inner.lock().unwrap().hosts.clone()
+    }
+
+    /// Publish an ECH config list for the host, as in its `HTTPS` record.
+    pub fn publish_ech(&self, host: &str, list: Vec<u8>) -> &Self {
+        let mut inner = self.inner.lock().unwrap();
+        inner.ech_config_lists.insert(host.to_string(), list);
+        self
+    }
+}
+
+impl Resolve for MockResolver {
+    fn resolve<'a>(&'a self, host: &'a str) -> BoxFuture<'a, crate::Result<DnsInfo>> {
+        let parts = self.resolve_partial(host).collect::<Vec<_>>();
+        Box::pin(async move {
+            let mut merged: Option<DnsInfo> = None;
+            let mut error = None;
+            for part in parts.await {
+                match (part, merged.as_mut()) {
+                    (Ok(info), Some(merged)) => merged.merge(info),
+                    (Ok(info), None) => merged = Some(info),
+                    (Err(e), _) => error = error.or(Some(e)),
+                }
+            }
+            match (merged, error) {
+                (Some(info), _) if !info.addrs.is_empty() => Ok(info),
+                (_, Some(e)) => Err(e),
+                (merged, None) => Ok(merged.unwrap_or_default()),
+            }
+        })
+    }
+
+    fn resolve_partial<'a>(&'a self, host: &'a str) -> BoxStream<'a, crate::Result<DnsInfo>> {
+        let Some(parts) = self.next(host) else {
+            return Box::pin(stream::once(async { Err(crate::Error::MockExhausted) }));
+        };
+        let parts = parts
+            .into_iter()
+            .map(|lookup| async move {
+                tokio::time::sleep(lookup.delay).await;
+                match lookup.failed {
+                    true => Err(crate::Error::EmptyResolveResult),
+                    false => Ok(lookup.info),
+                }
+            })
+            .collect::<FuturesUnordered<_>>();
+        Box::pin(parts)
+    }
+
+    fn ech_config_list<'a>(
+        &'a self,
+        host: &'a str,
+    ) -> BoxFuture<'a, crate::Result<Option<Vec<u8>>>> {
+        let inner = self.inner.lock().unwrap();
+        let list = inner.ech_config_lists.get(host).cloned();
+        Box::pin(async move { Ok(list) })
+    }
+}
+
+impl MockLookup {
+    pub fn addrs(addrs: &[IpAddr]) -> Self {
+        Self::info(DnsInfo::new(addrs.to_vec()))
+    }
+
+    /// A lookup answering with the given details.
+    pub fn info(info: DnsInfo) -> Self {
+        Self {
+            info,
+            failed: false,
+            delay: Duration::ZERO,
+        }
+    }
+
+    /// A lookup failing with [`Error::EmptyResolveResult`](crate::Error::EmptyResolveResult).
+    pub fn failure() -> Self {
+        Self {
+            info: DnsInfo::default(),
+            failed: true,
+            delay: Duration::ZERO,
+        }
+    }
+
+    /// The time the lookup takes, checked against the client's DNS timeout.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+}
+
+/// A local HTTP server answering every request with the same response.
+///
+/// The server listens on `127.0.0.1` and stops when dropped. With TLS, it uses
+/// a self-signed certificate for `localhost` and `127.0.0.1`, and negotiates
+/// `h2` or `http/1.1` through ALPN.
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use httptrace::{client::Client, test_util::TestServer};
+/// # async fn run() {
+/// let server = TestServer::builder()
+///     .header_delay(Duration::from_millis(100))
+///     .chunks(["hello", " world"], Duration::from_millis(50))
+///     .tls()
+///     .start()
+///     .await
+///     .unwrap();
+///
+/// let client = Client::builder().skip_tls_verify().build().unwrap();
+/// let response = client.get(server.url("/")).send().await.unwrap();
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct TestServer {
+    addr: SocketAddr,
+    certificate: Option<CertificateDer<'static>>,
+    hits: Arc<AtomicUsize>,
+    task: JoinHandle<()>,
+}
+
+/// A builder for a [`TestServer`].
+#[derive(Debug, Clone)]
+pub struct TestServerBuilder {
+    status: StatusCode,
+    headers: HeaderMap,
+    chunks: Vec<Bytes>,
+    chunked: bool,
+    header_delay: Duration,
+    body_delay: Duration,
+    chunk_interval: Duration,
+    tls: bool,
+}
+
+impl TestServer {
+    pub fn builder() -> TestServerBuilder {
+        TestServerBuilder {
+            status: StatusCode::OK,
+            headers: HeaderMap::new(),
+            chunks: Vec::new(),
+            chunked: false,
+            header_delay: Duration::ZERO,
+            body_delay: Duration::ZERO,
+            chunk_interval: Duration::ZERO,
+            tls: false,
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The URL of the given path on this server.
+    pub fn url(&self, path: &str) -> String {
+        let scheme = match self.certificate {
+            Some(_) => "https",
+            None => "http",
+        };
+        format!("{scheme}://{}{path}", self.addr)
+    }
+
+    /// The self-signed certificate, if TLS is enabled.
+    pub fn certificate(&self) -> Option<&CertificateDer<'static>> {
+        self.certificate.as_ref()
+    }
+
+    /// The number of requests served so far.
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl TestServerBuilder {
+    pub fn status(mut self, status: u16) -> Self {
+        self.status = StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        self
+    }
+
+    /// Add a header to the response.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the name or the value is invalid.
+    pub fn header<K, V>(mut self, key: K, value: V) -> Self
+    where
+        HeaderName: TryFrom<K>,
+        <HeaderName as TryFrom<K>>::Error: std::fmt::Debug,
+        HeaderValue: TryFrom<V>,
+        <HeaderValue as TryFrom<V>>::Error: std::fmt::Debug,
+    {
+        self.headers.append(
+            HeaderName::try_from(key).expect("invalid header name"),
+            HeaderValue::try_from(value).expect("invalid header value"),
+        );
+        self
+    }
+
+    /// Send the body at once, with a `Content-Length`.
+    pub fn body<B: Into<Bytes>>(mut self, body: B) -> Self {
+        self.chunks = vec![body.into()];
+        self.chunked = false;
+        self
+    }
+
+    /// Send the body with chunked encoding, waiting `interval` between chunks.
+    pub fn chunks<I, B>(mut self, chunks: I, interval: Duration) -> Self
+    where
+        I: IntoIterator<Item = B>,
+        B: Into<Bytes>,
+    {
+        self.chunks = chunks.into_iter().map(Into::into).collect();
+        self.chunked = true;
+        self.chunk_interval = interval;
+        self
+    }
+
+    /// The time between receiving the request and sending the response head.
+    pub fn header_delay(mut self, delay: Duration) -> Self {
+        self.header_delay = delay;
+        self
+    }
+
+    /// The time between sending the response head and the first body chunk.
+    pub fn body_delay(mut self, delay: Duration) -> Self {
+        self.body_delay = delay;
+        self
+    }
+
+    /// Serve over TLS with a self-signed certificate.
+    pub fn tls(mut self) -> Self {
+        self.tls = true;
+        self
+    }
+
+    pub async fn start(self) -> crate::Result<TestServer> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let addr = listener.local_addr()?;
+
+        let (acceptor, certificate) = match self.tls {
+            true => {
+                let (acceptor, certificate) = self_signed_acceptor()?;
+                (Some(acceptor), Some(certificate))
+            }
+            false => (None, None),
+        };
+
+        let hits = Arc::new(AtomicUsize::new(0));
+        let config = Arc::new(self);
+        let task = tokio::spawn({
+            let hits = hits.clone();
+            async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let acceptor = acceptor.clone();
+                    let config = config.clone();
+                    let hits = hits.clone();
+                    tokio::spawn(async move {
+                        let service = service_fn(move |_| {
+                            hits.fetch_add(1, Ordering::Relaxed);
+                            let config = config.clone();
+                            async move { Ok::<_, Infallible>(config.respond().await) }
+                        });
+                        let builder = auto::Builder::new(TokioExecutor::new());
+                        match acceptor {
+                            Some(acceptor) => {
+                                if let Ok(stream) = acceptor.accept(stream).await {
+                                    _ = builder
+                                        .serve_connection(TokioIo::new(stream), service)
+                                        .await;
+                                }
+                            }
+                            None => {
+                                _ = builder
+                                    .serve_connection(TokioIo::new(stream), service)
+                                    .await;
+                            }
+                        }
+                    });
+                }
+            }
+        });
+
+        Ok(TestServer {
+            addr,
+            certificate,
+            hits,
+            task,
+        })
+    }
+
+    async fn respond(
+        &self,
+    ) -> http::Response<StreamBody<BoxStream<'static, Result<Frame<Bytes>, Infallible>>>> {
+        tokio::time::sleep(self.header_delay).await;
+
+        let body_delay = self.body_delay;
+        let chunk_interval = self.chunk_interval;
+        let frames = stream::iter(self.chunks.clone().into_iter().enumerate())
+            .then(move |(i, chunk)| async move {
+                let delay = match i {
+                    0 => body_delay,
+                    _ => chunk_interval,
+                };
+                tokio::time::sleep(delay).await;
+                Ok(Frame::data(chunk))
+            })
+            .boxed();
+
+        let mut res = http::Response::new(StreamBody::new(frames));
+        *res.status_mut() = self.status;
+        *res.headers_mut() = self.headers.clone();
+        if !self.chunked {
+            let len = self.chunks.iter().map(Bytes::len).sum::<usize>();
+            res.headers_mut()
+                .insert(http::header::CONTENT_LENGTH, HeaderValue::from(len));
+        }
+        res
+    }
+}
+
+pub(crate) fn self_signed_acceptor() -> crate::Result<(TlsAcceptor, CertificateDer<'static>)> {
+    crate::client::ensure_crypto_provider();
+
+    let rcgen::CertifiedKey { cert, key_pair } =
+        rcgen::generate_simple_self_signed(["localhost".to_string(), "127.0.0.1".to_string()])
+            .map_err(std::io::Error::other)?;
+    let certificate = cert.der().clone();
+    let key = PrivateKeyDer::Pkcs8(key_pair.serialize_der().into());
+
+    let mut config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(vec![certificate.clone()], key)?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok((TlsAcceptor::from(Arc::new(config)), certificate))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use hickory_resolver::config::NameServerConfig;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::{MockFailure, MockLookup, MockResolver, MockResponse, MockTransport, TestServer};
+    use crate::{
+        client::{Alpn, Client, VersionPolicy},
+        dns::DnsSource,
+        request::{Request, RequestHead},
+        stats::{NegotiationReason, Recorder, StatsRecorder},
+    };
+
+    #[tokio::test]
+    async fn test_mock_transport() {
+        let mock = MockTransport::new();
+        mock.push(MockResponse::failure(MockFailure::Tcp)).push(
+            MockResponse::new(201)
+                .header("x-mock", "1")
+                .body("created")
+                .tls_delay(Duration::from_millis(10)),
+        );
+        let client = Client::builder()
+            .mock_transport(mock.clone())
+            .build()
+            .unwrap();
+
+        let ret = client.post("https://example.com/a").send().await;
+        assert!(matches!(ret, Err(crate::Error::AllTcpConnectFailed)));
+
+        let recorder = StatsRecorder::new();
+        let mut request = client
+            .post("https://example.com/b")
+            .body("payload")
+            .build()
+            .unwrap();
+        *request.recorder_mut() = Some(Arc::new(recorder.clone()));
+        let response = client.execute(request).await.unwrap();
+        assert_eq!(response.status(), 201);
+        assert_eq!(response.headers()["x-mock"], "1");
+        assert_eq!(response.text().await.unwrap(), "created");
+
+        let stats = recorder.finish();
+        assert!(stats.tls_stats.unwrap().duration >= Duration::from_millis(10));
+
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].uri.path(), "/b");
+        assert_eq!(requests[1].body.as_deref(), Some(&b"payload"[..]));
+
+        let ret = client.get("https://example.com/c").send().await;
+        assert!(matches!(ret, Err(crate::Error::MockExhausted)));
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid status code")]
+    fn test_mock_invalid_status() {
+        MockResponse::new(1000);
+    }
+
+    #[tokio::test]
+    async fn test_mock_resolver() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            use tokio::io::AsyncWriteExt;
+            while let Ok((mut stream, _)) = listener.accept().await {
+                _ = stream.write_all(b"HTTP/1.1 204 No Content\r\n\r\n").await;
+            }
+        });
+
+        let resolver = MockResolver::new();
+        resolver
+            .push("a.test", MockLookup::failure())
+            .push(
+                "a.test",
+                MockLookup::addrs(&["127.0.0.1".parse().unwrap()]).delay(Duration::from_millis(10)),
+            )
+            .push(
+                "a.test",
+                MockLookup::addrs(&["127.0.0.1".parse().unwrap()]).delay(Duration::from_secs(1)),
+            );
+        let client = Client::builder()
+            .dns_resolver(resolver.clone())
+            .dns_timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+        let url = format!("http://a.test:{port}/");
+
+        let ret = client.get(&url).send().await;
+        assert!(matches!(ret, Err(crate::Error::EmptyResolveResult)));
+
+        let recorder = StatsRecorder::new();
+        let mut request = client.get(&url).build().unwrap();
+        *request.recorder_mut() = Some(Arc::new(recorder.clone()));
+        let response = client.execute(request).await.unwrap();
+        assert_eq!(response.status(), 204);
+        let stats = recorder.finish();
+        assert!(stats.dns_stats.duration >= Duration::from_millis(10));
+        assert_eq!(
+            stats.dns_info.unwrap().addrs,
+            ["127.0.0.1".parse::<std::net::IpAddr>().unwrap()]
+        );
+
+        let ret = client.get(&url).send().await;
+        assert!(matches!(ret, Err(crate::Error::Timeout(_))));
+
+        let ret = client.get(&url).send().await;
+        assert!(matches!(ret, Err(crate::Error::MockExhausted)));
+        assert_eq!(resolver.hosts().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_drive_in_task() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            use tokio::io::{AsyncReadExt, AsyncWriteExt};
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = [0u8; 1024];
+                _ = stream.read(&mut buf).await;
+                _ = stream
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 11\r\n\r\nhello")
+                    .await;
+                // The rest of the body is only read if the connection is
+                // still driven once the head is returned.
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                _ = stream.write_all(b" world").await;
+            }
+        });
+
+        let client = Client::builder().drive_in_task().build().unwrap();
+        let url = format!("http://{addr}/");
+        for _ in 0..2 {
+            let response = client.get(&url).send().await.unwrap();
+            assert_eq!(response.status(), 200);
+            assert_eq!(response.text().await.unwrap(), "hello world");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_header_case() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            use tokio::io::{AsyncReadExt, AsyncWriteExt};
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = [0u8; 1024];
+                let n = stream.read(&mut buf).await.unwrap();
+                _ = tx.send(String::from_utf8_lossy(&buf[..n]).into_owned());
+                _ = stream.write_all(b"HTTP/1.1 204 No Content\r\n\r\n").await;
+            }
+        });
+
+        let url = format!("http://{addr}/");
+        let client = Client::builder().disable_auto_set_header().build().unwrap();
+        client
+            .get(&url)
+            .header_with_case("X-API-Key", "k")
+            .header("accept", "*/*")
+            .header_with_case("HOST", "a.test")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            rx.recv().await.unwrap(),
+            format!("GET {url} HTTP/1.1\r\nX-API-Key: k\r\naccept: */*\r\nHOST: a.test\r\n\r\n")
+        );
+
+        let client = Client::builder()
+            .disable_auto_set_header()
+            .title_case_headers()
+            .build()
+            .unwrap();
+        client
+            .get(&url)
+            .header("x-forwarded-for", "1.2.3.4")
+            .header_with_case("x-API-key", "k")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            rx.recv().await.unwrap(),
+            format!("GET {url} HTTP/1.1\r\nX-Forwarded-For: 1.2.3.4\r\nx-API-key: k\r\n\r\n")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_raw_head() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            use tokio::io::{AsyncReadExt, AsyncWriteExt};
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = [0u8; 1024];
+                let n = stream.read(&mut buf).await.unwrap();
+                _ = tx.send(String::from_utf8_lossy(&buf[..n]).into_owned());
+                _ = stream
+                    .write_all(b"HTTP/1.1 400 Bad Request\r\ncontent-length: 3\r\n\r\nbad")
+                    .await;
+            }
+        });
+
+        let recorder = StatsRecorder::new();
+        let raw = "GET /a b HTTP/1.1\r\nHost: x\r\nHost: y\r\n\r\n";
+        let client = Client::builder().build().unwrap();
+        let response = client
+            .get(format!("http://{addr}/"))
+            .raw_head(raw)
+            .recorder(Box::new(recorder.clone()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+        assert_eq!(response.text().await.unwrap(), "bad");
+        assert_eq!(rx.recv().await.unwrap(), raw);
+
+        let stats = recorder.finish();
+        assert_eq!(stats.tcp_info.unwrap().peer_addr, addr);
+        assert!(stats.request_stats.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_http1_only_requests() {
+        let server = TestServer::builder()
+            .tls()
+            .body("ok")
+            .start()
+            .await
+            .unwrap();
+        let client = Client::builder().skip_tls_verify().build().unwrap();
+        let url = server.url("/");
+        let raw = format!("GET / HTTP/1.1\r\nhost: {}\r\n\r\n", server.addr());
+        // Offered HTTP/1.1 only, though the server and the client prefer h2.
+        for request in [
+            client.get(&url).raw_head(raw),
+            client.get(&url).header_with_case("X-API-Key", "k"),
+            client.post(&url).body("hello").expect_continue(),
+        ] {
+            let response = request.send().await.unwrap();
+            assert_eq!(response.version(), http::Version::HTTP_11);
+            assert_eq!(response.text().await.unwrap(), "ok");
+        }
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.version(), http::Version::HTTP_2);
+    }
+
+    #[tokio::test]
+    async fn test_vhost() {
+        let server = TestServer::builder().tls().start().await.unwrap();
+        let client = Client::builder().skip_tls_verify().build().unwrap();
+
+        let recorder = StatsRecorder::new();
+        let response = client
+            .get(format!("https://origin.test:{}/", server.addr().port()))
+            .vhost(server.addr().ip(), "sni.test", "host.test")
+            .recorder(Box::new(recorder.clone()))
+            .send()
+            .await
+            .unwrap();
+        let head = response.extensions().get::<RequestHead>().unwrap();
+        assert_eq!(head.headers[http::header::HOST], "host.test");
+        response.bytes().await.unwrap();
+
+        let stats = recorder.finish();
+        assert_eq!(stats.dns_info.unwrap().source, DnsSource::Override);
+        assert_eq!(stats.tls_info.unwrap().sni.as_deref(), Some("sni.test"));
+        assert_eq!(server.hits(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_require_protocol() {
+        let server = TestServer::builder().tls().start().await.unwrap();
+        let url = format!("https://localhost:{}/", server.addr().port());
+
+        // Nothing offered, the connection falls back to HTTP/1.1.
+        let client = Client::builder()
+            .skip_tls_verify()
+            .alpn_protocols(vec![])
+            .build()
+            .unwrap();
+        let err = client.get(&url).require_protocol(Alpn::Http2).send().await;
+        assert!(matches!(
+            err.unwrap_err(),
+            crate::Error::ProtocolNotNegotiated { negotiated, .. } if negotiated == "http/1.1"
+        ));
+
+        // h2 is offered by default.
+        let client = Client::builder().skip_tls_verify().build().unwrap();
+        let response = client
+            .get(&url)
+            .require_protocol(Alpn::Http2)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.version(), http::Version::HTTP_2);
+    }
+
+    #[tokio::test]
+    async fn test_version_policy() {
+        let server = TestServer::builder().tls().start().await.unwrap();
+        let url = format!("https://localhost:{}/", server.addr().port());
+        let client = Client::builder()
+            .skip_tls_verify()
+            .alpn_protocols(vec![])
+            .build()
+            .unwrap();
+
+        let negotiate = |policy: Option<VersionPolicy>| {
+            let client = client.clone();
+            let url = url.clone();
+            async move {
+                let recorder = StatsRecorder::new();
+                let mut builder = client.get(url).recorder(Box::new(recorder.clone()));
+                if let Some(policy) = policy {
+                    builder = builder.version_policy(policy);
+                }
+                builder.send().await.unwrap().bytes().await.unwrap();
+                recorder.finish().negotiation.unwrap()
+            }
+        };
+        let negotiation = negotiate(None).await;
+        assert_eq!(negotiation.version, http::Version::HTTP_11);
+        assert_eq!(negotiation.reason, NegotiationReason::NotOffered);
+        let negotiation = negotiate(Some(VersionPolicy::PreferHttp2)).await;
+        assert_eq!(negotiation.version, http::Version::HTTP_2);
+        assert_eq!(negotiation.reason, NegotiationReason::Alpn("h2".into()));
+        let negotiation = negotiate(Some(VersionPolicy::Http1Only)).await;
+        assert_eq!(negotiation.version, http::Version::HTTP_11);
+        assert_eq!(
+            negotiation.reason,
+            NegotiationReason::Alpn("http/1.1".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_prior_knowledge() {
+        let server = TestServer::builder().body("ok").start().await.unwrap();
+        let client = Client::builder().http2_prior_knowledge().build().unwrap();
+
+        let recorder = StatsRecorder::new();
+        let response = client
+            .get(server.url("/"))
+            .recorder(Box::new(recorder.clone()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.version(), http::Version::HTTP_2);
+        assert_eq!(response.text().await.unwrap(), "ok");
+        let negotiation = recorder.finish().negotiation.unwrap();
+        assert_eq!(negotiation.version, http::Version::HTTP_2);
+        assert_eq!(negotiation.reason, NegotiationReason::PriorKnowledge);
+    }
+
+    #[tokio::test]
+    async fn test_mptcp() {
+        let server = TestServer::builder().start().await.unwrap();
+        let url = format!("http://{}/", server.addr());
+        // Only where the kernel has it enabled.
+        let supported =
+            std::fs::read_to_string("/proc/sys/net/mptcp/enabled").is_ok_and(|v| v.trim() == "1");
+
+        for mptcp in [true, false] {
+            let client = Client::builder().mptcp(mptcp).build().unwrap();
+            let recorder = StatsRecorder::new();
+            let response = client
+                .get(&url)
+                .recorder(Box::new(recorder.clone()))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), 200);
+            let tcp = recorder.finish().tcp_info.unwrap();
+            assert_eq!(tcp.mptcp, mptcp && supported);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_informational() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut head = Vec::new();
+            while !head.ends_with(b"\r\n\r\n") {
+                head.push(stream.read_u8().await.unwrap());
+            }
+            stream
+                .write_all(
+                    b"HTTP/1.1 103 Early Hints\r\nlink: </style.css>; rel=preload\r\n\r\n\
+                      HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok",
+                )
+                .await
+                .unwrap();
+        });
+
+        let recorder = StatsRecorder::new();
+        let response = Client::builder()
+            .build()
+            .unwrap()
+            .get(format!("http://{addr}/"))
+            .recorder(Box::new(recorder.clone()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.text().await.unwrap(), "ok");
+
+        let informational = recorder.finish().informational;
+        assert_eq!(informational.len(), 1);
+        assert_eq!(informational[0].status, 103);
+        assert_eq!(
+            informational[0].headers["link"],
+            "</style.css>; rel=preload"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pinned_version() {
+        let server = TestServer::builder().tls().start().await.unwrap();
+        let client = Client::builder()
+            .skip_tls_verify()
+            .alpn_protocols(vec![Alpn::Http2, Alpn::Http1])
+            .build()
+            .unwrap();
+        let send = |version| client.get(server.url("/")).version(version).send();
+
+        let response = send(http::Version::HTTP_11).await.unwrap();
+        assert_eq!(response.version(), http::Version::HTTP_11);
+        let response = send(http::Version::HTTP_2).await.unwrap();
+        assert_eq!(response.version(), http::Version::HTTP_2);
+        let err = send(http::Version::HTTP_3).await.unwrap_err();
+        assert!(matches!(err, crate::Error::UnsupportedVersion(_)));
+
+        // HTTP/2 without TLS is spoken with prior knowledge.
+        let server = TestServer::builder().start().await.unwrap();
+        let response = client
+            .get(server.url("/"))
+            .version(http::Version::HTTP_2)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.version(), http::Version::HTTP_2);
+    }
+
+    #[tokio::test]
+    async fn test_http2_settings() {
+        let body = "a".repeat(100_000);
+        let server = TestServer::builder()
+            .body(body.clone())
+            .start()
+            .await
+            .unwrap();
+        let client = Client::builder()
+            .http2_prior_knowledge()
+            .http2_initial_stream_window_size(1024)
+            .http2_initial_connection_window_size(2048)
+            .http2_max_concurrent_streams(4)
+            .http2_max_frame_size(32 * 1024)
+            .build()
+            .unwrap();
+
+        // The body still goes through, a window at a time.
+        let response = client.get(server.url("/")).send().await.unwrap();
+        assert_eq!(response.version(), http::Version::HTTP_2);
+        assert_eq!(response.text().await.unwrap(), body);
+
+        for size in [0, 16 * 1024 * 1024] {
+            let err = Client::builder()
+                .http2_max_frame_size(size)
+                .build()
+                .unwrap_err();
+            assert!(matches!(err, crate::Error::InvalidConfig(_)));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_elapsed() {
+        #[derive(Default)]
+        struct Elapsed(std::sync::Mutex<Vec<Duration>>);
+
+        impl Recorder for Arc<Elapsed> {
+            fn on_dns_start(&self, request: &Request, _: &[NameServerConfig], _host: &str) {
+                self.0.lock().unwrap().push(request.elapsed());
+            }
+
+            fn on_request_start(&self, request: &Request) {
+                self.0.lock().unwrap().push(request.elapsed());
+            }
+
+            fn on_response(&self, request: &Request, _response: &crate::response::Response) {
+                assert!(request.started_at().is_some());
+                self.0.lock().unwrap().push(request.elapsed());
+            }
+        }
+
+        let server = TestServer::builder().start().await.unwrap();
+        let elapsed = Arc::new(Elapsed::default());
+        let client = Client::builder().build().unwrap();
+        client
+            .get(server.url("/"))
+            .recorder(Box::new(elapsed.clone()))
+            .send()
+            .await
+            .unwrap();
+
+        let elapsed = elapsed.0.lock().unwrap();
+        assert_eq!(elapsed.len(), 3);
+        assert!(elapsed.is_sorted());
+    }
+
+    #[tokio::test]
+    async fn test_dns_overlap() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.2:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            use tokio::io::AsyncWriteExt;
+            while let Ok((mut stream, _)) = listener.accept().await {
+                _ = stream.write_all(b"HTTP/1.1 204 No Content\r\n\r\n").await;
+            }
+        });
+
+        // Nothing listens on the first answer, the connection waits for the
+        // second one.
+        let resolver = MockResolver::new();
+        resolver.push_partial(
+            "a.test",
+            [
+                MockLookup::addrs(&["127.0.0.3".parse().unwrap()]),
+                MockLookup::addrs(&["127.0.0.2".parse().unwrap()]).delay(Duration::from_millis(50)),
+            ],
+        );
+        let client = Client::builder().dns_resolver(resolver).build().unwrap();
+
+        let recorder = StatsRecorder::new();
+        let mut request = client
+            .get(format!("http://a.test:{port}/"))
+            .build()
+            .unwrap();
+        *request.recorder_mut() = Some(Arc::new(recorder.clone()));
+        let response = client.execute(request).await.unwrap();
+        assert_eq!(response.status(), 204);
+
+        let stats = recorder.finish();
+        assert_eq!(stats.connect_delay, Some(Duration::from_secs(3)));
+        let tcp_stats = stats.tcp_stats.unwrap();
+        assert_eq!(tcp_stats.len(), 2);
+        assert!(tcp_stats[0].error.is_some());
+        assert!(tcp_stats[0].started_at < stats.dns_stats.finished_at);
+        assert!(stats.dns_stats.duration >= Duration::from_millis(50));
+        assert_eq!(stats.dns_info.unwrap().addrs.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_shared_resolver() {
+        let resolver = MockResolver::new();
+        resolver
+            .push("a.test", MockLookup::failure())
+            .push("b.test", MockLookup::failure());
+        let shared: Arc<dyn crate::dns::Resolve> = Arc::new(resolver.clone());
+        for url in ["http://a.test/", "http://b.test/"] {
+            let client = Client::builder()
+                .shared_dns_resolver(shared.clone())
+                .build()
+                .unwrap();
+            let ret = client.get(url).send().await;
+            assert!(matches!(ret, Err(crate::Error::EmptyResolveResult)));
+        }
+        assert_eq!(resolver.hosts(), ["a.test", "b.test"]);
+    }
+
+    #[tokio::test]
+    async fn test_server() {
+        for tls in [false, true] {
+            let mut builder = TestServer::builder()
+                .header("x-test", "1")
+                .header_delay(Duration::from_millis(20))
+                .chunks(["a", "b", "c"], Duration::from_millis(10));
+            if tls {
+                builder = builder.tls();
+            }
+            let server = builder.start().await.unwrap();
+            assert_eq!(server.certificate().is_some(), tls);
+
+            let client = Client::builder()
+                .skip_tls_verify()
+                .version_policy(VersionPolicy::Http1Only)
+                .build()
+                .unwrap();
+            let recorder = StatsRecorder::new();
+            let mut request = client.get(server.url("/a")).build().unwrap();
+            *request.recorder_mut() = Some(Arc::new(recorder.clone()));
+            let response = client.execute(request).await.unwrap();
+            assert_eq!(response.headers()["x-test"], "1");
+            assert_eq!(response.headers()["transfer-encoding"], "chunked");
+            let conn = response.connection_info().unwrap().clone();
+            assert_eq!(response.text().await.unwrap(), "abc");
+
+            let stats = recorder.finish();
+            assert_eq!(conn.version, http::Version::HTTP_11);
+            assert_eq!(Some(&conn.tcp), stats.tcp_info.as_ref());
+            assert_eq!(conn.tls, stats.tls_info);
+            assert!(!conn.reused);
+            if tls {
+                let tls = stats.tls_info.as_ref().unwrap();
+                assert!(tls.cipher_suite.is_some());
+                assert!(tls.kx_group.is_some());
+                assert!(tls.cert_not_after.is_some());
+                assert_eq!(tls.sni, None);
+                assert!(!tls.resumed);
+                assert!(stats.tls_stats.as_ref().unwrap().extend.is_none());
+                assert!(stats.to_string().contains(">>> version: TLSv1_3 cipher: "));
+            }
+            let tcp = &stats.tcp_stats.as_ref().unwrap()[0];
+            assert_eq!(tcp.peer_addr, server.addr());
+            assert_eq!(tcp.local_addr, stats.tcp_info.as_ref().unwrap().local_addr);
+            assert!(tcp.local_addr.is_some());
+            assert_eq!(stats.started_at, stats.dns_stats.started_at);
+            assert!(tcp.started_at.unwrap() <= tcp.finished_at.unwrap());
+            // Every phase within the request, on the wall clock.
+            let phases = [
+                Some(&stats.dns_stats),
+                stats.tls_stats.as_ref(),
+                stats.request_stats.as_ref(),
+            ];
+            let times = phases
+                .into_iter()
+                .flatten()
+                .flat_map(|stat| [stat.started_at, stat.finished_at])
+                .chain([tcp.started_at, tcp.finished_at]);
+            for time in times {
+                assert!((stats.started_at..=stats.finished_at).contains(&time));
+            }
+            let request = stats.request_stats.as_ref().unwrap();
+            assert_eq!(request.finished_at, stats.finished_at);
+            assert_eq!(stats.tls_stats.is_some(), tls);
+            assert!(stats.request_stats.unwrap().duration >= Duration::from_millis(40));
+            assert_eq!(server.hits(), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connection_liveness() {
+        // Closes each connection after its response.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = Vec::new();
+                while !buf.ends_with(b"\r\n\r\n") {
+                    let mut byte = [0; 1];
+                    stream.read_exact(&mut byte).await.unwrap();
+                    buf.push(byte[0]);
+                }
+                let response = b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok";
+                stream.write_all(response).await.unwrap();
+            }
+        });
+        let url = format!("http://{addr}/");
+        let client = Client::builder().pool_max_idle_per_host(4).build().unwrap();
+        assert!(!client.is_alive(&url).unwrap());
+
+        let response = client.get(&url).send().await.unwrap();
+        let first = response.connection_info().unwrap().clone();
+        assert_eq!(response.text().await.unwrap(), "ok");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!client.is_alive(&url).unwrap());
+
+        let recorder = StatsRecorder::new();
+        let response = client
+            .get(&url)
+            .recorder(Box::new(recorder.clone()))
+            .send()
+            .await
+            .unwrap();
+        let second = response.connection_info().unwrap().clone();
+        assert_eq!(response.text().await.unwrap(), "ok");
+        let stats = recorder.finish();
+        assert_eq!(stats.dead_connections, [first.id]);
+        assert!(!second.reused && !stats.reused);
+        assert!(stats.tcp_stats.is_some());
+
+        let server = TestServer::builder().body("ok").start().await.unwrap();
+        let response = client.get(server.url("/")).send().await.unwrap();
+        response.text().await.unwrap();
+        assert!(client.is_alive(server.url("/")).unwrap());
+    }
+}
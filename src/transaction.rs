@@ -0,0 +1,313 @@
+//! Multi-step transactions: requests run in order, later ones using values
+//! extracted from earlier responses, traced step by step.
+//!
+//! ```no_run
+//! # async fn run() -> httptrace::Result<()> {
+//! use http::Method;
+//! use httptrace::{
+//!     client::Client,
+//!     template::{RequestTemplate, Vars},
+//!     transaction::{Extract, Transaction},
+//! };
+//!
+//! let client = Client::builder().build()?;
+//! let report = Transaction::new()
+//!     .step("login", RequestTemplate::new(Method::POST, "https://example.com/login"))
+//!     .extract("token", Extract::Header(http::header::AUTHORIZATION))
+//!     .step(
+//!         "profile",
+//!         RequestTemplate::new(Method::GET, "https://example.com/me")
+//!             .header("authorization", "{token}"),
+//!     )
+//!     .run(&client, Vars::new())
+//!     .await;
+//! println!("{report}");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{fmt, sync::Arc, time::Duration};
+
+use bytes::Bytes;
+use http::{HeaderMap, HeaderName, StatusCode};
+use tokio::time::Instant;
+
+use crate::{
+    client::Client,
+    stats::{Stats, StatsRecorder},
+    template::{RequestTemplate, Vars},
+};
+
+/// How to extract a value from the response of a step.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum Extract {
+    /// The value of a header.
+    Header(HeaderName),
+    /// The body text between the first `start` and the next `end`.
+    Between(String, String),
+    /// The value at a JSON pointer, e.g. `/data/token`. Strings are taken
+    /// as is, other values as their JSON text. Requires the `json` feature.
+    #[cfg(any(test, feature = "json"))]
+    JsonPointer(String),
+    /// The value returned by a function of the headers and the body.
+    Custom(fn(&HeaderMap, &[u8]) -> Option<String>),
+}
+
+struct Step {
+    name: String,
+    template: RequestTemplate,
+    extracts: Vec<(String, Extract)>,
+}
+
+/// The trace of one step.
+#[derive(Debug, Clone)]
+pub struct StepResult {
+    pub name: String,
+    pub status: Option<StatusCode>,
+    /// Why the step failed, if it did.
+    pub error: Option<String>,
+    pub stats: Stats,
+}
+
+/// The trace of a transaction, up to the first step that failed.
+#[derive(Debug, Clone)]
+pub struct TransactionReport {
+    pub steps: Vec<StepResult>,
+    /// The names of the steps not run after a failure.
+    pub skipped: Vec<String>,
+    /// The initial values along with the extracted ones.
+    pub vars: Vars,
+    /// From the start of the first step to the end of the last one.
+    pub total_duration: Duration,
+}
+
+/// A chain of requests run in order, see the [module](self) documentation.
+#[derive(Default)]
+pub struct Transaction {
+    steps: Vec<Step>,
+}
+
+impl Transaction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a step, its template may use the values extracted by the previous
+    /// steps.
+    pub fn step<S: Into<String>>(mut self, name: S, template: RequestTemplate) -> Self {
+        self.steps.push(Step {
+            name: name.into(),
+            template,
+            extracts: Vec::new(),
+        });
+        self
+    }
+
+    /// Extract a value named `var` from the response of the last step. The
+    /// step fails if it can't be found.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no step was added yet.
+    pub fn extract<S: Into<String>>(mut self, var: S, extract: Extract) -> Self {
+        let step = self.steps.last_mut().expect("extract before any step");
+        step.extracts.push((var.into(), extract));
+        self
+    }
+
+    /// Run the steps in order with the initial values `vars`, stopping at the
+    /// first failed step.
+    ///
+    /// A step fails if its request fails, its status is a 4xx or 5xx, or a
+    /// value can't be extracted.
+    pub async fn run(&self, client: &Client, mut vars: Vars) -> TransactionReport {
+        let start = Instant::now();
+        let mut steps = Vec::with_capacity(self.steps.len());
+        for step in self.steps.iter() {
+            let result = run_step(client, step, &mut vars).await;
+            let failed = result.error.is_some();
+            steps.push(result);
+            if failed {
+                break;
+            }
+        }
+        let skipped = self.steps[steps.len()..]
+            .iter()
+            .map(|step| step.name.clone())
+            .collect();
+        TransactionReport {
+            steps,
+            skipped,
+            vars,
+            total_duration: start.elapsed(),
+        }
+    }
+}
+
+async fn run_step(client: &Client, step: &Step, vars: &mut Vars) -> StepResult {
+    let recorder = StatsRecorder::new();
+    let ret = async {
+        let mut request = step.template.expand(client, vars)?;
+        *request.recorder_mut() = Some(Arc::new(recorder.clone()));
+        let response = client.execute(request).await?;
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response.bytes().await?;
+        Ok::<_, crate::Error>((status, headers, body))
+    }
+    .await;
+
+    let (status, error) = match ret {
+        Ok((status, headers, body)) => {
+            let error = match status.is_client_error() || status.is_server_error() {
+                true => Some(format!("unexpected status {}", status)),
+                false => extract_all(step, &headers, &body, vars).err(),
+            };
+            (Some(status), error)
+        }
+        Err(e) => (None, Some(e.to_string())),
+    };
+    StepResult {
+        name: step.name.clone(),
+        status,
+        error,
+        stats: recorder.finish(),
+    }
+}
+
+fn extract_all(
+    step: &Step,
+    headers: &HeaderMap,
+    body: &Bytes,
+    vars: &mut Vars,
+) -> Result<(), String> {
+    for (var, extract) in step.extracts.iter() {
+        let value = extract
+            .extract(headers, body)
+            .ok_or_else(|| format!("no value for {}", var))?;
+        vars.insert(var.clone(), value);
+    }
+    Ok(())
+}
+
+impl Extract {
+    fn extract(&self, headers: &HeaderMap, body: &[u8]) -> Option<String> {
+        match self {
+            Extract::Header(name) => headers.get(name)?.to_str().ok().map(|v| v.to_string()),
+            Extract::Between(start, end) => {
+                let body = String::from_utf8_lossy(body);
+                let (_, rest) = body.split_once(start.as_str())?;
+                let (value, _) = rest.split_once(end.as_str())?;
+                Some(value.to_string())
+            }
+            #[cfg(any(test, feature = "json"))]
+            Extract::JsonPointer(pointer) => {
+                let value = serde_json::from_slice::<serde_json::Value>(body).ok()?;
+                match value.pointer(pointer)? {
+                    serde_json::Value::String(value) => Some(value.clone()),
+                    value => Some(value.to_string()),
+                }
+            }
+            Extract::Custom(extract) => extract(headers, body),
+        }
+    }
+}
+
+impl TransactionReport {
+    /// Whether every step ran and succeeded.
+    pub fn passed(&self) -> bool {
+        self.skipped.is_empty() && self.steps.iter().all(|v| v.error.is_none())
+    }
+}
+
+impl fmt::Display for TransactionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, step) in self.steps.iter().enumerate() {
+            write!(
+                f,
+                "step {} {}: {:>4}ms",
+                index + 1,
+                step.name,
+                step.stats.total_duration.as_millis()
+            )?;
+            if let Some(status) = step.status {
+                write!(f, " {}", status.as_u16())?;
+            }
+            if let Some(error) = &step.error {
+                write!(f, " failed: {}", error)?;
+            }
+            writeln!(f)?;
+        }
+        for name in self.skipped.iter() {
+            writeln!(f, "skipped {}", name)?;
+        }
+        writeln!(f, "total: {:>4}ms", self.total_duration.as_millis())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use http::Method;
+
+    use super::{Extract, Transaction};
+    use crate::{
+        client::Client,
+        template::{RequestTemplate, Vars},
+        test_util::{MockResponse, MockTransport},
+    };
+
+    #[tokio::test(start_paused = true)]
+    async fn test_transaction() {
+        let mock = MockTransport::new();
+        mock.push(
+            MockResponse::new(200)
+                .header("x-session", "s1")
+                .body(r#"{"data": {"token": "abc", "ttl": 60}}"#)
+                .response_delay(Duration::from_millis(30)),
+        );
+        mock.push(
+            MockResponse::new(200)
+                .body("<p>id=42;</p>")
+                .response_delay(Duration::from_millis(20)),
+        );
+        mock.push(MockResponse::new(503));
+        let client = Client::builder()
+            .mock_transport(mock.clone())
+            .build()
+            .unwrap();
+
+        let get = |url: &str| RequestTemplate::new(Method::GET, url);
+        let transaction = Transaction::new()
+            .step("login", get("http://example.com/login?user={user}"))
+            .extract("token", Extract::JsonPointer("/data/token".to_string()))
+            .extract("ttl", Extract::JsonPointer("/data/ttl".to_string()))
+            .extract("session", Extract::Header("x-session".parse().unwrap()))
+            .step(
+                "profile",
+                get("http://example.com/me?ttl={ttl}").header("authorization", "Bearer {token}"),
+            )
+            .extract("id", Extract::Between("id=".to_string(), ";".to_string()))
+            .step("orders", get("http://example.com/users/{id}/orders"))
+            .step("logout", get("http://example.com/logout"));
+
+        let vars = Vars::from([("user".to_string(), "bob".to_string())]);
+        let report = transaction.run(&client, vars).await;
+        assert!(!report.passed());
+        assert_eq!(report.steps.len(), 3);
+        assert_eq!(report.skipped, ["logout"]);
+        assert_eq!(report.vars["session"], "s1");
+        assert_eq!(report.total_duration, Duration::from_millis(50));
+        assert_eq!(
+            report.steps[2].error.as_deref(),
+            Some("unexpected status 503 Service Unavailable")
+        );
+
+        let requests = mock.requests();
+        assert_eq!(requests[1].uri, "http://example.com/me?ttl=60");
+        assert_eq!(requests[1].headers["authorization"], "Bearer abc");
+        assert_eq!(requests[2].uri, "http://example.com/users/42/orders");
+    }
+}
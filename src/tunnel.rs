@@ -0,0 +1,193 @@
+//! Raw TCP tunnels, see [`Client::connect_tunnel`].
+//!
+//! A tunnel is opened like the connection of a request: the DNS and TCP
+//! phases, then the `CONNECT` or SOCKS handshake when a proxy applies, are
+//! reported to the recorder of the tunnel.
+
+use std::{
+    fmt,
+    io::IoSlice,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use http::{Method, Uri, uri::Authority};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+};
+
+use crate::{
+    capture::CaptureIo,
+    client::Client,
+    request::Request,
+    stats::{Recorder, TcpInfo},
+};
+
+/// A builder for a tunnel to a `host:port`, see [`Client::connect_tunnel`].
+pub struct TunnelBuilder {
+    client: Client,
+    request: crate::Result<Request>,
+}
+
+impl TunnelBuilder {
+    pub(crate) fn new(client: Client, authority: &str) -> Self {
+        let request = target(authority).map(|uri| Request::new(Method::CONNECT, uri));
+        TunnelBuilder { client, request }
+    }
+
+    pub fn recorder(mut self, recorder: Box<dyn Recorder>) -> Self {
+        if let Ok(ref mut req) = self.request {
+            *req.recorder_mut() = Some(Arc::from(recorder));
+        }
+        self
+    }
+
+    /// Give up opening the tunnel after `timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        if let Ok(ref mut req) = self.request {
+            *req.timeout_mut() = Some(timeout);
+        }
+        self
+    }
+
+    pub async fn send(self) -> crate::Result<Tunnel> {
+        self.client.open_tunnel(self.request?).await
+    }
+}
+
+/// The `https` URI of port 443, `http` otherwise, which picks the proxy.
+fn target(authority: &str) -> crate::Result<Uri> {
+    let authority: Authority = authority.parse()?;
+    let port = authority
+        .port_u16()
+        .ok_or_else(|| crate::Error::InvalidTunnelTarget(authority.to_string()))?;
+    let scheme = if port == 443 { "https" } else { "http" };
+    Ok(format!("{scheme}://{authority}/").parse()?)
+}
+
+/// An open tunnel, a byte stream to the target.
+pub struct Tunnel {
+    io: CaptureIo<TcpStream>,
+    tcp: TcpInfo,
+}
+
+impl Tunnel {
+    pub(crate) fn new(io: CaptureIo<TcpStream>, tcp: TcpInfo) -> Self {
+        Tunnel { io, tcp }
+    }
+
+    /// The TCP connection carrying the tunnel, to the proxy if one applies.
+    pub fn tcp_info(&self) -> &TcpInfo {
+        &self.tcp
+    }
+}
+
+impl fmt::Debug for Tunnel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tunnel").field("tcp", &self.tcp).finish()
+    }
+}
+
+impl AsyncRead for Tunnel {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.io).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Tunnel {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.io.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use crate::{client::Client, proxy::Proxy, stats::StatsRecorder};
+
+    #[tokio::test]
+    async fn test_connect_tunnel() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let proxy = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut head = Vec::new();
+            while !head.ends_with(b"\r\n\r\n") {
+                head.push(stream.read_u8().await.unwrap());
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                .await
+                .unwrap();
+            let mut buf = [0; 4];
+            stream.read_exact(&mut buf).await.unwrap();
+            stream.write_all(&buf).await.unwrap();
+            String::from_utf8(head).unwrap()
+        });
+
+        let client = Client::builder()
+            .proxy(Proxy::new(format!("http://{addr}")).unwrap())
+            .build()
+            .unwrap();
+        let recorder = StatsRecorder::new();
+        let mut tunnel = client
+            .connect_tunnel("target.test:25")
+            .recorder(Box::new(recorder.clone()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(tunnel.tcp_info().peer_addr, addr);
+        tunnel.write_all(b"EHLO").await.unwrap();
+        let mut buf = [0; 4];
+        tunnel.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"EHLO");
+
+        let head = proxy.await.unwrap();
+        assert!(head.starts_with("CONNECT target.test:25 HTTP/1.1\r\n"));
+        let stats = recorder.finish();
+        assert!(stats.tcp_stats.is_some());
+        assert!(stats.proxy_stats.unwrap().error.is_none());
+
+        let err = client
+            .connect_tunnel("target.test")
+            .send()
+            .await
+            .unwrap_err();
+        assert!(matches!(err, crate::Error::InvalidTunnelTarget(_)));
+    }
+}
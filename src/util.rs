@@ -21,3 +21,423 @@ where
     header.set_sensitive(true);
     header
 }
+
+/// A splitmix64 generator, enough to shuffle addresses reproducibly.
+#[derive(Debug, Clone)]
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    /// Seed from the current time, when reproducibility is not needed.
+    pub(crate) fn from_time() -> Self {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        Rng(now.as_nanos() as u64)
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Fisher-Yates shuffle.
+    pub(crate) fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = (self.next_u64() % (i as u64 + 1)) as usize;
+            items.swap(i, j);
+        }
+    }
+}
+
+/// Decode the `%XX` escapes of `value`, `None` if one is invalid or the
+/// result isn't UTF-8.
+pub(crate) fn percent_decode(value: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut iter = value.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(b);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// The `notAfter` of a DER encoded X.509 certificate.
+pub(crate) fn cert_not_after(der: &[u8]) -> Option<std::time::SystemTime> {
+    cert_fields(der).map(|cert| cert.not_after)
+}
+
+/// The fields of a DER encoded X.509 certificate read by [`cert_fields`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CertFields {
+    pub(crate) subject: String,
+    pub(crate) issuer: String,
+    pub(crate) not_before: std::time::SystemTime,
+    pub(crate) not_after: std::time::SystemTime,
+    pub(crate) subject_alt_names: Vec<String>,
+}
+
+/// The names and validity of a DER encoded X.509 certificate, `None` if it
+/// can't be read.
+pub(crate) fn cert_fields(der: &[u8]) -> Option<CertFields> {
+    // Certificate ::= SEQUENCE { tbsCertificate, .. }
+    let (_, cert, _) = der_read(der).filter(|(tag, ..)| *tag == 0x30)?;
+    let (_, tbs, _) = der_read(cert).filter(|(tag, ..)| *tag == 0x30)?;
+    // tbsCertificate ::= SEQUENCE { [0] version OPTIONAL, serialNumber,
+    //     signature, issuer, validity, subject, subjectPublicKeyInfo, ..,
+    //     [3] extensions OPTIONAL }
+    let mut rest = tbs;
+    if rest.first() == Some(&0xa0) {
+        rest = der_read(rest)?.2;
+    }
+    for _ in 0..2 {
+        rest = der_read(rest)?.2;
+    }
+    let (_, issuer, rest) = der_read(rest).filter(|(tag, ..)| *tag == 0x30)?;
+    let (_, validity, rest) = der_read(rest).filter(|(tag, ..)| *tag == 0x30)?;
+    let (not_before_tag, not_before, validity) = der_read(validity)?;
+    let (not_after_tag, not_after, _) = der_read(validity)?;
+
+    // Only the validity is needed for the expiry, the rest is best effort.
+    let (subject, mut rest) = match der_read(rest) {
+        Some((0x30, subject, rest)) => (subject, der_read(rest).map_or(&[][..], |v| v.2)),
+        _ => (&[][..], &[][..]),
+    };
+    let mut subject_alt_names = Vec::new();
+    while let Some((tag, contents, next)) = der_read(rest) {
+        if tag == 0xa3 {
+            subject_alt_names = alt_names(contents).unwrap_or_default();
+        }
+        rest = next;
+    }
+    Some(CertFields {
+        subject: dn_string(subject),
+        issuer: dn_string(issuer),
+        not_before: der_time(not_before_tag, not_before)?,
+        not_after: der_time(not_after_tag, not_after)?,
+        subject_alt_names,
+    })
+}
+
+/// A `UTCTime` or `GeneralizedTime`.
+fn der_time(tag: u8, time: &[u8]) -> Option<std::time::SystemTime> {
+    let time = std::str::from_utf8(time).ok()?;
+    // Digits and `Z` only, so the fields below fall on char boundaries.
+    if !time.is_ascii() {
+        return None;
+    }
+    let (year, time) = match tag {
+        // UTCTime, YYMMDDHHMMSSZ
+        0x17 => {
+            let year: i64 = time.get(..2)?.parse().ok()?;
+            (
+                if year < 50 { 2000 + year } else { 1900 + year },
+                &time[2..],
+            )
+        }
+        // GeneralizedTime, YYYYMMDDHHMMSSZ
+        0x18 => (time.get(..4)?.parse().ok()?, &time[4..]),
+        _ => return None,
+    };
+    if time.len() != 11 || !time.ends_with('Z') {
+        return None;
+    }
+    let field = |i: usize| time.get(i..i + 2)?.parse::<i64>().ok();
+    let (month, day) = (field(0)?, field(2)?);
+    let secs =
+        days_from_civil(year, month, day) * 86400 + field(4)? * 3600 + field(6)? * 60 + field(8)?;
+    let secs = u64::try_from(secs).ok()?;
+    Some(std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs))
+}
+
+/// A distinguished name in the order of its attributes, e.g.
+/// `CN=example.com, O=Example`.
+fn dn_string(mut name: &[u8]) -> String {
+    let mut attrs = Vec::new();
+    // Name ::= SEQUENCE OF SET OF SEQUENCE { type, value }
+    while let Some((_, mut rdn, next)) = der_read(name) {
+        while let Some((_, attr, rest)) = der_read(rdn) {
+            if let Some((0x06, oid, value)) = der_read(attr)
+                && let Some((tag, value, _)) = der_read(value)
+            {
+                let value = match tag {
+                    // BMPString
+                    0x1e => {
+                        let units = value
+                            .chunks_exact(2)
+                            .map(|v| u16::from_be_bytes([v[0], v[1]]));
+                        char::decode_utf16(units)
+                            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                            .collect()
+                    }
+                    _ => String::from_utf8_lossy(value).into_owned(),
+                };
+                let oid = oid_string(oid);
+                let key = match oid.as_str() {
+                    "2.5.4.3" => "CN",
+                    "2.5.4.6" => "C",
+                    "2.5.4.7" => "L",
+                    "2.5.4.8" => "ST",
+                    "2.5.4.10" => "O",
+                    "2.5.4.11" => "OU",
+                    "1.2.840.113549.1.9.1" => "emailAddress",
+                    oid => oid,
+                };
+                attrs.push(format!("{key}={value}"));
+            }
+            rdn = rest;
+        }
+        name = next;
+    }
+    attrs.join(", ")
+}
+
+/// The dotted form of an object identifier.
+fn oid_string(oid: &[u8]) -> String {
+    let mut arcs = Vec::new();
+    let mut value: u64 = 0;
+    for byte in oid {
+        value = (value << 7) | u64::from(byte & 0x7f);
+        if byte & 0x80 == 0 {
+            if arcs.is_empty() {
+                let first = (value / 40).min(2);
+                arcs.push(first);
+                arcs.push(value - first * 40);
+            } else {
+                arcs.push(value);
+            }
+            value = 0;
+        }
+    }
+    arcs.iter()
+        .map(u64::to_string)
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// The DNS names and IP addresses of the subject alternative name extension.
+fn alt_names(extensions: &[u8]) -> Option<Vec<String>> {
+    // [3] EXPLICIT SEQUENCE OF Extension ::= SEQUENCE { extnID, critical
+    //     BOOLEAN DEFAULT FALSE, extnValue OCTET STRING }
+    let (_, mut extensions, _) = der_read(extensions)?;
+    while let Some((_, extension, next)) = der_read(extensions) {
+        let (_, oid, mut rest) = der_read(extension)?;
+        if oid != [0x55, 0x1d, 0x11] {
+            extensions = next;
+            continue;
+        }
+        if rest.first() == Some(&0x01) {
+            rest = der_read(rest)?.2;
+        }
+        let (_, value, _) = der_read(rest)?;
+        // GeneralNames ::= SEQUENCE OF GeneralName
+        let (_, mut names, _) = der_read(value)?;
+        let mut ret = Vec::new();
+        while let Some((tag, name, next)) = der_read(names) {
+            match (tag, name.len()) {
+                // dNSName
+                (0x82, _) => ret.push(String::from_utf8_lossy(name).into_owned()),
+                // iPAddress
+                (0x87, 4) => {
+                    let ip: [u8; 4] = name.try_into().ok()?;
+                    ret.push(std::net::IpAddr::from(ip).to_string());
+                }
+                (0x87, 16) => {
+                    let ip: [u8; 16] = name.try_into().ok()?;
+                    ret.push(std::net::IpAddr::from(ip).to_string());
+                }
+                _ => {}
+            }
+            names = next;
+        }
+        return Some(ret);
+    }
+    Some(Vec::new())
+}
+
+/// Splits the first DER element into its tag, contents and the bytes after it.
+fn der_read(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, input) = input.split_first()?;
+    let (&len, mut input) = input.split_first()?;
+    let len = if len < 0x80 {
+        len as usize
+    } else {
+        let n = (len & 0x7f) as usize;
+        if n == 0 || n > 4 || input.len() < n {
+            return None;
+        }
+        let (bytes, rest) = input.split_at(n);
+        input = rest;
+        bytes.iter().fold(0, |len, v| (len << 8) | *v as usize)
+    };
+    if input.len() < len {
+        return None;
+    }
+    let (contents, rest) = input.split_at(len);
+    Some((tag, contents, rest))
+}
+
+/// Parse an RFC 3339 timestamp, e.g. `2024-05-01T12:30:00.250+02:00`.
+#[cfg(any(test, feature = "json"))]
+pub(crate) fn parse_rfc3339(value: &str) -> Option<std::time::SystemTime> {
+    // Fixed byte offsets below, which only fall on char boundaries in ASCII.
+    if !value.is_ascii() {
+        return None;
+    }
+    let bytes = value.as_bytes();
+    if bytes.len() < 20 || bytes[4] != b'-' || bytes[7] != b'-' || bytes[13] != b':' {
+        return None;
+    }
+    if !matches!(bytes[10], b'T' | b't' | b' ') || bytes[16] != b':' {
+        return None;
+    }
+    let field = |range: std::ops::Range<usize>| value.get(range)?.parse::<i64>().ok();
+    let days = days_from_civil(field(0..4)?, field(5..7)?, field(8..10)?);
+    let mut secs = days * 86400 + field(11..13)? * 3600 + field(14..16)? * 60 + field(17..19)?;
+
+    let mut rest = value.get(19..)?;
+    let mut nanos = 0;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let len = fraction
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(fraction.len());
+        let digits = &fraction[..len.min(9)];
+        nanos = format!("{:0<9}", digits).parse::<u32>().ok()?;
+        rest = &fraction[len..];
+    }
+    match rest {
+        "Z" | "z" => {}
+        _ => {
+            let sign = match rest.get(..1)? {
+                "+" => 1,
+                "-" => -1,
+                _ => return None,
+            };
+            if rest.len() != 6 || rest.get(3..4)? != ":" {
+                return None;
+            }
+            let hours: i64 = rest.get(1..3)?.parse().ok()?;
+            let minutes: i64 = rest.get(4..6)?.parse().ok()?;
+            secs -= sign * (hours * 3600 + minutes * 60);
+        }
+    }
+    let secs = u64::try_from(secs).ok()?;
+    Some(std::time::UNIX_EPOCH + std::time::Duration::new(secs, nanos))
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{Rng, cert_fields, cert_not_after, der_time, parse_rfc3339};
+
+    #[test]
+    fn test_seeded_shuffle() {
+        let shuffled = |seed| {
+            let mut rng = Rng::new(seed);
+            (0..3)
+                .map(|_| {
+                    let mut items = (0..8).collect::<Vec<_>>();
+                    rng.shuffle(&mut items);
+                    items
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(shuffled(42), shuffled(42));
+        assert_ne!(shuffled(42), shuffled(43));
+
+        let mut items = shuffled(42).remove(0);
+        items.sort();
+        assert_eq!(items, (0..8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_cert_not_after() {
+        let mut params = rcgen::CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+        params.not_after = rcgen::date_time_ymd(2031, 3, 4);
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = params.clone().self_signed(&key).unwrap();
+        assert_eq!(
+            cert_not_after(cert.der()),
+            Some(UNIX_EPOCH + Duration::from_secs(1930348800))
+        );
+
+        params.not_after = rcgen::date_time_ymd(2051, 1, 1);
+        let cert = params.self_signed(&key).unwrap();
+        assert_eq!(
+            cert_not_after(cert.der()),
+            Some(UNIX_EPOCH + Duration::from_secs(2556144000))
+        );
+        assert_eq!(cert_not_after(b"\x30\x03\x02\x01"), None);
+        assert_eq!(der_time(0x17, "250101000\u{e9}0Z".as_bytes()), None);
+    }
+
+    #[test]
+    fn test_cert_fields() {
+        let mut params =
+            rcgen::CertificateParams::new(vec!["localhost".to_string(), "127.0.0.1".to_string()])
+                .unwrap();
+        params.not_before = rcgen::date_time_ymd(2025, 1, 2);
+        params.not_after = rcgen::date_time_ymd(2031, 3, 4);
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "localhost");
+        params
+            .distinguished_name
+            .push(rcgen::DnType::OrganizationName, "Example");
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key).unwrap();
+
+        let fields = cert_fields(cert.der()).unwrap();
+        assert_eq!(fields.subject, "CN=localhost, O=Example");
+        assert_eq!(fields.issuer, fields.subject);
+        assert_eq!(
+            fields.not_before,
+            UNIX_EPOCH + Duration::from_secs(1735776000)
+        );
+        assert_eq!(
+            fields.not_after,
+            UNIX_EPOCH + Duration::from_secs(1930348800)
+        );
+        assert_eq!(fields.subject_alt_names, ["localhost", "127.0.0.1"]);
+    }
+
+    #[test]
+    fn test_parse_rfc3339() {
+        let at = |secs, nanos| Some(UNIX_EPOCH + Duration::new(secs, nanos));
+        assert_eq!(parse_rfc3339("2024-05-01T12:30:00Z"), at(1714566600, 0));
+        assert_eq!(
+            parse_rfc3339("2024-05-01T14:30:00.25+02:00"),
+            at(1714566600, 250_000_000)
+        );
+        assert_eq!(
+            parse_rfc3339("2024-05-01T12:30:00.123456789123Z"),
+            at(1714566600, 123_456_789)
+        );
+        assert_eq!(parse_rfc3339("2024-05-01"), None);
+        assert_eq!(parse_rfc3339("2024-05-01T12:30:00+0200"), None);
+        assert_eq!(parse_rfc3339("2024-05-01T12:30:0\u{e9}+02:00"), None);
+        assert_eq!(parse_rfc3339("2024-05-01T12:30:00+0\u{e9}00"), None);
+    }
+}
@@ -0,0 +1,427 @@
+use std::{
+    error::Error,
+    fmt,
+    io::Write,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use hickory_resolver::config::NameServerConfig;
+use http::{HeaderMap, StatusCode, Uri};
+use rustls::{client::EchStatus, pki_types::CertificateDer};
+
+use crate::{
+    alt_svc::AltSvcDecision,
+    dns::DnsInfo,
+    request::Request,
+    response::Response,
+    revocation::RevocationStatus,
+    sse::SseEvent,
+    stats::{ConnectionInfo, Negotiation, Recorder, TcpInfo, TlsInfo},
+};
+
+/// A recorder printing the exchange like `curl -v` does.
+///
+/// Connection events are prefixed with `*`, request headers with `>` and
+/// response headers with `<`. Bodies are not printed.
+///
+/// ```no_run
+/// # use httptrace::{client::Client, verbose::VerboseRecorder};
+/// # async fn run() {
+/// let client = Client::builder().build().unwrap();
+/// let response = client
+///     .get("https://www.example.com")
+///     .recorder(Box::new(VerboseRecorder::new()))
+///     .send()
+///     .await
+///     .unwrap();
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct VerboseRecorder {
+    out: Arc<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl VerboseRecorder {
+    /// Print to stderr.
+    pub fn new() -> Self {
+        Self::with_writer(std::io::stderr())
+    }
+
+    /// Print to the given writer.
+    pub fn with_writer<W: Write + Send + 'static>(writer: W) -> Self {
+        Self {
+            out: Arc::new(Mutex::new(Box::new(writer))),
+        }
+    }
+
+    fn print(&self, prefix: char, line: fmt::Arguments<'_>) {
+        let mut out = self.out.lock().unwrap();
+        _ = writeln!(out, "{prefix} {line}");
+    }
+
+    fn print_headers(&self, prefix: char, headers: &HeaderMap) {
+        let mut out = self.out.lock().unwrap();
+        for (name, value) in headers {
+            _ = writeln!(
+                out,
+                "{prefix} {name}: {}",
+                String::from_utf8_lossy(value.as_bytes())
+            );
+        }
+        _ = writeln!(out, "{prefix}");
+        _ = out.flush();
+    }
+}
+
+impl Default for VerboseRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for VerboseRecorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VerboseRecorder").finish_non_exhaustive()
+    }
+}
+
+impl Recorder for VerboseRecorder {
+    fn on_blocked_start(&self, _request: &Request) {
+        self.print('*', format_args!("Waiting for a free request slot"));
+    }
+
+    fn on_blocked_done(&self, _request: &Request) {
+        self.print('*', format_args!("Got a free request slot"));
+    }
+
+    fn on_dns_start(&self, _request: &Request, _name_servers: &[NameServerConfig], host: &str) {
+        self.print('*', format_args!("Resolving {host}"));
+    }
+
+    fn on_dns_done(
+        &self,
+        _request: &Request,
+        _name_servers: &[NameServerConfig],
+        host: &str,
+        result: Result<&DnsInfo, &dyn Error>,
+    ) {
+        match result {
+            Ok(info) => self.print(
+                '*',
+                format_args!(
+                    "Resolved {host} to {} ({:?})",
+                    info.addrs
+                        .iter()
+                        .map(|v| v.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    info.source,
+                ),
+            ),
+            Err(e) => self.print('*', format_args!("Could not resolve {host}: {e}")),
+        }
+    }
+
+    fn on_connect_delay(&self, _request: &Request, delay: Duration) {
+        self.print('*', format_args!("Connect attempts {delay:?} apart"));
+    }
+
+    fn on_tcp_start(&self, _request: &Request, dest: &SocketAddr) {
+        self.print('*', format_args!("Trying {dest}..."));
+    }
+
+    fn on_connection_reused(&self, _request: &Request, conn: &ConnectionInfo) {
+        self.print(
+            '*',
+            format_args!("Re-using existing connection #{}", conn.id),
+        );
+    }
+
+    fn on_connection_dead(&self, _request: &Request, conn: &ConnectionInfo) {
+        self.print('*', format_args!("Connection #{} found closed", conn.id));
+    }
+
+    fn on_tcp_done(
+        &self,
+        _request: &Request,
+        dest: &SocketAddr,
+        tcp: Result<&TcpInfo, &dyn Error>,
+    ) {
+        match tcp {
+            Ok(tcp) => match tcp.local_addr {
+                Some(local) => self.print('*', format_args!("Connected to {dest} from {local}")),
+                None => self.print('*', format_args!("Connected to {dest}")),
+            },
+            Err(e) => self.print('*', format_args!("Failed to connect to {dest}: {e}")),
+        }
+    }
+
+    fn on_proxy_start(&self, _request: &Request, proxy: &Uri) {
+        self.print(
+            '*',
+            format_args!("Establishing tunnel through proxy {proxy}"),
+        );
+    }
+
+    fn on_proxy_done(&self, _request: &Request, proxy: &Uri, result: Result<(), &dyn Error>) {
+        match result {
+            Ok(()) => self.print('*', format_args!("Tunnel established through {proxy}")),
+            Err(e) => self.print('*', format_args!("Proxy tunnel failed: {e}")),
+        }
+    }
+
+    fn on_proxy_auth_start(&self, _request: &Request, proxy: &Uri) {
+        self.print(
+            '*',
+            format_args!("Proxy {proxy} requires authentication, sending credentials"),
+        );
+    }
+
+    fn on_proxy_auth_done(&self, _request: &Request, _proxy: &Uri, result: Result<(), &dyn Error>) {
+        if let Err(e) = result {
+            self.print('*', format_args!("Proxy authentication failed: {e}"));
+        }
+    }
+
+    fn on_socks_start(&self, _request: &Request, proxy: &Uri) {
+        self.print('*', format_args!("SOCKS handshake with {proxy}"));
+    }
+
+    fn on_socks_done(&self, _request: &Request, proxy: &Uri, result: Result<(), &dyn Error>) {
+        match result {
+            Ok(()) => self.print(
+                '*',
+                format_args!("SOCKS tunnel established through {proxy}"),
+            ),
+            Err(e) => self.print('*', format_args!("SOCKS handshake failed: {e}")),
+        }
+    }
+
+    fn on_h2c_upgrade_done(&self, _request: &Request, switched: Result<bool, &dyn Error>) {
+        match switched {
+            Ok(true) => self.print('*', format_args!("Switched to HTTP/2 with h2c upgrade")),
+            Ok(false) => self.print(
+                '*',
+                format_args!("h2c upgrade refused, staying on HTTP/1.1"),
+            ),
+            Err(e) => self.print('*', format_args!("h2c upgrade failed: {e}")),
+        }
+    }
+
+    fn on_tls_start(&self, _request: &Request, _tcp: &TcpInfo) {
+        self.print('*', format_args!("TLS handshake"));
+    }
+
+    fn on_tls_done(&self, _request: &Request, tls: Result<&TlsInfo, &dyn Error>) {
+        match tls {
+            Ok(tls) => {
+                let mut version = tls
+                    .protocol_version
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("unknown")
+                    .to_string();
+                if let Some(suite) = tls.cipher_suite.and_then(|v| v.as_str()) {
+                    version = format!("{version} / {suite}");
+                }
+                match tls.alpn_protocol.as_deref() {
+                    Some(alpn) => self.print(
+                        '*',
+                        format_args!(
+                            "TLS connection using {version}, ALPN {}",
+                            String::from_utf8_lossy(alpn)
+                        ),
+                    ),
+                    None => self.print('*', format_args!("TLS connection using {version}")),
+                }
+                if let Some(ocsp) = tls.ocsp_response.as_deref() {
+                    self.print(
+                        '*',
+                        format_args!("OCSP response stapled ({} bytes)", ocsp.len()),
+                    );
+                }
+                match tls.ech {
+                    Some(EchStatus::Accepted) => self.print('*', format_args!("ECH accepted")),
+                    Some(_) => self.print('*', format_args!("ECH not offered, no config found")),
+                    None => {}
+                }
+            }
+            Err(e) => self.print('*', format_args!("TLS handshake failed: {e}")),
+        }
+    }
+
+    fn on_tls_early_data(&self, _request: &Request, accepted: bool) {
+        match accepted {
+            true => self.print('*', format_args!("TLS early data accepted")),
+            false => self.print(
+                '*',
+                format_args!("TLS early data rejected, request sent again"),
+            ),
+        }
+    }
+
+    fn on_tls_rejected_chain(&self, _request: &Request, chain: &[CertificateDer<'static>]) {
+        let sizes: Vec<_> = chain.iter().map(|cert| cert.len().to_string()).collect();
+        self.print(
+            '*',
+            format_args!(
+                "Server certificate rejected, chain of {} ({} bytes)",
+                chain.len(),
+                sizes.join(", ")
+            ),
+        );
+    }
+
+    fn on_revocation_done(&self, _request: &Request, status: Result<RevocationStatus, &dyn Error>) {
+        match status {
+            Ok(status) => self.print('*', format_args!("Certificate revocation status: {status}")),
+            Err(e) => self.print('*', format_args!("Revocation check failed: {e}")),
+        }
+    }
+
+    fn on_negotiated(&self, _request: &Request, negotiation: &Negotiation) {
+        self.print('*', format_args!("Using {negotiation}"));
+    }
+
+    fn on_http2_stream(&self, _request: &Request, stream_id: u32) {
+        self.print(
+            '*',
+            format_args!("Opened HTTP/2 stream {stream_id} (estimated)"),
+        );
+    }
+
+    fn on_h2_fallback(&self, _request: &Request, error: &dyn Error) {
+        self.print(
+            '*',
+            format_args!("HTTP/2 failed ({error}), retrying over HTTP/1.1"),
+        );
+    }
+
+    fn on_alt_svc(&self, _request: &Request, decision: &AltSvcDecision) {
+        for alt in decision.skipped.iter() {
+            self.print('*', format_args!("Skipping alternative service {alt}"));
+        }
+        if let Some(alt) = decision.used.as_ref() {
+            self.print('*', format_args!("Connecting to alternative service {alt}"));
+        }
+    }
+
+    fn on_request_start(&self, request: &Request) {
+        let target = request.uri().path_and_query().map_or("/", |v| v.as_str());
+        self.print(
+            '>',
+            format_args!("{} {target} {:?}", request.method(), request.version()),
+        );
+        self.print_headers('>', request.headers());
+    }
+
+    fn on_expect_continue_done(&self, _request: &Request, received: bool) {
+        if !received {
+            self.print('*', format_args!("Done waiting for 100-continue"));
+        }
+    }
+
+    fn on_informational(&self, _request: &Request, status: StatusCode, headers: &HeaderMap) {
+        self.print('<', format_args!("HTTP/1.1 {status}"));
+        self.print_headers('<', headers);
+    }
+
+    fn on_response(&self, _request: &Request, response: &Response) {
+        let status = response.status();
+        self.print(
+            '<',
+            format_args!(
+                "{:?} {} {}",
+                response.version(),
+                status.as_u16(),
+                status.canonical_reason().unwrap_or_default()
+            ),
+        );
+        self.print_headers('<', response.headers());
+    }
+
+    fn on_sse_event(&self, _request: &Request, event: &SseEvent) {
+        let len = event.data.len();
+        self.print(
+            '*',
+            format_args!("Event {} with {len} bytes of data", event.event),
+        );
+    }
+
+    fn on_cache_read_done(&self, _request: &Request, key: &str, hit: Result<bool, &dyn Error>) {
+        match hit {
+            Ok(true) => self.print('*', format_args!("Cache hit for {key}")),
+            Ok(false) => self.print('*', format_args!("Cache miss for {key}")),
+            Err(e) => self.print('*', format_args!("Cache read failed for {key}: {e}")),
+        }
+    }
+
+    fn on_cache_write_done(&self, _request: &Request, key: &str, written: Result<u64, &dyn Error>) {
+        match written {
+            Ok(n) => self.print('*', format_args!("Cached {n} bytes for {key}")),
+            Err(e) => self.print('*', format_args!("Cache write failed for {key}: {e}")),
+        }
+    }
+
+    fn on_cache_revalidate_done(
+        &self,
+        _request: &Request,
+        key: &str,
+        status: Result<StatusCode, &dyn Error>,
+    ) {
+        match status {
+            Ok(status) => self.print('*', format_args!("Revalidated {key}: {status}")),
+            Err(e) => self.print('*', format_args!("Revalidation failed for {key}: {e}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        sync::{Arc, Mutex},
+    };
+
+    use super::VerboseRecorder;
+    use crate::{
+        client::Client,
+        test_util::{MockResponse, MockTransport},
+    };
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_verbose_recorder() {
+        let mock = MockTransport::new();
+        mock.push(MockResponse::new(404).header("content-type", "text/plain"));
+        let client = Client::builder().mock_transport(mock).build().unwrap();
+
+        let buffer = Buffer::default();
+        client
+            .get("https://example.com/a?b=c")
+            .header("accept", "*/*")
+            .recorder(Box::new(VerboseRecorder::with_writer(buffer.clone())))
+            .send()
+            .await
+            .unwrap();
+
+        let out = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert!(out.starts_with("* Resolving example.com\n"));
+        assert!(out.contains("* Connected to 127.0.0.1:443 from 127.0.0.1:50000\n"));
+        assert!(out.contains("> GET /a?b=c HTTP/1.1\n> accept: */*\n"));
+        assert!(out.contains("< HTTP/1.1 404 Not Found\n< content-type: text/plain\n<\n"));
+    }
+}
@@ -0,0 +1,186 @@
+//! What the verifier of a handshake is shown but the connection doesn't
+//! keep: the chain of a rejected certificate, see
+//! [`ClientBuilder::tls_capture_rejected_chain`](crate::client::ClientBuilder::tls_capture_rejected_chain),
+//! and the stapled OCSP response.
+//!
+//! The verifier is shared by the connections of a client, it keeps what it's
+//! shown in the [`scope`] of the handshake it's called from.
+
+use std::sync::{Arc, Mutex};
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+
+tokio::task_local! {
+    static VERIFIED: Arc<Mutex<Verified>>;
+}
+
+/// What a [`ConnectionVerifier`] was shown during a handshake.
+#[derive(Debug, Default)]
+pub(crate) struct Verified {
+    /// The chain presented, the end entity first, kept only if rejected.
+    pub(crate) rejected_chain: Vec<CertificateDer<'static>>,
+    pub(crate) ocsp_response: Option<Vec<u8>>,
+}
+
+/// Runs the handshake `f`, with what the verifier was shown. Empty for a
+/// resumed session, it isn't verified again.
+pub(crate) async fn scope<F: Future>(f: F) -> (F::Output, Verified) {
+    let verified = Arc::new(Mutex::new(Verified::default()));
+    let ret = VERIFIED.scope(verified.clone(), f).await;
+    let verified = std::mem::take(&mut *verified.lock().unwrap());
+    (ret, verified)
+}
+
+/// Verifies like `inner`, keeping what it's shown for the handshake in
+/// [`scope`].
+#[derive(Debug)]
+pub(crate) struct ConnectionVerifier {
+    inner: Arc<dyn ServerCertVerifier>,
+    capture_rejected_chain: bool,
+}
+
+impl ConnectionVerifier {
+    pub(crate) fn new(inner: Arc<dyn ServerCertVerifier>, capture_rejected_chain: bool) -> Self {
+        Self {
+            inner,
+            capture_rejected_chain,
+        }
+    }
+}
+
+impl ServerCertVerifier for ConnectionVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        let ret = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        );
+        _ = VERIFIED.try_with(|verified| {
+            let mut verified = verified.lock().unwrap();
+            if !ocsp_response.is_empty() {
+                verified.ocsp_response = Some(ocsp_response.to_vec());
+            }
+            if ret.is_err() && self.capture_rejected_chain {
+                verified.rejected_chain = std::iter::once(end_entity)
+                    .chain(intermediates)
+                    .map(|cert| cert.clone().into_owned())
+                    .collect();
+            }
+        });
+        ret
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+
+    fn requires_raw_public_keys(&self) -> bool {
+        self.inner.requires_raw_public_keys()
+    }
+
+    fn root_hint_subjects(&self) -> Option<&[rustls::DistinguishedName]> {
+        self.inner.root_hint_subjects()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::net::TcpListener;
+
+    use crate::{client::Client, stats::StatsRecorder, test_util::TestServer};
+
+    #[tokio::test]
+    async fn test_tls_capture_rejected_chain() {
+        let server = TestServer::builder().tls().start().await.unwrap();
+        let url = format!("https://localhost:{}/", server.addr().port());
+        let other = rcgen::generate_simple_self_signed(["other.test".to_string()]).unwrap();
+        let client = Client::builder()
+            .add_root_certificate(other.cert.der().clone())
+            .tls_built_in_root_certs(false)
+            .tls_capture_rejected_chain(true)
+            .build()
+            .unwrap();
+        let recorder = StatsRecorder::new();
+        let err = client
+            .get(&url)
+            .recorder(Box::new(recorder.clone()))
+            .send()
+            .await
+            .unwrap_err();
+        let certificate = server.certificate().unwrap().clone();
+        let crate::Error::CertificateRejected { error, chain } = err else {
+            panic!("{err}");
+        };
+        assert!(matches!(error, rustls::Error::InvalidCertificate(_)));
+        assert_eq!(chain, std::slice::from_ref(&certificate));
+        let stats = recorder.finish();
+        assert_eq!(stats.rejected_certificates, [certificate]);
+        assert!(stats.tls_stats.unwrap().error.is_some());
+
+        let client = Client::builder().build().unwrap();
+        let err = client.get(&url).send().await.unwrap_err();
+        assert!(matches!(err, crate::Error::Io(_) | crate::Error::Rustls(_)));
+    }
+
+    #[tokio::test]
+    async fn test_ocsp_response() {
+        crate::client::ensure_crypto_provider();
+        let rcgen::CertifiedKey { cert, key_pair } =
+            rcgen::generate_simple_self_signed(["localhost".to_string()]).unwrap();
+        let key = rustls::pki_types::PrivateKeyDer::Pkcs8(key_pair.serialize_der().into());
+        let config = rustls::ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert_with_ocsp(vec![cert.der().clone()], key, b"ocsp".to_vec())
+            .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                _ = acceptor.accept(stream).await;
+            }
+        });
+
+        let client = Client::builder().skip_tls_verify().build().unwrap();
+        let url = format!("https://localhost:{port}/");
+        let probe = client.probe_certificate(url).await.unwrap();
+        assert_eq!(probe.tls.ocsp_response.as_deref(), Some(&b"ocsp"[..]));
+        let stats = probe.stats.tls_info.unwrap();
+        assert_eq!(stats.ocsp_response.as_deref(), Some(&b"ocsp"[..]));
+
+        let server = TestServer::builder().tls().start().await.unwrap();
+        let url = format!("https://localhost:{}/", server.addr().port());
+        let probe = client.probe_certificate(url).await.unwrap();
+        assert_eq!(probe.tls.ocsp_response, None);
+    }
+}
@@ -0,0 +1,402 @@
+//! WebSocket client, see
+//! [`RequestBuilder::upgrade_websocket`](crate::request::RequestBuilder::upgrade_websocket).
+//!
+//! The handshake is a request like any other, so its recorder gets the DNS,
+//! TCP and TLS phases and the `101` response; the messages then go over the
+//! upgraded connection.
+
+use bytes::{Bytes, BytesMut};
+use http::{HeaderMap, HeaderValue, StatusCode, Uri, header};
+use hyper::upgrade::{OnUpgrade, Upgraded};
+use hyper_util::rt::TokioIo;
+use ring::rand::{SecureRandom, SystemRandom};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+
+use crate::{client::Client, request::Request};
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+// Larger messages fail instead of growing the buffer without bound.
+const MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xa;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Bytes),
+    /// Answered with a pong before being handed out.
+    Ping(Bytes),
+    Pong(Bytes),
+    Close(Option<CloseFrame>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseFrame {
+    pub code: u16,
+    pub reason: String,
+}
+
+/// An open WebSocket, read with [`WebSocket::recv`] and written with
+/// [`WebSocket::send`].
+#[derive(Debug)]
+pub struct WebSocket {
+    io: BufReader<TokioIo<Upgraded>>,
+    headers: HeaderMap,
+    rng: SystemRandom,
+    // The opcode and payload of a fragmented message not complete yet.
+    partial: Option<(u8, BytesMut)>,
+    close_sent: bool,
+    close_received: bool,
+}
+
+/// Send `request` as the handshake of a WebSocket, `ws` and `wss` URLs being
+/// sent to `http` and `https`.
+pub(crate) async fn connect(client: Client, mut request: Request) -> crate::Result<WebSocket> {
+    *request.uri_mut() = http_uri(request.uri())?;
+
+    let rng = SystemRandom::new();
+    let mut nonce = [0u8; 16];
+    rng.fill(&mut nonce)
+        .map_err(|_| crate::Error::WebSocketHandshake("no random source".into()))?;
+    let key = base64_encode(&nonce);
+
+    let headers = request.headers_mut();
+    headers.insert(header::CONNECTION, HeaderValue::from_static("upgrade"));
+    headers.insert(header::UPGRADE, HeaderValue::from_static("websocket"));
+    headers.insert(
+        header::SEC_WEBSOCKET_VERSION,
+        HeaderValue::from_static("13"),
+    );
+    headers.insert(header::SEC_WEBSOCKET_KEY, HeaderValue::from_str(&key)?);
+
+    let mut response = client.execute(request).await?;
+    if response.status() != StatusCode::SWITCHING_PROTOCOLS {
+        return Err(crate::Error::WebSocketHandshake(format!(
+            "unexpected status {}",
+            response.status()
+        )));
+    }
+    let accept = response.headers().get(header::SEC_WEBSOCKET_ACCEPT);
+    if accept.is_none_or(|v| v.as_bytes() != accept_key(&key).as_bytes()) {
+        return Err(crate::Error::WebSocketHandshake(
+            "invalid Sec-WebSocket-Accept".into(),
+        ));
+    }
+
+    let upgrade = response
+        .extensions_mut()
+        .remove::<OnUpgrade>()
+        .ok_or_else(|| crate::Error::WebSocketHandshake("connection not upgradable".into()))?;
+    let io = upgrade.await?;
+    Ok(WebSocket {
+        io: BufReader::new(TokioIo::new(io)),
+        headers: response.headers().clone(),
+        rng,
+        partial: None,
+        close_sent: false,
+        close_received: false,
+    })
+}
+
+impl WebSocket {
+    /// The headers of the `101` response, e.g. the agreed
+    /// `Sec-WebSocket-Protocol`.
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// The next message, `None` once the connection is closed.
+    ///
+    /// Pings are answered and a close is echoed before being returned.
+    pub async fn recv(&mut self) -> Option<crate::Result<Message>> {
+        if self.close_received {
+            return None;
+        }
+        match self.read_message().await {
+            Ok(Some(message)) => Some(Ok(message)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+
+    pub async fn send(&mut self, message: Message) -> crate::Result<()> {
+        match message {
+            Message::Text(text) => self.write_frame(OP_TEXT, text.as_bytes()).await,
+            Message::Binary(data) => self.write_frame(OP_BINARY, &data).await,
+            Message::Ping(data) => self.write_frame(OP_PING, &data).await,
+            Message::Pong(data) => self.write_frame(OP_PONG, &data).await,
+            Message::Close(frame) => self.close(frame).await,
+        }
+    }
+
+    /// Send a close frame, the peer answers with its own through
+    /// [`WebSocket::recv`].
+    pub async fn close(&mut self, frame: Option<CloseFrame>) -> crate::Result<()> {
+        if self.close_sent {
+            return Ok(());
+        }
+        let mut payload = Vec::new();
+        if let Some(frame) = frame {
+            payload.extend_from_slice(&frame.code.to_be_bytes());
+            payload.extend_from_slice(frame.reason.as_bytes());
+        }
+        self.write_frame(OP_CLOSE, &payload).await?;
+        self.close_sent = true;
+        Ok(())
+    }
+
+    async fn read_message(&mut self) -> crate::Result<Option<Message>> {
+        loop {
+            let Some((fin, opcode, payload)) = self.read_frame().await? else {
+                return Ok(None);
+            };
+            match opcode {
+                OP_PING => {
+                    if !self.close_sent {
+                        self.write_frame(OP_PONG, &payload).await?;
+                    }
+                    return Ok(Some(Message::Ping(payload.freeze())));
+                }
+                OP_PONG => return Ok(Some(Message::Pong(payload.freeze()))),
+                OP_CLOSE => {
+                    self.close_received = true;
+                    let frame = close_frame(&payload)?;
+                    if !self.close_sent {
+                        // Echo the status code only.
+                        self.write_frame(OP_CLOSE, payload.get(..2).unwrap_or_default())
+                            .await?;
+                        self.close_sent = true;
+                    }
+                    return Ok(Some(Message::Close(frame)));
+                }
+                OP_TEXT | OP_BINARY if self.partial.is_none() => {
+                    if !fin {
+                        self.partial = Some((opcode, payload));
+                        continue;
+                    }
+                    return message(opcode, payload).map(Some);
+                }
+                OP_CONTINUATION if self.partial.is_some() => {
+                    let (_, buf) = self.partial.as_mut().expect("checked above");
+                    if buf.len() + payload.len() > MAX_MESSAGE_SIZE {
+                        return Err(crate::Error::WebSocket("message too large".into()));
+                    }
+                    buf.extend_from_slice(&payload);
+                    if fin {
+                        let (opcode, buf) = self.partial.take().expect("checked above");
+                        return message(opcode, buf).map(Some);
+                    }
+                }
+                opcode => {
+                    return Err(crate::Error::WebSocket(format!(
+                        "unexpected opcode {opcode:#x}"
+                    )));
+                }
+            }
+        }
+    }
+
+    /// The next frame as `(fin, opcode, payload)`, `None` if the connection
+    /// ends between frames.
+    async fn read_frame(&mut self) -> crate::Result<Option<(bool, u8, BytesMut)>> {
+        let mut head = [0u8; 2];
+        match self.io.read_exact(&mut head[..1]).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        self.io.read_exact(&mut head[1..]).await?;
+
+        let fin = head[0] & 0x80 != 0;
+        let opcode = head[0] & 0x0f;
+        if head[1] & 0x80 != 0 {
+            return Err(crate::Error::WebSocket("masked frame from server".into()));
+        }
+        let len = match head[1] & 0x7f {
+            126 => self.io.read_u16().await? as u64,
+            127 => self.io.read_u64().await?,
+            len => len as u64,
+        };
+        if len > MAX_MESSAGE_SIZE as u64 {
+            return Err(crate::Error::WebSocket("message too large".into()));
+        }
+        let mut payload = BytesMut::zeroed(len as usize);
+        self.io.read_exact(&mut payload).await?;
+        Ok(Some((fin, opcode, payload)))
+    }
+
+    /// Write a single final frame, masked as required from clients.
+    async fn write_frame(&mut self, opcode: u8, payload: &[u8]) -> crate::Result<()> {
+        let mut mask = [0u8; 4];
+        self.rng
+            .fill(&mut mask)
+            .map_err(|_| crate::Error::WebSocket("no random source".into()))?;
+
+        let mut frame = Vec::with_capacity(payload.len() + 14);
+        frame.push(0x80 | opcode);
+        match payload.len() {
+            len if len < 126 => frame.push(0x80 | len as u8),
+            len if len <= u16::MAX as usize => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(0x80 | 127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().zip(mask.iter().cycle()).map(|(b, m)| b ^ m));
+
+        self.io.write_all(&frame).await?;
+        self.io.flush().await?;
+        Ok(())
+    }
+}
+
+fn message(opcode: u8, payload: BytesMut) -> crate::Result<Message> {
+    match opcode {
+        OP_TEXT => String::from_utf8(payload.to_vec())
+            .map(Message::Text)
+            .map_err(|_| crate::Error::WebSocket("invalid UTF-8 in text message".into())),
+        _ => Ok(Message::Binary(payload.freeze())),
+    }
+}
+
+fn close_frame(payload: &[u8]) -> crate::Result<Option<CloseFrame>> {
+    match payload {
+        [] => Ok(None),
+        [hi, lo, reason @ ..] => {
+            let reason = std::str::from_utf8(reason)
+                .map_err(|_| crate::Error::WebSocket("invalid UTF-8 in close reason".into()))?;
+            Ok(Some(CloseFrame {
+                code: u16::from_be_bytes([*hi, *lo]),
+                reason: reason.to_string(),
+            }))
+        }
+        _ => Err(crate::Error::WebSocket("truncated close frame".into())),
+    }
+}
+
+/// `uri` with the `ws` and `wss` schemes mapped to `http` and `https`.
+fn http_uri(uri: &Uri) -> crate::Result<Uri> {
+    let scheme = match uri.scheme_str() {
+        Some(scheme) if scheme.eq_ignore_ascii_case("ws") => "http",
+        Some(scheme) if scheme.eq_ignore_ascii_case("wss") => "https",
+        _ => return Ok(uri.clone()),
+    };
+    let mut parts = uri.clone().into_parts();
+    parts.scheme = Some(scheme.parse()?);
+    Ok(Uri::from_parts(parts).map_err(http::Error::from)?)
+}
+
+/// The `Sec-WebSocket-Accept` expected for `key`.
+fn accept_key(key: &str) -> String {
+    let mut ctx = ring::digest::Context::new(&ring::digest::SHA1_FOR_LEGACY_USE_ONLY);
+    ctx.update(key.as_bytes());
+    ctx.update(GUID.as_bytes());
+    base64_encode(ctx.finish().as_ref())
+}
+
+fn base64_encode(data: &[u8]) -> String {
+    use base64::Engine;
+
+    base64::prelude::BASE64_STANDARD.encode(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::{CloseFrame, Message, accept_key};
+    use crate::client::Client;
+
+    #[test]
+    fn test_accept_key() {
+        // The example of RFC 6455.
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[tokio::test]
+    async fn test_echo() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut head = Vec::new();
+            while !head.ends_with(b"\r\n\r\n") {
+                head.push(stream.read_u8().await.unwrap());
+            }
+            let head = String::from_utf8(head).unwrap();
+            let key = head
+                .lines()
+                .find_map(|line| line.strip_prefix("sec-websocket-key: "))
+                .unwrap();
+            let response = format!(
+                "HTTP/1.1 101 Switching Protocols\r\nupgrade: websocket\r\n\
+                 connection: upgrade\r\nsec-websocket-accept: {}\r\n\r\n",
+                accept_key(key)
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+
+            // Echo the frames unmasked, until the close is echoed.
+            loop {
+                let op = stream.read_u8().await.unwrap();
+                let len = (stream.read_u8().await.unwrap() & 0x7f) as usize;
+                let mut mask = [0u8; 4];
+                stream.read_exact(&mut mask).await.unwrap();
+                let mut payload = vec![0u8; len];
+                stream.read_exact(&mut payload).await.unwrap();
+                payload
+                    .iter_mut()
+                    .zip(mask.iter().cycle())
+                    .for_each(|(b, m)| *b ^= m);
+                let mut frame = vec![op, len as u8];
+                frame.extend(payload);
+                stream.write_all(&frame).await.unwrap();
+                if op & 0x0f == 0x8 {
+                    break;
+                }
+            }
+        });
+
+        let client = Client::builder().build().unwrap();
+        let mut ws = client
+            .get(format!("ws://{addr}/chat"))
+            .upgrade_websocket()
+            .await
+            .unwrap();
+        ws.send(Message::Text("hello".into())).await.unwrap();
+        assert_eq!(
+            ws.recv().await.unwrap().unwrap(),
+            Message::Text("hello".into())
+        );
+        ws.close(Some(CloseFrame {
+            code: 1000,
+            reason: "bye".into(),
+        }))
+        .await
+        .unwrap();
+        assert_eq!(
+            ws.recv().await.unwrap().unwrap(),
+            Message::Close(Some(CloseFrame {
+                code: 1000,
+                reason: "bye".into(),
+            }))
+        );
+        assert!(ws.recv().await.is_none());
+        server.await.unwrap();
+    }
+}